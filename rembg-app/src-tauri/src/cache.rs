@@ -0,0 +1,100 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use rembg_rs::{model, runtime};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CachedModel {
+	pub name: &'static str,
+	pub size: u64
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CachedRuntime {
+	pub package: String,
+	pub version: String,
+	pub size: u64,
+	/// The version a run loads; the others only take space.
+	pub active: bool
+}
+
+/// Disk usage of the download cache, for the settings screen.
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheStats {
+	pub models_dir: PathBuf,
+	pub models: Vec<CachedModel>,
+	pub runtimes_dir: PathBuf,
+	pub runtimes: Vec<CachedRuntime>,
+	/// TensorRT engines and OpenVINO compiled models, rebuilt (slowly) when missing.
+	pub compiled_size: u64
+}
+
+/// What `cache_clean` deletes.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheKind {
+	Models,
+	/// Every runtime; the next run downloads one again.
+	Runtimes,
+	/// Runtime versions a run no longer loads (older releases, interrupted downloads).
+	StaleRuntimes,
+	Compiled
+}
+
+pub fn stats() -> Result<CacheStats> {
+	let mut models = Vec::new();
+	for &name in model::MODEL_IDS {
+		let size: u64 = model::model_files(name)?
+			.iter()
+			.filter_map(|f| std::fs::metadata(f).ok())
+			.map(|m| m.len())
+			.sum();
+		if size > 0 {
+			models.push(CachedModel { name, size });
+		}
+	}
+	let runtimes = runtime::list_installed()?
+		.into_iter()
+		.map(|rt| CachedRuntime {
+			package: rt.package,
+			version: rt.version,
+			size: rt.size,
+			active: rt.active
+		})
+		.collect();
+	Ok(CacheStats {
+		models_dir: model::models_dir()?,
+		models,
+		runtimes_dir: runtime::runtimes_dir()?,
+		runtimes,
+		compiled_size: runtime::dir_size(&runtime::tensorrt_cache_dir()?)
+			+ runtime::dir_size(&runtime::openvino_cache_dir()?)
+	})
+}
+
+/// Delete one part of the cache and return the bytes freed.
+pub fn clean(kind: CacheKind) -> Result<u64> {
+	match kind {
+		CacheKind::Models => {
+			let mut freed = 0;
+			for model in stats()?.models {
+				model::remove_model(model.name)?;
+				freed += model.size;
+			}
+			Ok(freed)
+		}
+		CacheKind::Runtimes => runtime::clean(),
+		CacheKind::StaleRuntimes => Ok(runtime::prune()?.iter().map(|rt| rt.size).sum()),
+		CacheKind::Compiled => {
+			let mut freed = 0;
+			for dir in [runtime::tensorrt_cache_dir()?, runtime::openvino_cache_dir()?] {
+				if dir.exists() {
+					freed += runtime::dir_size(&dir);
+					std::fs::remove_dir_all(&dir).with_context(|| format!("remove {}", dir.display()))?;
+				}
+			}
+			Ok(freed)
+		}
+	}
+}
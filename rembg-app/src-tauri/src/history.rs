@@ -0,0 +1,124 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use image::GrayImage;
+use rembg_rs::core::RemoveOptions;
+
+/// Undo steps kept per image.
+pub const HISTORY_LIMIT: usize = 20;
+/// Images whose history is kept; the oldest is dropped when a new one starts.
+const MAX_IMAGES: usize = 8;
+
+/// Everything needed to re-composite a result without running the model again.
+#[derive(Clone)]
+pub struct Snapshot {
+	pub input_bytes: Arc<Vec<u8>>,
+	pub mask: GrayImage,
+	pub options: RemoveOptions
+}
+
+struct Entry {
+	mask: GrayImage,
+	options: RemoveOptions
+}
+
+struct ImageHistory {
+	input_bytes: Arc<Vec<u8>>,
+	entries: Vec<Entry>,
+	/// Index of the entry currently shown; entries after it are redo steps.
+	cursor: usize
+}
+
+impl ImageHistory {
+	fn snapshot(&self) -> Snapshot {
+		let e = &self.entries[self.cursor];
+		Snapshot {
+			input_bytes: self.input_bytes.clone(),
+			mask: e.mask.clone(),
+			options: e.options.clone()
+		}
+	}
+}
+
+/// Per-request (mask, options) history, held in memory by the Tauri backend.
+#[derive(Default)]
+pub struct History {
+	images: Mutex<VecDeque<(u64, ImageHistory)>>
+}
+
+impl History {
+	/// Begin a fresh history for `request_id` with the model's mask as the first state.
+	pub fn start(&self, request_id: u64, input_bytes: Vec<u8>, mask: GrayImage, options: RemoveOptions) {
+		let mut images = self.images.lock().unwrap();
+		images.retain(|(id, _)| *id != request_id);
+		if images.len() >= MAX_IMAGES {
+			images.pop_front();
+		}
+		let h = ImageHistory {
+			input_bytes: Arc::new(input_bytes),
+			entries: vec![Entry { mask, options }],
+			cursor: 0
+		};
+		images.push_back((request_id, h));
+	}
+
+	/// Record a new state (edited mask and/or new options) and drop any redo steps.
+	/// `mask: None` keeps the current mask.
+	pub fn push(&self, request_id: u64, mask: Option<GrayImage>, options: RemoveOptions) -> Result<Snapshot, String> {
+		self.with(request_id, |h| {
+			let mask = match mask {
+				Some(m) => m,
+				None => h.entries[h.cursor].mask.clone()
+			};
+			h.entries.truncate(h.cursor + 1);
+			h.entries.push(Entry { mask, options });
+			if h.entries.len() > HISTORY_LIMIT {
+				h.entries.remove(0);
+			}
+			h.cursor = h.entries.len() - 1;
+			h.snapshot()
+		})
+	}
+
+	/// Step back; `None` if already at the oldest state.
+	pub fn undo(&self, request_id: u64) -> Result<Option<Snapshot>, String> {
+		self.with(request_id, |h| {
+			if h.cursor == 0 {
+				return None;
+			}
+			h.cursor -= 1;
+			Some(h.snapshot())
+		})
+	}
+
+	/// Step forward again; `None` if there is nothing to redo.
+	pub fn redo(&self, request_id: u64) -> Result<Option<Snapshot>, String> {
+		self.with(request_id, |h| {
+			if h.cursor + 1 >= h.entries.len() {
+				return None;
+			}
+			h.cursor += 1;
+			Some(h.snapshot())
+		})
+	}
+
+	/// The state currently shown.
+	pub fn current(&self, request_id: u64) -> Result<Snapshot, String> {
+		self.with(request_id, |h| h.snapshot())
+	}
+
+	/// `(can_undo, can_redo)` for the GUI buttons.
+	pub fn status(&self, request_id: u64) -> (bool, bool) {
+		self.with(request_id, |h| (h.cursor > 0, h.cursor + 1 < h.entries.len()))
+			.unwrap_or((false, false))
+	}
+
+	fn with<T>(&self, request_id: u64, f: impl FnOnce(&mut ImageHistory) -> T) -> Result<T, String> {
+		let mut images = self.images.lock().unwrap();
+		let (_, h) = images
+			.iter_mut()
+			.find(|(id, _)| *id == request_id)
+			.ok_or_else(|| format!("no history for request {request_id}"))?;
+		Ok(f(h))
+	}
+}
@@ -0,0 +1,164 @@
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rembg_rs::core::RemoveOptions;
+use serde::{Deserialize, Serialize};
+
+/// Entries kept; the oldest (and its files) go first.
+const MAX_ENTRIES: usize = 100;
+/// Longest side of the stored thumbnails.
+const THUMBNAIL_SIZE: u32 = 128;
+const INDEX_FILE: &str = "index.json";
+
+/// A processed image as recorded in the index. The input and a thumbnail of the result are kept
+/// next to it as `<id>.input` and `<id>.png`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+	pub id: u64,
+	/// Shown in the GUI, e.g. the input file name.
+	pub name: String,
+	/// Seconds since the Unix epoch.
+	pub time: u64,
+	/// Without `bg_image`, which would bloat the index; see `background`.
+	pub options: RemoveOptions,
+	/// `options.bg_image` is kept next to the entry as `<id>.bg`.
+	#[serde(default)]
+	pub background: bool,
+	/// Where the result was exported, if it was.
+	pub output_path: Option<PathBuf>
+}
+
+/// An entry as `list_history` reports it.
+#[derive(Debug, Clone, Serialize)]
+pub struct JournalItem {
+	#[serde(flatten)]
+	pub entry: JournalEntry,
+	pub thumbnail_png: Option<Vec<u8>>
+}
+
+/// Processing history, persisted under the app data dir so past jobs can be reopened or run
+/// again after a restart. Unlike `History` (undo steps of the open image) it only holds finished
+/// results.
+pub struct Journal {
+	dir: PathBuf,
+	entries: Mutex<Vec<JournalEntry>>
+}
+
+impl Journal {
+	/// Load the index in `dir`; a missing or unreadable one starts an empty history.
+	pub fn open(dir: PathBuf) -> Journal {
+		let entries = std::fs::read_to_string(dir.join(INDEX_FILE))
+			.ok()
+			.and_then(|text| serde_json::from_str(&text).ok())
+			.unwrap_or_default();
+		Journal {
+			dir,
+			entries: Mutex::new(entries)
+		}
+	}
+
+	/// Record a result; `output` is its encoded image, used for the thumbnail.
+	pub fn record(
+		&self,
+		name: String,
+		input_bytes: &[u8],
+		mut options: RemoveOptions,
+		output: &[u8],
+		output_path: Option<PathBuf>
+	) -> Result<JournalEntry, String> {
+		let mut entries = self.entries.lock().unwrap();
+		let background = options.bg_image.take();
+		let entry = JournalEntry {
+			id: entries.iter().map(|e| e.id + 1).max().unwrap_or(1),
+			name,
+			time: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+			options,
+			background: background.is_some(),
+			output_path
+		};
+		std::fs::create_dir_all(&self.dir).map_err(|e| format!("create {}: {e}", self.dir.display()))?;
+		write(&self.input_path(entry.id), input_bytes)?;
+		if let Some(bg) = &background {
+			write(&self.background_path(entry.id), bg)?;
+		}
+		// A result the thumbnailer can't read is still worth a history entry.
+		if let Some(thumb) = thumbnail(output) {
+			write(&self.thumbnail_path(entry.id), &thumb)?;
+		}
+
+		entries.push(entry.clone());
+		while entries.len() > MAX_ENTRIES {
+			let old = entries.remove(0);
+			let _ = std::fs::remove_file(self.input_path(old.id));
+			let _ = std::fs::remove_file(self.thumbnail_path(old.id));
+			let _ = std::fs::remove_file(self.background_path(old.id));
+		}
+		self.save(&entries)?;
+		Ok(entry)
+	}
+
+	/// Newest first, with thumbnails.
+	pub fn list(&self) -> Vec<JournalItem> {
+		let entries = self.entries.lock().unwrap();
+		entries
+			.iter()
+			.rev()
+			.map(|entry| JournalItem {
+				thumbnail_png: std::fs::read(self.thumbnail_path(entry.id)).ok(),
+				entry: entry.clone()
+			})
+			.collect()
+	}
+
+	/// An entry, with its background back in `options`, and its stored input.
+	pub fn get(&self, id: u64) -> Result<(JournalEntry, Vec<u8>), String> {
+		let mut entry = self
+			.entries
+			.lock()
+			.unwrap()
+			.iter()
+			.find(|e| e.id == id)
+			.cloned()
+			.ok_or_else(|| format!("no history entry {id}"))?;
+		let path = self.input_path(id);
+		let input = std::fs::read(&path).map_err(|e| format!("read {}: {e}", path.display()))?;
+		if entry.background {
+			let path = self.background_path(id);
+			entry.options.bg_image = Some(std::fs::read(&path).map_err(|e| format!("read {}: {e}", path.display()))?);
+		}
+		Ok((entry, input))
+	}
+
+	fn save(&self, entries: &[JournalEntry]) -> Result<(), String> {
+		let text = serde_json::to_string(entries).map_err(|e| e.to_string())?;
+		let path = self.dir.join(INDEX_FILE);
+		let tmp = path.with_extension("json.tmp");
+		write(&tmp, text.as_bytes())?;
+		std::fs::rename(&tmp, &path).map_err(|e| format!("write {}: {e}", path.display()))
+	}
+
+	fn input_path(&self, id: u64) -> PathBuf {
+		self.dir.join(format!("{id}.input"))
+	}
+
+	fn thumbnail_path(&self, id: u64) -> PathBuf {
+		self.dir.join(format!("{id}.png"))
+	}
+
+	fn background_path(&self, id: u64) -> PathBuf {
+		self.dir.join(format!("{id}.bg"))
+	}
+}
+
+fn write(path: &Path, bytes: &[u8]) -> Result<(), String> {
+	std::fs::write(path, bytes).map_err(|e| format!("write {}: {e}", path.display()))
+}
+
+fn thumbnail(image_bytes: &[u8]) -> Option<Vec<u8>> {
+	let img = image::load_from_memory(image_bytes).ok()?.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE);
+	let mut png = Vec::new();
+	img.write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png).ok()?;
+	Some(png)
+}
@@ -1,49 +1,466 @@
-use tauri::Emitter;
+use tauri::{Emitter, Manager};
+use tauri_plugin_clipboard_manager::ClipboardExt;
 use serde::Serialize;
+use std::collections::HashMap;
 use std::path::PathBuf;
+use std::sync::Mutex;
+
+use rembg_rs::cancel::CancellationToken;
+use rembg_rs::error::ErrorInfo;
+
+mod cache;
+mod history;
+mod journal;
+mod queue;
+mod scan;
+mod settings;
+
+use cache::{CacheKind, CacheStats};
+use history::{History, Snapshot};
+use journal::{Journal, JournalItem};
+use queue::{JobInfo, JobState, Queue, ReadyJob};
+use scan::ImageEntry;
+use settings::Settings;
+
+/// A re-composited result from the history, with the options it was made with so the GUI can
+/// sync its controls.
+#[derive(Serialize)]
+struct HistoryStep {
+	#[serde(flatten)]
+	result: rembg_rs::core::RemoveResult,
+	options: rembg_rs::core::RemoveOptions,
+	can_undo: bool,
+	can_redo: bool
+}
+
+/// Cancellation tokens of the `remove_background` calls still running, by request id.
+#[derive(Default)]
+struct InFlight(Mutex<HashMap<u64, CancellationToken>>);
+
+/// A `rembg:progress` event, tagged with the request it belongs to.
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct UiProgress {
+	request_id: u64,
+	#[serde(flatten)]
+	evt: rembg_rs::core::ProgressEvent
+}
+
+fn emit_progress(window: &tauri::Window, request_id: u64, evt: rembg_rs::core::ProgressEvent) {
+	let _ = window.emit("rembg:progress", UiProgress { request_id, evt });
+}
 
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
 #[tauri::command]
 async fn remove_background(
+	app: tauri::AppHandle,
 	window: tauri::Window,
 	request_id: u64,
 	input_bytes: Vec<u8>,
 	options: rembg_rs::core::RemoveOptions
-) -> Result<rembg_rs::core::RemoveResult, String> {
+) -> Result<rembg_rs::core::RemoveResult, ErrorInfo> {
 	let win = window.clone();
+	let cancel = CancellationToken::new();
+	app.state::<InFlight>().0.lock().unwrap().insert(request_id, cancel.clone());
+	let job_app = app.clone();
+	let result = tauri::async_runtime::spawn_blocking(move || -> anyhow::Result<_> {
+		let emit = |evt| emit_progress(&win, request_id, evt);
+		let (mask, timings) = rembg_rs::core::predict_mask_bytes(&input_bytes, &options, &cancel, emit)?;
+		cancel.check()?;
+		let encode = rembg_rs::core::ProgressEvent::started("encode");
+		emit(encode.clone());
+		let mut result = rembg_rs::core::compose_result(&input_bytes, &mask, &options)?;
+		emit(encode.finished());
+		// `compose_result` decodes the input again; both decodes count.
+		result.timings = rembg_rs::core::Timings {
+			decode_ms: timings.decode_ms + result.timings.decode_ms,
+			compose_ms: result.timings.compose_ms,
+			encode_ms: result.timings.encode_ms,
+			..timings
+		};
+		job_app.state::<History>().start(request_id, input_bytes, mask, options);
+		Ok(result)
+	})
+	.await;
+	app.state::<InFlight>().0.lock().unwrap().remove(&request_id);
+	let result = result.map_err(anyhow::Error::from).and_then(|r| r).map_err(ErrorInfo::from);
+	if matches!(&result, Err(e) if e.code == "cancelled") {
+		emit_progress(
+			&window,
+			request_id,
+			rembg_rs::core::ProgressEvent {
+				done: Some(true),
+				..rembg_rs::core::ProgressEvent::stage("cancelled")
+			}
+		);
+	}
+	result
+}
+
+/// Abort a running `remove_background` call. It then fails with code "cancelled" after a last
+/// `rembg:progress` event with stage "cancelled". Unknown or finished requests are ignored.
+#[tauri::command]
+fn cancel_remove(app: tauri::AppHandle, request_id: u64) {
+	if let Some(cancel) = app.state::<InFlight>().0.lock().unwrap().get(&request_id) {
+		cancel.cancel();
+	}
+}
+
+/// Re-composite the result of `request_id` with new options and/or a brush-edited mask (PNG),
+/// without running the model again. `strokes` are painted over the mask (`mask_png` if given, else
+/// the current one). The new state becomes undoable.
+#[tauri::command]
+async fn recompose(
+	app: tauri::AppHandle,
+	request_id: u64,
+	options: rembg_rs::core::RemoveOptions,
+	mask_png: Option<Vec<u8>>,
+	strokes: Option<Vec<rembg_rs::core::BrushStroke>>
+) -> Result<HistoryStep, String> {
 	tauri::async_runtime::spawn_blocking(move || {
-		rembg_rs::core::remove_background_bytes(&input_bytes, &options, |evt| {
-			#[derive(Serialize, Clone)]
-			#[serde(rename_all = "camelCase")]
-			struct UiProgress {
-				request_id: u64,
-				#[serde(flatten)]
-				evt: rembg_rs::core::ProgressEvent
+		let mask = match mask_png {
+			Some(bytes) => Some(
+				image::load_from_memory(&bytes)
+					.map_err(|e| format!("decode mask: {e}"))?
+					.into_luma8()
+			),
+			None => None
+		};
+		let history = app.state::<History>();
+		let mask = match strokes {
+			Some(strokes) => {
+				let base = match mask {
+					Some(m) => m,
+					None => history.current(request_id)?.mask
+				};
+				Some(rembg_rs::core::apply_mask_edits(&base, &strokes))
 			}
-			let _ = win.emit("rembg:progress", UiProgress { request_id, evt });
-		})
-		.map_err(|e| e.to_string())
+			None => mask
+		};
+		let snap = history.push(request_id, mask, options)?;
+		history_step(&history, request_id, snap)
+	})
+	.await
+	.map_err(|e| e.to_string())?
+}
+
+/// Stateless `recompose`: re-composite `input_bytes` with a mask from an earlier result's
+/// `mask_png` and new options, without running the model.
+#[tauri::command]
+async fn recomposite(
+	mask_png: Vec<u8>,
+	input_bytes: Vec<u8>,
+	options: rembg_rs::core::RemoveOptions
+) -> Result<rembg_rs::core::RemoveResult, ErrorInfo> {
+	let result =
+		tauri::async_runtime::spawn_blocking(move || rembg_rs::core::recomposite(&mask_png, &input_bytes, &options)).await;
+	Ok(result.map_err(anyhow::Error::from)??)
+}
+
+#[tauri::command]
+async fn undo(app: tauri::AppHandle, request_id: u64) -> Result<Option<HistoryStep>, String> {
+	tauri::async_runtime::spawn_blocking(move || {
+		let history = app.state::<History>();
+		match history.undo(request_id)? {
+			Some(snap) => history_step(&history, request_id, snap).map(Some),
+			None => Ok(None)
+		}
 	})
 	.await
 	.map_err(|e| e.to_string())?
 }
 
+#[tauri::command]
+async fn redo(app: tauri::AppHandle, request_id: u64) -> Result<Option<HistoryStep>, String> {
+	tauri::async_runtime::spawn_blocking(move || {
+		let history = app.state::<History>();
+		match history.redo(request_id)? {
+			Some(snap) => history_step(&history, request_id, snap).map(Some),
+			None => Ok(None)
+		}
+	})
+	.await
+	.map_err(|e| e.to_string())?
+}
+
+fn history_step(history: &History, request_id: u64, snap: Snapshot) -> Result<HistoryStep, String> {
+	let result = rembg_rs::core::compose_result(&snap.input_bytes, &snap.mask, &snap.options).map_err(|e| e.to_string())?;
+	let (can_undo, can_redo) = history.status(request_id);
+	Ok(HistoryStep {
+		result,
+		options: snap.options,
+		can_undo,
+		can_redo
+	})
+}
+
+/// A queue job changed state; finished jobs carry their result.
+#[derive(Serialize, Clone)]
+struct JobUpdate {
+	#[serde(flatten)]
+	job: JobInfo,
+	result: Option<rembg_rs::core::RemoveResult>
+}
+
+/// Add an image to the batch queue; it runs once a slot is free. Progress arrives as
+/// `rembg:job` events. Returns the job id.
+#[tauri::command]
+fn enqueue_job(
+	app: tauri::AppHandle,
+	name: String,
+	input_bytes: Vec<u8>,
+	options: rembg_rs::core::RemoveOptions
+) -> u64 {
+	let id = app.state::<Queue>().enqueue(name, input_bytes, options);
+	start_jobs(&app);
+	id
+}
+
+#[tauri::command]
+fn list_jobs(app: tauri::AppHandle) -> Vec<JobInfo> {
+	app.state::<Queue>().list()
+}
+
+/// Drop a queued job or stop a running one; finished and unknown jobs are ignored.
+#[tauri::command]
+fn cancel_job(app: tauri::AppHandle, job_id: u64) {
+	if let Some(job) = app.state::<Queue>().cancel(job_id) {
+		let _ = app.emit("rembg:job", JobUpdate { job, result: None });
+	}
+}
+
+/// How many queued jobs run at once (at least 1), and so how many inference workers. Raising it
+/// starts waiting jobs right away.
+#[tauri::command]
+fn set_concurrency(app: tauri::AppHandle, concurrency: usize) {
+	app.state::<Queue>().set_concurrency(concurrency);
+	rembg_rs::worker::set_pool_size(concurrency);
+	start_jobs(&app);
+}
+
+/// Start as many queued jobs as there are free slots; each starts the next one when it ends.
+fn start_jobs(app: &tauri::AppHandle) {
+	for job in app.state::<Queue>().start_ready() {
+		let app = app.clone();
+		let _ = app.emit("rembg:job", JobUpdate { job: job.info.clone(), result: None });
+		tauri::async_runtime::spawn_blocking(move || {
+			let id = job.info.id;
+			let (state, result) = run_job(&job);
+			if let Some(result) = &result {
+				let journal = app.state::<Journal>();
+				let _ = journal.record(job.info.name, &job.input_bytes, job.options, &result.output_png, None);
+			}
+			if let Some(job) = app.state::<Queue>().finish(id, state) {
+				let _ = app.emit("rembg:job", JobUpdate { job, result });
+			}
+			start_jobs(&app);
+		});
+	}
+}
+
+fn run_job(job: &ReadyJob) -> (JobState, Option<rembg_rs::core::RemoveResult>) {
+	match rembg_rs::core::remove_background_bytes(&job.input_bytes, &job.options, &job.cancel, |_| {}) {
+		Ok(result) => (JobState::Done, Some(result)),
+		Err(e) => {
+			let error = ErrorInfo::from(e);
+			if error.code == "cancelled" {
+				(JobState::Cancelled, None)
+			} else {
+				(JobState::Failed { error }, None)
+			}
+		}
+	}
+}
+
+/// Disk usage of cached models, runtimes and compiled accelerator engines.
+#[tauri::command]
+async fn cache_stats() -> Result<CacheStats, ErrorInfo> {
+	let result = tauri::async_runtime::spawn_blocking(cache::stats).await;
+	Ok(result.map_err(anyhow::Error::from)??)
+}
+
+/// Delete part of the download cache; returns the bytes freed.
+#[tauri::command]
+async fn cache_clean(kind: CacheKind) -> Result<u64, ErrorInfo> {
+	let result = tauri::async_runtime::spawn_blocking(move || cache::clean(kind)).await;
+	Ok(result.map_err(anyhow::Error::from)??)
+}
+
+/// Supported models with their download sizes and whether they're cached, for the model picker.
+#[tauri::command]
+fn list_models() -> Result<Vec<rembg_rs::model::ModelInfo>, ErrorInfo> {
+	Ok(rembg_rs::model::list_models()?)
+}
+
+/// GPU backends and devices for the device picker.
+#[tauri::command]
+async fn list_devices() -> Result<rembg_rs::core::DeviceList, ErrorInfo> {
+	let result = tauri::async_runtime::spawn_blocking(rembg_rs::core::list_devices).await;
+	Ok(result.map_err(anyhow::Error::from)??)
+}
+
+/// The images in a dropped folder, with their sizes and thumbnails, ready to `enqueue_job`.
+#[tauri::command]
+async fn scan_folder(path: PathBuf, recursive: bool) -> Result<Vec<ImageEntry>, String> {
+	tauri::async_runtime::spawn_blocking(move || scan::scan(&path, recursive))
+		.await
+		.map_err(|e| e.to_string())?
+}
+
+/// The image on the clipboard (e.g. a screenshot), as PNG.
+#[tauri::command]
+fn read_clipboard_image(app: tauri::AppHandle) -> Result<Vec<u8>, String> {
+	let img = app
+		.clipboard()
+		.read_image()
+		.map_err(|e| format!("clipboard has no image: {e}"))?;
+	let rgba = image::RgbaImage::from_raw(img.width(), img.height(), img.rgba().to_vec())
+		.ok_or("clipboard image has an unexpected size")?;
+	let mut png = Vec::new();
+	rgba.write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+		.map_err(|e| format!("encode clipboard image: {e}"))?;
+	Ok(png)
+}
+
+/// Put a PNG (e.g. a result) on the clipboard, transparency included.
+#[tauri::command]
+fn write_clipboard_image(app: tauri::AppHandle, png_bytes: Vec<u8>) -> Result<(), String> {
+	let rgba = image::load_from_memory(&png_bytes)
+		.map_err(|e| format!("decode image: {e}"))?
+		.into_rgba8();
+	let (w, h) = rgba.dimensions();
+	app.clipboard()
+		.write_image(&tauri::image::Image::new_owned(rgba.into_raw(), w, h))
+		.map_err(|e| format!("write clipboard: {e}"))
+}
+
+#[tauri::command]
+fn load_settings(app: tauri::AppHandle) -> Result<Settings, String> {
+	settings::load(&settings_dir(&app)?)
+}
+
+#[tauri::command]
+fn save_settings(app: tauri::AppHandle, settings: Settings) -> Result<(), String> {
+	settings::save(&settings_dir(&app)?, settings)
+}
+
+fn settings_dir(app: &tauri::AppHandle) -> Result<PathBuf, String> {
+	app.path().app_config_dir().map_err(|e| e.to_string())
+}
+
+/// Write the result of `request_id` (as currently shown) to `path` and record it in the
+/// processing history under `name`.
+#[tauri::command]
+fn export_result(app: tauri::AppHandle, request_id: u64, name: String, path: String, bytes: Vec<u8>) -> Result<(), String> {
+	let snap = app.state::<History>().current(request_id)?;
+	let path = PathBuf::from(path);
+	write_bytes(&path, &bytes)?;
+	app.state::<Journal>()
+		.record(name, &snap.input_bytes, snap.options, &bytes, Some(path))
+		.map(|_| ())
+}
+
+/// Past results, newest first, with thumbnails.
+#[tauri::command]
+fn list_history(app: tauri::AppHandle) -> Vec<JournalItem> {
+	app.state::<Journal>().list()
+}
+
+/// A past job's input and options, for loading it back into the editor.
+#[derive(Serialize)]
+struct Reopened {
+	name: String,
+	input_bytes: Vec<u8>,
+	options: rembg_rs::core::RemoveOptions
+}
+
+#[tauri::command]
+fn reopen_history(app: tauri::AppHandle, id: u64) -> Result<Reopened, String> {
+	let (entry, input_bytes) = app.state::<Journal>().get(id)?;
+	Ok(Reopened {
+		name: entry.name,
+		input_bytes,
+		options: entry.options
+	})
+}
+
+/// Run a past job again with the options it was made with; returns the queue job id.
+#[tauri::command]
+fn rerun_history(app: tauri::AppHandle, id: u64) -> Result<u64, String> {
+	let (entry, input_bytes) = app.state::<Journal>().get(id)?;
+	Ok(enqueue_job(app, entry.name, input_bytes, entry.options))
+}
+
 #[tauri::command]
 fn write_file_bytes(path: String, bytes: Vec<u8>) -> Result<(), String> {
-	let p = PathBuf::from(path);
-	if let Some(parent) = p.parent() {
+	write_bytes(&PathBuf::from(path), &bytes)
+}
+
+fn write_bytes(path: &std::path::Path, bytes: &[u8]) -> Result<(), String> {
+	if let Some(parent) = path.parent() {
 		std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
 	}
-	std::fs::write(&p, bytes).map_err(|e| e.to_string())
+	std::fs::write(path, bytes).map_err(|e| e.to_string())
 }
 
+/// First argument that makes the app executable serve as its own inference worker.
+const WORKER_ARG: &str = "--inference-worker";
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    // Inference runs in a second copy of this executable (see `rembg_rs::worker`), so switching
+    // between CPU and GPU runtimes needs no restart and a GPU driver crash fails only the job.
+    if std::env::args().nth(1).as_deref() == Some(WORKER_ARG) {
+        if let Err(e) = rembg_rs::worker::serve() {
+            eprintln!("inference worker: {e:#}");
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Ok(exe) = std::env::current_exe() {
+        rembg_rs::worker::enable(exe, vec![WORKER_ARG.to_string()]);
+        rembg_rs::worker::set_pool_size(queue::DEFAULT_CONCURRENCY);
+    }
+
     tauri::Builder::default()
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_fs::init())
         .plugin(tauri_plugin_opener::init())
-        .invoke_handler(tauri::generate_handler![remove_background, write_file_bytes])
+        .plugin(tauri_plugin_clipboard_manager::init())
+        .manage(History::default())
+        .manage(InFlight::default())
+        .manage(Queue::default())
+        .setup(|app| {
+            let dir = app.path().app_data_dir()?.join("history");
+            app.manage(Journal::open(dir));
+            Ok(())
+        })
+        .invoke_handler(tauri::generate_handler![
+            remove_background,
+            cancel_remove,
+            recompose,
+            recomposite,
+            undo,
+            redo,
+            enqueue_job,
+            list_jobs,
+            cancel_job,
+            set_concurrency,
+            scan_folder,
+            list_devices,
+            list_models,
+            cache_stats,
+            cache_clean,
+            export_result,
+            list_history,
+            reopen_history,
+            rerun_history,
+            read_clipboard_image,
+            write_clipboard_image,
+            load_settings,
+            save_settings,
+            write_file_bytes
+        ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
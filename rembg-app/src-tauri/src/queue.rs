@@ -0,0 +1,167 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use rembg_rs::cancel::CancellationToken;
+use rembg_rs::core::RemoveOptions;
+use rembg_rs::error::ErrorInfo;
+use serde::Serialize;
+
+/// Jobs run at once unless the GUI changes it.
+pub const DEFAULT_CONCURRENCY: usize = 2;
+/// Finished jobs kept for `list_jobs`; the oldest are dropped first.
+const MAX_FINISHED: usize = 200;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum JobState {
+	Queued,
+	Running,
+	Done,
+	Failed { error: ErrorInfo },
+	Cancelled
+}
+
+impl JobState {
+	fn finished(&self) -> bool {
+		matches!(self, JobState::Done | JobState::Failed { .. } | JobState::Cancelled)
+	}
+}
+
+/// A job as `list_jobs` reports it.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobInfo {
+	pub id: u64,
+	/// Shown in the GUI, e.g. the file name.
+	pub name: String,
+	#[serde(flatten)]
+	pub state: JobState
+}
+
+/// A job taken off the queue to run now.
+pub struct ReadyJob {
+	/// Already in the `Running` state.
+	pub info: JobInfo,
+	pub input_bytes: Vec<u8>,
+	pub options: RemoveOptions,
+	pub cancel: CancellationToken
+}
+
+struct Job {
+	info: JobInfo,
+	/// Taken when the job starts.
+	work: Option<(Vec<u8>, RemoveOptions)>,
+	cancel: CancellationToken
+}
+
+struct Inner {
+	jobs: VecDeque<Job>,
+	next_id: u64,
+	concurrency: usize,
+	running: usize
+}
+
+/// Background-removal jobs for batches: at most `concurrency` run at once, the rest wait in order.
+pub struct Queue {
+	inner: Mutex<Inner>
+}
+
+impl Default for Queue {
+	fn default() -> Self {
+		Queue {
+			inner: Mutex::new(Inner {
+				jobs: VecDeque::new(),
+				next_id: 1,
+				concurrency: DEFAULT_CONCURRENCY,
+				running: 0
+			})
+		}
+	}
+}
+
+impl Queue {
+	pub fn enqueue(&self, name: String, input_bytes: Vec<u8>, options: RemoveOptions) -> u64 {
+		let mut inner = self.inner.lock().unwrap();
+		let id = inner.next_id;
+		inner.next_id += 1;
+		inner.jobs.push_back(Job {
+			info: JobInfo {
+				id,
+				name,
+				state: JobState::Queued
+			},
+			work: Some((input_bytes, options)),
+			cancel: CancellationToken::new()
+		});
+		id
+	}
+
+	pub fn list(&self) -> Vec<JobInfo> {
+		self.inner.lock().unwrap().jobs.iter().map(|j| j.info.clone()).collect()
+	}
+
+	/// Cancel a queued job right away or ask a running one to stop. Returns the new state of a
+	/// queued job (running ones report through `finish`), `None` if there was nothing to cancel.
+	pub fn cancel(&self, id: u64) -> Option<JobInfo> {
+		let mut inner = self.inner.lock().unwrap();
+		let job = inner.jobs.iter_mut().find(|j| j.info.id == id)?;
+		match job.info.state {
+			JobState::Queued => {
+				job.work = None;
+				job.info.state = JobState::Cancelled;
+				Some(job.info.clone())
+			}
+			JobState::Running => {
+				job.cancel.cancel();
+				None
+			}
+			_ => None
+		}
+	}
+
+	pub fn set_concurrency(&self, concurrency: usize) {
+		self.inner.lock().unwrap().concurrency = concurrency.max(1);
+	}
+
+	/// Mark queued jobs as running while there are free slots and hand them out.
+	pub fn start_ready(&self) -> Vec<ReadyJob> {
+		let mut inner = self.inner.lock().unwrap();
+		let mut ready = Vec::new();
+		while inner.running < inner.concurrency {
+			let Some(job) = inner.jobs.iter_mut().find(|j| matches!(j.info.state, JobState::Queued)) else {
+				break;
+			};
+			let (input_bytes, options) = job.work.take().expect("queued jobs have work");
+			job.info.state = JobState::Running;
+			ready.push(ReadyJob {
+				info: job.info.clone(),
+				input_bytes,
+				options,
+				cancel: job.cancel.clone()
+			});
+			inner.running += 1;
+		}
+		ready
+	}
+
+	/// Record how a running job ended and free its slot.
+	pub fn finish(&self, id: u64, state: JobState) -> Option<JobInfo> {
+		let mut inner = self.inner.lock().unwrap();
+		inner.running -= 1;
+		let job = inner.jobs.iter_mut().find(|j| j.info.id == id)?;
+		job.info.state = state;
+		let info = job.info.clone();
+
+		let finished = inner.jobs.iter().filter(|j| j.info.state.finished()).count();
+		if finished > MAX_FINISHED {
+			let mut excess = finished - MAX_FINISHED;
+			inner.jobs.retain(|j| {
+				let drop = excess > 0 && j.info.state.finished();
+				if drop {
+					excess -= 1;
+				}
+				!drop
+			});
+		}
+		Some(info)
+	}
+}
@@ -0,0 +1,66 @@
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use image::ImageReader;
+use serde::Serialize;
+
+/// Longest side of `ImageEntry::thumbnail_png`.
+const THUMBNAIL_SIZE: u32 = 128;
+
+/// An image found by `scan_folder`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ImageEntry {
+	pub path: PathBuf,
+	/// Path relative to the scanned folder, for display.
+	pub name: String,
+	pub width: u32,
+	pub height: u32,
+	/// Small PNG preview; `None` if the header reads but the pixels don't.
+	pub thumbnail_png: Option<Vec<u8>>
+}
+
+/// The images in `dir` (and its subfolders with `recursive`), sorted by path. Files that aren't
+/// in a readable image format are left out.
+pub fn scan(dir: &Path, recursive: bool) -> Result<Vec<ImageEntry>, String> {
+	let mut paths = Vec::new();
+	walk(dir, recursive, &mut paths)?;
+	paths.sort();
+	Ok(paths.into_iter().filter_map(|p| entry(dir, p)).collect())
+}
+
+fn walk(dir: &Path, recursive: bool, out: &mut Vec<PathBuf>) -> Result<(), String> {
+	let entries = std::fs::read_dir(dir).map_err(|e| format!("read dir {}: {e}", dir.display()))?;
+	for entry in entries {
+		let entry = entry.map_err(|e| format!("read dir {}: {e}", dir.display()))?;
+		let path = entry.path();
+		if path.is_file() {
+			if image::ImageFormat::from_path(&path).is_ok_and(|f| f.reading_enabled()) {
+				out.push(path);
+			}
+		} else if recursive && entry.file_type().is_ok_and(|t| t.is_dir()) {
+			// `file_type` doesn't follow symlinks, so linked folders can't send us in circles.
+			walk(&path, recursive, out)?;
+		}
+	}
+	Ok(())
+}
+
+fn entry(root: &Path, path: PathBuf) -> Option<ImageEntry> {
+	let reader = ImageReader::open(&path).ok()?.with_guessed_format().ok()?;
+	let (width, height) = reader.into_dimensions().ok()?;
+	let name = path.strip_prefix(root).unwrap_or(&path).to_string_lossy().into_owned();
+	Some(ImageEntry {
+		thumbnail_png: thumbnail(&path),
+		path,
+		name,
+		width,
+		height
+	})
+}
+
+fn thumbnail(path: &Path) -> Option<Vec<u8>> {
+	let img = image::open(path).ok()?.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE);
+	let mut png = Vec::new();
+	img.write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png).ok()?;
+	Some(png)
+}
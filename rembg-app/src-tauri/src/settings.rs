@@ -0,0 +1,65 @@
+use std::path::{Path, PathBuf};
+
+use rembg_rs::core::RemoveOptions;
+use serde::{Deserialize, Serialize};
+
+const FILE_NAME: &str = "settings.json";
+/// `options.bg_image`, kept out of the JSON.
+const BACKGROUND_FILE: &str = "background.bin";
+
+/// GUI choices kept across launches, as `settings.json` in the app config dir.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+	/// Options new images start with, output format and encoder settings included; `None` keeps
+	/// the GUI's built-in defaults. Per-image prompts (`points`, `bbox`) aren't kept; the background
+	/// image is, in a file of its own.
+	pub options: Option<RemoveOptions>,
+	/// Folder the export dialog opens in.
+	pub output_dir: Option<PathBuf>
+}
+
+/// The saved settings, or the defaults if nothing was saved yet.
+pub fn load(dir: &Path) -> Result<Settings, String> {
+	let path = dir.join(FILE_NAME);
+	let text = match std::fs::read_to_string(&path) {
+		Ok(text) => text,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Settings::default()),
+		Err(e) => return Err(format!("read {}: {e}", path.display()))
+	};
+	let mut settings: Settings = serde_json::from_str(&text).map_err(|e| format!("parse {}: {e}", path.display()))?;
+	let bg_path = dir.join(BACKGROUND_FILE);
+	if let Some(options) = &mut settings.options {
+		if options.bg_image.is_none() && bg_path.is_file() {
+			options.bg_image = Some(std::fs::read(&bg_path).map_err(|e| format!("read {}: {e}", bg_path.display()))?);
+		}
+	}
+	Ok(settings)
+}
+
+/// Write `settings` to `dir`, replacing the file in one step so a crash can't leave half of it.
+pub fn save(dir: &Path, mut settings: Settings) -> Result<(), String> {
+	let mut background = None;
+	if let Some(options) = &mut settings.options {
+		options.points.clear();
+		options.bbox = None;
+		background = options.bg_image.take();
+	}
+	std::fs::create_dir_all(dir).map_err(|e| format!("create {}: {e}", dir.display()))?;
+	let bg_path = dir.join(BACKGROUND_FILE);
+	match background {
+		Some(bg) => std::fs::write(&bg_path, bg).map_err(|e| format!("write {}: {e}", bg_path.display()))?,
+		None => {
+			if let Err(e) = std::fs::remove_file(&bg_path) {
+				if e.kind() != std::io::ErrorKind::NotFound {
+					return Err(format!("remove {}: {e}", bg_path.display()));
+				}
+			}
+		}
+	}
+	let path = dir.join(FILE_NAME);
+	let tmp = path.with_extension("json.tmp");
+	let text = serde_json::to_string_pretty(&settings).map_err(|e| e.to_string())?;
+	std::fs::write(&tmp, text).map_err(|e| format!("write {}: {e}", tmp.display()))?;
+	std::fs::rename(&tmp, &path).map_err(|e| format!("write {}: {e}", path.display()))
+}
@@ -0,0 +1,77 @@
+#ifndef REMBG_H
+#define REMBG_H
+
+/* Generated by cbindgen from rembg-ffi/src/lib.rs; do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Success.
+ */
+#define REMBG_OK 0
+
+/**
+ * A required pointer was null or `options_json` wasn't valid UTF-8/JSON.
+ */
+#define REMBG_ERR_INVALID_ARGUMENT 1
+
+/**
+ * Decoding, runtime/model setup, inference or encoding failed.
+ */
+#define REMBG_ERR_FAILED 2
+
+/**
+ * A Rust panic was caught at the boundary.
+ */
+#define REMBG_ERR_PANIC 3
+
+/**
+ * Receives the result. The buffers are only valid during the call; copy what you need.
+ * `mask` is null (and `mask_len` 0) unless `include_mask` was set.
+ */
+typedef void (*RembgOutputCallback)(void *user_data,
+                                    const uint8_t *output,
+                                    size_t output_len,
+                                    const uint8_t *mask,
+                                    size_t mask_len);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Remove the background of the encoded image `bytes[0..len]`.
+ *
+ * `options_json` is a JSON object with any subset of the core `RemoveOptions` fields (e.g.
+ * `{"model": "u2netp", "bgcolor": "FFFFFF"}`), or null for the defaults. On success
+ * `out_callback` is called once with the encoded result and `REMBG_OK` is returned; otherwise
+ * the error code is returned and `rembg_last_error` describes it.
+ *
+ * # Safety
+ * `bytes` must point to `len` readable bytes; `options_json` must be null or a NUL-terminated string.
+ */
+int32_t rembg_remove_background(const uint8_t *bytes,
+                                size_t len,
+                                const char *options_json,
+                                RembgOutputCallback out_callback,
+                                void *user_data);
+
+/**
+ * Message of the last failed call on this thread, or null. Valid until the next call on this thread.
+ */
+const char *rembg_last_error(void);
+
+/**
+ * Drop cached model sessions (they are kept warm between calls), e.g. to free GPU memory.
+ */
+void rembg_clear_session_cache(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* REMBG_H */
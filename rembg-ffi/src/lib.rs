@@ -0,0 +1,99 @@
+use std::cell::RefCell;
+use std::ffi::{CStr, CString, c_char, c_void};
+use std::panic::{self, AssertUnwindSafe};
+
+use rembg_rs::cancel::CancellationToken;
+use rembg_rs::core::{self, RemoveOptions};
+
+/// Success.
+pub const REMBG_OK: i32 = 0;
+/// A required pointer was null or `options_json` wasn't valid UTF-8/JSON.
+pub const REMBG_ERR_INVALID_ARGUMENT: i32 = 1;
+/// Decoding, runtime/model setup, inference or encoding failed.
+pub const REMBG_ERR_FAILED: i32 = 2;
+/// A Rust panic was caught at the boundary.
+pub const REMBG_ERR_PANIC: i32 = 3;
+
+/// Receives the result. The buffers are only valid during the call; copy what you need.
+/// `mask` is null (and `mask_len` 0) unless `include_mask` was set.
+pub type RembgOutputCallback = Option<
+	unsafe extern "C" fn(user_data: *mut c_void, output: *const u8, output_len: usize, mask: *const u8, mask_len: usize)
+>;
+
+thread_local! {
+	static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Remove the background of the encoded image `bytes[0..len]`.
+///
+/// `options_json` is a JSON object with any subset of the core `RemoveOptions` fields (e.g.
+/// `{"model": "u2netp", "bgcolor": "FFFFFF"}`), or null for the defaults. On success
+/// `out_callback` is called once with the encoded result and `REMBG_OK` is returned; otherwise
+/// the error code is returned and `rembg_last_error` describes it.
+///
+/// # Safety
+/// `bytes` must point to `len` readable bytes; `options_json` must be null or a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rembg_remove_background(
+	bytes: *const u8,
+	len: usize,
+	options_json: *const c_char,
+	out_callback: RembgOutputCallback,
+	user_data: *mut c_void
+) -> i32 {
+	let result = panic::catch_unwind(AssertUnwindSafe(|| {
+		let (Some(callback), false) = (out_callback, bytes.is_null()) else {
+			return Err((REMBG_ERR_INVALID_ARGUMENT, "bytes and out_callback must not be null".to_string()));
+		};
+		// SAFETY: the caller guarantees `bytes` points to `len` readable bytes.
+		let input = unsafe { std::slice::from_raw_parts(bytes, len) };
+		// SAFETY: the caller guarantees `options_json` is null or NUL-terminated.
+		let opts = unsafe { parse_options(options_json) }.map_err(|e| (REMBG_ERR_INVALID_ARGUMENT, e))?;
+
+		let result = core::remove_background_bytes(input, &opts, &CancellationToken::new(), |_| {}).map_err(|e| (REMBG_ERR_FAILED, format!("{e:#}")))?;
+		let (mask, mask_len) = match &result.mask_png {
+			Some(m) => (m.as_ptr(), m.len()),
+			None => (std::ptr::null(), 0)
+		};
+		// SAFETY: the buffers outlive the call; the callback contract is documented above.
+		unsafe { callback(user_data, result.output_png.as_ptr(), result.output_png.len(), mask, mask_len) };
+		Ok(())
+	}));
+
+	let (code, message) = match result {
+		Ok(Ok(())) => return REMBG_OK,
+		Ok(Err(e)) => e,
+		Err(_) => (REMBG_ERR_PANIC, "internal panic".to_string())
+	};
+	set_last_error(message);
+	code
+}
+
+/// Message of the last failed call on this thread, or null. Valid until the next call on this thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn rembg_last_error() -> *const c_char {
+	LAST_ERROR.with(|e| e.borrow().as_ref().map_or(std::ptr::null(), |s| s.as_ptr()))
+}
+
+/// Drop cached model sessions (they are kept warm between calls), e.g. to free GPU memory.
+#[unsafe(no_mangle)]
+pub extern "C" fn rembg_clear_session_cache() {
+	core::clear_session_cache();
+}
+
+/// `RemoveOptions::default()` with the given JSON fields applied.
+unsafe fn parse_options(options_json: *const c_char) -> Result<RemoveOptions, String> {
+	if options_json.is_null() {
+		return Ok(RemoveOptions::default());
+	}
+	// SAFETY: non-null and NUL-terminated per the caller's contract.
+	let json = unsafe { CStr::from_ptr(options_json) }
+		.to_str()
+		.map_err(|_| "options_json is not valid UTF-8".to_string())?;
+	RemoveOptions::from_partial_json(json).map_err(|e| format!("options_json: {e:#}"))
+}
+
+fn set_last_error(message: String) {
+	let message = CString::new(message.replace('\0', " ")).expect("no interior NUL");
+	LAST_ERROR.with(|e| *e.borrow_mut() = Some(message));
+}
@@ -0,0 +1,81 @@
+use napi::bindgen_prelude::*;
+use napi::threadsafe_function::{ErrorStrategy, ThreadsafeFunction, ThreadsafeFunctionCallMode};
+use napi::{JsObject, JsString, JsUnknown};
+use napi_derive::napi;
+use rembg_rs::cancel::CancellationToken;
+use rembg_rs::core::{self, RemoveOptions};
+
+type ProgressCallback = ThreadsafeFunction<serde_json::Value, ErrorStrategy::Fatal>;
+
+/// Remove the background from an encoded image; resolves with the encoded result.
+///
+/// `options` takes any subset of the core `RemoveOptions` fields (`bg_image` as a Buffer), and
+/// `onProgress` receives the same progress events as the CLI and GUI. Runs on the libuv thread pool.
+#[napi(ts_return_type = "Promise<Buffer>")]
+pub fn remove_background(
+	env: Env,
+	input: Buffer,
+	#[napi(ts_arg_type = "Record<string, unknown>")] options: Option<JsObject>,
+	#[napi(ts_arg_type = "(event: Record<string, unknown>) => void")] on_progress: Option<ProgressCallback>
+) -> Result<AsyncTask<RemoveBackground>> {
+	Ok(AsyncTask::new(RemoveBackground {
+		input: input.to_vec(),
+		opts: parse_options(&env, options)?,
+		on_progress
+	}))
+}
+
+/// Drop the cached model sessions (they're kept between `removeBackground` calls).
+#[napi]
+pub fn clear_session_cache() {
+	core::clear_session_cache();
+}
+
+pub struct RemoveBackground {
+	input: Vec<u8>,
+	opts: RemoveOptions,
+	on_progress: Option<ProgressCallback>
+}
+
+impl Task for RemoveBackground {
+	type Output = Vec<u8>;
+	type JsValue = Buffer;
+
+	fn compute(&mut self) -> Result<Vec<u8>> {
+		let on_progress = self.on_progress.as_ref();
+		let result = core::remove_background_bytes(&self.input, &self.opts, &CancellationToken::new(), |ev| {
+			if let Some(cb) = on_progress
+				&& let Ok(ev) = serde_json::to_value(&ev)
+			{
+				cb.call(ev, ThreadsafeFunctionCallMode::NonBlocking);
+			}
+		})
+		.map_err(|e| Error::from_reason(format!("{e:#}")))?;
+		Ok(result.output_png)
+	}
+
+	fn resolve(&mut self, _env: Env, output: Vec<u8>) -> Result<Buffer> {
+		Ok(output.into())
+	}
+}
+
+/// `RemoveOptions::default()` with the object's fields applied. `bg_image` is read separately:
+/// a Buffer doesn't map onto JSON.
+fn parse_options(env: &Env, options: Option<JsObject>) -> Result<RemoveOptions> {
+	let Some(options) = options else {
+		return Ok(RemoveOptions::default());
+	};
+	let mut fields = serde_json::Map::new();
+	let names = options.get_property_names()?;
+	for i in 0..names.get_array_length()? {
+		let name = names.get_element::<JsString>(i)?.into_utf8()?.into_owned()?;
+		if name != "bg_image" {
+			let value: JsUnknown = options.get_named_property(&name)?;
+			fields.insert(name, env.from_js_value(value)?);
+		}
+	}
+	let mut opts = RemoveOptions::from_partial_json(&serde_json::Value::Object(fields).to_string())
+		.map_err(|e| Error::new(Status::InvalidArg, format!("{e:#}")))?;
+	opts.bg_image = options.get::<_, Buffer>("bg_image")?.map(|b| b.to_vec());
+	Ok(opts)
+}
@@ -0,0 +1,45 @@
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+use rembg_rs::cancel::CancellationToken;
+use rembg_rs::core::{self, RemoveOptions};
+
+#[pymodule]
+mod rembg_rs_py {
+	#[pymodule_export]
+	use super::{clear_session_cache, remove};
+}
+
+/// Remove the background from an encoded image and return the encoded result.
+///
+/// Keyword arguments are the core `RemoveOptions` fields (`model`, `device`, `bgcolor`,
+/// `output_format`, ...); `bg_image` takes the encoded background image as bytes.
+#[pyfunction]
+#[pyo3(signature = (data, *, bg_image = None, **options))]
+fn remove<'py>(
+	py: Python<'py>,
+	data: &[u8],
+	bg_image: Option<Vec<u8>>,
+	options: Option<&Bound<'py, PyDict>>
+) -> PyResult<Bound<'py, PyBytes>> {
+	let mut opts = match options {
+		Some(options) => {
+			let json: String = py.import("json")?.call_method1("dumps", (options,))?.extract()?;
+			RemoveOptions::from_partial_json(&json).map_err(|e| PyValueError::new_err(format!("{e:#}")))?
+		}
+		None => RemoveOptions::default()
+	};
+	opts.bg_image = bg_image;
+
+	// Inference takes seconds; let other Python threads run meanwhile.
+	let result = py
+		.detach(|| core::remove_background_bytes(data, &opts, &CancellationToken::new(), |_| {}))
+		.map_err(|e| PyRuntimeError::new_err(format!("{e:#}")))?;
+	Ok(PyBytes::new(py, &result.output_png))
+}
+
+/// Drop the cached model sessions (they're kept between `remove` calls).
+#[pyfunction]
+fn clear_session_cache() {
+	core::clear_session_cache();
+}
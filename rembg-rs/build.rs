@@ -0,0 +1,5 @@
+fn main() {
+	// Generated gRPC code; needs `protoc` (on PATH or via $PROTOC).
+	#[cfg(feature = "grpc")]
+	tonic_prost_build::compile_protos("proto/rembg.proto").expect("compile proto/rembg.proto");
+}
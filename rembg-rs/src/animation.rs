@@ -0,0 +1,120 @@
+use std::io::Cursor;
+
+use anyhow::{Context, Result};
+use image::codecs::gif::GifDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::{AnimationDecoder, Frames, RgbaImage};
+
+use crate::cancel::CancellationToken;
+use crate::encode::EncodeOptions;
+
+/// One full-canvas frame of an animation and how long it shows.
+#[derive(Debug, Clone)]
+pub struct AnimationFrame {
+	pub image: RgbaImage,
+	pub delay_ms: u32
+}
+
+/// The frames of an animated GIF or WebP, or `None` for anything else (including single-frame
+/// animations), which then goes through the still-image path. `cancel` is checked between frames.
+pub fn decode_frames(bytes: &[u8], cancel: &CancellationToken) -> Result<Option<Vec<AnimationFrame>>> {
+	let frames = match image::guess_format(bytes) {
+		Ok(image::ImageFormat::Gif) => GifDecoder::new(Cursor::new(bytes)).context("decode gif")?.into_frames(),
+		Ok(image::ImageFormat::WebP) => {
+			let dec = WebPDecoder::new(Cursor::new(bytes)).context("decode webp")?;
+			if !dec.has_animation() {
+				return Ok(None);
+			}
+			dec.into_frames()
+		}
+		_ => return Ok(None)
+	};
+	let frames = collect(frames, cancel)?;
+	Ok((frames.len() > 1).then_some(frames))
+}
+
+fn collect(frames: Frames<'_>, cancel: &CancellationToken) -> Result<Vec<AnimationFrame>> {
+	frames
+		.map(|f| {
+			cancel.check()?;
+			let f = f.context("decode animation frame")?;
+			let (num, den) = f.delay().numer_denom_ms();
+			Ok(AnimationFrame {
+				delay_ms: num / den.max(1),
+				image: f.into_buffer()
+			})
+		})
+		.collect()
+}
+
+/// Animated PNG, looping forever. Every frame must have the first frame's size; `cancel` is checked
+/// between frames.
+pub fn encode_apng(frames: &[AnimationFrame], cancel: &CancellationToken) -> Result<Vec<u8>> {
+	let first = frames.first().context("animation has no frames")?;
+	let mut buf = Vec::new();
+	let mut enc = png::Encoder::new(&mut buf, first.image.width(), first.image.height());
+	enc.set_color(png::ColorType::Rgba);
+	enc.set_depth(png::BitDepth::Eight);
+	enc.set_animated(frames.len() as u32, 0).context("encode apng")?;
+	let mut writer = enc.write_header().context("encode apng")?;
+	for f in frames {
+		cancel.check()?;
+		writer
+			.set_frame_delay(f.delay_ms.min(u16::MAX as u32) as u16, 1000)
+			.context("encode apng")?;
+		writer.write_image_data(f.image.as_raw()).context("encode apng frame")?;
+	}
+	writer.finish().context("encode apng")?;
+	Ok(buf)
+}
+
+/// Animated WebP, looping forever, with `opts`' quality settings; `cancel` is checked between frames.
+#[cfg(feature = "native")]
+pub fn encode_webp(frames: &[AnimationFrame], opts: &EncodeOptions, cancel: &CancellationToken) -> Result<Vec<u8>> {
+	let first = frames.first().context("animation has no frames")?;
+	let mut config = webp::WebPConfig::new().map_err(|_| anyhow::anyhow!("encode webp: invalid config"))?;
+	config.lossless = opts.webp_lossless as i32;
+	config.quality = opts.webp_quality;
+	let mut enc = webp::AnimEncoder::new(first.image.width(), first.image.height(), &config);
+	enc.set_loop_count(0);
+	let mut timestamp = 0;
+	for f in frames {
+		cancel.check()?;
+		enc.add_frame(webp::AnimFrame::from_rgba(f.image.as_raw(), f.image.width(), f.image.height(), timestamp));
+		timestamp += f.delay_ms as i32;
+	}
+	let mut out = enc.try_encode().map_err(|e| anyhow::anyhow!("encode webp: {e:?}"))?.to_vec();
+	set_last_duration(&mut out, timestamp as u32);
+	Ok(out)
+}
+
+/// Make the last frame of an animated WebP end at `end_ms`. `AnimEncoder` finalizes libwebp's
+/// encoder with an end timestamp of 0, which libwebp rejects and replaces with the average frame
+/// duration, so the last frame's own delay would be lost. libwebp merges identical frames, so the
+/// last `ANMF` chunk gets whatever the earlier ones leave over.
+#[cfg(feature = "native")]
+fn set_last_duration(webp: &mut [u8], end_ms: u32) {
+	// RIFF header, then chunks of fourcc, little-endian size and payload padded to an even length;
+	// an ANMF payload has its 24-bit duration after the 12 bytes of offset and size.
+	let mut durations = Vec::new();
+	let mut pos = 12;
+	while pos + 8 <= webp.len() {
+		let size = u32::from_le_bytes(webp[pos + 4..pos + 8].try_into().expect("4 bytes")) as usize;
+		if &webp[pos..pos + 4] == b"ANMF" && pos + 8 + 15 <= webp.len() {
+			durations.push(pos + 8 + 12);
+		}
+		pos += 8 + size + size % 2;
+	}
+	let Some((&last, earlier)) = durations.split_last() else {
+		return;
+	};
+	let read = |at: usize| u32::from_le_bytes([webp[at], webp[at + 1], webp[at + 2], 0]);
+	let start: u32 = earlier.iter().map(|&at| read(at)).sum();
+	let duration = end_ms.saturating_sub(start).min(0xFF_FFFF);
+	webp[last..last + 3].copy_from_slice(&duration.to_le_bytes()[..3]);
+}
+
+#[cfg(not(feature = "native"))]
+pub fn encode_webp(_frames: &[AnimationFrame], _opts: &EncodeOptions, _cancel: &CancellationToken) -> Result<Vec<u8>> {
+	anyhow::bail!("WebP output is not available in this build (needs the `native` feature)")
+}
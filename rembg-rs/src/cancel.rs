@@ -0,0 +1,31 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Cooperative cancellation for a job. Clones share the flag: keep one to call `cancel` on while
+/// the job checks another between stages, inside download loops and while the model runs.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn cancel(&self) {
+		self.0.store(true, Ordering::Relaxed);
+	}
+
+	pub fn is_cancelled(&self) -> bool {
+		self.0.load(Ordering::Relaxed)
+	}
+
+	/// `Err(Cancelled)` once `cancel` has been called.
+	pub fn check(&self) -> Result<(), Cancelled> {
+		if self.is_cancelled() { Err(Cancelled) } else { Ok(()) }
+	}
+}
+
+/// The error a cancelled job fails with; find it with `err.is::<Cancelled>()`.
+#[derive(Debug, thiserror::Error)]
+#[error("cancelled")]
+pub struct Cancelled;
@@ -1,41 +1,460 @@
 use std::path::PathBuf;
 
-use clap::{Parser, ValueEnum};
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::Deserialize;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+use crate::compose::{Anchor, BgFit};
+use crate::encode::OutputFormat;
+use crate::u2net::{ClothClass, GraphOptLevel};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Device {
 	/// Always use CPU inference.
 	Cpu,
 	/// Prefer GPU. Falls back to CPU if unavailable.
 	Gpu,
-	/// Default device selection (CPU unless you confirm GPU on Windows).
+	/// Default device selection (CPU unless you confirm GPU on Windows, or pass `--gpu-backend coreml`
+	/// on macOS).
 	Auto
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum GpuBackend {
-	/// Platform default (Windows: DirectML, Linux: CUDA).
+	/// Platform default (Windows: DirectML, Linux: CUDA, macOS: CoreML).
 	Auto,
 	/// Windows only, uses DirectML (DirectX 12).
 	Directml,
 	/// NVIDIA CUDA execution provider (Windows x64, Linux x64/aarch64).
-	Cuda
+	Cuda,
+	/// macOS only, uses CoreML (Apple GPU / Neural Engine).
+	Coreml,
+	/// NVIDIA TensorRT (same platforms as CUDA; needs TensorRT installed). The first run builds and
+	/// caches an engine, which takes minutes.
+	Tensorrt,
+	/// Intel GPUs/NPUs via OpenVINO (Windows/Linux x64).
+	Openvino
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ClothCategory {
+	/// Tops, jackets, dresses' upper half.
+	Upper,
+	/// Trousers, skirts.
+	Lower,
+	/// Full-body garments (dresses, jumpsuits).
+	Full,
+	/// One output per category, named `<output>_upper`, `_lower` and `_full`.
+	All
+}
+
+impl ClothCategory {
+	pub fn classes(self) -> &'static [ClothClass] {
+		match self {
+			ClothCategory::Upper => &[ClothClass::Upper],
+			ClothCategory::Lower => &[ClothClass::Lower],
+			ClothCategory::Full => &[ClothClass::Full],
+			ClothCategory::All => &[ClothClass::Upper, ClothClass::Lower, ClothClass::Full]
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum VideoCodec {
+	/// VP9 with alpha, for `.webm`.
+	Vp9,
+	/// ProRes 4444 with alpha, for `.mov`.
+	Prores
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ProgressFormat {
+	/// Progress lines for people (redrawn with `\r` on a terminal).
+	Human,
+	/// One `ProgressEvent` JSON object per line, for wrappers (CI, GUIs, scripts).
+	Json
+}
+
+/// What to do when an output file already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OnConflict {
+	/// Stop with an error for that input.
+	Error,
+	/// Replace the existing file.
+	Overwrite,
+	/// Leave the existing file and skip the input.
+	Skip,
+	/// Write next to it with a numeric suffix (`photo_rembg_1.png`, ...).
+	Rename
+}
+
+/// File format of `--raw-mask`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RawMaskFormat {
+	/// 32-bit float OpenEXR.
+	Exr,
+	/// 16-bit grayscale PNG.
+	Png16
+}
+
+/// File format of the mask (`--mask-format`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MaskFormat {
+	/// Grayscale PNG.
+	Png,
+	/// The binarized outline traced into an SVG path.
+	Svg
+}
+
+impl MaskFormat {
+	pub fn output_format(self) -> OutputFormat {
+		match self {
+			MaskFormat::Png => OutputFormat::Png,
+			MaskFormat::Svg => OutputFormat::Svg
+		}
+	}
+}
+
+/// What `--only-mask` writes (`--mask-mode`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MaskMode {
+	/// The model's soft alpha, never thresholded.
+	Soft,
+	/// 0 or 255, cut at `--mask-threshold` (default 128).
+	Binary,
+	/// The model's logits, `ln(p / (1 - p))`, as floats; needs `--format exr`.
+	Logits
+}
+
+/// Pixel dimensions given as `WxH` (e.g. `256x256`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Size {
+	pub width: u32,
+	pub height: u32
+}
+
+impl std::str::FromStr for Size {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let invalid = || format!("invalid size {s:?} (expected WxH, e.g. 256x256)");
+		let (w, h) = s.split_once(['x', 'X']).ok_or_else(invalid)?;
+		let dim = |v: &str| v.trim().parse::<u32>().map_err(|_| invalid());
+		Ok(Size { width: dim(w)?, height: dim(h)? })
+	}
+}
+
+impl std::fmt::Display for Size {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}x{}", self.width, self.height)
+	}
+}
+
+/// A pixel offset given as `X,Y` (e.g. `10,-4`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Offset {
+	pub x: i32,
+	pub y: i32
+}
+
+impl std::str::FromStr for Offset {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let invalid = || format!("invalid offset {s:?} (expected X,Y, e.g. 10,10)");
+		let (x, y) = s.split_once(',').ok_or_else(invalid)?;
+		let px = |v: &str| v.trim().parse::<i32>().map_err(|_| invalid());
+		Ok(Offset { x: px(x)?, y: px(y)? })
+	}
+}
+
+/// Where `--place` puts the subject: `scale=F,x=PX,y=PX,rotate=DEG`, every key optional
+/// (e.g. `scale=0.8,x=100,y=50`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Place {
+	pub scale: f32,
+	pub x: i32,
+	pub y: i32,
+	/// Degrees, clockwise.
+	pub rotate: f32
+}
+
+impl std::str::FromStr for Place {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let invalid = || format!("invalid placement {s:?} (expected scale=F,x=PX,y=PX,rotate=DEG, e.g. scale=0.8,x=100,y=50)");
+		let mut place = Place { scale: 1.0, x: 0, y: 0, rotate: 0.0 };
+		for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+			let (key, value) = part.split_once('=').ok_or_else(invalid)?;
+			let value = value.trim();
+			match key.trim() {
+				"scale" => place.scale = value.parse().map_err(|_| invalid())?,
+				"x" => place.x = value.parse().map_err(|_| invalid())?,
+				"y" => place.y = value.parse().map_err(|_| invalid())?,
+				"rotate" => place.rotate = value.trim_end_matches("deg").parse().map_err(|_| invalid())?,
+				_ => return Err(invalid())
+			}
+		}
+		if !(place.scale.is_finite() && place.scale > 0.0 && place.rotate.is_finite()) {
+			return Err(invalid());
+		}
+		Ok(place)
+	}
+}
+
+/// A numbered file name such as `frames/%05d.png`: `%d` stands for the frame number, `%0Nd` for
+/// the number zero-padded to N digits. Only the file name may contain it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameTemplate {
+	dir: PathBuf,
+	prefix: String,
+	width: usize,
+	suffix: String
+}
+
+impl std::str::FromStr for FrameTemplate {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let invalid = || format!("invalid frame template {s:?} (expected a file name with %d or %0Nd, e.g. frames/%05d.png)");
+		let path = std::path::Path::new(s);
+		let name = path.file_name().and_then(|n| n.to_str()).ok_or_else(invalid)?;
+		let (prefix, rest) = name.split_once('%').ok_or_else(invalid)?;
+		let (width, suffix) = rest.split_once('d').ok_or_else(invalid)?;
+		let width = match width {
+			"" => 0,
+			w if w.starts_with('0') => w.parse().map_err(|_| invalid())?,
+			_ => return Err(invalid())
+		};
+		if suffix.contains('%') {
+			return Err(invalid());
+		}
+		Ok(FrameTemplate {
+			dir: path.parent().map(PathBuf::from).unwrap_or_default(),
+			prefix: prefix.to_string(),
+			width,
+			suffix: suffix.to_string()
+		})
+	}
+}
+
+impl FrameTemplate {
+	/// The directory the frames are in (empty for the current directory).
+	pub fn dir(&self) -> &std::path::Path {
+		&self.dir
+	}
+
+	pub fn path(&self, frame: u64) -> PathBuf {
+		self.dir.join(format!("{}{frame:0width$}{}", self.prefix, self.suffix, width = self.width))
+	}
+
+	/// The frame number in `file_name`, if the name is exactly what `path` gives for it.
+	pub fn frame(&self, file_name: &str) -> Option<u64> {
+		let digits = file_name.strip_prefix(&self.prefix)?.strip_suffix(&self.suffix)?;
+		if !digits.bytes().all(|b| b.is_ascii_digit()) {
+			return None;
+		}
+		let frame: u64 = digits.parse().ok()?;
+		(format!("{frame:0width$}", width = self.width) == digits).then_some(frame)
+	}
+}
+
+impl std::fmt::Display for FrameTemplate {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let number = if self.width == 0 { "%d".to_string() } else { format!("%0{}d", self.width) };
+		write!(f, "{}", self.dir.join(format!("{}{number}{}", self.prefix, self.suffix)).display())
+	}
+}
+
+/// A rectangle given as `X0,Y0,X1,Y1` (top-left and bottom-right corners).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rect {
+	pub x0: f32,
+	pub y0: f32,
+	pub x1: f32,
+	pub y1: f32
+}
+
+impl std::str::FromStr for Rect {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let invalid = || format!("invalid box {s:?} (expected X0,Y0,X1,Y1, e.g. 40,10,600,480)");
+		let v = s
+			.split(',')
+			.map(|v| v.trim().parse::<f32>().map_err(|_| invalid()))
+			.collect::<Result<Vec<_>, _>>()?;
+		let [x0, y0, x1, y1] = v[..] else {
+			return Err(invalid());
+		};
+		Ok(Rect { x0, y0, x1, y1 })
+	}
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+	/// Keep the runtime and models loaded and take jobs over a local socket (Unix socket / named pipe).
+	Daemon {
+		/// Socket path (Unix) or pipe name (Windows). Default: `$XDG_RUNTIME_DIR/rembg-rs.sock` or
+		/// `<temp dir>/rembg-rs.sock`; `\\.\pipe\rembg-rs` on Windows.
+		#[arg(long)]
+		socket: Option<String>
+	},
+	/// Run inference jobs for a parent process over stdin/stdout (see `worker::enable`).
+	#[command(hide = true)]
+	Worker,
+	/// Serve a small HTTP API: `POST /remove` with the image, options as query parameters.
+	Serve {
+		#[arg(long, default_value_t = 7000)]
+		port: u16,
+		/// Interface to bind; use 0.0.0.0 to accept connections from other machines.
+		#[arg(long, default_value = "127.0.0.1")]
+		host: String
+	},
+	/// Serve the gRPC API from `proto/rembg.proto` (streams progress, then the result). Needs `--features grpc`.
+	Grpc {
+		#[arg(long, default_value_t = 50051)]
+		port: u16,
+		/// Interface to bind; use 0.0.0.0 to accept connections from other machines.
+		#[arg(long, default_value = "127.0.0.1")]
+		host: String
+	},
+	/// Download the runtime and a model ahead of time, both at once (e.g. while building an image).
+	Setup {
+		#[arg(short = 'm', long, default_value = "u2netp")]
+		model: String,
+		#[arg(long, value_enum, default_value_t = Device::Cpu)]
+		device: Device,
+		#[arg(long, value_enum, default_value_t = GpuBackend::Auto)]
+		gpu_backend: GpuBackend
+	},
+	/// Remove the background from every frame of a video. Needs `ffmpeg` and `ffprobe` on `PATH`.
+	Video {
+		input: PathBuf,
+		/// `.webm` (VP9) or `.mov` (ProRes 4444); both keep the alpha channel.
+		#[arg(short, long)]
+		output: PathBuf,
+		/// Output codec. Inferred from the output extension when omitted.
+		#[arg(long, value_enum)]
+		codec: Option<VideoCodec>,
+		#[arg(short = 'm', long, default_value = "u2netp")]
+		model: String,
+		#[arg(long, value_enum, default_value_t = Device::Cpu)]
+		device: Device,
+		#[arg(long, value_enum, default_value_t = GpuBackend::Auto)]
+		gpu_backend: GpuBackend,
+		/// Blend each frame's mask with the previous one (0 = off; closer to 1 flickers less but
+		/// lags behind fast motion).
+		#[arg(long, value_name = "0..1", default_value_t = 0.5)]
+		temporal_smoothing: f32,
+		/// Run the model a second time on a crop around the subject in every frame, as `--refine`
+		/// does for images.
+		#[arg(long)]
+		refine: bool,
+		/// Download the runtime without asking.
+		#[arg(short = 'y', long)]
+		yes: bool
+	},
+	/// Show or clean up the cached ONNX Runtime packages.
+	Runtime {
+		#[command(subcommand)]
+		action: RuntimeCommand
+	},
+	/// List, pre-download or delete cached models.
+	Models {
+		#[command(subcommand)]
+		action: ModelsCommand
+	},
+	/// Show an ONNX model's inputs, outputs and opsets, and whether `--model-path` can run it.
+	Inspect { model: PathBuf }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum RuntimeCommand {
+	/// Show every cached package version, its size and which one runs.
+	List,
+	/// Delete the versions that no longer run (older releases, interrupted downloads).
+	Prune,
+	/// Delete every cached runtime; the next run downloads one again.
+	Clean
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ModelsCommand {
+	/// Show every supported model, whether it's cached and its size on disk.
+	List,
+	/// Download models now (e.g. while building an image or before going offline).
+	Download {
+		#[arg(required = true)]
+		names: Vec<String>,
+		/// Accept files whose checksum doesn't match the pinned one.
+		#[arg(long)]
+		no_verify: bool
+	},
+	/// Delete cached models.
+	Remove {
+		#[arg(required = true)]
+		names: Vec<String>
+	},
+	/// Print the model cache directory, or the cached file(s) of one model.
+	Path { name: Option<String> }
 }
 
 #[derive(Debug, Parser)]
-#[command(name = "rembg-rs", version, about = "Background removal (rembg-like) as a single CLI binary")]
+#[command(
+	name = "rembg-rs",
+	version,
+	about = "Background removal (rembg-like) as a single CLI binary",
+	args_conflicts_with_subcommands = true,
+	subcommand_negates_reqs = true
+)]
 pub struct Args {
-	/// Input image path.
-	pub input: PathBuf,
+	#[command(subcommand)]
+	pub command: Option<Command>,
+
+	/// Input image file(s) or directories (every image directly inside a directory is processed).
+	/// `-` reads a single image from stdin; `http(s)://` URLs are downloaded (results go to the
+	/// current directory unless `--output`/`--output-dir` say otherwise). `s3://` and `gs://` objects
+	/// need the `cloud-storage` feature.
+	#[arg(required_unless_present = "sequence")]
+	pub input: Vec<PathBuf>,
 
-	/// Output image path (defaults to `<input>.png` or `<input>_mask.png`).
+	/// Process the numbered frames matching this template instead, in frame order (e.g.
+	/// `frames/%05d.png`). `-o` is then a template too and every result keeps its frame's number.
+	#[arg(long, value_name = "TEMPLATE", conflicts_with = "input")]
+	pub sequence: Option<FrameTemplate>,
+
+	/// Output image path (defaults to `<input>_rembg.<ext>` or `<input>_mask.<ext>`). Single input only.
+	/// `-` writes to stdout (the default when reading from stdin).
 	#[arg(short, long)]
 	pub output: Option<PathBuf>,
 
+	/// Write results into this directory instead of next to each input.
+	#[arg(long, value_name = "DIR", conflicts_with = "output")]
+	pub output_dir: Option<PathBuf>,
+
+	/// Output format. Inferred from the output extension when omitted (PNG otherwise, including stdout).
+	#[arg(long, visible_alias = "output-format", value_enum)]
+	pub format: Option<OutputFormat>,
+
 	/// Model name (see `rembg-rs/src/model.rs` for the supported list).
 	#[arg(short = 'm', long, default_value = "u2netp")]
 	pub model: String,
 
+	/// Accept model downloads whose checksum doesn't match the pinned one (e.g. from a mirror).
+	#[arg(long)]
+	pub no_verify: bool,
+
+	/// Run a local ONNX segmentation export instead of a built-in model (`--model` is then ignored).
+	#[arg(long, value_name = "PATH")]
+	pub model_path: Option<PathBuf>,
+
+	/// Square input side of the `--model-path` model (default 320).
+	#[arg(long, value_name = "PX", requires = "model_path")]
+	pub model_input_size: Option<u32>,
+
 	/// Device selection.
 	#[arg(long, value_enum, default_value_t = Device::Auto)]
 	pub device: Device,
@@ -44,25 +463,339 @@ pub struct Args {
 	#[arg(long, value_enum, default_value_t = GpuBackend::Auto)]
 	pub gpu_backend: GpuBackend,
 
+	/// Which GPU the DirectML, CUDA or TensorRT backend runs on (default: the first), numbered as
+	/// `nvidia-smi` or, for DirectML, the system's display adapter list numbers them.
+	#[arg(long, value_name = "ID")]
+	pub device_id: Option<u32>,
+
+	/// ONNX Runtime intra-op threads (default: one per physical core). Lower it when running several
+	/// jobs side by side on a many-core server.
+	#[arg(long, value_name = "N")]
+	pub ort_intra_threads: Option<usize>,
+
+	/// ONNX Runtime graph optimization level (default: all).
+	#[arg(long, value_enum, value_name = "LEVEL")]
+	pub ort_graph_opt_level: Option<GraphOptLevel>,
+
+	/// ONNX Runtime memory pattern planning (default: on); `false` lowers peak memory.
+	#[arg(long, value_name = "BOOL")]
+	pub ort_mem_pattern: Option<bool>,
+
+	/// Keep peak memory low for 2 GB machines and small containers, at some cost in speed: no ORT
+	/// memory arena or memory pattern, at most 2 intra-op threads, and plain PNG cutouts composed
+	/// and written a band of rows at a time.
+	#[arg(long)]
+	pub low_memory: bool,
+
+	/// `u2net_cloth_seg`: keep only this clothing category instead of every garment.
+	#[arg(long, value_enum, value_name = "CATEGORY")]
+	pub cloth_category: Option<ClothCategory>,
+
+	/// Run the model a second time on a crop around the subject it found: sharper edges for small
+	/// subjects in large frames, at up to twice the inference time.
+	#[arg(long)]
+	pub refine: bool,
+
+	/// Snap the mask's edges to edges in the photo with a guided filter. The model sees a small
+	/// copy of the image, so upscaled masks otherwise come out soft and slightly off on large photos.
+	#[arg(long)]
+	pub guided_filter: bool,
+
+	/// Re-estimate hair and other soft edges with a local matting model (ViTMatte, MODNet, ...). It
+	/// runs at full resolution, but only on tiles along the mask's edge.
+	#[arg(long, value_name = "PATH")]
+	pub matting_model: Option<PathBuf>,
+
+	/// Square input side of the `--matting-model` model (default 512).
+	#[arg(long, value_name = "PX", requires = "matting_model")]
+	pub matting_input_size: Option<u32>,
+
+	/// `sam` model: a point on the subject to keep, in input pixels (repeatable). Without any point or
+	/// box, SAM segments the object at the image center.
+	#[arg(long, value_name = "X,Y")]
+	pub sam_point: Vec<Offset>,
+
+	/// `sam` model: a point on something to leave out (repeatable).
+	#[arg(long, value_name = "X,Y")]
+	pub sam_exclude: Vec<Offset>,
+
+	/// `sam` model: a box around the subject.
+	#[arg(long, value_name = "X0,Y0,X1,Y1")]
+	pub sam_box: Option<Rect>,
+
+	/// Also process images in subdirectories of input directories, mirroring the tree under `--output-dir`.
+	#[arg(short, long)]
+	pub recursive: bool,
+
+	/// What to do when an output file already exists (e.g. two inputs named `photo.jpg` and
+	/// `photo.png`, or `--output` naming the input itself).
+	#[arg(long, value_enum, default_value_t = OnConflict::Error)]
+	pub on_conflict: OnConflict,
+
+	/// Skip inputs whose output file already exists (makes re-runs incremental). Same as `--on-conflict skip`.
+	#[arg(long, conflicts_with = "on_conflict")]
+	pub skip_existing: bool,
+
+	/// Append each input to this file once it's done (written or skipped), so an interrupted batch
+	/// can pick up where it stopped with `--resume`. Starts the file afresh without `--resume`.
+	#[arg(long, value_name = "FILE")]
+	pub journal: Option<PathBuf>,
+
+	/// Skip the inputs already listed in `--journal` and keep appending to it (a missing journal
+	/// just means nothing is done yet).
+	#[arg(long, requires = "journal")]
+	pub resume: bool,
+
+	/// Skip inputs narrower or shorter than this (e.g. `64x64` to ignore thumbnails and icons).
+	#[arg(long, value_name = "WxH")]
+	pub min_size: Option<Size>,
+
+	/// Skip inputs wider or taller than this.
+	#[arg(long, value_name = "WxH")]
+	pub max_size: Option<Size>,
+
+	/// Process this many inputs at once, each worker with its own model session (more memory per
+	/// worker). Helps on many-core CPUs, where one image leaves cores idle between operators.
+	#[arg(short, long, value_name = "N", default_value_t = 1, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..), conflicts_with = "tiff_stack")]
+	pub jobs: usize,
+
+	/// Write every result as a page of this single multi-page TIFF instead of individual files.
+	#[arg(long, value_name = "PATH", conflicts_with_all = ["output", "output_dir", "format"])]
+	pub tiff_stack: Option<PathBuf>,
+
+	/// Write each separate subject as its own cutout cropped to it (`photo_rembg_1.png`,
+	/// `photo_rembg_2.png`, ... left to right), plus `photo_rembg.json` with their bounding boxes in
+	/// input pixels. Specks under 1% of the largest subject's area are left out.
+	#[arg(long, conflicts_with = "tiff_stack")]
+	pub split_instances: bool,
+
+	/// JPEG: quality 1-100.
+	#[arg(long, default_value_t = 90, value_parser = clap::value_parser!(u8).range(1..=100))]
+	pub jpeg_quality: u8,
+
+	/// WebP: encode losslessly (larger, but edges and alpha stay exact).
+	#[arg(long)]
+	pub webp_lossless: bool,
+
+	/// WebP: lossy quality 0-100.
+	#[arg(long, default_value_t = 90.0)]
+	pub webp_quality: f32,
+
+	/// JPEG XL: encode losslessly.
+	#[arg(long)]
+	pub jxl_lossless: bool,
+
+	/// JPEG XL: lossy Butteraugli distance (0.1-15; 1.0 is visually lossless, higher is smaller).
+	#[arg(long, default_value_t = 1.0)]
+	pub jxl_distance: f32,
+
+	/// PNG: run a lossless optimization pass (oxipng preset 0-6, default 2 when given without a value).
+	/// Slower, but typically 20-40% smaller files.
+	#[arg(long, value_name = "LEVEL", num_args = 0..=1, default_missing_value = "2", value_parser = clap::value_parser!(u8).range(0..=6))]
+	pub optimize_png: Option<u8>,
+
+	/// PNG: use Zopfli during `--optimize-png` (much slower, slightly smaller).
+	#[arg(long, requires = "optimize_png")]
+	pub zopfli: bool,
+
+	/// Copy EXIF/XMP (capture date, camera, GPS, copyright, ...) from the input to the output.
+	/// By default only DPI and the ICC profile are kept.
+	#[arg(long, conflicts_with = "strip_metadata")]
+	pub keep_metadata: bool,
+
+	/// Don't carry over any input metadata, not even DPI or the ICC profile.
+	#[arg(long)]
+	pub strip_metadata: bool,
+
 	/// Output just the mask (grayscale PNG), not an RGBA cutout.
 	#[arg(long)]
 	pub only_mask: bool,
 
+	/// With `--only-mask`: write the soft mask, a binary one, or logits. Without it the mask is soft
+	/// unless `--mask-threshold` is given.
+	#[arg(long, value_enum, value_name = "MODE", requires = "only_mask")]
+	pub mask_mode: Option<MaskMode>,
+
+	/// Format of the mask alone: with `--only-mask` the output's, otherwise the mask is written
+	/// next to the cutout as `photo_rembg_mask.png` or `.svg`. The cutout keeps `--format`. Not
+	/// written for animations.
+	#[arg(long, value_enum, value_name = "FORMAT")]
+	pub mask_format: Option<MaskFormat>,
+
+	/// Also save the model's probability map before it is quantized to 8 bits, next to the output
+	/// as `photo_rembg_raw.exr` or `photo_rembg_raw.png`, for compositing that needs the exact alpha.
+	/// Not written for animations.
+	#[arg(long, value_enum, value_name = "FORMAT", conflicts_with = "tiff_stack")]
+	pub raw_mask: Option<RawMaskFormat>,
+
+	/// Also write a QA view of each result: the input with the mask tinted over it and the subject's
+	/// bounding box. A file for a single input; with several inputs a directory, which gets
+	/// `photo_rembg_overlay.png` for output `photo_rembg.png` (mirroring `--output-dir`'s layout).
+	/// Not written for animations.
+	#[arg(long, value_name = "PATH", conflicts_with = "tiff_stack")]
+	pub visualize: Option<PathBuf>,
+
 	/// Binarize the mask: alpha becomes 0 or 255 based on this threshold (0-255).
 	/// Helps remove residual "inner background" caused by soft masks.
 	#[arg(long, value_parser = clap::value_parser!(u8))]
 	pub mask_threshold: Option<u8>,
 
+	/// Fill holes in the mask (background regions enclosed by the subject) up to this many pixels
+	/// (default 1000 when given without a value), e.g. highlights mistaken for background.
+	#[arg(long, value_name = "MAX_AREA", num_args = 0..=1, default_missing_value = "1000")]
+	pub fill_holes: Option<u32>,
+
+	/// Gaussian-blur the mask edges by this sigma (in pixels), e.g. to soften `--mask-threshold` jaggies.
+	#[arg(long, value_name = "SIGMA")]
+	pub feather: Option<f32>,
+
+	/// Only feather within this many pixels of the mask boundary; the rest of the mask is left untouched.
+	#[arg(long, value_name = "PX", requires = "feather")]
+	pub feather_band: Option<u32>,
+
 	/// Force alpha=0 for pixels close to the estimated background color (sampled from corners).
 	/// Format: 0-255, where higher removes more. Recommended start: 20-40. (0 disables)
 	#[arg(long, value_parser = clap::value_parser!(u8))]
 	pub color_key_tolerance: Option<u8>,
 
-	/// Composite the foreground over a solid background instead of transparency.
-	/// Format: RRGGBB or #RRGGBB.
+	/// Key out this color (RRGGBB or #RRGGBB, e.g. `#00FF00` for a green screen) instead of the one
+	/// estimated from the corners, which is wrong when the subject touches a corner.
+	#[arg(long, value_name = "COLOR", requires = "color_key_tolerance")]
+	pub color_key_color: Option<String>,
+
+	/// With color keying: remove the key's color cast (green/blue spill) from the subject's
+	/// semi-transparent edges.
+	#[arg(long, requires = "color_key_tolerance")]
+	pub despill: bool,
+
+	/// Put the subject on a fixed-size canvas: it is cropped to its bounds and scaled to fit.
+	/// Backgrounds (`--bgcolor`, `--background-file`, ...) then fill the whole canvas.
+	#[arg(long, value_name = "WxH")]
+	pub canvas: Option<Size>,
+
+	/// Where the subject sits on `--canvas`.
+	#[arg(long, value_enum, default_value_t = Anchor::Center, requires = "canvas")]
+	pub position: Anchor,
+
+	/// Minimum free space in pixels between the subject and each `--canvas` edge.
+	#[arg(long, value_name = "PX", default_value_t = 0, requires = "canvas")]
+	pub canvas_margin: u32,
+
+	/// Put the subject (cropped to its bounds) at an exact spot instead: scaled by `scale`, rotated
+	/// `rotate` degrees clockwise about its center, its top-left at `x`,`y`. The output is the
+	/// `--canvas` size if given, else the `--background-file`'s own size, else the input's.
+	#[arg(long, value_name = "scale=F,x=PX,y=PX,rotate=DEG", conflicts_with_all = ["position", "canvas_margin"])]
+	pub place: Option<Place>,
+
+	/// Resize the finished result to fit within WxH, keeping its aspect ratio (may upscale).
+	#[arg(long, value_name = "WxH", conflicts_with_all = ["max_dim", "scale"])]
+	pub resize: Option<Size>,
+
+	/// Shrink the finished result so its longer side is at most N pixels; smaller results are left alone.
+	#[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..), conflicts_with = "scale")]
+	pub max_dim: Option<u32>,
+
+	/// Scale the finished result by this factor (e.g. `0.5`).
+	#[arg(long, value_name = "F")]
+	pub scale: Option<f32>,
+
+	/// Composite the foreground over a solid color or gradient instead of transparency.
+	/// Format: RRGGBB, #RRGGBB, or linear:#RRGGBB-#RRGGBB[:ANGLEdeg] (0deg = bottom to top, 90deg = left to right).
 	#[arg(long)]
 	pub bgcolor: Option<String>,
 
+	/// Composite the foreground over this image instead of transparency.
+	/// `--bgcolor` (if given) fills any area the image doesn't cover.
+	#[arg(long, value_name = "PATH", visible_alias = "bg-image")]
+	pub background_file: Option<PathBuf>,
+
+	/// Repeat this texture image behind the cutout (shorthand for `--background-file PATH --bg-fit tile`).
+	#[arg(long, value_name = "PATH", conflicts_with = "background_file")]
+	pub bg_tile: Option<PathBuf>,
+
+	/// Put a checkerboard behind the cutout, with this cell size in pixels (default 16).
+	/// Handy for previews/proofs where transparency must stay visible in a flat image.
+	#[arg(long, value_name = "CELL", num_args = 0..=1, default_missing_value = "16", conflicts_with_all = ["background_file", "bg_tile"])]
+	pub checkerboard: Option<u32>,
+
+	/// Checkerboard colors. Format: RRGGBB,RRGGBB (default FFFFFF,CCCCCC).
+	#[arg(long, requires = "checkerboard")]
+	pub checkerboard_colors: Option<String>,
+
+	/// Portrait mode: put the sharp cutout over a Gaussian-blurred copy of the original photo (sigma in pixels).
+	#[arg(long, value_name = "SIGMA", conflicts_with_all = ["background_file", "bg_tile", "checkerboard"])]
+	pub bg_blur: Option<f32>,
+
+	/// How `--background-file` is fitted to the output size.
+	#[arg(long, value_enum, default_value_t = BgFit::Cover)]
+	pub bg_fit: BgFit,
+
+	/// Which part of `--background-file` stays visible when it is cropped/letterboxed (also the tile origin).
+	#[arg(long, value_enum, default_value_t = Anchor::Center)]
+	pub bg_anchor: Anchor,
+
+	/// Multiply the background image's brightness (e.g. 0.7 to darken it behind the subject).
+	#[arg(long, default_value_t = 1.0)]
+	pub background_brightness: f32,
+
+	/// Gaussian blur sigma applied to the background image (in output pixels).
+	#[arg(long, value_name = "SIGMA")]
+	pub background_blur: Option<f32>,
+
+	/// Match the cutout's exposure/white balance to the background image (Lab statistics transfer).
+	/// Optional strength 0-1 (default 0.5 when given without a value).
+	#[arg(long, value_name = "STRENGTH", num_args = 0..=1, default_missing_value = "0.5")]
+	pub harmonize: Option<f32>,
+
+	/// Add a mirrored, fading reflection of the subject beneath it (extends the canvas downward if needed).
+	#[arg(long)]
+	pub reflection: bool,
+
+	/// Reflection height as a fraction of the subject height (0-1).
+	#[arg(long, default_value_t = 0.35)]
+	pub reflection_height: f32,
+
+	/// Reflection opacity at the subject's base (0-1).
+	#[arg(long, default_value_t = 0.4)]
+	pub reflection_opacity: f32,
+
+	/// Reflection fade curve exponent (1 = linear, higher fades faster).
+	#[arg(long, default_value_t = 1.5)]
+	pub reflection_fade: f32,
+
+	/// Gap in pixels between the subject and its reflection.
+	#[arg(long, default_value_t = 0)]
+	pub reflection_gap: u32,
+
+	/// Add a soft drop shadow beneath the subject. Needs a background (`--bgcolor`, `--background-file`, ...).
+	#[arg(long)]
+	pub shadow: bool,
+
+	/// Shadow offset in pixels. Format: X,Y (positive = right/down).
+	#[arg(long, value_name = "X,Y", default_value = "10,10", allow_hyphen_values = true)]
+	pub shadow_offset: Offset,
+
+	/// Shadow blur sigma in pixels.
+	#[arg(long, value_name = "SIGMA", default_value_t = 8.0)]
+	pub shadow_blur: f32,
+
+	/// Shadow opacity (0-1).
+	#[arg(long, default_value_t = 0.5)]
+	pub shadow_opacity: f32,
+
+	/// Shadow color. Format: RRGGBB.
+	#[arg(long, value_name = "RRGGBB", default_value = "000000")]
+	pub shadow_color: String,
+
+	/// ONNX Runtime release to download and load (e.g. `1.23.2`) instead of the newest one this build
+	/// supports.
+	#[arg(long, value_name = "VERSION", global = true)]
+	pub runtime_version: Option<String>,
+
+	/// How download and inference progress is reported on stderr.
+	#[arg(long, value_enum, default_value_t = ProgressFormat::Human, global = true)]
+	pub progress: ProgressFormat,
+
 	/// Assume "yes" for interactive prompts (e.g., downloading GPU backend).
 	#[arg(short = 'y', long)]
 	pub yes: bool
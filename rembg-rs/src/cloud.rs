@@ -0,0 +1,302 @@
+use std::path::Path;
+
+use anyhow::Result;
+
+/// True for `s3://bucket/key` and `gs://bucket/key` paths, which are read and written as cloud
+/// objects instead of local files.
+pub fn is_object_url(path: &Path) -> bool {
+	path.to_str().is_some_and(|s| s.starts_with("s3://") || s.starts_with("gs://"))
+}
+
+/// Download an object.
+#[cfg(feature = "cloud-storage")]
+pub fn get(path: &Path) -> Result<Vec<u8>> {
+	use std::io::Read;
+
+	use anyhow::Context;
+
+	let obj = Object::parse(path)?;
+	let mut req = ureq::get(&obj.url);
+	for (name, value) in obj.auth_headers("GET", &[]) {
+		req = req.header(name, value);
+	}
+	let resp = req.call().with_context(|| format!("GET {}", obj.url))?;
+	let mut bytes = Vec::new();
+	resp.into_body().into_reader().read_to_end(&mut bytes).context("read response body")?;
+	Ok(bytes)
+}
+
+/// Upload `bytes` as an object, replacing any object at that key.
+#[cfg(feature = "cloud-storage")]
+pub fn put(path: &Path, bytes: &[u8]) -> Result<()> {
+	use anyhow::Context;
+
+	let obj = Object::parse(path)?;
+	let content_type = crate::encode::OutputFormat::from_path(path).map_or("application/octet-stream", |f| f.mime_type());
+	let mut req = ureq::put(&obj.url).header("content-type", content_type);
+	for (name, value) in obj.auth_headers("PUT", bytes) {
+		req = req.header(name, value);
+	}
+	req.send(bytes).with_context(|| format!("PUT {}", obj.url))?;
+	Ok(())
+}
+
+#[cfg(not(feature = "cloud-storage"))]
+pub fn get(path: &Path) -> Result<Vec<u8>> {
+	anyhow::bail!("{}: s3:// and gs:// support is not compiled in (rebuild with `--features cloud-storage`)", path.display())
+}
+
+#[cfg(not(feature = "cloud-storage"))]
+pub fn put(path: &Path, _bytes: &[u8]) -> Result<()> {
+	anyhow::bail!("{}: s3:// and gs:// support is not compiled in (rebuild with `--features cloud-storage`)", path.display())
+}
+
+/// An object's HTTPS location and the credentials to sign requests for it with (AWS Signature
+/// Version 4, which Cloud Storage's XML API accepts with HMAC keys). Without credentials requests
+/// go out unsigned, which works for public buckets.
+#[cfg(feature = "cloud-storage")]
+struct Object {
+	url: String,
+	host: String,
+	/// URI-encoded path of `url`, as signed.
+	path: String,
+	region: String,
+	credentials: Option<Credentials>
+}
+
+#[cfg(feature = "cloud-storage")]
+struct Credentials {
+	access_key: String,
+	secret_key: String,
+	session_token: Option<String>
+}
+
+#[cfg(feature = "cloud-storage")]
+impl Object {
+	/// S3: `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`, `AWS_SESSION_TOKEN`, `AWS_REGION` (or
+	/// `AWS_DEFAULT_REGION`, else `us-east-1`) and `AWS_ENDPOINT_URL` for S3-compatible stores
+	/// (MinIO, R2, ...). Cloud Storage: `GOOGLE_HMAC_ACCESS_KEY_ID` and `GOOGLE_HMAC_SECRET`.
+	fn parse(path: &Path) -> Result<Object> {
+		use anyhow::{Context, bail};
+
+		let env = |name: &str| std::env::var(name).ok().filter(|v| !v.trim().is_empty());
+		let s = path.to_str().context("object URL is not UTF-8")?.replace('\\', "/");
+		let (scheme, rest) = s.split_once("://").context("expected s3://bucket/key or gs://bucket/key")?;
+		let (bucket, key) = rest.split_once('/').filter(|(b, k)| !b.is_empty() && !k.is_empty()).with_context(|| format!("{s}: expected {scheme}://bucket/key"))?;
+		let key = uri_encode(key);
+
+		let (endpoint, region, access, secret, token) = match scheme {
+			"s3" => {
+				let region = env("AWS_REGION").or_else(|| env("AWS_DEFAULT_REGION")).unwrap_or_else(|| "us-east-1".to_string());
+				// Path-style on custom endpoints, which mostly don't have per-bucket host names.
+				let endpoint = match env("AWS_ENDPOINT_URL") {
+					Some(e) => format!("{}/{bucket}", e.trim_end_matches('/')),
+					None => format!("https://{bucket}.s3.{region}.amazonaws.com")
+				};
+				(endpoint, region, env("AWS_ACCESS_KEY_ID"), env("AWS_SECRET_ACCESS_KEY"), env("AWS_SESSION_TOKEN"))
+			}
+			"gs" => (
+				format!("https://storage.googleapis.com/{bucket}"),
+				"auto".to_string(),
+				env("GOOGLE_HMAC_ACCESS_KEY_ID"),
+				env("GOOGLE_HMAC_SECRET"),
+				None
+			),
+			_ => bail!("{s}: unsupported scheme {scheme:?} (expected s3 or gs)")
+		};
+		let (origin, base) = match endpoint.find("://").map(|i| i + 3).and_then(|i| endpoint[i..].find('/').map(|j| i + j)) {
+			Some(i) => endpoint.split_at(i),
+			None => (endpoint.as_str(), "")
+		};
+		let host = origin.split_once("://").map_or(origin, |(_, h)| h).to_string();
+		Ok(Object {
+			url: format!("{origin}{base}/{key}"),
+			host,
+			path: format!("{base}/{key}"),
+			region,
+			credentials: access.zip(secret).map(|(access_key, secret_key)| Credentials {
+				access_key,
+				secret_key,
+				session_token: token
+			})
+		})
+	}
+
+	/// The SigV4 headers for a `method` request on this object with `body`; none without credentials.
+	fn auth_headers(&self, method: &str, body: &[u8]) -> Vec<(&'static str, String)> {
+		use sha2::{Digest, Sha256};
+
+		let Some(creds) = &self.credentials else {
+			return Vec::new();
+		};
+
+		let (date, time) = utc_date_time(now_secs());
+		let amz_date = format!("{date}T{time}Z");
+		let payload_hash = hex::encode(Sha256::digest(body));
+		let mut headers = vec![("host", self.host.clone()), ("x-amz-content-sha256", payload_hash.clone()), ("x-amz-date", amz_date.clone())];
+		if let Some(token) = &creds.session_token {
+			headers.push(("x-amz-security-token", token.clone()));
+		}
+		let signed_headers = headers.iter().map(|(k, _)| *k).collect::<Vec<_>>().join(";");
+		let canonical_request = canonical_request(method, &self.path, "", &headers, &payload_hash);
+		let scope = format!("{date}/{}/s3/aws4_request", self.region);
+		let signature = signature(&creds.secret_key, &scope, &amz_date, &canonical_request);
+
+		let authorization = format!("AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}", creds.access_key);
+		// ureq sets `host` itself.
+		headers.remove(0);
+		headers.push(("authorization", authorization));
+		headers
+	}
+}
+
+/// A SigV4 canonical request. `headers` are the signed ones, lowercase and sorted by name; `query`
+/// is already canonical (encoded, sorted).
+#[cfg(feature = "cloud-storage")]
+fn canonical_request(method: &str, path: &str, query: &str, headers: &[(&str, String)], payload_hash: &str) -> String {
+	let signed_headers = headers.iter().map(|(k, _)| *k).collect::<Vec<_>>().join(";");
+	let canonical_headers: String = headers.iter().map(|(k, v)| format!("{k}:{}\n", v.trim())).collect();
+	format!("{method}\n{path}\n{query}\n{canonical_headers}\n{signed_headers}\n{payload_hash}")
+}
+
+/// The hex SigV4 signature of `canonical_request` for `scope` (`date/region/service/aws4_request`).
+#[cfg(feature = "cloud-storage")]
+fn signature(secret_key: &str, scope: &str, amz_date: &str, canonical_request: &str) -> String {
+	use sha2::{Digest, Sha256};
+
+	let string_to_sign = format!("AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}", hex::encode(Sha256::digest(canonical_request.as_bytes())));
+	let key = scope
+		.split('/')
+		.fold(format!("AWS4{secret_key}").into_bytes(), |k, part| hmac_sha256(&k, part.as_bytes()).to_vec());
+	hex::encode(hmac_sha256(&key, string_to_sign.as_bytes()))
+}
+
+/// Percent-encode an object key for the request path, keeping `/` separators.
+#[cfg(feature = "cloud-storage")]
+fn uri_encode(key: &str) -> String {
+	use std::fmt::Write;
+
+	let mut out = String::with_capacity(key.len());
+	for b in key.bytes() {
+		if b.is_ascii_alphanumeric() || b"-_.~/".contains(&b) {
+			out.push(b as char);
+		} else {
+			let _ = write!(out, "%{b:02X}");
+		}
+	}
+	out
+}
+
+#[cfg(feature = "cloud-storage")]
+fn hmac_sha256(key: &[u8], msg: &[u8]) -> [u8; 32] {
+	use sha2::{Digest, Sha256};
+
+	const BLOCK: usize = 64;
+	let mut k = [0u8; BLOCK];
+	if key.len() > BLOCK {
+		k[..32].copy_from_slice(&Sha256::digest(key));
+	} else {
+		k[..key.len()].copy_from_slice(key);
+	}
+	let pad = |byte: u8| k.map(|b| b ^ byte);
+	let inner = Sha256::new().chain_update(pad(0x36)).chain_update(msg).finalize();
+	Sha256::new().chain_update(pad(0x5c)).chain_update(inner).finalize().into()
+}
+
+#[cfg(feature = "cloud-storage")]
+fn now_secs() -> u64 {
+	std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// `secs` since the Unix epoch as a UTC date and time, `YYYYMMDD` and `HHMMSS`.
+#[cfg(feature = "cloud-storage")]
+fn utc_date_time(secs: u64) -> (String, String) {
+	let (days, rem) = ((secs / 86_400) as i64, secs % 86_400);
+	// Days since 1970-01-01 to a civil date (Howard Hinnant's algorithm).
+	let z = days + 719_468;
+	let era = z.div_euclid(146_097);
+	let doe = z - era * 146_097;
+	let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let day = doy - (153 * mp + 2) / 5 + 1;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 };
+	let year = yoe + era * 400 + (month <= 2) as i64;
+	(format!("{year:04}{month:02}{day:02}"), format!("{:02}{:02}{:02}", rem / 3600, rem / 60 % 60, rem % 60))
+}
+
+#[cfg(all(test, feature = "cloud-storage"))]
+mod tests {
+	use super::*;
+
+	const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+	// RFC 4231, test cases 1, 2, 6 and 7 (the last two have keys longer than a block).
+	#[test]
+	fn hmac_sha256_rfc4231() {
+		let long_key = [0xaa; 131];
+		let cases: [(&[u8], &[u8], &str); 4] = [
+			(&[0x0b; 20], b"Hi There", "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"),
+			(b"Jefe", b"what do ya want for nothing?", "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"),
+			(
+				&long_key,
+				b"Test Using Larger Than Block-Size Key - Hash Key First",
+				"60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+			),
+			(
+				&long_key,
+				b"This is a test using a larger than block-size key and a larger than block-size data. The key needs to be hashed before being used by the HMAC algorithm.",
+				"9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2"
+			)
+		];
+		for (key, msg, expected) in cases {
+			assert_eq!(hex::encode(hmac_sha256(key, msg)), expected);
+		}
+	}
+
+	// `get-vanilla` from the AWS SigV4 test suite.
+	#[test]
+	fn sigv4_get_vanilla() {
+		let headers = [("host", "example.amazonaws.com".to_string()), ("x-amz-date", "20150830T123600Z".to_string())];
+		let request = canonical_request("GET", "/", "", &headers, EMPTY_SHA256);
+		let signature = signature("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY", "20150830/us-east-1/service/aws4_request", "20150830T123600Z", &request);
+		assert_eq!(signature, "5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31");
+	}
+
+	// The GET Object, GET Bucket Lifecycle and GET Bucket (list objects) examples from the S3
+	// SigV4 documentation.
+	#[test]
+	fn sigv4_s3_examples() {
+		let secret = "wJalrXUtnFEMI/K7MDENG/bPxRfiCYEXAMPLEKEY";
+		let scope = "20130524/us-east-1/s3/aws4_request";
+		let amz_date = "20130524T000000Z";
+		let headers = |extra: Option<(&'static str, &str)>| {
+			let mut headers = vec![("host", "examplebucket.s3.amazonaws.com".to_string())];
+			headers.extend(extra.map(|(k, v)| (k, v.to_string())));
+			headers.push(("x-amz-content-sha256", EMPTY_SHA256.to_string()));
+			headers.push(("x-amz-date", amz_date.to_string()));
+			headers
+		};
+		let cases = [
+			("/test.txt", "", headers(Some(("range", "bytes=0-9"))), "f0e8bdb87c964420e857bd35b5d6ed310bd44f0170aba48dd91039c6036bdb41"),
+			("/", "lifecycle=", headers(None), "fea454ca298b7da1c68078a5d1bdbfbbe0d65c699e0f91ac7a200a0136783543"),
+			("/", "max-keys=2&prefix=J", headers(None), "34b48302e7b5fa45bde8084f4b7868a86f0a534bc59db6670ed5711ef69dc6f7")
+		];
+		for (path, query, headers, expected) in cases {
+			let request = canonical_request("GET", path, query, &headers, EMPTY_SHA256);
+			assert_eq!(signature(secret, scope, amz_date, &request), expected, "{path}?{query}");
+		}
+	}
+
+	#[test]
+	fn utc_date_time_from_epoch_secs() {
+		assert_eq!(utc_date_time(0), ("19700101".to_string(), "000000".to_string()));
+		assert_eq!(utc_date_time(1_369_353_600), ("20130524".to_string(), "000000".to_string()));
+		assert_eq!(utc_date_time(951_868_798), ("20000229".to_string(), "235958".to_string()));
+	}
+
+	#[test]
+	fn uri_encode_keeps_separators() {
+		assert_eq!(uri_encode("a b/c+d~é.png"), "a%20b/c%2Bd~%C3%A9.png");
+	}
+}
@@ -1,45 +1,199 @@
 use anyhow::{Context, Result, bail};
-use image::{DynamicImage, GrayImage, RgbImage, Rgba, RgbaImage};
+use clap::ValueEnum;
+use image::imageops::FilterType;
+use image::{DynamicImage, GrayImage, ImageBuffer, Luma, Rgb, RgbImage, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
 
-pub fn apply_alpha(img: &RgbImage, mask: &GrayImage, threshold: Option<u8>, color_key_tolerance: Option<u8>) -> DynamicImage {
-	let (w, h) = (img.width(), img.height());
-	let mut out = RgbaImage::new(w, h);
+/// How a background image is mapped onto the output canvas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BgFit {
+	/// Scale to cover the whole canvas, cropping the overflow.
+	#[default]
+	Cover,
+	/// Scale to fit inside the canvas; uncovered areas stay transparent (or `--bgcolor`).
+	Contain,
+	/// Resize to the canvas size, ignoring aspect ratio.
+	Stretch,
+	/// Repeat the image at its native size.
+	Tile
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Anchor {
+	#[default]
+	Center,
+	Top,
+	Bottom,
+	Left,
+	Right,
+	TopLeft,
+	TopRight,
+	BottomLeft,
+	BottomRight
+}
+
+impl Anchor {
+	/// Offset of an `inner_w`x`inner_h` box inside an `outer_w`x`outer_h` box (negative if it overflows).
+	pub fn offset(self, outer_w: u32, outer_h: u32, inner_w: u32, inner_h: u32) -> (i64, i64) {
+		let dx = outer_w as i64 - inner_w as i64;
+		let dy = outer_h as i64 - inner_h as i64;
+		let x = match self {
+			Anchor::Left | Anchor::TopLeft | Anchor::BottomLeft => 0,
+			Anchor::Right | Anchor::TopRight | Anchor::BottomRight => dx,
+			Anchor::Center | Anchor::Top | Anchor::Bottom => dx / 2
+		};
+		let y = match self {
+			Anchor::Top | Anchor::TopLeft | Anchor::TopRight => 0,
+			Anchor::Bottom | Anchor::BottomLeft | Anchor::BottomRight => dy,
+			Anchor::Center | Anchor::Left | Anchor::Right => dy / 2
+		};
+		(x, y)
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BgPlacement {
+	pub fit: BgFit,
+	pub anchor: Anchor,
+	/// Multiplier applied to the background's RGB (1.0 = unchanged).
+	pub brightness: f32,
+	/// Gaussian blur sigma applied to the placed background, in output pixels.
+	pub blur: Option<f32>
+}
 
-	let bg = color_key_tolerance.and_then(|t| {
-		if t == 0 {
-			None
-		} else {
-			Some((estimate_bg_rgb(img), (t as i32) * (t as i32)))
+impl Default for BgPlacement {
+	fn default() -> Self {
+		Self {
+			fit: BgFit::Cover,
+			anchor: Anchor::Center,
+			brightness: 1.0,
+			blur: None
 		}
-	});
+	}
+}
 
-	for y in 0..h {
-		for x in 0..w {
-			let p = img.get_pixel(x, y);
-			let mut a = mask.get_pixel(x, y)[0];
+pub fn apply_alpha(img: &RgbImage, mask: &GrayImage, threshold: Option<u8>, key: Option<ColorKey>) -> DynamicImage {
+	DynamicImage::ImageRgba8(apply_alpha_rows(img, mask, threshold, key, 0, img.height()))
+}
+
+/// The background color `color_key_tolerance` punches out: given (e.g. a green screen), or
+/// sampled from the image corners.
+#[derive(Debug, Clone, Copy)]
+pub struct ColorKey {
+	rgb: (u8, u8, u8),
+	max_dist2: i32,
+	/// Channel to despill on semi-transparent pixels: the one the key color is strongest in.
+	spill: Option<usize>
+}
+
+impl ColorKey {
+	/// Key out `color`, or the color estimated from `img`'s corners. `None` when there's no
+	/// tolerance or it is 0.
+	///
+	/// With `despill`, edge pixels (partly transparent) of a green or blue (or any clearly tinted)
+	/// key have that channel limited to the larger of the other two, removing the screen's color
+	/// cast from hair and outlines.
+	pub fn new(img: &RgbImage, tolerance: Option<u8>, color: Option<(u8, u8, u8)>, despill: bool) -> Option<ColorKey> {
+		let t = tolerance.filter(|&t| t > 0)?;
+		let rgb = color.unwrap_or_else(|| estimate_bg_rgb(img));
+		let c = [rgb.0, rgb.1, rgb.2];
+		let spill = (0..3).find(|&i| (0..3).all(|j| j == i || c[i] > c[j]));
+		Some(ColorKey {
+			rgb,
+			max_dist2: (t as i32) * (t as i32),
+			spill: spill.filter(|_| despill)
+		})
+	}
+
+	/// Suppress the key's channel in `p` (a pixel with partial alpha) relative to the other two.
+	fn despill<T: Ord + Copy>(&self, mut p: [T; 3]) -> [T; 3] {
+		if let Some(i) = self.spill {
+			p[i] = p[i].min(p[(i + 1) % 3].max(p[(i + 2) % 3]));
+		}
+		p
+	}
+}
+
+/// Rows `y0..y0 + rows` of `apply_alpha`'s result, so a large cutout can be built and encoded a
+/// band at a time instead of all at once.
+pub fn apply_alpha_rows(img: &RgbImage, mask: &GrayImage, threshold: Option<u8>, key: Option<ColorKey>, y0: u32, rows: u32) -> RgbaImage {
+	let mut out = RgbaImage::new(img.width(), rows);
+	for y in 0..rows {
+		for x in 0..img.width() {
+			let p = img.get_pixel(x, y0 + y);
+			let mut a = mask.get_pixel(x, y0 + y)[0];
 			if let Some(t) = threshold {
 				a = if a >= t { 255 } else { 0 };
 			}
 
-			if let Some(((br, bgc, bb), tol2)) = bg {
+			if let Some(ColorKey { rgb: (br, bgc, bb), max_dist2, .. }) = key {
 				let dr = p[0] as i32 - br as i32;
 				let dg = p[1] as i32 - bgc as i32;
 				let db = p[2] as i32 - bb as i32;
 				let d2 = dr * dr + dg * dg + db * db;
-				if d2 <= tol2 {
+				if d2 <= max_dist2 {
 					a = 0;
 				}
 			}
 
-			out.put_pixel(x, y, Rgba([p[0], p[1], p[2], a]));
+			let [r, g, b] = match key {
+				Some(key) if a < 255 => key.despill(p.0),
+				_ => p.0
+			};
+			out.put_pixel(x, y, Rgba([r, g, b, a]));
 		}
 	}
-	DynamicImage::ImageRgba8(out)
+	out
+}
+
+pub type Rgb16Image = ImageBuffer<Rgb<u16>, Vec<u16>>;
+
+/// True for sources with more than 8 bits per channel (16-bit PNG/TIFF, float EXR/HDR).
+pub fn is_high_bit_depth(img: &DynamicImage) -> bool {
+	img.color().bytes_per_pixel() / img.color().channel_count() > 1
+}
+
+/// 16-bit variant of `apply_alpha` for high-bit-depth sources: color keeps its full precision and
+/// the 8-bit mask is widened to 16 bits. The color key compares the top 8 bits of each channel.
+pub fn apply_alpha_16(img: &Rgb16Image, mask: &GrayImage, threshold: Option<u8>, key: Option<ColorKey>) -> DynamicImage {
+	let out = ImageBuffer::from_fn(img.width(), img.height(), |x, y| {
+		let p = img.get_pixel(x, y);
+		let mut a = mask.get_pixel(x, y)[0];
+		if let Some(t) = threshold {
+			a = if a >= t { 255 } else { 0 };
+		}
+
+		if let Some(ColorKey { rgb: (br, bgc, bb), max_dist2, .. }) = key {
+			let dr = (p[0] >> 8) as i32 - br as i32;
+			let dg = (p[1] >> 8) as i32 - bgc as i32;
+			let db = (p[2] >> 8) as i32 - bb as i32;
+			if dr * dr + dg * dg + db * db <= max_dist2 {
+				a = 0;
+			}
+		}
+
+		let [r, g, b] = match key {
+			Some(key) if a < 255 => key.despill(p.0),
+			_ => p.0
+		};
+		Rgba([r, g, b, a as u16 * 257])
+	});
+	DynamicImage::ImageRgba16(out)
 }
 
 pub fn composite_over_bg(img: &RgbImage, mask: &GrayImage, threshold: Option<u8>, bgcolor: &str) -> Result<DynamicImage> {
-	let (bg_r, bg_g, bg_b) = parse_hex_rgb(bgcolor)?;
 	let (w, h) = (img.width(), img.height());
+	let (bg_r, bg_g, bg_b) = match parse_fill(bgcolor)? {
+		Fill::Solid(c) => c,
+		gradient => {
+			let fg = apply_alpha(img, mask, threshold, None).into_rgba8();
+			let out = flatten(&fg, &gradient.canvas(w, h));
+			return Ok(DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(out).to_rgb8()));
+		}
+	};
 	let mut out = RgbImage::new(w, h);
 
 	for y in 0..h {
@@ -63,6 +217,462 @@ pub fn composite_over_bg(img: &RgbImage, mask: &GrayImage, threshold: Option<u8>
 	Ok(DynamicImage::ImageRgb8(out))
 }
 
+/// Map `bg` onto a `w`x`h` canvas. Areas the background doesn't cover (only possible with
+/// `BgFit::Contain`) are filled with `fill`, or left transparent.
+pub fn place_background(bg: &RgbImage, w: u32, h: u32, placement: &BgPlacement, fill: Option<&Fill>) -> RgbaImage {
+	let mut canvas = match fill {
+		Some(f) => f.canvas(w, h),
+		None => RgbaImage::new(w, h)
+	};
+	let (bw, bh) = (bg.width(), bg.height());
+	if bw == 0 || bh == 0 || w == 0 || h == 0 {
+		return canvas;
+	}
+	let src = DynamicImage::ImageRgb8(bg.clone()).to_rgba8();
+
+	match placement.fit {
+		BgFit::Stretch => {
+			canvas = image::imageops::resize(&src, w, h, FilterType::Lanczos3);
+		}
+		BgFit::Cover | BgFit::Contain => {
+			let sx = w as f64 / bw as f64;
+			let sy = h as f64 / bh as f64;
+			let s = if placement.fit == BgFit::Cover { sx.max(sy) } else { sx.min(sy) };
+			let nw = ((bw as f64 * s).round() as u32).max(1);
+			let nh = ((bh as f64 * s).round() as u32).max(1);
+			let scaled = image::imageops::resize(&src, nw, nh, FilterType::Lanczos3);
+			let (ox, oy) = placement.anchor.offset(w, h, nw, nh);
+			image::imageops::overlay(&mut canvas, &scaled, ox, oy);
+		}
+		BgFit::Tile => {
+			let (ox, oy) = placement.anchor.offset(w, h, bw, bh);
+			for (x, y, p) in canvas.enumerate_pixels_mut() {
+				let tx = (x as i64 - ox).rem_euclid(bw as i64) as u32;
+				let ty = (y as i64 - oy).rem_euclid(bh as i64) as u32;
+				*p = *src.get_pixel(tx, ty);
+			}
+		}
+	}
+
+	if (placement.brightness - 1.0).abs() > f32::EPSILON {
+		let k = placement.brightness.max(0.0);
+		for p in canvas.pixels_mut() {
+			for c in 0..3 {
+				p.0[c] = (p.0[c] as f32 * k).round().clamp(0.0, 255.0) as u8;
+			}
+		}
+	}
+	if let Some(sigma) = placement.blur.filter(|s| *s > 0.0) {
+		canvas = image::imageops::blur(&canvas, sigma);
+	}
+	canvas
+}
+
+/// One 2x2-cell period of a checkerboard, meant to be repeated with `BgFit::Tile`.
+pub fn checkerboard_tile(cell: u32, a: (u8, u8, u8), b: (u8, u8, u8)) -> RgbImage {
+	let cell = cell.max(1);
+	RgbImage::from_fn(cell * 2, cell * 2, |x, y| {
+		let (r, g, bl) = if (x / cell + y / cell).is_multiple_of(2) { a } else { b };
+		image::Rgb([r, g, bl])
+	})
+}
+
+/// Alpha-composite a cutout over a background canvas of the same size.
+pub fn flatten(fg: &RgbaImage, bg: &RgbaImage) -> RgbaImage {
+	let mut out = bg.clone();
+	for (dst, src) in out.pixels_mut().zip(fg.pixels()) {
+		*dst = over(src.0, dst.0);
+	}
+	out
+}
+
+/// Alpha-composite a cutout over an opaque solid color.
+pub fn flatten_onto_color(fg: &RgbaImage, (r, g, b): (u8, u8, u8)) -> RgbImage {
+	let bg = RgbaImage::from_pixel(fg.width(), fg.height(), Rgba([r, g, b, 255]));
+	DynamicImage::ImageRgba8(flatten(fg, &bg)).to_rgb8()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Reflection {
+	/// Reflection height as a fraction of the subject's height.
+	pub height: f32,
+	/// Opacity at the subject's base line (0-1).
+	pub opacity: f32,
+	/// Fade curve exponent: 1 is linear, higher fades out faster.
+	pub fade: f32,
+	/// Gap between the subject and its reflection, in pixels.
+	pub gap: u32
+}
+
+impl Default for Reflection {
+	fn default() -> Self {
+		Self {
+			height: 0.35,
+			opacity: 0.4,
+			fade: 1.5,
+			gap: 0
+		}
+	}
+}
+
+/// Render a vertically mirrored, fading copy of the cutout below its lowest opaque row.
+/// The canvas grows downward if the reflection doesn't fit.
+pub fn add_reflection(cutout: &RgbaImage, r: &Reflection) -> RgbaImage {
+	let (w, h) = cutout.dimensions();
+	let has_alpha = |y: u32| (0..w).any(|x| cutout.get_pixel(x, y)[3] != 0);
+	let Some(bottom) = (0..h).rev().find(|&y| has_alpha(y)) else {
+		return cutout.clone();
+	};
+	let top = (0..h).find(|&y| has_alpha(y)).unwrap_or(0);
+
+	let subject_h = bottom - top + 1;
+	let refl_h = ((subject_h as f32 * r.height.clamp(0.0, 1.0)).round() as u32).min(subject_h);
+	if refl_h == 0 || r.opacity <= 0.0 {
+		return cutout.clone();
+	}
+
+	let start = bottom + 1 + r.gap;
+	let mut out = RgbaImage::new(w, h.max(start + refl_h));
+	image::imageops::replace(&mut out, cutout, 0, 0);
+
+	let opacity = r.opacity.clamp(0.0, 1.0);
+	let fade = r.fade.max(0.01);
+	for i in 0..refl_h {
+		let t = i as f32 / refl_h as f32;
+		let k = opacity * (1.0 - t).powf(fade);
+		for x in 0..w {
+			let p = cutout.get_pixel(x, bottom - i);
+			let a = (p[3] as f32 * k).round() as u8;
+			let dst = out.get_pixel_mut(x, start + i);
+			*dst = over([p[0], p[1], p[2], a], dst.0);
+		}
+	}
+	out
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Shadow {
+	/// Shadow displacement in pixels (positive = right/down).
+	pub offset_x: i32,
+	pub offset_y: i32,
+	/// Gaussian blur sigma in pixels.
+	pub blur: f32,
+	/// Opacity under fully opaque subject pixels (0-1).
+	pub opacity: f32,
+	pub color: (u8, u8, u8)
+}
+
+impl Default for Shadow {
+	fn default() -> Self {
+		Self {
+			offset_x: 10,
+			offset_y: 10,
+			blur: 8.0,
+			opacity: 0.5,
+			color: (0, 0, 0)
+		}
+	}
+}
+
+/// Put a soft drop shadow, made from the cutout's own alpha, beneath it. The canvas size is kept;
+/// shadow parts pushed past the edges are clipped.
+pub fn add_shadow(cutout: &RgbaImage, s: &Shadow) -> RgbaImage {
+	let (w, h) = cutout.dimensions();
+	let opacity = s.opacity.clamp(0.0, 1.0);
+	let mut alpha = GrayImage::new(w, h);
+	for (x, y, p) in cutout.enumerate_pixels() {
+		let sx = x as i64 + s.offset_x as i64;
+		let sy = y as i64 + s.offset_y as i64;
+		if (0..w as i64).contains(&sx) && (0..h as i64).contains(&sy) {
+			alpha.put_pixel(sx as u32, sy as u32, Luma([(p[3] as f32 * opacity).round() as u8]));
+		}
+	}
+	if s.blur > 0.0 {
+		alpha = image::imageops::blur(&alpha, s.blur);
+	}
+
+	let (r, g, b) = s.color;
+	let mut out = RgbaImage::from_fn(w, h, |x, y| Rgba([r, g, b, alpha.get_pixel(x, y)[0]]));
+	for (dst, src) in out.pixels_mut().zip(cutout.pixels()) {
+		*dst = over(src.0, dst.0);
+	}
+	out
+}
+
+/// Shift the cutout's color statistics (per-channel mean/std in CIE Lab) toward the background's,
+/// so exposure and white balance roughly match. `strength` blends between the original (0) and a full
+/// Reinhard-style transfer (1). Both sides are weighted by their own alpha.
+pub fn harmonize(fg: &RgbaImage, bg: &RgbaImage, strength: f32) -> RgbaImage {
+	let strength = strength.clamp(0.0, 1.0);
+	if strength == 0.0 {
+		return fg.clone();
+	}
+
+	let fg_stats = lab_stats(fg.pixels().map(|p| ([p[0], p[1], p[2]], p[3])));
+	let bg_stats = lab_stats(bg.pixels().map(|p| ([p[0], p[1], p[2]], p[3])));
+	let (Some((fm, fs)), Some((bm, bs))) = (fg_stats, bg_stats) else {
+		return fg.clone();
+	};
+
+	// Limit contrast changes; a low-variance background (e.g. a studio sweep) would otherwise flatten the subject.
+	let mut scale = [1.0f32; 3];
+	for c in 0..3 {
+		scale[c] = if fs[c] > 1e-3 { (bs[c] / fs[c]).clamp(0.5, 2.0) } else { 1.0 };
+	}
+
+	let mut out = fg.clone();
+	for p in out.pixels_mut() {
+		if p[3] == 0 {
+			continue;
+		}
+		let lab = srgb_to_lab([p[0], p[1], p[2]]);
+		let mut t = [0.0f32; 3];
+		for c in 0..3 {
+			let moved = (lab[c] - fm[c]) * scale[c] + bm[c];
+			t[c] = lab[c] + (moved - lab[c]) * strength;
+		}
+		let rgb = lab_to_srgb(t);
+		p.0 = [rgb[0], rgb[1], rgb[2], p[3]];
+	}
+	out
+}
+
+/// Weighted mean/std of Lab values; weights are 0..255. `None` if all weights are zero.
+fn lab_stats(pixels: impl Iterator<Item = ([u8; 3], u8)>) -> Option<([f32; 3], [f32; 3])> {
+	let mut wsum = 0.0f64;
+	let mut sum = [0.0f64; 3];
+	let mut sq = [0.0f64; 3];
+	for (rgb, w) in pixels {
+		if w == 0 {
+			continue;
+		}
+		let w = w as f64 / 255.0;
+		let lab = srgb_to_lab(rgb);
+		for c in 0..3 {
+			sum[c] += lab[c] as f64 * w;
+			sq[c] += (lab[c] as f64) * (lab[c] as f64) * w;
+		}
+		wsum += w;
+	}
+	if wsum <= 0.0 {
+		return None;
+	}
+	let mut mean = [0.0f32; 3];
+	let mut std = [0.0f32; 3];
+	for c in 0..3 {
+		let m = sum[c] / wsum;
+		mean[c] = m as f32;
+		std[c] = (sq[c] / wsum - m * m).max(0.0).sqrt() as f32;
+	}
+	Some((mean, std))
+}
+
+// sRGB (D65) <-> CIE Lab.
+const WHITE_D65: [f32; 3] = [0.950_47, 1.0, 1.088_83];
+
+fn srgb_to_lab(rgb: [u8; 3]) -> [f32; 3] {
+	let lin = |v: u8| {
+		let c = v as f32 / 255.0;
+		if c <= 0.040_45 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+	};
+	let (r, g, b) = (lin(rgb[0]), lin(rgb[1]), lin(rgb[2]));
+	let x = 0.412_456_4 * r + 0.357_576_1 * g + 0.180_437_5 * b;
+	let y = 0.212_672_9 * r + 0.715_152_2 * g + 0.072_175 * b;
+	let z = 0.019_333_9 * r + 0.119_192 * g + 0.950_304_1 * b;
+
+	let f = |t: f32| if t > 0.008_856 { t.cbrt() } else { 7.787 * t + 16.0 / 116.0 };
+	let (fx, fy, fz) = (f(x / WHITE_D65[0]), f(y / WHITE_D65[1]), f(z / WHITE_D65[2]));
+	[116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+fn lab_to_srgb(lab: [f32; 3]) -> [u8; 3] {
+	let fy = (lab[0] + 16.0) / 116.0;
+	let fx = fy + lab[1] / 500.0;
+	let fz = fy - lab[2] / 200.0;
+	let finv = |t: f32| {
+		let t3 = t * t * t;
+		if t3 > 0.008_856 { t3 } else { (t - 16.0 / 116.0) / 7.787 }
+	};
+	let (x, y, z) = (finv(fx) * WHITE_D65[0], finv(fy) * WHITE_D65[1], finv(fz) * WHITE_D65[2]);
+
+	let r = 3.240_454_2 * x - 1.537_138_5 * y - 0.498_531_4 * z;
+	let g = -0.969_266 * x + 1.876_010_8 * y + 0.041_556 * z;
+	let b = 0.055_643_4 * x - 0.204_025_9 * y + 1.057_225_2 * z;
+
+	let enc = |c: f32| {
+		let c = c.clamp(0.0, 1.0);
+		let v = if c <= 0.003_130_8 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 };
+		(v * 255.0).round().clamp(0.0, 255.0) as u8
+	};
+	[enc(r), enc(g), enc(b)]
+}
+
+/// Porter-Duff "over" for straight (non-premultiplied) 8-bit RGBA.
+fn over(fg: [u8; 4], bg: [u8; 4]) -> Rgba<u8> {
+	let fa = fg[3] as u32;
+	let ba = bg[3] as u32;
+	// out_a = fa + ba * (1 - fa), all in 0..255 fixed point.
+	let oa = fa * 255 + ba * (255 - fa);
+	if oa == 0 {
+		return Rgba([0, 0, 0, 0]);
+	}
+	let mut o = [0u8; 4];
+	for c in 0..3 {
+		let v = fg[c] as u32 * fa * 255 + bg[c] as u32 * ba * (255 - fa);
+		o[c] = ((v + oa / 2) / oa) as u8;
+	}
+	o[3] = ((oa + 127) / 255) as u8;
+	Rgba(o)
+}
+
+/// Crop to the bounding box of pixels with non-zero alpha. Fully transparent images are returned unchanged.
+pub fn crop_to_alpha(img: &RgbaImage) -> RgbaImage {
+	let (mut x0, mut y0, mut x1, mut y1) = (u32::MAX, u32::MAX, 0u32, 0u32);
+	for (x, y, p) in img.enumerate_pixels() {
+		if p[3] != 0 {
+			x0 = x0.min(x);
+			y0 = y0.min(y);
+			x1 = x1.max(x);
+			y1 = y1.max(y);
+		}
+	}
+	if x0 > x1 || y0 > y1 {
+		return img.clone();
+	}
+	image::imageops::crop_imm(img, x0, y0, x1 - x0 + 1, y1 - y0 + 1).to_image()
+}
+
+/// Tint of `overlay`'s mask.
+const OVERLAY_COLOR: [u8; 3] = [255, 0, 128];
+/// Mask opacity of `overlay`: 0.5 at full alpha.
+const OVERLAY_OPACITY: f32 = 0.5;
+/// Bounding-box color of `overlay`.
+const OVERLAY_BOX_COLOR: [u8; 3] = [0, 255, 0];
+
+/// A QA view of a mask: `img` with `mask` tinted over it, and the bounding box of the subject
+/// (mask >= 128) outlined with a line about 1/400 of the image's longer side thick.
+pub fn overlay(img: &RgbImage, mask: &GrayImage) -> RgbImage {
+	let mut out = img.clone();
+	let (mut x0, mut y0, mut x1, mut y1) = (u32::MAX, u32::MAX, 0u32, 0u32);
+	for ((x, y, px), m) in out.enumerate_pixels_mut().zip(mask.pixels()) {
+		let a = m.0[0] as f32 / 255.0 * OVERLAY_OPACITY;
+		for (v, tint) in px.0.iter_mut().zip(OVERLAY_COLOR) {
+			*v = (*v as f32 * (1.0 - a) + tint as f32 * a).round() as u8;
+		}
+		if m.0[0] >= 128 {
+			x0 = x0.min(x);
+			y0 = y0.min(y);
+			x1 = x1.max(x);
+			y1 = y1.max(y);
+		}
+	}
+	if x0 > x1 {
+		return out;
+	}
+	let t = (img.width().max(img.height()) / 400).max(1);
+	for (x, y, px) in out.enumerate_pixels_mut() {
+		let inside = x >= x0 && x <= x1 && y >= y0 && y <= y1;
+		let near_edge = x < x0 + t || x + t > x1 || y < y0 + t || y + t > y1;
+		if inside && near_edge {
+			*px = Rgb(OVERLAY_BOX_COLOR);
+		}
+	}
+	out
+}
+
+/// Crop `cutout` to its subject and scale it, up or down, to fit a transparent `w`x`h` canvas
+/// less `margin` pixels on every side. `anchor` says where in that area it goes when its aspect
+/// ratio leaves room on one axis.
+pub fn place_on_canvas(cutout: &RgbaImage, w: u32, h: u32, margin: u32, anchor: Anchor) -> RgbaImage {
+	let subject = crop_to_alpha(cutout);
+	let avail_w = w.saturating_sub(margin.saturating_mul(2)).max(1);
+	let avail_h = h.saturating_sub(margin.saturating_mul(2)).max(1);
+	let scale = (avail_w as f32 / subject.width() as f32).min(avail_h as f32 / subject.height() as f32);
+	let sw = ((subject.width() as f32 * scale).round() as u32).clamp(1, avail_w);
+	let sh = ((subject.height() as f32 * scale).round() as u32).clamp(1, avail_h);
+	let scaled = if (sw, sh) == subject.dimensions() {
+		subject
+	} else {
+		image::imageops::resize(&subject, sw, sh, FilterType::Lanczos3)
+	};
+
+	let mut out = RgbaImage::new(w, h);
+	let (x, y) = anchor.offset(avail_w, avail_h, sw, sh);
+	image::imageops::overlay(&mut out, &scaled, x + margin as i64, y + margin as i64);
+	out
+}
+
+/// Crop `cutout` to its subject, scale it by `scale`, rotate it `rotate_deg` clockwise about its
+/// center and draw it on a transparent `w`x`h` canvas with its unrotated top-left at (`x`, `y`).
+pub fn place_transformed(cutout: &RgbaImage, w: u32, h: u32, scale: f32, rotate_deg: f32, x: i64, y: i64) -> RgbaImage {
+	let subject = crop_to_alpha(cutout);
+	let sw = ((subject.width() as f32 * scale).round() as u32).max(1);
+	let sh = ((subject.height() as f32 * scale).round() as u32).max(1);
+	let scaled = if (sw, sh) == subject.dimensions() {
+		subject
+	} else {
+		image::imageops::resize(&subject, sw, sh, FilterType::Lanczos3)
+	};
+	let rotated = if rotate_deg % 360.0 == 0.0 { scaled } else { rotate(&scaled, rotate_deg) };
+
+	// Rotation grows the box around the same center.
+	let dx = (rotated.width() as i64 - sw as i64) / 2;
+	let dy = (rotated.height() as i64 - sh as i64) / 2;
+	let mut out = RgbaImage::new(w, h);
+	image::imageops::overlay(&mut out, &rotated, x - dx, y - dy);
+	out
+}
+
+/// Rotate by `deg` clockwise into a box just large enough to hold the result, sampling
+/// bilinearly with premultiplied alpha so edges don't pick up the transparent pixels' color.
+fn rotate(img: &RgbaImage, deg: f32) -> RgbaImage {
+	let (sin, cos) = deg.to_radians().sin_cos();
+	let (w, h) = (img.width() as f32, img.height() as f32);
+	let rw = (w * cos.abs() + h * sin.abs()).ceil().max(1.0);
+	let rh = (w * sin.abs() + h * cos.abs()).ceil().max(1.0);
+	let premul = |x: i64, y: i64| -> [f32; 4] {
+		if x < 0 || y < 0 || x >= img.width() as i64 || y >= img.height() as i64 {
+			return [0.0; 4];
+		}
+		let p = img.get_pixel(x as u32, y as u32).0;
+		let a = p[3] as f32 / 255.0;
+		[p[0] as f32 * a, p[1] as f32 * a, p[2] as f32 * a, p[3] as f32]
+	};
+	RgbaImage::from_fn(rw as u32, rh as u32, |ox, oy| {
+		// Inverse rotation about the centers, to pixel-center coordinates in `img`.
+		let (px, py) = (ox as f32 + 0.5 - rw / 2.0, oy as f32 + 0.5 - rh / 2.0);
+		let sx = cos * px + sin * py + w / 2.0 - 0.5;
+		let sy = -sin * px + cos * py + h / 2.0 - 0.5;
+		let (x0, y0) = (sx.floor(), sy.floor());
+		let (fx, fy) = (sx - x0, sy - y0);
+		let (x0, y0) = (x0 as i64, y0 as i64);
+		let mut acc = [0.0f32; 4];
+		for (x, y, wgt) in [(x0, y0, (1.0 - fx) * (1.0 - fy)), (x0 + 1, y0, fx * (1.0 - fy)), (x0, y0 + 1, (1.0 - fx) * fy), (x0 + 1, y0 + 1, fx * fy)] {
+			let p = premul(x, y);
+			for c in 0..4 {
+				acc[c] += p[c] * wgt;
+			}
+		}
+		let a = acc[3];
+		if a <= 0.0 {
+			return Rgba([0, 0, 0, 0]);
+		}
+		let unpremul = |v: f32| (v * 255.0 / a).round().clamp(0.0, 255.0) as u8;
+		Rgba([unpremul(acc[0]), unpremul(acc[1]), unpremul(acc[2]), a.round().clamp(0.0, 255.0) as u8])
+	})
+}
+
+/// Center `img` on a transparent `w`x`h` canvas (cropping if it is larger).
+pub fn pad_to(img: &RgbaImage, w: u32, h: u32) -> RgbaImage {
+	let mut out = RgbaImage::new(w, h);
+	let x = (w as i64 - img.width() as i64) / 2;
+	let y = (h as i64 - img.height() as i64) / 2;
+	image::imageops::overlay(&mut out, img, x, y);
+	out
+}
+
 fn estimate_bg_rgb(img: &RgbImage) -> (u8, u8, u8) {
 	let w = img.width();
 	let h = img.height();
@@ -93,7 +703,66 @@ fn estimate_bg_rgb(img: &RgbImage) -> (u8, u8, u8) {
 	((sum[0] / n) as u8, (sum[1] / n) as u8, (sum[2] / n) as u8)
 }
 
-fn parse_hex_rgb(s: &str) -> Result<(u8, u8, u8)> {
+/// A `--bgcolor` value: a solid color or a two-stop linear gradient.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fill {
+	Solid((u8, u8, u8)),
+	/// CSS-like angle: 0deg runs bottom to top, 90deg left to right.
+	Linear { from: (u8, u8, u8), to: (u8, u8, u8), angle_deg: f32 }
+}
+
+impl Fill {
+	/// An opaque `w`x`h` canvas painted with this fill.
+	pub fn canvas(&self, w: u32, h: u32) -> RgbaImage {
+		match *self {
+			Fill::Solid((r, g, b)) => RgbaImage::from_pixel(w, h, Rgba([r, g, b, 255])),
+			Fill::Linear { from, to, angle_deg } => {
+				let (sin, cos) = angle_deg.to_radians().sin_cos();
+				// Half the gradient line length, so the end colors land exactly on the corners (as in CSS).
+				let half = ((w as f32 * sin).abs() + (h as f32 * cos).abs()) / 2.0;
+				let lerp = |a: u8, b: u8, t: f32| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+				RgbaImage::from_fn(w, h, |x, y| {
+					let dx = x as f32 + 0.5 - w as f32 / 2.0;
+					let dy = y as f32 + 0.5 - h as f32 / 2.0;
+					let t = if half > 0.0 { ((dx * sin - dy * cos) / half + 1.0) / 2.0 } else { 0.5 };
+					let t = t.clamp(0.0, 1.0);
+					Rgba([lerp(from.0, to.0, t), lerp(from.1, to.1, t), lerp(from.2, to.2, t), 255])
+				})
+			}
+		}
+	}
+}
+
+/// Parse `RRGGBB`/`#RRGGBB` or a gradient `linear:#RRGGBB-#RRGGBB[:ANGLEdeg]` (angle defaults to 180deg, top to bottom).
+pub fn parse_fill(s: &str) -> Result<Fill> {
+	let Some(spec) = s.trim().strip_prefix("linear:") else {
+		return Ok(Fill::Solid(parse_hex_rgb(s)?));
+	};
+	let (colors, angle) = match spec.split_once(':') {
+		Some((c, a)) => (c, Some(a)),
+		None => (spec, None)
+	};
+	let Some((from, to)) = colors.split_once('-') else {
+		bail!("invalid gradient {s:?} (expected linear:#RRGGBB-#RRGGBB[:ANGLEdeg])");
+	};
+	let angle_deg = match angle {
+		Some(a) => {
+			let a = a.trim();
+			a.strip_suffix("deg")
+				.unwrap_or(a)
+				.parse::<f32>()
+				.with_context(|| format!("invalid gradient angle {a:?}"))?
+		}
+		None => 180.0
+	};
+	Ok(Fill::Linear {
+		from: parse_hex_rgb(from)?,
+		to: parse_hex_rgb(to)?,
+		angle_deg
+	})
+}
+
+pub fn parse_hex_rgb(s: &str) -> Result<(u8, u8, u8)> {
 	let s = s.trim();
 	let s = s.strip_prefix('#').unwrap_or(s);
 	if s.len() != 6 {
@@ -0,0 +1,136 @@
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use anyhow::{Context, Result};
+use clap::ArgMatches;
+use clap::parser::ValueSource;
+use serde::Deserialize;
+
+use crate::cli;
+use crate::encode::OutputFormat;
+
+/// Defaults from `config.toml` (`~/.config/rembg-rs/config.toml` on Linux). Command-line flags win
+/// over the file; the `REMBG_*` environment variables win over its mirror settings.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+	pub model: Option<String>,
+	pub device: Option<cli::Device>,
+	pub gpu_backend: Option<cli::GpuBackend>,
+	pub format: Option<OutputFormat>,
+	/// Replaces the per-user cache directory for runtimes and models.
+	pub cache_dir: Option<PathBuf>,
+	/// See `model::MODEL_BASE_URL_ENV`.
+	pub model_base_url: Option<String>,
+	/// See `pypi::INDEX_URL_ENV`.
+	pub pypi_index_url: Option<String>,
+	/// See `runtime::LIB_ENV`.
+	pub onnxruntime_lib: Option<PathBuf>,
+	/// ONNX Runtime release to download and load instead of the newest supported one
+	/// (`--runtime-version`).
+	pub runtime_version: Option<String>
+}
+
+static INSTALLED: OnceLock<Config> = OnceLock::new();
+
+/// Path of the config file (whether or not it exists).
+pub fn path() -> Result<PathBuf> {
+	let dirs = project_dirs()?;
+	Ok(dirs.config_dir().join("config.toml"))
+}
+
+/// Read the config file; a missing file is an empty config.
+pub fn load() -> Result<Config> {
+	let path = path()?;
+	let text = match std::fs::read_to_string(&path) {
+		Ok(text) => text,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
+		Err(e) => return Err(e).with_context(|| format!("read config: {}", path.display()))
+	};
+	toml::from_str(&text).with_context(|| format!("parse config: {}", path.display()))
+}
+
+/// Make `config` the process-wide settings for the cache directory and mirrors. Library users that
+/// never call this get the built-in defaults.
+pub fn install(config: Config) {
+	let _ = INSTALLED.set(config);
+}
+
+pub fn current() -> &'static Config {
+	INSTALLED.get_or_init(Config::default)
+}
+
+/// Root of the runtime/model cache.
+pub fn cache_dir() -> Result<PathBuf> {
+	if let Some(dir) = &current().cache_dir {
+		return Ok(dir.clone());
+	}
+	Ok(project_dirs()?.cache_dir().to_path_buf())
+}
+
+impl Config {
+	/// Fill the options of `args` that weren't given on the command line from the file.
+	pub fn apply(&self, args: &mut cli::Args, matches: &ArgMatches) {
+		let unset = |m: &ArgMatches, id: &str| m.value_source(id) != Some(ValueSource::CommandLine);
+
+		match (&mut args.command, matches.subcommand()) {
+			(
+				Some(
+					cli::Command::Setup {
+						model,
+						device,
+						gpu_backend
+					}
+					| cli::Command::Video {
+						model,
+						device,
+						gpu_backend,
+						..
+					}
+				),
+				Some(("setup" | "video", m))
+			) => {
+				if let Some(v) = &self.model
+					&& unset(m, "model")
+				{
+					*model = v.clone();
+				}
+				if let Some(v) = self.device
+					&& unset(m, "device")
+				{
+					*device = v;
+				}
+				if let Some(v) = self.gpu_backend
+					&& unset(m, "gpu_backend")
+				{
+					*gpu_backend = v;
+				}
+			}
+			_ => {
+				if let Some(v) = &self.model
+					&& unset(matches, "model")
+				{
+					args.model = v.clone();
+				}
+				if let Some(v) = self.device
+					&& unset(matches, "device")
+				{
+					args.device = v;
+				}
+				if let Some(v) = self.gpu_backend
+					&& unset(matches, "gpu_backend")
+				{
+					args.gpu_backend = v;
+				}
+				// An output path's extension still decides over the file's format.
+				if args.format.is_none() && args.output.is_none() {
+					args.format = self.format;
+				}
+			}
+		}
+	}
+}
+
+fn project_dirs() -> Result<directories::ProjectDirs> {
+	directories::ProjectDirs::from("rs", "rembg", "rembg-rs").context("unable to resolve user config/cache directory")
+}
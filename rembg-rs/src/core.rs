@@ -1,10 +1,43 @@
-use std::io::Cursor;
+use std::path::PathBuf;
+#[cfg(feature = "native")]
+use std::sync::{Arc, Mutex, mpsc};
 
-use anyhow::{Context, Result};
-use image::{DynamicImage, GrayImage, ImageFormat};
+use anyhow::{Context, Result, bail};
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, GrayImage};
 use serde::{Deserialize, Serialize};
 
-use crate::{compose, model, runtime, u2net};
+use crate::encode::{self, EncodeOptions, OutputFormat, encode_png};
+use crate::error::RembgError;
+use crate::metadata::{self, ImageMetadata, MetadataPolicy};
+use crate::compose::BgPlacement;
+use crate::compose;
+use crate::mask;
+use crate::sam;
+use crate::u2net::{ClothClass, SessionConfig};
+#[cfg(feature = "native")]
+use crate::cancel::CancellationToken;
+#[cfg(feature = "native")]
+use crate::u2net::{self, Remover};
+#[cfg(feature = "native")]
+use crate::devices::{self, GpuDevice};
+#[cfg(feature = "native")]
+use crate::{model, runtime};
+
+/// Warm sessions for `remove_background_bytes`/`predict_mask_bytes`, keyed by model id and execution
+/// provider. Each session is locked while it runs; distinct models/EPs run concurrently.
+#[cfg(feature = "native")]
+static SESSIONS: Mutex<Vec<CachedSession>> = Mutex::new(Vec::new());
+
+#[cfg(feature = "native")]
+struct CachedSession {
+	model: String,
+	model_path: Option<(PathBuf, u32)>,
+	matting: Option<(PathBuf, u32)>,
+	ep: Option<runtime::PreferredEp>,
+	session: SessionConfig,
+	remover: Arc<Mutex<Remover>>
+}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
@@ -18,59 +51,824 @@ pub enum Device {
 pub enum GpuBackend {
 	Auto,
 	Directml,
-	Cuda
+	Cuda,
+	Coreml,
+	Tensorrt,
+	Openvino
 }
 
+/// Options of one background removal. Outside this crate, start from `RemoveOptions::builder()`
+/// or `RemoveOptions::default()`: the struct is `#[non_exhaustive]`, so new options don't break
+/// callers.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[non_exhaustive]
 pub struct RemoveOptions {
 	pub model: String,
+	/// Local ONNX export to run instead of `model` (see `model::local_model`). Not accepted by the
+	/// HTTP and gRPC servers, which shouldn't open arbitrary files for their clients.
+	#[serde(default)]
+	pub model_path: Option<PathBuf>,
+	/// Square input side of `model_path` (default 320).
+	#[serde(default)]
+	pub model_input_size: Option<u32>,
 	pub device: Device,
 	pub gpu_backend: GpuBackend,
+	/// ONNX Runtime session tuning (threads, graph optimization, memory pattern).
+	#[serde(default)]
+	pub session: SessionConfig,
+	/// Clicks on (or off) the subject, for promptable models (`sam`); other models ignore them.
+	#[serde(default)]
+	pub points: Vec<sam::Point>,
+	/// `[x0, y0, x1, y1]` around the subject, in input pixels; `sam` only.
+	#[serde(default)]
+	pub bbox: Option<[f32; 4]>,
+	/// `u2net_cloth_seg` only: keep this clothing category instead of every garment.
+	#[serde(default)]
+	pub cloth_category: Option<ClothClass>,
+	/// Run the model a second time on a crop around the subject it found, for sharper edges when
+	/// the subject is small in the frame (see `Remover::predict_probabilities_refined`).
+	#[serde(default)]
+	pub refine: bool,
+	/// Snap the upsampled mask's edges to edges in the photo (`mask::guided_filter`).
+	#[serde(default)]
+	pub guided_filter: bool,
+	/// Local matting model (ViTMatte, MODNet, ...) to re-estimate hair and other soft edges with
+	/// (see `Remover::refine_edges`). Like `model_path`, not accepted by the HTTP and gRPC servers.
+	#[serde(default)]
+	pub matting_model: Option<PathBuf>,
+	/// Square input side of `matting_model` (default 512).
+	#[serde(default)]
+	pub matting_input_size: Option<u32>,
 	pub mask_threshold: Option<u8>,
+	/// `RRGGBB`, `#RRGGBB` or a gradient `linear:#RRGGBB-#RRGGBB[:ANGLEdeg]`.
 	pub bgcolor: Option<String>,
+	/// Encoded background image to composite the cutout over; `bgcolor` fills whatever it leaves uncovered.
+	#[serde(default)]
+	pub bg_image: Option<Vec<u8>>,
+	/// How `bg_image` is fitted (cover/contain/stretch/tile), anchored and adjusted.
+	#[serde(default)]
+	pub bg_placement: BgPlacement,
 	/// If set, uses a simple color-key to force alpha=0 for pixels close to the estimated background color.
 	/// Useful for punching "inner background" holes when the model returns a solid silhouette.
 	pub color_key_tolerance: Option<u8>,
+	/// Key out this color (RRGGBB or #RRGGBB) instead of estimating the background from the corners.
+	#[serde(default)]
+	pub color_key_color: Option<String>,
+	/// Remove the key color's cast from semi-transparent edges when color keying.
+	#[serde(default)]
+	pub despill: bool,
 	/// If false, backend returns an error instead of downloading runtime/model.
 	pub allow_download: bool,
 	/// If true, return mask bytes as well.
-	pub include_mask: bool
+	pub include_mask: bool,
+	/// Encoding of `RemoveResult::output_png` (the mask is always PNG).
+	#[serde(default)]
+	pub output_format: OutputFormat,
+	#[serde(default)]
+	pub encode: EncodeOptions,
+	/// Which input metadata (DPI/ICC, EXIF/XMP) is written to the outputs.
+	#[serde(default)]
+	pub metadata: MetadataPolicy,
+	/// Shrink the input so its longer side is at most this many pixels before anything runs, for a
+	/// quick low-res preview; the result (and mask) come out at the shrunk size. Prompts are scaled
+	/// along, so they stay in original-image pixels.
+	#[serde(default)]
+	pub max_dim: Option<u32>
+}
+
+impl Default for RemoveOptions {
+	/// The defaults of the HTTP and gRPC APIs: `u2net` on CPU, downloads allowed.
+	fn default() -> Self {
+		Self {
+			model: "u2net".to_string(),
+			model_path: None,
+			model_input_size: None,
+			device: Device::Cpu,
+			gpu_backend: GpuBackend::Auto,
+			session: SessionConfig::default(),
+			points: Vec::new(),
+			bbox: None,
+			cloth_category: None,
+			refine: false,
+			guided_filter: false,
+			matting_model: None,
+			matting_input_size: None,
+			mask_threshold: None,
+			bgcolor: None,
+			bg_image: None,
+			bg_placement: BgPlacement::default(),
+			color_key_tolerance: None,
+			color_key_color: None,
+			despill: false,
+			allow_download: true,
+			include_mask: false,
+			output_format: OutputFormat::default(),
+			encode: EncodeOptions::default(),
+			metadata: MetadataPolicy::default(),
+			max_dim: None
+		}
+	}
+}
+
+impl RemoveOptions {
+	/// `RemoveOptions::default()`, to adjust with the builder's setters.
+	pub fn builder() -> RemoveOptionsBuilder {
+		RemoveOptionsBuilder::default()
+	}
+
+	/// Overlay a JSON object with any subset of the fields on `RemoveOptions::default()`.
+	pub fn from_partial_json(json: &str) -> Result<RemoveOptions> {
+		let overrides: serde_json::Value = serde_json::from_str(json).context("parse options JSON")?;
+		let serde_json::Value::Object(overrides) = overrides else {
+			bail!("options must be a JSON object");
+		};
+		let mut merged = serde_json::to_value(RemoveOptions::default())?;
+		if let serde_json::Value::Object(base) = &mut merged {
+			base.extend(overrides);
+		}
+		serde_json::from_value(merged).context("invalid options")
+	}
+
+	pub fn prompt(&self) -> sam::Prompt {
+		sam::Prompt {
+			points: self.points.clone(),
+			bbox: self.bbox
+		}
+	}
+}
+
+/// Sets `RemoveOptions` fields one at a time on top of the defaults; see the fields for what each
+/// does. Options that take an `Option` are set by passing the value itself.
+#[derive(Debug, Clone, Default)]
+pub struct RemoveOptionsBuilder(RemoveOptions);
+
+impl RemoveOptionsBuilder {
+	pub fn model(mut self, value: impl Into<String>) -> Self {
+		self.0.model = value.into();
+		self
+	}
+
+	pub fn model_path(mut self, value: impl Into<PathBuf>) -> Self {
+		self.0.model_path = Some(value.into());
+		self
+	}
+
+	pub fn model_input_size(mut self, value: u32) -> Self {
+		self.0.model_input_size = Some(value);
+		self
+	}
+
+	pub fn device(mut self, value: Device) -> Self {
+		self.0.device = value;
+		self
+	}
+
+	pub fn gpu_backend(mut self, value: GpuBackend) -> Self {
+		self.0.gpu_backend = value;
+		self
+	}
+
+	pub fn session(mut self, value: SessionConfig) -> Self {
+		self.0.session = value;
+		self
+	}
+
+	/// Shorthand for `session.device_id`: a GPU `id` from `list_devices`.
+	pub fn device_id(mut self, value: u32) -> Self {
+		self.0.session.device_id = Some(value);
+		self
+	}
+
+	pub fn points(mut self, value: Vec<sam::Point>) -> Self {
+		self.0.points = value;
+		self
+	}
+
+	pub fn bbox(mut self, value: [f32; 4]) -> Self {
+		self.0.bbox = Some(value);
+		self
+	}
+
+	pub fn cloth_category(mut self, value: ClothClass) -> Self {
+		self.0.cloth_category = Some(value);
+		self
+	}
+
+	pub fn refine(mut self, value: bool) -> Self {
+		self.0.refine = value;
+		self
+	}
+
+	pub fn guided_filter(mut self, value: bool) -> Self {
+		self.0.guided_filter = value;
+		self
+	}
+
+	pub fn matting_model(mut self, value: impl Into<PathBuf>) -> Self {
+		self.0.matting_model = Some(value.into());
+		self
+	}
+
+	pub fn matting_input_size(mut self, value: u32) -> Self {
+		self.0.matting_input_size = Some(value);
+		self
+	}
+
+	pub fn mask_threshold(mut self, value: u8) -> Self {
+		self.0.mask_threshold = Some(value);
+		self
+	}
+
+	pub fn bgcolor(mut self, value: impl Into<String>) -> Self {
+		self.0.bgcolor = Some(value.into());
+		self
+	}
+
+	pub fn bg_image(mut self, value: Vec<u8>) -> Self {
+		self.0.bg_image = Some(value);
+		self
+	}
+
+	pub fn bg_placement(mut self, value: BgPlacement) -> Self {
+		self.0.bg_placement = value;
+		self
+	}
+
+	pub fn color_key_tolerance(mut self, value: u8) -> Self {
+		self.0.color_key_tolerance = Some(value);
+		self
+	}
+
+	pub fn color_key_color(mut self, value: impl Into<String>) -> Self {
+		self.0.color_key_color = Some(value.into());
+		self
+	}
+
+	pub fn despill(mut self, value: bool) -> Self {
+		self.0.despill = value;
+		self
+	}
+
+	pub fn allow_download(mut self, value: bool) -> Self {
+		self.0.allow_download = value;
+		self
+	}
+
+	pub fn include_mask(mut self, value: bool) -> Self {
+		self.0.include_mask = value;
+		self
+	}
+
+	pub fn output_format(mut self, value: OutputFormat) -> Self {
+		self.0.output_format = value;
+		self
+	}
+
+	pub fn encode(mut self, value: EncodeOptions) -> Self {
+		self.0.encode = value;
+		self
+	}
+
+	pub fn metadata(mut self, value: MetadataPolicy) -> Self {
+		self.0.metadata = value;
+		self
+	}
+
+	pub fn max_dim(mut self, value: u32) -> Self {
+		self.0.max_dim = Some(value);
+		self
+	}
+
+	pub fn build(self) -> RemoveOptions {
+		self.0
+	}
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RemoveResult {
+	/// Encoded as `RemoveOptions::output_format`; the name predates non-PNG output.
 	pub output_png: Vec<u8>,
-	pub mask_png: Option<Vec<u8>>
+	pub mask_png: Option<Vec<u8>>,
+	#[serde(default)]
+	pub timings: Timings
+}
+
+/// Wall-clock time of each stage of one job, in milliseconds. Stages a call didn't run are 0; the
+/// wasm build has no clock and reports 0 throughout.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Timings {
+	pub decode_ms: u64,
+	/// Ensuring the runtime and model (downloads included) and loading the session; about 0 when a
+	/// warm session is reused.
+	pub runtime_ms: u64,
+	pub inference_ms: u64,
+	/// Applying the mask and any background.
+	pub compose_ms: u64,
+	pub encode_ms: u64
+}
+
+/// Times a stage for `Timings`.
+struct Stopwatch {
+	#[cfg(feature = "native")]
+	start: std::time::Instant
+}
+
+impl Stopwatch {
+	fn start() -> Stopwatch {
+		Stopwatch {
+			#[cfg(feature = "native")]
+			start: std::time::Instant::now()
+		}
+	}
+
+	fn ms(&self) -> u64 {
+		#[cfg(feature = "native")]
+		return self.start.elapsed().as_millis() as u64;
+		#[cfg(not(feature = "native"))]
+		0
+	}
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProgressEvent {
-	pub stage: String,          // "runtime" | "model" | "infer" | "encode"
+	pub stage: String,          // "input" | "runtime" | "model" | "decode" | "infer" | "encode" | "cancelled"
 	pub url: Option<String>,    // for downloads
 	pub downloaded: Option<u64>,
 	pub total: Option<u64>,
 	pub done: Option<bool>,
-	pub message: Option<String>
+	pub message: Option<String>,
+	/// Sub-step within "infer": "load" | "preprocess" | "run" | "postprocess".
+	#[serde(default)]
+	pub step: Option<String>,
+	/// Time spent in the current step so far (sent as a heartbeat while the model runs).
+	#[serde(default)]
+	pub elapsed_ms: Option<u64>,
+	/// When the stage began (Unix time in ms), on the events that open and close "decode", "infer"
+	/// and "encode".
+	#[serde(default)]
+	pub started_at_ms: Option<u64>,
+	/// When the stage ended, on its closing event (the one with `done`).
+	#[serde(default)]
+	pub ended_at_ms: Option<u64>
 }
 
+impl ProgressEvent {
+	pub fn stage(stage: &str) -> ProgressEvent {
+		ProgressEvent {
+			stage: stage.to_string(),
+			url: None,
+			downloaded: None,
+			total: None,
+			done: None,
+			message: None,
+			step: None,
+			elapsed_ms: None,
+			started_at_ms: None,
+			ended_at_ms: None
+		}
+	}
+
+	/// Opens `stage`; `finished` on the returned event closes it.
+	#[cfg(feature = "native")]
+	pub fn started(stage: &str) -> ProgressEvent {
+		ProgressEvent {
+			started_at_ms: Some(unix_ms()),
+			..ProgressEvent::stage(stage)
+		}
+	}
+
+	/// The closing event of the stage this event opened.
+	#[cfg(feature = "native")]
+	pub fn finished(&self) -> ProgressEvent {
+		ProgressEvent {
+			done: Some(true),
+			started_at_ms: self.started_at_ms,
+			ended_at_ms: Some(unix_ms()),
+			..ProgressEvent::stage(&self.stage)
+		}
+	}
+
+	#[cfg(feature = "native")]
+	pub fn infer(p: u2net::InferProgress) -> ProgressEvent {
+		let (step, elapsed) = match p {
+			u2net::InferProgress::LoadSession => ("load", None),
+			u2net::InferProgress::Preprocess => ("preprocess", None),
+			u2net::InferProgress::Running { elapsed } => ("run", Some(elapsed.as_millis() as u64)),
+			u2net::InferProgress::Postprocess => ("postprocess", None)
+		};
+		ProgressEvent {
+			step: Some(step.to_string()),
+			elapsed_ms: elapsed,
+			..ProgressEvent::stage("infer")
+		}
+	}
+
+	#[cfg(feature = "native")]
+	pub fn download(stage: &str, url: &str, progress: crate::download::Progress) -> ProgressEvent {
+		ProgressEvent {
+			url: Some(url.to_string()),
+			downloaded: Some(progress.downloaded),
+			total: progress.total,
+			done: Some(progress.done),
+			..ProgressEvent::stage(stage)
+		}
+	}
+}
+
+/// Cancelling `cancel` aborts the job between stages, mid-download or mid-inference; it then fails
+/// with `cancel::Cancelled`.
+#[cfg(feature = "native")]
 pub fn remove_background_bytes(
 	input_bytes: &[u8],
 	opts: &RemoveOptions,
+	cancel: &CancellationToken,
 	mut on_progress: impl FnMut(ProgressEvent)
 ) -> Result<RemoveResult> {
-	on_progress(ProgressEvent {
-		stage: "decode".to_string(),
-		url: None,
-		downloaded: None,
-		total: None,
-		done: None,
-		message: None
+	let (img, mask, timings) = infer(input_bytes, opts, cancel, &mut on_progress)?;
+	cancel.check()?;
+	timed("encode", &mut on_progress, |_| compose_img(input_bytes, &img, &mask, opts, timings)).map(|(r, _)| r)
+}
+
+/// Progress of a `remove_background_bytes_async` job: a `Stream` of its `ProgressEvent`s that ends
+/// when the job does, then `result()`. Dropping the job cancels it.
+#[cfg(feature = "async")]
+pub struct RemoveJob {
+	events: tokio::sync::mpsc::UnboundedReceiver<ProgressEvent>,
+	result: tokio::sync::oneshot::Receiver<Result<RemoveResult>>,
+	cancel: CancellationToken
+}
+
+#[cfg(feature = "async")]
+impl RemoveJob {
+	/// Wait for the job to finish; progress events not read yet are skipped.
+	pub async fn result(mut self) -> Result<RemoveResult> {
+		match (&mut self.result).await {
+			Ok(r) => r,
+			Err(_) => bail!("remove job panicked")
+		}
+	}
+
+	pub fn cancel(&self) {
+		self.cancel.cancel();
+	}
+}
+
+#[cfg(feature = "async")]
+impl tokio_stream::Stream for RemoveJob {
+	type Item = ProgressEvent;
+
+	fn poll_next(
+		mut self: std::pin::Pin<&mut Self>,
+		cx: &mut std::task::Context<'_>
+	) -> std::task::Poll<Option<ProgressEvent>> {
+		self.events.poll_recv(cx)
+	}
+}
+
+#[cfg(feature = "async")]
+impl Drop for RemoveJob {
+	fn drop(&mut self) {
+		self.cancel.cancel();
+	}
+}
+
+/// `remove_background_bytes` on Tokio's blocking pool (downloads included: they use a blocking
+/// HTTP client), so async servers don't stall their executor. Must be called within a Tokio runtime.
+#[cfg(feature = "async")]
+pub fn remove_background_bytes_async(input_bytes: Vec<u8>, opts: RemoveOptions) -> RemoveJob {
+	let (tx, events) = tokio::sync::mpsc::unbounded_channel();
+	let (done, result) = tokio::sync::oneshot::channel();
+	let cancel = CancellationToken::new();
+	let job_cancel = cancel.clone();
+	tokio::task::spawn_blocking(move || {
+		let r = remove_background_bytes(&input_bytes, &opts, &job_cancel, |evt| {
+			let _ = tx.send(evt);
+		});
+		let _ = done.send(r);
 	});
+	RemoveJob { events, result, cancel }
+}
 
-	let img = image::load_from_memory(input_bytes).context("decode input image")?;
-	let rgb = img.to_rgb8();
+/// Prepare the runtime and model once, for `remove_background_with` calls over many images.
+#[cfg(feature = "native")]
+pub fn load_remover(
+	opts: &RemoveOptions,
+	cancel: &CancellationToken,
+	mut on_progress: impl FnMut(ProgressEvent)
+) -> Result<Remover> {
+	load(opts, cancel, &mut on_progress)
+}
+
+/// `remove_background_bytes` with an already loaded model; `opts.model`/`device` are not consulted.
+#[cfg(feature = "native")]
+pub fn remove_background_with(
+	remover: &mut Remover,
+	input_bytes: &[u8],
+	opts: &RemoveOptions,
+	cancel: &CancellationToken,
+	mut on_progress: impl FnMut(ProgressEvent)
+) -> Result<RemoveResult> {
+	cancel.check()?;
+	let ((img, scale), decode_ms) = timed("decode", &mut on_progress, |_| decode_input(input_bytes, opts))?;
+	let (mask, inference_ms) = timed("infer", &mut on_progress, |emit| predict(remover, &img, scale, opts, cancel, emit))?;
+	cancel.check()?;
+	let timings = Timings {
+		decode_ms,
+		inference_ms,
+		..Timings::default()
+	};
+	timed("encode", &mut on_progress, |_| compose_img(input_bytes, &img, &mask, opts, timings)).map(|(r, _)| r)
+}
 
-	let plan = runtime::plan_noninteractive(
+/// A GPU backend `RemoveOptions::gpu_backend` can pick on this platform.
+#[cfg(feature = "native")]
+#[derive(Debug, Clone, Serialize)]
+pub struct ProviderInfo {
+	pub backend: GpuBackend,
+	/// Its ONNX Runtime package is downloaded already.
+	pub cached: bool,
+	/// Whether the EP is built into the runtime that would run it. In-process that's the runtime
+	/// already loaded, which can't be swapped without a restart; `None` until a run loads one. With
+	/// the inference worker it's the backend's own runtime, asked of a worker; `None` until that
+	/// runtime is downloaded, or while every worker is busy.
+	pub available: Option<bool>
+}
+
+/// What a device picker can offer, from `list_devices`.
+#[cfg(feature = "native")]
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceList {
+	pub providers: Vec<ProviderInfo>,
+	pub directml_adapters: Vec<GpuDevice>,
+	pub cuda_devices: Vec<GpuDevice>
+}
+
+/// The GPU backends this platform supports and the GPUs the system reports. Nothing is downloaded;
+/// with the inference worker, workers load the cached runtimes to check their EPs.
+#[cfg(feature = "native")]
+pub fn list_devices() -> Result<DeviceList> {
+	let backends = [GpuBackend::Directml, GpuBackend::Cuda, GpuBackend::Tensorrt, GpuBackend::Coreml, GpuBackend::Openvino];
+	let mut providers = Vec::new();
+	for backend in backends {
+		let Ok(plan) = plan(&gpu_options(backend)) else {
+			continue;
+		};
+		let ep = plan.ep.expect("GPU plans name an EP");
+		let cached = runtime::has_any_cached_runtime(plan.runtime_package)?;
+		let available = if crate::worker::enabled() {
+			if cached { crate::worker::ep_available(backend, plan.runtime_package)? } else { None }
+		} else if runtime::ort_loaded() {
+			Some(u2net::ep_available(ep)?)
+		} else {
+			None
+		};
+		providers.push(ProviderInfo { backend, cached, available });
+	}
+	Ok(DeviceList {
+		providers,
+		directml_adapters: devices::directml_adapters(),
+		cuda_devices: devices::cuda_devices()
+	})
+}
+
+/// Load the runtime `backend` runs on, if it's cached, and ask it whether the EP is built in; a
+/// runtime that can't be loaded has none. For `worker::serve`.
+#[cfg(feature = "native")]
+pub(crate) fn probe_ep(backend: GpuBackend) -> Result<bool> {
+	let plan = plan(&gpu_options(backend))?;
+	let rt = runtime::ensure_onnxruntime_noninteractive(&plan, &CancellationToken::new(), |_| {})?;
+	if runtime::init_ort(&rt).is_err() {
+		return Ok(false);
+	}
+	u2net::ep_available(plan.ep.expect("GPU plans name an EP"))
+}
+
+/// Options that plan `backend` without allowing a download.
+#[cfg(feature = "native")]
+fn gpu_options(backend: GpuBackend) -> RemoveOptions {
+	RemoveOptions {
+		device: Device::Gpu,
+		gpu_backend: backend,
+		allow_download: false,
+		..RemoveOptions::default()
+	}
+}
+
+/// Drop every cached session, releasing the models' memory (and GPU resources).
+#[cfg(feature = "native")]
+pub fn clear_session_cache() {
+	SESSIONS.lock().unwrap().clear();
+	crate::worker::stop();
+}
+
+/// Drop the cached sessions of `model` (any execution provider), e.g. after re-downloading it.
+#[cfg(feature = "native")]
+pub fn evict_session(model: &str) {
+	SESSIONS.lock().unwrap().retain(|s| s.model != model);
+	// The worker's sessions can't be picked out one by one.
+	crate::worker::stop();
+}
+
+/// Run the model only and return the soft mask at input resolution, for callers that keep the
+/// mask around and re-composite it later (see `compose_result`). The timings cover decode,
+/// runtime and inference.
+#[cfg(feature = "native")]
+pub fn predict_mask_bytes(
+	input_bytes: &[u8],
+	opts: &RemoveOptions,
+	cancel: &CancellationToken,
+	mut on_progress: impl FnMut(ProgressEvent)
+) -> Result<(GrayImage, Timings)> {
+	let (_, mask, timings) = infer(input_bytes, opts, cancel, &mut on_progress)?;
+	Ok((mask, timings))
+}
+
+/// Composite and encode from an existing mask; no runtime or model is needed, so this is cheap
+/// enough to call on every option tweak or mask edit.
+pub fn compose_result(input_bytes: &[u8], mask: &GrayImage, opts: &RemoveOptions) -> Result<RemoveResult> {
+	let clock = Stopwatch::start();
+	let (img, _) = decode_input(input_bytes, opts)?;
+	let timings = Timings {
+		decode_ms: clock.ms(),
+		..Timings::default()
+	};
+	if mask.dimensions() != img.dimensions() {
+		bail!(
+			"mask is {}x{} but the image is {}x{}",
+			mask.width(),
+			mask.height(),
+			img.width(),
+			img.height()
+		);
+	}
+	compose_img(input_bytes, &img, mask, opts, timings)
+}
+
+/// `compose_result` from an encoded mask, e.g. the `mask_png` of an earlier result (requested with
+/// `include_mask`), so a caller that keeps no state can change the threshold, background or
+/// color key without running the model again. A mask saved with `mask_threshold` set is already
+/// hard-edged; leave it unset on the first run to keep the soft edges.
+pub fn recomposite(mask_png: &[u8], original_bytes: &[u8], opts: &RemoveOptions) -> Result<RemoveResult> {
+	let mask = decode(mask_png, "mask")?.into_luma8();
+	compose_result(original_bytes, &mask, opts)
+}
+
+/// Whether a `BrushStroke` paints the subject back in or erases it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BrushMode {
+	Add,
+	Remove
+}
+
+/// One manual touch-up of a mask, in mask pixels.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BrushStroke {
+	pub mode: BrushMode,
+	/// The path the brush center followed, as `[x, y]`; a single point is one dab.
+	pub points: Vec<[f32; 2]>,
+	pub radius: f32,
+	/// 0 to 1: the share of `radius` painted at full strength; the rest fades out. 1 is a hard brush.
+	pub hardness: f32
+}
+
+/// `mask` with `strokes` painted over it in order, e.g. edits from a GUI brush; pass the result to
+/// `compose_result` to re-composite. A stroke changes pixels in proportion to its strength, so a
+/// soft brush blends into the soft edges the model left.
+pub fn apply_mask_edits(mask: &GrayImage, strokes: &[BrushStroke]) -> GrayImage {
+	let mut out = mask.clone();
+	for s in strokes {
+		mask::paint_stroke(&mut out, &s.points, s.radius, s.hardness, s.mode == BrushMode::Add);
+	}
+	out
+}
+
+fn decode(bytes: &[u8], what: &'static str) -> Result<DynamicImage> {
+	crate::decode::load_from_memory(bytes).map_err(|source| RembgError::Decode { what, source }.into())
+}
+
+/// The input image, shrunk to `opts.max_dim` if it's larger, and the factor it was shrunk by.
+fn decode_input(input_bytes: &[u8], opts: &RemoveOptions) -> Result<(DynamicImage, f32)> {
+	let img = decode(input_bytes, "input image")?;
+	match opts.max_dim {
+		Some(max) if max > 0 && img.width().max(img.height()) > max => {
+			let small = img.resize(max, max, FilterType::Triangle);
+			let scale = small.width() as f32 / img.width() as f32;
+			Ok((small, scale))
+		}
+		_ => Ok((img, 1.0))
+	}
+}
+
+#[cfg(feature = "native")]
+fn infer<F: FnMut(ProgressEvent)>(
+	input_bytes: &[u8],
+	opts: &RemoveOptions,
+	cancel: &CancellationToken,
+	on_progress: &mut F
+) -> Result<(DynamicImage, GrayImage, Timings)> {
+	cancel.check()?;
+	if crate::worker::enabled() {
+		// Decoded here as well for compositing (first, so decode errors keep their code); the
+		// worker's events cover its own decode, the runtime and inference.
+		let (img, _) = decode_input(input_bytes, opts)?;
+		let (mask, timings) = crate::worker::predict_mask(input_bytes, opts, cancel, on_progress)?;
+		return Ok((img, mask, timings));
+	}
+	let ((img, scale), decode_ms) = timed("decode", on_progress, |_| decode_input(input_bytes, opts))?;
+	let clock = Stopwatch::start();
+	let remover = cached_remover(opts, cancel, on_progress)?;
+	let runtime_ms = clock.ms();
+	cancel.check()?;
+	let (mask, inference_ms) = timed("infer", on_progress, |emit| {
+		predict(&mut remover.lock().unwrap(), &img, scale, opts, cancel, emit)
+	})?;
+	let timings = Timings {
+		decode_ms,
+		runtime_ms,
+		inference_ms,
+		..Timings::default()
+	};
+	Ok((img, mask, timings))
+}
+
+/// Run `f` as `stage`: open it, time it, and close it with a `done` event (not on failure).
+#[cfg(feature = "native")]
+fn timed<T, F: FnMut(ProgressEvent)>(stage: &str, on_progress: &mut F, f: impl FnOnce(&mut F) -> Result<T>) -> Result<(T, u64)> {
+	let start = ProgressEvent::started(stage);
+	on_progress(start.clone());
+	let clock = Stopwatch::start();
+	let value = f(on_progress)?;
+	let ms = clock.ms();
+	on_progress(start.finished());
+	Ok((value, ms))
+}
+
+#[cfg(feature = "native")]
+fn unix_ms() -> u64 {
+	std::time::SystemTime::now()
+		.duration_since(std::time::UNIX_EPOCH)
+		.map_or(0, |d| d.as_millis() as u64)
+}
+
+/// The warm session for `opts.model` on the planned EP, loading it on first use.
+#[cfg(feature = "native")]
+fn cached_remover(
+	opts: &RemoveOptions,
+	cancel: &CancellationToken,
+	on_progress: &mut impl FnMut(ProgressEvent)
+) -> Result<Arc<Mutex<Remover>>> {
+	let ep = plan(opts)?.ep;
+	let lookup = |sessions: &[CachedSession]| {
+		sessions
+			.iter()
+			.find(|s| {
+				s.model == opts.model
+					&& s.model_path == local_model(opts)
+					&& s.matting == matting_model(opts)
+					&& s.ep == ep
+					&& s.session == opts.session
+			})
+			.map(|s| s.remover.clone())
+	};
+	if let Some(r) = lookup(&SESSIONS.lock().unwrap()) {
+		return Ok(r);
+	}
+
+	// Load without holding the cache lock; if another call won the race, keep its session.
+	let remover = Arc::new(Mutex::new(load(opts, cancel, on_progress)?));
+	let mut sessions = SESSIONS.lock().unwrap();
+	if let Some(r) = lookup(&sessions) {
+		return Ok(r);
+	}
+	sessions.push(CachedSession {
+		model: opts.model.clone(),
+		model_path: local_model(opts),
+		matting: matting_model(opts),
+		ep,
+		session: opts.session,
+		remover: remover.clone()
+	});
+	Ok(remover)
+}
+
+/// `opts.model_path` with its input size, when a local model replaces `opts.model`.
+#[cfg(feature = "native")]
+fn local_model(opts: &RemoveOptions) -> Option<(PathBuf, u32)> {
+	let path = opts.model_path.clone()?;
+	Some((path, opts.model_input_size.unwrap_or(model::LOCAL_INPUT_SIZE)))
+}
+
+/// `opts.matting_model` with its input size.
+#[cfg(feature = "native")]
+fn matting_model(opts: &RemoveOptions) -> Option<(PathBuf, u32)> {
+	let path = opts.matting_model.clone()?;
+	Some((path, opts.matting_input_size.unwrap_or(model::MATTING_INPUT_SIZE)))
+}
+
+#[cfg(feature = "native")]
+pub(crate) fn plan(opts: &RemoveOptions) -> Result<runtime::Plan> {
+	runtime::plan_noninteractive(
 		match opts.device {
 			Device::Cpu => crate::cli::Device::Cpu,
 			Device::Gpu => crate::cli::Device::Gpu
@@ -78,94 +876,172 @@ pub fn remove_background_bytes(
 		match opts.gpu_backend {
 			GpuBackend::Auto => crate::cli::GpuBackend::Auto,
 			GpuBackend::Directml => crate::cli::GpuBackend::Directml,
-			GpuBackend::Cuda => crate::cli::GpuBackend::Cuda
+			GpuBackend::Cuda => crate::cli::GpuBackend::Cuda,
+			GpuBackend::Coreml => crate::cli::GpuBackend::Coreml,
+			GpuBackend::Tensorrt => crate::cli::GpuBackend::Tensorrt,
+			GpuBackend::Openvino => crate::cli::GpuBackend::Openvino
 		},
 		opts.allow_download
-	)?;
-
-	on_progress(ProgressEvent {
-		stage: "runtime".to_string(),
-		url: None,
-		downloaded: None,
-		total: None,
-		done: None,
-		message: Some(format!("Ensure ONNX Runtime ({})", plan.runtime_package))
-	});
+	)
+}
 
-	let rt = runtime::ensure_onnxruntime_noninteractive(&plan, |p| {
-		on_progress(ProgressEvent {
-			stage: "runtime".to_string(),
-			url: Some(p.url.to_string()),
-			downloaded: Some(p.progress.downloaded),
-			total: p.progress.total,
-			done: Some(p.progress.done),
-			message: None
-		});
-	})?;
+#[cfg(feature = "native")]
+fn load(opts: &RemoveOptions, cancel: &CancellationToken, on_progress: &mut impl FnMut(ProgressEvent)) -> Result<Remover> {
+	let plan = plan(opts)?;
+	let (rt, model_install) = match local_model(opts) {
+		Some((path, input_size)) => {
+			on_progress(runtime_started(&plan));
+			let rt = runtime::ensure_onnxruntime_noninteractive(&plan, cancel, |p| {
+				on_progress(ProgressEvent::download("runtime", p.url, p.progress));
+			})?;
+			(rt, model::local_model(&path, input_size)?)
+		}
+		None => prefetch(&plan, &opts.model, cancel, &mut *on_progress)?
+	};
 	runtime::init_ort(&rt)?;
+	cancel.check()?;
 
-	on_progress(ProgressEvent {
-		stage: "model".to_string(),
-		url: None,
-		downloaded: None,
-		total: None,
-		done: None,
-		message: Some(format!("Ensure model ({})", opts.model))
-	});
+	on_progress(ProgressEvent::infer(u2net::InferProgress::LoadSession));
+	let mut remover = Remover::from_install(&model_install, plan.ep, &opts.session)
+		.with_context(|| format!("load model: {}", model_install.path.display()))?;
+	if let Some((path, input_size)) = matting_model(opts) {
+		remover
+			.load_matting(&path, input_size, plan.ep, &opts.session)
+			.with_context(|| format!("load matting model: {}", path.display()))?;
+	}
+	Ok(remover)
+}
 
-	let model_install = model::ensure_model_noninteractive(&opts.model, opts.allow_download, |p| {
-		on_progress(ProgressEvent {
-			stage: "model".to_string(),
-			url: Some(p.url.to_string()),
-			downloaded: Some(p.progress.downloaded),
-			total: p.progress.total,
-			done: Some(p.progress.done),
-			message: None
+/// Ensure the runtime for `plan` and model `model`, downloading both at the same time; their
+/// "runtime" and "model" progress events arrive interleaved. Does not load either.
+#[cfg(feature = "native")]
+pub fn prefetch(
+	plan: &runtime::Plan,
+	model: &str,
+	cancel: &CancellationToken,
+	mut on_progress: impl FnMut(ProgressEvent)
+) -> Result<(runtime::OnnxRuntimeInstall, model::ModelInstall)> {
+	let (tx, rx) = mpsc::channel();
+	let (rt, model_install) = std::thread::scope(|scope| {
+		let runtime_tx = tx.clone();
+		let runtime_job = scope.spawn(move || {
+			let _ = runtime_tx.send(runtime_started(plan));
+			runtime::ensure_onnxruntime_noninteractive(plan, cancel, |p| {
+				let _ = runtime_tx.send(ProgressEvent::download("runtime", p.url, p.progress));
+			})
 		});
-	})?;
-
-	on_progress(ProgressEvent {
-		stage: "infer".to_string(),
-		url: None,
-		downloaded: None,
-		total: None,
-		done: None,
-		message: None
+		let model_tx = tx;
+		let model_job = scope.spawn(move || {
+			let _ = model_tx.send(ProgressEvent {
+				message: Some(format!("Ensure model ({model})")),
+				..ProgressEvent::stage("model")
+			});
+			model::ensure_model_noninteractive(model, plan.allow_download, true, cancel, |p| {
+				let _ = model_tx.send(ProgressEvent::download("model", p.url, p.progress));
+			})
+		});
+		// Ends once both jobs have dropped their senders.
+		for evt in rx {
+			on_progress(evt);
+		}
+		(
+			runtime_job.join().expect("runtime download panicked"),
+			model_job.join().expect("model download panicked")
+		)
 	});
+	Ok((rt?, model_install?))
+}
+
+#[cfg(feature = "native")]
+fn runtime_started(plan: &runtime::Plan) -> ProgressEvent {
+	ProgressEvent {
+		message: Some(format!("Ensure ONNX Runtime ({})", plan.runtime_package)),
+		..ProgressEvent::stage("runtime")
+	}
+}
 
-	let mask = u2net::predict_mask(&model_install.path, model_install.input_size, &rgb, plan.ep)
-		.with_context(|| format!("run model: {}", model_install.path.display()))?;
+#[cfg(feature = "native")]
+fn predict(
+	remover: &mut Remover,
+	img: &DynamicImage,
+	scale: f32,
+	opts: &RemoveOptions,
+	cancel: &CancellationToken,
+	on_progress: &mut impl FnMut(ProgressEvent)
+) -> Result<GrayImage> {
+	let img = img.to_rgb8();
+	// Prompts are in original-image pixels; `img` may have been shrunk by `max_dim`.
+	let mut prompt = opts.prompt();
+	for p in &mut prompt.points {
+		p.x *= scale;
+		p.y *= scale;
+	}
+	if let Some(bbox) = &mut prompt.bbox {
+		bbox.iter_mut().for_each(|v| *v *= scale);
+	}
+	let mut on_progress = |p| on_progress(ProgressEvent::infer(p));
+	let probs = match opts.cloth_category {
+		Some(class) => remover
+			.predict_classes(&img, &[class], cancel, &mut on_progress)
+			.map(|mut p| p.remove(0)),
+		None if opts.refine => remover.predict_probabilities_refined(&img, &prompt, cancel, &mut on_progress),
+		None => remover.predict_probabilities_prompted(&img, &prompt, cancel, &mut on_progress)
+	}
+	.context("run model")?;
+	let mut probs = remover
+		.refine_edges(&img, probs, cancel, on_progress)
+		.context("run matting model")?;
+	if opts.guided_filter {
+		probs = mask::guided_filter(&probs, &img);
+	}
+	Ok(u2net::quantize_mask(&probs))
+}
 
-	let out_img: DynamicImage = if let Some(bg) = opts.bgcolor.as_deref() {
-		compose::composite_over_bg(&rgb, &mask, opts.mask_threshold, bg)?
+/// Composite and encode; `timings` holds the earlier stages and gets compose and encode added.
+fn compose_img(
+	input_bytes: &[u8],
+	img: &DynamicImage,
+	mask: &GrayImage,
+	opts: &RemoveOptions,
+	mut timings: Timings
+) -> Result<RemoveResult> {
+	let clock = Stopwatch::start();
+	let out_img: DynamicImage = if let Some(bg_bytes) = opts.bg_image.as_deref() {
+		let bg = decode(bg_bytes, "background image")?.to_rgb8();
+		let fill = opts.bgcolor.as_deref().map(compose::parse_fill).transpose()?;
+		let fg = compose::apply_alpha(&img.to_rgb8(), mask, opts.mask_threshold, None).into_rgba8();
+		let canvas = compose::place_background(&bg, fg.width(), fg.height(), &opts.bg_placement, fill.as_ref());
+		DynamicImage::ImageRgba8(compose::flatten(&fg, &canvas))
+	} else if let Some(bg) = opts.bgcolor.as_deref() {
+		compose::composite_over_bg(&img.to_rgb8(), mask, opts.mask_threshold, bg)?
 	} else {
-		compose::apply_alpha(&rgb, &mask, opts.mask_threshold, opts.color_key_tolerance)
+		let rgb = img.to_rgb8();
+		let color = opts.color_key_color.as_deref().map(compose::parse_hex_rgb).transpose().context("color_key_color")?;
+		let key = compose::ColorKey::new(&rgb, opts.color_key_tolerance, color, opts.despill);
+		if compose::is_high_bit_depth(img) {
+			compose::apply_alpha_16(&img.to_rgb16(), mask, opts.mask_threshold, key)
+		} else {
+			compose::apply_alpha(&rgb, mask, opts.mask_threshold, key)
+		}
 	};
 
-	on_progress(ProgressEvent {
-		stage: "encode".to_string(),
-		url: None,
-		downloaded: None,
-		total: None,
-		done: None,
-		message: None
-	});
+	timings.compose_ms = clock.ms();
 
-	let output_png = encode_png(&out_img)?;
+	let clock = Stopwatch::start();
+	let meta = ImageMetadata::read_with(input_bytes, opts.metadata);
+	let output_png = encode::encode(&out_img, opts.output_format, &opts.encode, &meta)?;
 	let mask_png = if opts.include_mask {
-		Some(encode_mask_png(&mask, opts.mask_threshold)?)
+		Some(metadata::embed_png(&encode_mask_png(mask, opts.mask_threshold)?, &meta)?)
 	} else {
 		None
 	};
+	timings.encode_ms = clock.ms();
 
-	Ok(RemoveResult { output_png, mask_png })
-}
-
-fn encode_png(img: &DynamicImage) -> Result<Vec<u8>> {
-	let mut buf = Vec::new();
-	let mut cur = Cursor::new(&mut buf);
-	img.write_to(&mut cur, ImageFormat::Png).context("encode png")?;
-	Ok(buf)
+	Ok(RemoveResult {
+		output_png,
+		mask_png,
+		timings
+	})
 }
 
 fn encode_mask_png(mask: &GrayImage, threshold: Option<u8>) -> Result<Vec<u8>> {
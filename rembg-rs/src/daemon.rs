@@ -0,0 +1,162 @@
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+use anyhow::{Context, Result, bail};
+use interprocess::local_socket::prelude::*;
+use interprocess::local_socket::{GenericFilePath, GenericNamespaced, ListenerOptions, Name, Stream};
+use serde::{Deserialize, Serialize};
+
+use crate::cancel::CancellationToken;
+use crate::core::{self, RemoveOptions};
+
+/// Frames above this size are rejected instead of allocated.
+pub const MAX_FRAME_LEN: u32 = 256 * 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DaemonRequest {
+	pub options: RemoveOptions
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DaemonResponse {
+	pub ok: bool,
+	#[serde(default)]
+	pub error: Option<String>,
+	/// A mask PNG frame follows the output frame.
+	#[serde(default)]
+	pub mask: bool
+}
+
+/// `$XDG_RUNTIME_DIR/rembg-rs.sock` (else in the temp dir) on Unix, the pipe `\\.\pipe\rembg-rs` on Windows.
+pub fn default_socket() -> String {
+	if cfg!(windows) {
+		return "rembg-rs".to_string();
+	}
+	let dir = std::env::var_os("XDG_RUNTIME_DIR")
+		.map(PathBuf::from)
+		.unwrap_or_else(std::env::temp_dir);
+	dir.join("rembg-rs.sock").display().to_string()
+}
+
+/// Serve `core::remove_background_bytes` on `socket` until the process is killed, so the runtime and
+/// models stay loaded between jobs. Each connection gets its own thread and can carry any number of jobs.
+///
+/// Every message is a frame: a 4-byte big-endian length, then that many bytes. A job is a JSON
+/// `DaemonRequest` frame followed by a frame with the encoded input image. The reply is a JSON
+/// `DaemonResponse` frame; on success it is followed by the output frame and, if `mask` is set, a
+/// mask PNG frame.
+pub fn serve(socket: &str) -> Result<()> {
+	let listener = bind(socket)?;
+	eprintln!("rembg-rs daemon listening on {socket}");
+	for conn in listener.incoming() {
+		let conn = match conn {
+			Ok(c) => c,
+			Err(e) => {
+				eprintln!("accept failed: {e}");
+				continue;
+			}
+		};
+		std::thread::spawn(move || {
+			if let Err(e) = handle(conn) {
+				eprintln!("connection closed: {e:#}");
+			}
+		});
+	}
+	Ok(())
+}
+
+fn socket_name(socket: &str) -> io::Result<Name<'_>> {
+	if cfg!(windows) {
+		socket.to_ns_name::<GenericNamespaced>()
+	} else {
+		socket.to_fs_name::<GenericFilePath>()
+	}
+}
+
+fn bind(socket: &str) -> Result<LocalSocketListener> {
+	let listen = || ListenerOptions::new().name(socket_name(socket)?).create_sync();
+	match listen() {
+		Ok(l) => Ok(l),
+		// A socket file left behind by a daemon that didn't exit cleanly: nobody answers on it.
+		Err(e) if e.kind() == io::ErrorKind::AddrInUse && !cfg!(windows) => {
+			if Stream::connect(socket_name(socket)?).is_ok() {
+				bail!("another daemon is already listening on {socket}");
+			}
+			std::fs::remove_file(socket).with_context(|| format!("remove stale socket: {socket}"))?;
+			listen().with_context(|| format!("listen on {socket}"))
+		}
+		Err(e) => Err(e).with_context(|| format!("listen on {socket}"))
+	}
+}
+
+fn handle(mut conn: Stream) -> Result<()> {
+	// A clean EOF between jobs is the client hanging up.
+	while let Some(header) = read_frame_opt(&mut conn)? {
+		let input = read_frame(&mut conn)?;
+		let result = serde_json::from_slice::<DaemonRequest>(&header)
+			.context("parse request")
+			.and_then(|req| core::remove_background_bytes(&input, &req.options, &CancellationToken::new(), |_| {}));
+		match result {
+			Ok(r) => {
+				write_json(&mut conn, &DaemonResponse {
+					ok: true,
+					error: None,
+					mask: r.mask_png.is_some()
+				})?;
+				write_frame(&mut conn, &r.output_png)?;
+				if let Some(mask) = &r.mask_png {
+					write_frame(&mut conn, mask)?;
+				}
+			}
+			Err(e) => write_json(&mut conn, &DaemonResponse {
+				ok: false,
+				error: Some(format!("{e:#}")),
+				mask: false
+			})?
+		}
+		conn.flush()?;
+	}
+	Ok(())
+}
+
+/// Read one length-prefixed frame.
+pub fn read_frame(r: &mut impl Read) -> io::Result<Vec<u8>> {
+	read_frame_opt(r)?.ok_or_else(|| io::ErrorKind::UnexpectedEof.into())
+}
+
+/// Write one length-prefixed frame.
+pub fn write_frame(w: &mut impl Write, payload: &[u8]) -> io::Result<()> {
+	let len = u32::try_from(payload.len())
+		.ok()
+		.filter(|&n| n <= MAX_FRAME_LEN)
+		.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "frame too large"))?;
+	w.write_all(&len.to_be_bytes())?;
+	w.write_all(payload)
+}
+
+pub(crate) fn write_json(w: &mut impl Write, value: &impl Serialize) -> Result<()> {
+	write_frame(w, &serde_json::to_vec(value)?)?;
+	Ok(())
+}
+
+/// `None` on EOF before the first length byte.
+pub(crate) fn read_frame_opt(r: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+	let mut len = [0u8; 4];
+	let mut filled = 0;
+	while filled < len.len() {
+		match r.read(&mut len[filled..]) {
+			Ok(0) if filled == 0 => return Ok(None),
+			Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+			Ok(n) => filled += n,
+			Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+			Err(e) => return Err(e)
+		}
+	}
+	let len = u32::from_be_bytes(len);
+	if len > MAX_FRAME_LEN {
+		return Err(io::Error::new(io::ErrorKind::InvalidData, format!("frame of {len} bytes exceeds the limit")));
+	}
+	let mut buf = vec![0u8; len as usize];
+	r.read_exact(&mut buf)?;
+	Ok(Some(buf))
+}
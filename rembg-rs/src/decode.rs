@@ -0,0 +1,47 @@
+use image::error::ImageFormatHint;
+use image::{DynamicImage, ImageError, ImageResult};
+
+/// Decode an image from memory: every format `image` reads, plus JPEG XL (with the `jxl` feature).
+pub fn load_from_memory(bytes: &[u8]) -> ImageResult<DynamicImage> {
+	if is_jxl(bytes) {
+		return decode_jxl(bytes);
+	}
+	image::load_from_memory(bytes)
+}
+
+/// Whether `bytes` start like a JPEG XL codestream or container.
+pub fn is_jxl(bytes: &[u8]) -> bool {
+	bytes.starts_with(&[0xff, 0x0a]) || bytes.starts_with(b"\0\0\0\x0cJXL \r\n\x87\n")
+}
+
+/// A `.jxl` path, when this build can decode JPEG XL (for picking inputs out of directories).
+pub fn is_jxl_path(path: &std::path::Path) -> bool {
+	cfg!(feature = "jxl") && path.extension().is_some_and(|e| e.eq_ignore_ascii_case("jxl"))
+}
+
+/// Alpha comes through as an RGBA (or gray + alpha) image.
+#[cfg(feature = "jxl")]
+fn decode_jxl(bytes: &[u8]) -> ImageResult<DynamicImage> {
+	use jpegxl_rs::decoder_builder;
+	use jpegxl_rs::image::ToDynamic;
+
+	let decoder = decoder_builder().build().map_err(jxl_error)?;
+	decoder
+		.decode_to_image(bytes)
+		.map_err(jxl_error)?
+		.ok_or_else(|| jxl_error("unsupported JPEG XL pixel format"))
+}
+
+#[cfg(not(feature = "jxl"))]
+fn decode_jxl(_bytes: &[u8]) -> ImageResult<DynamicImage> {
+	use image::error::{UnsupportedError, UnsupportedErrorKind};
+
+	// "The image format `JPEG XL (rebuild with --features jxl)` is not supported".
+	let format = ImageFormatHint::Name("JPEG XL (rebuild with --features jxl)".to_string());
+	Err(ImageError::Unsupported(UnsupportedError::from_format_and_kind(format.clone(), UnsupportedErrorKind::Format(format))))
+}
+
+#[cfg(feature = "jxl")]
+fn jxl_error(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> ImageError {
+	ImageError::Decoding(image::error::DecodingError::new(ImageFormatHint::Name("JPEG XL".to_string()), err))
+}
@@ -0,0 +1,81 @@
+use std::process::Command;
+
+use serde::Serialize;
+
+/// A GPU as the system tools report it.
+#[derive(Debug, Clone, Serialize)]
+pub struct GpuDevice {
+	/// Position in the tool's list; see the functions below for how it maps to EP device ids.
+	pub id: u32,
+	pub name: String,
+	pub memory_mb: Option<u64>,
+	/// `id` is inferred from the listing order rather than read from the EP's own enumeration, so
+	/// it may name a different adapter.
+	pub id_guessed: bool
+}
+
+/// NVIDIA GPUs from `nvidia-smi`, in PCI bus order. CUDA numbers devices the same way only when
+/// `CUDA_DEVICE_ORDER=PCI_BUS_ID` (its default puts the fastest first), so otherwise every `id`
+/// here is `id_guessed`. Empty without a driver.
+pub fn cuda_devices() -> Vec<GpuDevice> {
+	let id_guessed = !std::env::var("CUDA_DEVICE_ORDER").is_ok_and(|order| order == "PCI_BUS_ID");
+	let Some(text) = run(Command::new("nvidia-smi").args([
+		"--query-gpu=index,name,memory.total",
+		"--format=csv,noheader,nounits"
+	])) else {
+		return Vec::new();
+	};
+	text.lines()
+		.filter_map(|line| {
+			let mut fields = line.split(',').map(str::trim);
+			Some(GpuDevice {
+				id: fields.next()?.parse().ok()?,
+				name: fields.next()?.to_string(),
+				memory_mb: fields.next().and_then(|m| m.parse().ok()),
+				id_guessed
+			})
+		})
+		.collect()
+}
+
+/// Display adapters DirectML can run on, in the order WMI lists them. DirectML numbers adapters in
+/// DXGI order, which WMI usually but not always matches, so every `id` here is `id_guessed`.
+/// Software renderers are left out; empty on other systems.
+pub fn directml_adapters() -> Vec<GpuDevice> {
+	if !cfg!(windows) {
+		return Vec::new();
+	}
+	let Some(text) = run(Command::new("powershell").args([
+		"-NoProfile",
+		"-NonInteractive",
+		"-Command",
+		"Get-CimInstance Win32_VideoController | ForEach-Object { $_.Name }"
+	])) else {
+		return Vec::new();
+	};
+	text.lines()
+		.map(str::trim)
+		.filter(|name| !name.is_empty() && !name.contains("Basic Render"))
+		.enumerate()
+		.map(|(i, name)| GpuDevice {
+			id: i as u32,
+			name: name.to_string(),
+			// Win32_VideoController caps AdapterRAM at 4 GiB, so it's no use here.
+			memory_mb: None,
+			id_guessed: true
+		})
+		.collect()
+}
+
+/// Stdout of a successful run; `None` if the tool is missing or fails.
+fn run(cmd: &mut Command) -> Option<String> {
+	#[cfg(windows)]
+	{
+		use std::os::windows::process::CommandExt;
+		// No console window flashing up when the GUI lists devices.
+		const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+		cmd.creation_flags(CREATE_NO_WINDOW);
+	}
+	let out = cmd.output().ok()?;
+	out.status.success().then(|| String::from_utf8_lossy(&out.stdout).into_owned())
+}
@@ -1,14 +1,16 @@
-use std::{
-	fs,
-	fs::File,
-	io::{Read, Write},
-	path::Path,
-	time::Instant
-};
+use std::io::Write;
+#[cfg(feature = "download")]
+use std::{fs, fs::File, io::Read, path::Path, time::Instant};
 
+#[cfg(feature = "download")]
 use anyhow::{Context, Result, bail};
+#[cfg(feature = "download")]
 use sha2::Digest as _;
 
+#[cfg(feature = "download")]
+use crate::cancel::CancellationToken;
+
+#[cfg(feature = "download")]
 pub struct Digests {
 	pub sha256_hex: Option<String>,
 	pub md5_hex: Option<String>
@@ -22,19 +24,51 @@ pub struct Progress {
 	pub done: bool
 }
 
+/// Seconds between progress lines when stderr isn't a terminal (logs, CI).
+const LOG_PROGRESS_INTERVAL: f64 = 5.0;
+
+#[cfg(feature = "download")]
 pub fn download_to_path(url: &str, dst: &Path, digests: Digests) -> Result<()> {
-	download_to_path_with_progress(url, dst, digests, |p| {
+	let mut human = HumanProgress::default();
+	download_to_path_with_progress(url, dst, digests, &CancellationToken::new(), |p| human.report(url, p))
+}
+
+/// The CLI's download display on stderr: a `\r`-redrawn line on a terminal, otherwise a line every
+/// few seconds. One value can report several downloads in a row.
+#[derive(Debug, Default)]
+pub struct HumanProgress {
+	url: String,
+	next_log: f64
+}
+
+impl HumanProgress {
+	pub fn report(&mut self, url: &str, p: Progress) {
+		if self.url != url {
+			self.url = url.to_string();
+			self.next_log = 0.0;
+		}
+		if !crate::term::live_progress() {
+			if p.done || p.secs >= self.next_log {
+				eprintln!("{}", progress_line(url, p.downloaded, p.total, p.secs));
+				self.next_log = p.secs + LOG_PROGRESS_INTERVAL;
+			}
+			return;
+		}
 		print_progress(url, p.downloaded, p.total, p.secs);
 		if p.done {
 			eprintln!();
 		}
-	})
+	}
 }
 
+/// Download `url` to `dst` (via a `.part` file), verifying the digests; `cancel` is checked
+/// between chunks.
+#[cfg(feature = "download")]
 pub fn download_to_path_with_progress(
 	url: &str,
 	dst: &Path,
 	digests: Digests,
+	cancel: &CancellationToken,
 	mut on_progress: impl FnMut(Progress)
 ) -> Result<()> {
 	if let Some(parent) = dst.parent() {
@@ -71,6 +105,11 @@ pub fn download_to_path_with_progress(
 	let mut last = Instant::now();
 
 	loop {
+		if cancel.is_cancelled() {
+			drop(file);
+			let _ = fs::remove_file(&tmp);
+			return Err(crate::cancel::Cancelled.into());
+		}
 		let n = reader.read(&mut buf).context("read response body")?;
 		if n == 0 {
 			break;
@@ -122,21 +161,24 @@ pub fn download_to_path_with_progress(
 }
 
 fn print_progress(url: &str, downloaded: u64, total: Option<u64>, secs: f64) {
+	eprint!("\r{}", progress_line(url, downloaded, total, secs));
+	let _ = std::io::stderr().flush();
+}
+
+fn progress_line(url: &str, downloaded: u64, total: Option<u64>, secs: f64) -> String {
 	let mb = |b: u64| (b as f64) / (1024.0 * 1024.0);
 	let speed = if secs > 0.0 { mb(downloaded) / secs } else { 0.0 };
 
 	match total {
 		Some(t) if t > 0 => {
 			let pct = (downloaded as f64) * 100.0 / (t as f64);
-			eprint!("\rDownloading {:.1}/{:.1} MiB ({:.0}%) {:.1} MiB/s  {}", mb(downloaded), mb(t), pct, speed, url);
-		}
-		_ => {
-			eprint!("\rDownloading {:.1} MiB {:.1} MiB/s  {}", mb(downloaded), speed, url);
+			format!("Downloading {:.1}/{:.1} MiB ({:.0}%) {:.1} MiB/s  {}", mb(downloaded), mb(t), pct, speed, url)
 		}
+		_ => format!("Downloading {:.1} MiB {:.1} MiB/s  {}", mb(downloaded), speed, url)
 	}
-	let _ = std::io::stderr().flush();
 }
 
+#[cfg(feature = "download")]
 fn eq_hex(a: &str, b: &str) -> bool {
 	a.trim().trim_start_matches("0x").eq_ignore_ascii_case(b.trim().trim_start_matches("0x"))
 }
@@ -0,0 +1,438 @@
+use std::{
+	fs::File,
+	io::{Cursor, Seek, Write},
+	path::Path
+};
+
+use anyhow::{Context, Result, bail};
+use clap::ValueEnum;
+use image::codecs::ico::{IcoEncoder, IcoFrame};
+use image::imageops::FilterType;
+use image::{DynamicImage, ExtendedColorType, ImageFormat, RgbaImage};
+use serde::{Deserialize, Serialize};
+use tiff::encoder::{Compression, DeflateLevel, Rational, TiffEncoder, TiffValue, colortype};
+use tiff::tags::{ResolutionUnit, Tag};
+
+use crate::compose;
+use crate::metadata::{self, ImageMetadata};
+use crate::u2net::ProbabilityMap;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+	/// PNG with alpha (16-bit when the source is).
+	#[default]
+	Png,
+	/// Animated PNG for animated (GIF/WebP) inputs; a still input gives a plain PNG.
+	Apng,
+	/// WebP with alpha; lossy unless `webp_lossless` is set.
+	Webp,
+	/// JPEG. No alpha: transparent areas are flattened onto white.
+	#[value(alias = "jpg")]
+	#[serde(alias = "jpg")]
+	Jpeg,
+	/// TIFF with unassociated alpha (Deflate-compressed, 16-bit when the source is).
+	#[value(alias = "tif")]
+	#[serde(alias = "tif")]
+	Tiff,
+	/// Windows icon with 16/32/48/256px frames, cropped to the subject and centered on a square.
+	Ico,
+	/// JPEG XL with alpha (requires the `jxl` cargo feature).
+	Jxl,
+	/// Single-channel 32-bit float OpenEXR. Mask output only.
+	Exr,
+	/// The mask's outline traced into an SVG path (laser cutting, CSS `clip-path`). Mask output only.
+	Svg
+}
+
+impl OutputFormat {
+	pub fn extension(self) -> &'static str {
+		match self {
+			OutputFormat::Png | OutputFormat::Apng => "png",
+			OutputFormat::Webp => "webp",
+			OutputFormat::Jpeg => "jpg",
+			OutputFormat::Tiff => "tiff",
+			OutputFormat::Ico => "ico",
+			OutputFormat::Jxl => "jxl",
+			OutputFormat::Exr => "exr",
+			OutputFormat::Svg => "svg"
+		}
+	}
+
+	pub fn mime_type(self) -> &'static str {
+		match self {
+			OutputFormat::Png => "image/png",
+			OutputFormat::Apng => "image/apng",
+			OutputFormat::Webp => "image/webp",
+			OutputFormat::Jpeg => "image/jpeg",
+			OutputFormat::Tiff => "image/tiff",
+			OutputFormat::Ico => "image/x-icon",
+			OutputFormat::Jxl => "image/jxl",
+			OutputFormat::Exr => "image/x-exr",
+			OutputFormat::Svg => "image/svg+xml"
+		}
+	}
+
+	/// Guess the format from a file extension (`None` if unknown or missing).
+	pub fn from_path(path: &Path) -> Option<OutputFormat> {
+		let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+		match ext.as_str() {
+			"png" => Some(OutputFormat::Png),
+			"apng" => Some(OutputFormat::Apng),
+			"webp" => Some(OutputFormat::Webp),
+			"jpg" | "jpeg" => Some(OutputFormat::Jpeg),
+			"tif" | "tiff" => Some(OutputFormat::Tiff),
+			"ico" => Some(OutputFormat::Ico),
+			"jxl" => Some(OutputFormat::Jxl),
+			"exr" => Some(OutputFormat::Exr),
+			"svg" => Some(OutputFormat::Svg),
+			_ => None
+		}
+	}
+
+	pub fn supports_cutout(self) -> bool {
+		!matches!(self, OutputFormat::Exr | OutputFormat::Svg)
+	}
+}
+
+/// Per-format encoder settings. Fields only apply to their own format.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EncodeOptions {
+	/// JPEG XL: store pixels bit-exact instead of lossy.
+	pub jxl_lossless: bool,
+	/// JPEG XL: Butteraugli distance for lossy mode (0.1-15, 1.0 is visually lossless).
+	pub jxl_distance: f32,
+	/// PNG: run an oxipng pass at this preset (0-6, higher is slower/smaller).
+	pub png_optimize: Option<u8>,
+	/// PNG: use Zopfli for the optimization pass (much slower, a few % smaller).
+	pub png_zopfli: bool,
+	/// JPEG: quality 1-100.
+	pub jpeg_quality: u8,
+	/// WebP: encode losslessly.
+	pub webp_lossless: bool,
+	/// WebP: lossy quality 0-100.
+	pub webp_quality: f32
+}
+
+impl Default for EncodeOptions {
+	fn default() -> Self {
+		Self {
+			jxl_lossless: false,
+			jxl_distance: 1.0,
+			png_optimize: None,
+			png_zopfli: false,
+			jpeg_quality: 90,
+			webp_lossless: false,
+			webp_quality: 90.0
+		}
+	}
+}
+
+/// Encode a finished cutout/mask in `format`, carrying over metadata where the format can hold it.
+pub fn encode(img: &DynamicImage, format: OutputFormat, opts: &EncodeOptions, meta: &ImageMetadata) -> Result<Vec<u8>> {
+	match format {
+		OutputFormat::Png | OutputFormat::Apng => encode_png_with(img, opts, meta),
+		OutputFormat::Webp => encode_webp(img, opts, meta),
+		OutputFormat::Jpeg => encode_jpeg(img, opts, meta),
+		OutputFormat::Tiff => encode_tiff(img, meta),
+		OutputFormat::Ico => encode_ico(img),
+		OutputFormat::Jxl => encode_jxl(img, opts),
+		OutputFormat::Exr => bail!("exr output is only supported for masks"),
+		OutputFormat::Svg => bail!("svg output is only supported for masks")
+	}
+}
+
+pub fn encode_png(img: &DynamicImage) -> Result<Vec<u8>> {
+	let mut buf = Vec::new();
+	let mut cur = Cursor::new(&mut buf);
+	img.write_to(&mut cur, ImageFormat::Png).context("encode png")?;
+	Ok(buf)
+}
+
+/// Encode as PNG, run the oxipng pass if requested, then attach carried-over metadata.
+pub fn encode_png_with(img: &DynamicImage, opts: &EncodeOptions, meta: &ImageMetadata) -> Result<Vec<u8>> {
+	let mut png = encode_png(img)?;
+	if let Some(level) = opts.png_optimize {
+		png = optimize_png(&png, level, opts.png_zopfli)?;
+	}
+	metadata::embed_png(&png, meta)
+}
+
+/// Rows per band in `write_png_strips`.
+const STRIP_ROWS: u32 = 64;
+
+/// Stream an 8-bit RGBA PNG with `meta` to `out` a band of rows at a time; `band(y, rows)` returns
+/// the pixels of rows `y..y + rows`. Only one band is ever in memory, not the image or the
+/// encoded file.
+pub fn write_png_strips(
+	out: impl Write,
+	width: u32,
+	height: u32,
+	meta: &ImageMetadata,
+	mut band: impl FnMut(u32, u32) -> RgbaImage
+) -> Result<()> {
+	let mut encoder = png::Encoder::new(out, width, height);
+	encoder.set_color(png::ColorType::Rgba);
+	encoder.set_depth(png::BitDepth::Eight);
+	let mut writer = encoder.write_header().context("encode png")?;
+	for (kind, data) in metadata::png_metadata_chunks(meta, true)? {
+		writer.write_chunk(png::chunk::ChunkType(kind), &data).context("encode png")?;
+	}
+	let mut stream = writer.stream_writer().context("encode png")?;
+	let mut y = 0;
+	while y < height {
+		let rows = STRIP_ROWS.min(height - y);
+		stream.write_all(band(y, rows).as_raw()).context("encode png")?;
+		y += rows;
+	}
+	stream.finish().context("encode png")
+}
+
+/// Lossless re-compression: filter search + deflate tuning. Ancillary chunks (ICC, pHYs, EXIF) are kept.
+#[cfg(feature = "native")]
+pub fn optimize_png(png: &[u8], level: u8, zopfli: bool) -> Result<Vec<u8>> {
+	if level > 6 {
+		bail!("invalid png optimization level {level} (expected 0-6)");
+	}
+	let mut o = oxipng::Options::from_preset(level);
+	if zopfli {
+		o.deflater = oxipng::Deflater::Zopfli(oxipng::ZopfliOptions::default());
+	}
+	oxipng::optimize_from_memory(png, &o).map_err(|e| anyhow::anyhow!("optimize png: {e}"))
+}
+
+#[cfg(not(feature = "native"))]
+pub fn optimize_png(_png: &[u8], _level: u8, _zopfli: bool) -> Result<Vec<u8>> {
+	bail!("PNG optimization is not available in this build (needs the `native` feature)")
+}
+
+#[cfg(feature = "native")]
+pub fn encode_webp(img: &DynamicImage, opts: &EncodeOptions, meta: &ImageMetadata) -> Result<Vec<u8>> {
+	if !(0.0..=100.0).contains(&opts.webp_quality) {
+		bail!("invalid webp quality {} (expected 0-100)", opts.webp_quality);
+	}
+	let rgba = img.to_rgba8();
+	let enc = webp::Encoder::from_rgba(rgba.as_raw(), rgba.width(), rgba.height());
+	let mem = enc
+		.encode_simple(opts.webp_lossless, opts.webp_quality)
+		.map_err(|e| anyhow::anyhow!("encode webp: {e:?}"))?;
+	metadata::embed_webp(&mem, meta)
+}
+
+#[cfg(not(feature = "native"))]
+pub fn encode_webp(_img: &DynamicImage, _opts: &EncodeOptions, _meta: &ImageMetadata) -> Result<Vec<u8>> {
+	bail!("WebP output is not available in this build (needs the `native` feature)")
+}
+
+/// JPEG has no alpha channel, so anything transparent is flattened onto white first.
+pub fn encode_jpeg(img: &DynamicImage, opts: &EncodeOptions, meta: &ImageMetadata) -> Result<Vec<u8>> {
+	use image::ImageEncoder;
+	use image::codecs::jpeg::{JpegEncoder, PixelDensity, PixelDensityUnit};
+
+	if !(1..=100).contains(&opts.jpeg_quality) {
+		bail!("invalid jpeg quality {} (expected 1-100)", opts.jpeg_quality);
+	}
+	let flat = match img {
+		DynamicImage::ImageLuma8(_) | DynamicImage::ImageRgb8(_) => img.clone(),
+		other => DynamicImage::ImageRgb8(compose::flatten_onto_color(&other.to_rgba8(), (255, 255, 255)))
+	};
+
+	let mut buf = Vec::new();
+	let mut enc = JpegEncoder::new_with_quality(&mut buf, opts.jpeg_quality);
+	if let Some(d) = meta.dpi {
+		let dpi = |v: f64| v.round().clamp(1.0, u16::MAX as f64) as u16;
+		enc.set_pixel_density(PixelDensity {
+			density: (dpi(d.x), dpi(d.y)),
+			unit: PixelDensityUnit::Inches
+		});
+	}
+	if let Some(icc) = meta.icc.clone()
+		&& flat.color().has_color()
+	{
+		enc.set_icc_profile(icc).context("attach icc profile")?;
+	}
+	if let Some(exif) = meta.exif.clone() {
+		enc.set_exif_metadata(exif).context("attach exif")?;
+	}
+	flat.write_with_encoder(enc).context("encode jpeg")?;
+	metadata::embed_jpeg(&buf, meta)
+}
+
+/// Single-page TIFF, same page layout as `TiffStackWriter`.
+pub fn encode_tiff(img: &DynamicImage, meta: &ImageMetadata) -> Result<Vec<u8>> {
+	let mut cur = Cursor::new(Vec::new());
+	let mut enc = TiffEncoder::new(&mut cur)
+		.context("create tiff encoder")?
+		.with_compression(Compression::Deflate(DeflateLevel::Balanced));
+	write_tiff_page(&mut enc, img, meta)?;
+	Ok(cur.into_inner())
+}
+
+/// Sizes written into `.ico` files; 256 is stored as PNG, which every Windows version since Vista reads.
+pub const ICO_SIZES: [u32; 4] = [16, 32, 48, 256];
+
+pub fn encode_ico(img: &DynamicImage) -> Result<Vec<u8>> {
+	let rgba = compose::crop_to_alpha(&img.to_rgba8());
+	let side = rgba.width().max(rgba.height()).max(1);
+	let square = compose::pad_to(&rgba, side, side);
+
+	let resized: Vec<RgbaImage> = ICO_SIZES
+		.iter()
+		.map(|&s| image::imageops::resize(&square, s, s, FilterType::Lanczos3))
+		.collect();
+	let frames = resized
+		.iter()
+		.map(|f| IcoFrame::as_png(f.as_raw(), f.width(), f.height(), ExtendedColorType::Rgba8))
+		.collect::<Result<Vec<_>, _>>()
+		.context("encode ico frame")?;
+
+	let mut buf = Vec::new();
+	IcoEncoder::new(&mut buf).encode_images(&frames).context("encode ico")?;
+	Ok(buf)
+}
+
+#[cfg(feature = "jxl")]
+pub fn encode_jxl(img: &DynamicImage, opts: &EncodeOptions) -> Result<Vec<u8>> {
+	use jpegxl_rs::encode::{EncoderFrame, EncoderResult, encoder_builder};
+
+	if !(0.0..=15.0).contains(&opts.jxl_distance) {
+		bail!("invalid jxl distance {} (expected 0-15)", opts.jxl_distance);
+	}
+
+	let rgba = img.to_rgba8();
+	let mut enc = encoder_builder()
+		.has_alpha(true)
+		.lossless(opts.jxl_lossless)
+		.uses_original_profile(opts.jxl_lossless)
+		.quality(opts.jxl_distance)
+		.build()
+		.context("create jxl encoder")?;
+	let frame = EncoderFrame::new(rgba.as_raw()).num_channels(4);
+	let res: EncoderResult<u8> = enc
+		.encode_frame(&frame, rgba.width(), rgba.height())
+		.context("encode jxl")?;
+	Ok(res.data)
+}
+
+#[cfg(not(feature = "jxl"))]
+pub fn encode_jxl(_img: &DynamicImage, _opts: &EncodeOptions) -> Result<Vec<u8>> {
+	bail!("JPEG XL support is not compiled in (rebuild with `--features jxl`)")
+}
+
+/// Streams processed images into one multi-page TIFF (one IFD per image), for DAM/prepress
+/// systems that would rather ingest a single file than thousands of loose PNGs.
+pub struct TiffStackWriter {
+	// Unbuffered: strips are written whole, and every page is complete on disk once `push` returns.
+	enc: TiffEncoder<File>,
+	pages: usize
+}
+
+impl TiffStackWriter {
+	pub fn create(path: &Path) -> Result<TiffStackWriter> {
+		let f = File::create(path).with_context(|| format!("create file: {}", path.display()))?;
+		let enc = TiffEncoder::new(f)
+			.context("create tiff encoder")?
+			.with_compression(Compression::Deflate(DeflateLevel::Balanced));
+		Ok(TiffStackWriter { enc, pages: 0 })
+	}
+
+	pub fn push(&mut self, img: &DynamicImage, meta: &ImageMetadata) -> Result<()> {
+		write_tiff_page(&mut self.enc, img, meta)?;
+		self.pages += 1;
+		Ok(())
+	}
+
+	pub fn pages(&self) -> usize {
+		self.pages
+	}
+}
+
+/// Append `img` as a new TIFF directory. Gray/RGB and 16-bit RGBA stay as-is; anything else
+/// becomes 8-bit RGBA with unassociated alpha.
+fn write_tiff_page<W: Write + Seek>(enc: &mut TiffEncoder<W>, img: &DynamicImage, meta: &ImageMetadata) -> Result<()> {
+	match img {
+		DynamicImage::ImageLuma8(g) => {
+			let meta = ImageMetadata { icc: None, ..meta.clone() };
+			tiff_page::<_, colortype::Gray8>(enc, g.width(), g.height(), g.as_raw(), &meta, false)
+		}
+		DynamicImage::ImageRgb8(rgb) => tiff_page::<_, colortype::RGB8>(enc, rgb.width(), rgb.height(), rgb.as_raw(), meta, false),
+		DynamicImage::ImageRgba16(rgba) => {
+			tiff_page::<_, colortype::RGBA16>(enc, rgba.width(), rgba.height(), rgba.as_raw(), meta, true)
+		}
+		other => {
+			let rgba = other.to_rgba8();
+			tiff_page::<_, colortype::RGBA8>(enc, rgba.width(), rgba.height(), rgba.as_raw(), meta, true)
+		}
+	}
+}
+
+fn tiff_page<W: Write + Seek, C: colortype::ColorType>(
+	enc: &mut TiffEncoder<W>,
+	w: u32,
+	h: u32,
+	data: &[C::Inner],
+	meta: &ImageMetadata,
+	alpha: bool
+) -> Result<()>
+where
+	[C::Inner]: TiffValue
+{
+	let mut page = enc.new_image::<C>(w, h).context("start tiff page")?;
+	if alpha {
+		// ExtraSamples = 2: unassociated (straight) alpha.
+		page.encoder()
+			.write_tag(Tag::ExtraSamples, &[2u16][..])
+			.context("write tiff alpha tag")?;
+	}
+	if let Some(icc) = meta.icc.as_deref() {
+		page.encoder()
+			.write_tag(Tag::IccProfile, icc)
+			.context("write tiff icc profile")?;
+	}
+	if let Some(xmp) = meta.xmp.as_deref() {
+		// XMLPacket (700); EXIF would need its own sub-IFD, so TIFF output doesn't carry it.
+		page.encoder()
+			.write_tag(Tag::Unknown(700), xmp)
+			.context("write tiff xmp packet")?;
+	}
+	if let Some(d) = meta.dpi {
+		let r = |v: f64| Rational {
+			n: (v * 100.0).round().clamp(1.0, u32::MAX as f64) as u32,
+			d: 100
+		};
+		page.resolution_unit(ResolutionUnit::Inch);
+		page.x_resolution(r(d.x));
+		page.y_resolution(r(d.y));
+	}
+	page.write_data(data).context("write tiff page")?;
+	Ok(())
+}
+
+/// Encode a probability map as a single-channel (`Y`) float32 OpenEXR, the layout compositors
+/// (Nuke/Fusion) expect for a matte.
+pub fn encode_exr_mask(probs: &ProbabilityMap) -> Result<Vec<u8>> {
+	use exr::prelude::*;
+
+	let (w, h) = (probs.width() as usize, probs.height() as usize);
+	if w == 0 || h == 0 {
+		bail!("cannot encode empty mask as exr");
+	}
+
+	let channels = SpecificChannels::build()
+		.with_channel("Y")
+		.with_pixel_fn(|pos: Vec2<usize>| (probs.get_pixel(pos.x() as u32, pos.y() as u32).0[0],));
+	let img = Image::from_channels((w, h), channels);
+
+	let mut cur = Cursor::new(Vec::new());
+	img.write().to_buffered(&mut cur).context("encode exr")?;
+	Ok(cur.into_inner())
+}
+
+/// Encode a probability map as a 16-bit grayscale PNG, for tools that don't read EXR.
+pub fn encode_png16_mask(probs: &ProbabilityMap) -> Result<Vec<u8>> {
+	let img = image::ImageBuffer::from_fn(probs.width(), probs.height(), |x, y| {
+		image::Luma([(probs.get_pixel(x, y).0[0].clamp(0.0, 1.0) * 65535.0).round() as u16])
+	});
+	encode_png(&DynamicImage::ImageLuma16(img))
+}
@@ -0,0 +1,61 @@
+use serde::Serialize;
+
+use crate::cancel::Cancelled;
+
+/// Failures callers may want to handle differently from a generic error. The API returns them
+/// inside `anyhow::Error` (wrapped in context); `RembgError::find` digs them out.
+#[derive(Debug, thiserror::Error)]
+pub enum RembgError {
+	/// A runtime package or model isn't cached and `allow_download` is off.
+	#[error("download required: {what} ({url})")]
+	DownloadRequired { what: String, url: String },
+	#[error("unsupported model: {name} (supported: {supported})")]
+	UnsupportedModel { name: String, supported: String },
+	/// The input (or background, or mask) bytes aren't an image `image` can read.
+	#[error("decode {what}")]
+	Decode {
+		what: &'static str,
+		#[source]
+		source: image::ImageError
+	}
+}
+
+impl RembgError {
+	/// Stable identifier for frontends and bindings; the message may change, this doesn't.
+	pub fn code(&self) -> &'static str {
+		match self {
+			RembgError::DownloadRequired { .. } => "download_required",
+			RembgError::UnsupportedModel { .. } => "unsupported_model",
+			RembgError::Decode { .. } => "decode_failed"
+		}
+	}
+
+	/// The `RembgError` somewhere in `err`'s chain (as the cause or as context), if any.
+	pub fn find(err: &anyhow::Error) -> Option<&RembgError> {
+		err.downcast_ref().or_else(|| err.chain().find_map(|e| e.downcast_ref()))
+	}
+}
+
+/// An error as a frontend receives it, e.g. from a Tauri command.
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorInfo {
+	pub code: &'static str,
+	pub message: String
+}
+
+impl From<anyhow::Error> for ErrorInfo {
+	fn from(err: anyhow::Error) -> Self {
+		ErrorInfo {
+			code: code(&err),
+			message: format!("{err:#}")
+		}
+	}
+}
+
+/// `err`'s stable code: a `RembgError` code, `"cancelled"`, or `"other"`.
+pub fn code(err: &anyhow::Error) -> &'static str {
+	if err.chain().any(|e| e.is::<Cancelled>()) {
+		return "cancelled";
+	}
+	RembgError::find(err).map_or("other", RembgError::code)
+}
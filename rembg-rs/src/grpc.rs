@@ -0,0 +1,188 @@
+use anyhow::Result;
+
+#[cfg(feature = "grpc")]
+pub mod proto {
+	tonic::include_proto!("rembg");
+}
+
+/// Serve the `rembg.Rembg` gRPC service (see `proto/rembg.proto`) on `host:port` until killed.
+#[cfg(feature = "grpc")]
+pub fn serve(host: &str, port: u16) -> Result<()> {
+	use std::net::ToSocketAddrs;
+
+	use anyhow::Context;
+
+	let addr = (host, port)
+		.to_socket_addrs()
+		.with_context(|| format!("resolve {host}:{port}"))?
+		.next()
+		.with_context(|| format!("resolve {host}:{port}"))?;
+	let rt = tokio::runtime::Builder::new_multi_thread()
+		.enable_all()
+		.build()
+		.context("start tokio runtime")?;
+	let service = proto::rembg_server::RembgServer::new(service::Service)
+		.max_decoding_message_size(MAX_MESSAGE_LEN)
+		.max_encoding_message_size(MAX_MESSAGE_LEN);
+
+	eprintln!("rembg-rs gRPC listening on {addr}");
+	rt.block_on(tonic::transport::Server::builder().add_service(service).serve(addr))
+		.context("gRPC server")
+}
+
+#[cfg(not(feature = "grpc"))]
+pub fn serve(_host: &str, _port: u16) -> Result<()> {
+	anyhow::bail!("gRPC support is not compiled in (rebuild with `--features grpc`)")
+}
+
+/// Requests carry whole images, well past tonic's 4 MiB default.
+#[cfg(feature = "grpc")]
+const MAX_MESSAGE_LEN: usize = 64 * 1024 * 1024;
+
+#[cfg(feature = "grpc")]
+mod service {
+	use tokio_stream::StreamExt;
+	use tokio_stream::wrappers::ReceiverStream;
+	use tonic::{Request, Response, Status};
+
+	use super::proto::{self, remove_reply::Event};
+	use crate::compose::BgPlacement;
+	use crate::core::{self, Device, GpuBackend, ProgressEvent, RemoveOptions};
+	use crate::encode::{EncodeOptions, OutputFormat};
+	use crate::metadata::MetadataPolicy;
+	use crate::sam;
+	use crate::u2net::ClothClass;
+
+	pub struct Service;
+
+	#[tonic::async_trait]
+	impl proto::rembg_server::Rembg for Service {
+		type RemoveStream = ReceiverStream<Result<proto::RemoveReply, Status>>;
+
+		async fn remove(&self, request: Request<proto::RemoveRequest>) -> Result<Response<Self::RemoveStream>, Status> {
+			let req = request.into_inner();
+			let opts = remove_options(req.options.unwrap_or_default());
+			let (tx, rx) = tokio::sync::mpsc::channel(64);
+
+			let mut job = core::remove_background_bytes_async(req.image, opts);
+			tokio::spawn(async move {
+				while let Some(evt) = job.next().await {
+					// The stream is dropped when the client goes away; dropping the job stops it too.
+					if tx.send(Ok(reply(Event::Progress(progress(evt))))).await.is_err() {
+						return;
+					}
+				}
+				let last = match job.result().await {
+					Ok(r) => Ok(reply(Event::Result(proto::RemoveResult {
+						output: r.output_png,
+						mask_png: r.mask_png,
+						timings: Some(proto::Timings {
+							decode_ms: r.timings.decode_ms,
+							runtime_ms: r.timings.runtime_ms,
+							inference_ms: r.timings.inference_ms,
+							compose_ms: r.timings.compose_ms,
+							encode_ms: r.timings.encode_ms
+						})
+					}))),
+					Err(e) => Err(Status::internal(format!("{e:#}")))
+				};
+				let _ = tx.send(last).await;
+			});
+
+			Ok(Response::new(ReceiverStream::new(rx)))
+		}
+	}
+
+	fn reply(event: Event) -> proto::RemoveReply {
+		proto::RemoveReply { event: Some(event) }
+	}
+
+	fn progress(evt: ProgressEvent) -> proto::Progress {
+		proto::Progress {
+			stage: evt.stage,
+			step: evt.step,
+			url: evt.url,
+			downloaded: evt.downloaded,
+			total: evt.total,
+			done: evt.done,
+			message: evt.message,
+			elapsed_ms: evt.elapsed_ms,
+			started_at_ms: evt.started_at_ms,
+			ended_at_ms: evt.ended_at_ms
+		}
+	}
+
+	fn remove_options(o: proto::RemoveOptions) -> RemoveOptions {
+		let defaults = RemoveOptions::default();
+		let encode_defaults = EncodeOptions::default();
+		RemoveOptions {
+			model: if o.model.is_empty() { defaults.model } else { o.model.clone() },
+			// Clients don't get to point the server at files on its disk.
+			model_path: None,
+			model_input_size: None,
+			device: match o.device() {
+				proto::Device::Cpu => Device::Cpu,
+				proto::Device::Gpu => Device::Gpu
+			},
+			gpu_backend: match o.gpu_backend() {
+				proto::GpuBackend::Auto => GpuBackend::Auto,
+				proto::GpuBackend::Directml => GpuBackend::Directml,
+				proto::GpuBackend::Cuda => GpuBackend::Cuda,
+				proto::GpuBackend::Coreml => GpuBackend::Coreml,
+				proto::GpuBackend::Tensorrt => GpuBackend::Tensorrt,
+				proto::GpuBackend::Openvino => GpuBackend::Openvino
+			},
+			// Session tuning is the server operator's business, not the client's.
+			session: defaults.session,
+			points: o
+				.points
+				.iter()
+				.map(|p| sam::Point {
+					x: p.x,
+					y: p.y,
+					foreground: !p.exclude
+				})
+				.collect(),
+			bbox: o.bbox.map(|b| [b.x0, b.y0, b.x1, b.y1]),
+			cloth_category: match o.cloth_category() {
+				proto::ClothCategory::Any => None,
+				proto::ClothCategory::Upper => Some(ClothClass::Upper),
+				proto::ClothCategory::Lower => Some(ClothClass::Lower),
+				proto::ClothCategory::Full => Some(ClothClass::Full)
+			},
+			refine: o.refine,
+			guided_filter: o.guided_filter,
+			matting_model: None,
+			matting_input_size: None,
+			mask_threshold: o.mask_threshold.map(|v| v.min(255) as u8),
+			bgcolor: o.bgcolor.clone(),
+			bg_image: o.bg_image.clone(),
+			bg_placement: BgPlacement::default(),
+			color_key_tolerance: o.color_key_tolerance.map(|v| v.min(255) as u8),
+			color_key_color: o.color_key_color.clone(),
+			despill: o.despill,
+			allow_download: o.allow_download.unwrap_or(defaults.allow_download),
+			include_mask: o.include_mask,
+			output_format: match o.output_format() {
+				proto::OutputFormat::Png => OutputFormat::Png,
+				proto::OutputFormat::Webp => OutputFormat::Webp,
+				proto::OutputFormat::Jpeg => OutputFormat::Jpeg,
+				proto::OutputFormat::Tiff => OutputFormat::Tiff,
+				proto::OutputFormat::Ico => OutputFormat::Ico,
+				proto::OutputFormat::Jxl => OutputFormat::Jxl
+			},
+			encode: EncodeOptions {
+				jpeg_quality: o.jpeg_quality.map_or(encode_defaults.jpeg_quality, |q| q.clamp(1, 100) as u8),
+				webp_quality: o.webp_quality.unwrap_or(encode_defaults.webp_quality),
+				webp_lossless: o.webp_lossless,
+				..encode_defaults
+			},
+			metadata: match o.metadata() {
+				proto::MetadataPolicy::Basic => MetadataPolicy::Basic,
+				proto::MetadataPolicy::Keep => MetadataPolicy::Keep,
+				proto::MetadataPolicy::Strip => MetadataPolicy::Strip
+			},
+			max_dim: None
+		}
+	}
+}
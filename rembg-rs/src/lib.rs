@@ -1,10 +1,45 @@
+#[cfg(all(feature = "load-dynamic", feature = "static-ort"))]
+compile_error!("`static-ort` links ONNX Runtime in and can't be combined with `load-dynamic`: build with `--no-default-features --features static-ort,download`");
+#[cfg(all(feature = "native", not(any(feature = "load-dynamic", feature = "static-ort"))))]
+compile_error!("`native` needs a way to get ONNX Runtime: add `load-dynamic` (load it at run time) or `static-ort` (link it in)");
+
+pub mod animation;
+pub mod cancel;
 pub mod cli;
+#[cfg(feature = "native")]
+pub mod cloud;
 pub mod compose;
+#[cfg(feature = "native")]
+pub mod config;
+#[cfg(feature = "native")]
+pub mod daemon;
+pub mod decode;
+#[cfg(feature = "native")]
+pub mod devices;
+#[cfg(feature = "native")]
 pub mod download;
+pub mod encode;
+pub mod error;
+pub mod grpc;
+pub mod mask;
+pub mod metadata;
+#[cfg(feature = "native")]
 pub mod model;
+pub mod onnx;
+#[cfg(feature = "download")]
 pub mod pypi;
+#[cfg(feature = "native")]
 pub mod runtime;
+#[cfg(feature = "native")]
+pub mod server;
+pub mod sam;
+pub mod term;
+pub mod trace;
 pub mod u2net;
+#[cfg(feature = "native")]
+pub mod video;
+#[cfg(feature = "native")]
+pub mod worker;
 
 pub mod core;
 
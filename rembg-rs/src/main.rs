@@ -1,68 +1,1662 @@
-use std::path::PathBuf;
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock, mpsc};
 
-use anyhow::{Context, Result};
-use clap::Parser;
+use anyhow::{Context, Result, bail};
+use image::imageops::FilterType;
+use image::{DynamicImage, GrayImage, RgbImage};
+use clap::{CommandFactory, FromArgMatches};
 
-use rembg_rs::{cli, compose, model, runtime, u2net};
+use rembg_rs::animation::{self, AnimationFrame};
+use rembg_rs::cancel::{Cancelled, CancellationToken};
+use rembg_rs::encode::{self, EncodeOptions, OutputFormat, TiffStackWriter};
+use rembg_rs::error::RembgError;
+use rembg_rs::metadata::{ImageMetadata, MetadataPolicy};
+use rembg_rs::{cli, cloud, compose, config, core, daemon, grpc, model, runtime, sam, server, term, u2net, video, worker};
 
 fn main() {
 	// Keep stdout clean for piping; errors go to stderr via `anyhow`.
 	if let Err(e) = run() {
 		eprintln!("{e:#}");
-		std::process::exit(1);
+		std::process::exit(ExitStatus::of(&e) as i32);
+	}
+}
+
+/// Process exit status by failure class, so scripts can branch on it (documented in the README).
+/// Clap exits with 2 on usage errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExitStatus {
+	Failure = 1,
+	Decode = 3,
+	Model = 4,
+	DownloadRefused = 5,
+	Inference = 6,
+	Io = 7,
+	Interrupted = 130
+}
+
+impl ExitStatus {
+	fn of(err: &anyhow::Error) -> ExitStatus {
+		if let Some(Classified(status, _)) = err.chain().find_map(|e| e.downcast_ref()) {
+			return *status;
+		}
+		if err.chain().any(|e| e.is::<Cancelled>()) {
+			return ExitStatus::Interrupted;
+		}
+		match RembgError::find(err) {
+			Some(RembgError::Decode { .. }) => ExitStatus::Decode,
+			Some(RembgError::UnsupportedModel { .. }) => ExitStatus::Model,
+			Some(RembgError::DownloadRequired { .. }) => ExitStatus::DownloadRefused,
+			None if err.chain().any(|e| e.is::<std::io::Error>()) => ExitStatus::Io,
+			None => ExitStatus::Failure
+		}
+	}
+}
+
+/// Cancelled by the first Ctrl-C, so downloads, inference and encoding stop at their next check
+/// and the run ends with `ExitStatus::Interrupted`; a second Ctrl-C exits at once. The handler goes
+/// in on first use, so the servers keep the default behavior.
+fn interrupt() -> &'static CancellationToken {
+	static TOKEN: OnceLock<CancellationToken> = OnceLock::new();
+	TOKEN.get_or_init(|| {
+		let token = CancellationToken::new();
+		let handler = token.clone();
+		let installed = ctrlc::set_handler(move || {
+			if handler.is_cancelled() {
+				std::process::exit(ExitStatus::Interrupted as i32);
+			}
+			eprintln!("\ninterrupted; stopping (press Ctrl-C again to quit now)");
+			handler.cancel();
+		});
+		if let Err(e) = installed {
+			eprintln!("warning: Ctrl-C won't stop jobs cleanly: {e}");
+		}
+		token
+	})
+}
+
+/// An error tagged with its `ExitStatus`; prints as the error it wraps.
+#[derive(Debug)]
+struct Classified(ExitStatus, anyhow::Error);
+
+impl std::fmt::Display for Classified {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{:#}", self.1)
+	}
+}
+
+impl std::error::Error for Classified {}
+
+trait Classify<T> {
+	fn classify(self, status: ExitStatus) -> Result<T>;
+}
+
+impl<T> Classify<T> for Result<T> {
+	fn classify(self, status: ExitStatus) -> Result<T> {
+		self.map_err(|e| Classified(status, e).into())
 	}
 }
 
 fn run() -> Result<()> {
-	let args = cli::Args::parse();
+	let matches = cli::Args::command().get_matches();
+	let mut args = cli::Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+	let mut config = config::load()?;
+	config.apply(&mut args, &matches);
+	if let Some(version) = &args.runtime_version {
+		config.runtime_version = Some(version.clone());
+	}
+	config::install(config);
+	match &args.command {
+		Some(cli::Command::Daemon { socket }) => {
+			let socket = socket.clone().unwrap_or_else(daemon::default_socket);
+			return daemon::serve(&socket);
+		}
+		Some(cli::Command::Worker) => return worker::serve(),
+		Some(cli::Command::Serve { port, host }) => return server::serve(host, *port),
+		Some(cli::Command::Grpc { port, host }) => return grpc::serve(host, *port),
+		Some(cli::Command::Setup { model, device, gpu_backend }) => return setup(model, *device, *gpu_backend, args.progress),
+		Some(cli::Command::Runtime { action }) => return runtimes(action),
+		Some(cli::Command::Models { action }) => return models(action),
+		Some(cli::Command::Inspect { model }) => return inspect(model),
+		Some(cli::Command::Video {
+			input,
+			output,
+			codec,
+			model,
+			device,
+			gpu_backend,
+			temporal_smoothing,
+			refine,
+			yes
+		}) => {
+			let plan = runtime::plan_noninteractive(*device, *gpu_backend, *yes)?;
+			let options = video::FrameOptions {
+				smoothing: *temporal_smoothing,
+				refine: *refine
+			};
+			return remove_video(input, output, *codec, model, &plan, options, args.progress);
+		}
+		None => {}
+	}
+	interrupt();
+
+	let batch = args.sequence.is_some() || args.input.len() > 1 || args.input.iter().any(|p| p.is_dir());
+	if batch && args.input.iter().any(|p| is_stdio(p)) {
+		bail!("`-` (stdin) can't be combined with other inputs");
+	}
+	if batch && args.sequence.is_none() && args.output.is_some() {
+		bail!("--output takes a single input file; use --output-dir when processing several");
+	}
+	if batch && args.visualize.as_deref().and_then(OutputFormat::from_path).is_some() {
+		bail!("--visualize takes a directory when processing several inputs");
+	}
+	if let Some(dir) = args.output_dir.as_deref()
+		&& !cloud::is_object_url(dir)
+	{
+		std::fs::create_dir_all(dir).with_context(|| format!("create dir: {}", dir.display()))?;
+	}
+	let mut inputs = match &args.sequence {
+		Some(template) => {
+			let output = match args.output.as_deref() {
+				Some(p) => Some(
+					p.to_str()
+						.unwrap_or_default()
+						.parse::<cli::FrameTemplate>()
+						.map_err(|e| anyhow::anyhow!("--output with --sequence: {e}"))?
+				),
+				None => None
+			};
+			collect_sequence(template, output.as_ref())?
+		}
+		None => collect_inputs(&args.input, args.recursive, args.output_dir.as_deref())?
+	};
+
+	if args.only_mask
+		&& let (Some(format), Some(mask_format)) = (args.format, args.mask_format)
+		&& format != mask_format.output_format()
+	{
+		bail!("--format and --mask-format disagree; with --only-mask the mask is the output, so pass one of them");
+	}
+	let mask_format = args.mask_format.filter(|_| args.only_mask).map(cli::MaskFormat::output_format);
+	let format = args.format.or(mask_format).or_else(|| args.output.as_deref().and_then(OutputFormat::from_path));
+	if let Some(f) = format
+		&& !args.only_mask
+		&& !f.supports_cutout()
+	{
+		bail!("--format {} is only supported together with --only-mask", f.extension());
+	}
+	match args.mask_mode {
+		Some(cli::MaskMode::Soft) if args.mask_threshold.is_some() => {
+			bail!("--mask-mode soft never thresholds; drop --mask-threshold or use --mask-mode binary")
+		}
+		Some(cli::MaskMode::Logits) if format != Some(OutputFormat::Exr) => {
+			bail!("--mask-mode logits writes unbounded floats and needs --format exr")
+		}
+		_ => {}
+	}
+	if args.shadow && !has_background(&args) {
+		bail!("--shadow needs a background (--bgcolor, --background-file, --bg-tile, --bg-blur or --checkerboard)");
+	}
+	if let Some(s) = args.scale
+		&& !(s > 0.0 && s.is_finite())
+	{
+		bail!("--scale must be a positive number");
+	}
+
+	let reserved = Mutex::new(HashSet::new());
+	let mut job = Job {
+		args: &args,
+		format,
+		encode_opts: EncodeOptions {
+			jxl_lossless: args.jxl_lossless,
+			jxl_distance: args.jxl_distance,
+			png_optimize: args.optimize_png,
+			png_zopfli: args.zopfli,
+			jpeg_quality: args.jpeg_quality,
+			webp_lossless: args.webp_lossless,
+			webp_quality: args.webp_quality
+		},
+		metadata: if args.strip_metadata {
+			MetadataPolicy::Strip
+		} else if args.keep_metadata {
+			MetadataPolicy::Keep
+		} else {
+			MetadataPolicy::Basic
+		},
+		engine: None,
+		stack: match args.tiff_stack.as_deref() {
+			Some(p) => Some((TiffStackWriter::create(p)?, p.to_path_buf())),
+			None => None
+		},
+		reserved: &reserved
+	};
+
+	let mut journal = match args.journal.as_deref() {
+		Some(path) => Some(Journal::open(path, args.resume)?),
+		None => None
+	};
+	if let Some(journal) = &journal {
+		let total = inputs.len();
+		inputs.retain(|input| !journal.is_done(&input.path));
+		if inputs.len() < total {
+			eprintln!("resuming: {} of {total} inputs already done", total - inputs.len());
+		}
+		if inputs.is_empty() {
+			return Ok(());
+		}
+	}
 
-	let plan = runtime::resolve_plan(&args)?;
-	let rt = runtime::ensure_onnxruntime(&plan)?;
-	runtime::init_ort(&rt)?;
+	if !batch {
+		let input = &inputs[0];
+		if let Outcome::Skipped(reason) = job.process(input)? {
+			eprintln!("skipped {}: {reason}", input.path.display());
+		}
+		if let Some(journal) = journal.as_mut() {
+			journal.record(&input.path)?;
+		}
+		return Ok(());
+	}
 
-	let model = model::ensure_model(&args.model)?;
+	// Runtime/model setup problems affect every file; fail once up front instead of per input.
+	job.engine()?;
+	// `--jobs`: each worker has its own session and takes the next input as it frees up.
+	let mut workers = Vec::new();
+	for _ in 1..args.jobs.min(inputs.len()) {
+		workers.push(job.worker()?);
+	}
+	workers.push(job);
+	let next = AtomicUsize::new(0);
+	let (tx, rx) = mpsc::channel();
 
-	let input_path = &args.input;
-	let img = image::open(input_path).with_context(|| format!("open image: {}", input_path.display()))?;
-	let img_rgb = img.to_rgb8();
+	// A sequence gets one progress line instead of a line per frame.
+	let mut sequence_progress = args.sequence.as_ref().map(|_| CountProgress::new("sequence", args.progress));
+	let mut written = 0;
+	let mut skipped = Vec::new();
+	let mut failed: Vec<(&PathBuf, ExitStatus)> = Vec::new();
+	let mut journal_error = None;
+	std::thread::scope(|scope| {
+		for mut worker in workers {
+			let (tx, next, inputs) = (tx.clone(), &next, &inputs);
+			scope.spawn(move || {
+				// After Ctrl-C, inputs not yet started are left for a `--resume`.
+				while !interrupt().is_cancelled()
+					&& let Some(input) = inputs.get(next.fetch_add(1, Ordering::Relaxed))
+				{
+					if tx.send((input, worker.process(input))).is_err() {
+						break;
+					}
+				}
+			});
+		}
+		drop(tx);
 
-	let mask = u2net::predict_mask(&model.path, model.input_size, &img_rgb, plan.ep)
-		.with_context(|| format!("run model: {}", model.path.display()))?;
+		// Results arrive in completion order; the count is of finished inputs.
+		for (done, (input, result)) in rx.iter().enumerate() {
+			let n = format!("[{}/{}]", done + 1, inputs.len());
+			// Failed inputs stay out of the journal so `--resume` retries them.
+			let finished = result.is_ok();
+			match result {
+				Ok(Outcome::Written(out)) => {
+					if sequence_progress.is_none() {
+						let out: Vec<_> = out.iter().map(|p| p.display().to_string()).collect();
+						eprintln!("{n} {} -> {}", input.path.display(), out.join(", "));
+					}
+					written += 1;
+				}
+				Ok(Outcome::Skipped(reason)) => {
+					if sequence_progress.is_none() {
+						eprintln!("{n} {} skipped: {reason}", input.path.display());
+					}
+					skipped.push((&input.path, reason));
+				}
+				Err(e) => {
+					if let Some(p) = sequence_progress.as_mut() {
+						p.break_line();
+					}
+					eprintln!("{n} {} failed: {e:#}", input.path.display());
+					failed.push((&input.path, ExitStatus::of(&e)));
+				}
+			}
+			if finished
+				&& let Some(journal) = journal.as_mut()
+				&& let Err(e) = journal.record(&input.path)
+			{
+				// Not worth stopping the batch over; the next run just redoes some inputs.
+				journal_error.get_or_insert(e);
+			}
+			if let Some(p) = sequence_progress.as_mut() {
+				p.update(done as u64 + 1, Some(inputs.len() as u64));
+			}
+		}
+	});
+	if let Some(e) = journal_error {
+		eprintln!("warning: journal not updated: {e:#}");
+	}
+	if let Some(p) = sequence_progress.as_mut() {
+		p.break_line();
+	}
+
+	eprintln!(
+		"\n{written} written, {} skipped, {} failed (of {})",
+		skipped.len(),
+		failed.len(),
+		inputs.len()
+	);
+	for (p, reason) in &skipped {
+		eprintln!("  skipped {}: {reason}", p.display());
+	}
+	for (p, _) in &failed {
+		eprintln!("  failed  {}", p.display());
+	}
+	if interrupt().is_cancelled() {
+		return Err(Cancelled).context("interrupted");
+	}
+	if let Some(&(_, first)) = failed.first() {
+		// One failure class for the whole run keeps its status; a mix is a plain failure.
+		let status = if failed.iter().all(|&(_, s)| s == first) { first } else { ExitStatus::Failure };
+		return Err(anyhow::anyhow!("{} of {} inputs failed", failed.len(), inputs.len())).classify(status);
+	}
+	Ok(())
+}
 
-	let out_path: PathBuf = match args.output {
-		Some(p) => p,
-		None => {
-			let stem = input_path
-				.file_stem()
-				.and_then(|s| s.to_str())
-				.unwrap_or("out");
-			let suffix = if args.only_mask { "_mask.png" } else { "_rembg.png" };
-			input_path.with_file_name(format!("{stem}{suffix}"))
+fn setup(model: &str, device: cli::Device, gpu_backend: cli::GpuBackend, progress: cli::ProgressFormat) -> Result<()> {
+	let plan = runtime::plan_noninteractive(device, gpu_backend, true)?;
+	if progress == cli::ProgressFormat::Json {
+		let (rt, model) = core::prefetch(&plan, model, interrupt(), |evt| emit_json(&evt))?;
+		eprintln!("runtime: {}", rt.main_lib.display());
+		eprintln!("model: {}", model.path.display());
+		return Ok(());
+	}
+	let live = term::live_progress();
+	// Latest (downloaded, total) per stage, drawn as one line.
+	let mut state: Vec<(String, u64, Option<u64>)> = Vec::new();
+	let mut last_log = std::time::Instant::now();
+	let (rt, model) = core::prefetch(&plan, model, interrupt(), |evt| {
+		let Some(downloaded) = evt.downloaded else {
+			return;
+		};
+		match state.iter_mut().find(|(stage, ..)| *stage == evt.stage) {
+			Some(s) => *s = (evt.stage.clone(), downloaded, evt.total),
+			None => state.push((evt.stage.clone(), downloaded, evt.total))
+		}
+		if !live && last_log.elapsed().as_secs() < 5 && evt.done != Some(true) {
+			return;
+		}
+		last_log = std::time::Instant::now();
+		let line: Vec<String> = state
+			.iter()
+			.map(|(stage, done, total)| match total {
+				Some(t) => format!("{stage} {} / {}", format_mib(*done), format_mib(*t)),
+				None => format!("{stage} {}", format_mib(*done))
+			})
+			.collect();
+		if live {
+			eprint!("\r{}   ", line.join("  |  "));
+		} else {
+			eprintln!("{}", line.join("  |  "));
 		}
+	})?;
+	if live && !state.is_empty() {
+		eprintln!();
+	}
+	eprintln!("runtime: {}", rt.main_lib.display());
+	eprintln!("model: {}", model.path.display());
+	Ok(())
+}
+
+fn remove_video(
+	input: &Path,
+	output: &Path,
+	codec: Option<cli::VideoCodec>,
+	model: &str,
+	plan: &runtime::Plan,
+	options: video::FrameOptions,
+	progress: cli::ProgressFormat
+) -> Result<()> {
+	if !(0.0..1.0).contains(&options.smoothing) {
+		bail!("--temporal-smoothing must be at least 0 and below 1 (1 would keep the first frame's mask)");
+	}
+	let codec = codec
+		.or_else(|| cli::VideoCodec::from_path(output))
+		.context("can't tell the codec from the output name; use .webm or .mov, or pass --codec")?;
+	init_runtime(plan, progress)?;
+	let model = fetch_model(model, true, progress)?;
+	let mut remover = u2net::Remover::from_install(&model, plan.ep, &u2net::SessionConfig::default())
+		.with_context(|| format!("load model: {}", model.path.display()))
+		.classify(ExitStatus::Model)?;
+
+	let mut line = CountProgress::new("video", progress);
+	let frames = video::remove_background(input, output, codec, options, &mut remover, interrupt(), |p| {
+		line.update(p.frame, p.total)
+	});
+	line.break_line();
+	eprintln!("{} frames -> {}", frames?, output.display());
+	Ok(())
+}
+
+/// `--journal`: the inputs finished so far, one path per line, appended (and synced) as each
+/// completes so a crash loses at most the inputs in flight.
+struct Journal {
+	done: HashSet<String>,
+	file: std::fs::File
+}
+
+impl Journal {
+	/// With `resume`, load the entries already in `path` and append after them; otherwise start empty.
+	fn open(path: &Path, resume: bool) -> Result<Journal> {
+		let done = match std::fs::read_to_string(path) {
+			Ok(text) if resume => text.lines().filter(|l| !l.is_empty()).map(str::to_string).collect(),
+			Err(e) if resume && e.kind() != std::io::ErrorKind::NotFound => {
+				return Err(e).with_context(|| format!("read journal: {}", path.display()));
+			}
+			_ => Default::default()
+		};
+		let file = std::fs::OpenOptions::new()
+			.create(true)
+			.append(resume)
+			.write(true)
+			.truncate(!resume)
+			.open(path)
+			.with_context(|| format!("open journal: {}", path.display()))?;
+		Ok(Journal { done, file })
+	}
+
+	fn is_done(&self, input: &Path) -> bool {
+		self.done.contains(&*input.to_string_lossy())
+	}
+
+	fn record(&mut self, input: &Path) -> Result<()> {
+		writeln!(self.file, "{}", input.to_string_lossy())?;
+		self.file.sync_data()?;
+		Ok(())
+	}
+}
+
+/// Progress through a count of frames as one line: redrawn with `\r` on a terminal, logged every
+/// few seconds otherwise, or as `stage` events (the count in `message`) with `--progress json`.
+struct CountProgress {
+	stage: &'static str,
+	format: cli::ProgressFormat,
+	live: bool,
+	started: std::time::Instant,
+	last_log: std::time::Instant,
+	drawn: bool
+}
+
+impl CountProgress {
+	fn new(stage: &'static str, format: cli::ProgressFormat) -> Self {
+		let now = std::time::Instant::now();
+		CountProgress {
+			stage,
+			format,
+			live: term::live_progress(),
+			started: now,
+			last_log: now,
+			drawn: false
+		}
+	}
+
+	fn update(&mut self, done: u64, total: Option<u64>) {
+		let count = match total {
+			Some(total) => format!("{done}/{total}"),
+			None => done.to_string()
+		};
+		if self.format == cli::ProgressFormat::Json {
+			emit_json(&core::ProgressEvent {
+				message: Some(count),
+				..core::ProgressEvent::stage(self.stage)
+			});
+			return;
+		}
+		let last = total == Some(done);
+		if !self.live && !last && self.last_log.elapsed().as_secs() < 5 {
+			return;
+		}
+		self.last_log = std::time::Instant::now();
+		let fps = done as f64 / self.started.elapsed().as_secs_f64();
+		if self.live {
+			eprint!("\rframe {count} ({fps:.1} fps)   ");
+			self.drawn = true;
+		} else {
+			eprintln!("frame {count} ({fps:.1} fps)");
+		}
+	}
+
+	/// End a redrawn line so the next message starts on its own.
+	fn break_line(&mut self) {
+		if self.drawn {
+			eprintln!();
+			self.drawn = false;
+		}
+	}
+}
+
+fn runtimes(action: &cli::RuntimeCommand) -> Result<()> {
+	match action {
+		cli::RuntimeCommand::List => {
+			let installed = runtime::list_installed()?;
+			for rt in &installed {
+				let active = if rt.active { "  (active)" } else { "" };
+				println!("{:<24} {:<12} {:>10}{active}", rt.package, rt.version, format_mib(rt.size));
+			}
+			let total: u64 = installed.iter().map(|rt| rt.size).sum();
+			println!("\n{} total in {}", format_mib(total), runtime::runtimes_dir()?.display());
+		}
+		cli::RuntimeCommand::Prune => {
+			let removed = runtime::prune()?;
+			for rt in &removed {
+				eprintln!("removed {} {}", rt.package, rt.version);
+			}
+			let freed: u64 = removed.iter().map(|rt| rt.size).sum();
+			eprintln!("freed {}", format_mib(freed));
+		}
+		cli::RuntimeCommand::Clean => eprintln!("freed {}", format_mib(runtime::clean()?))
+	}
+	Ok(())
+}
+
+fn format_mib(bytes: u64) -> String {
+	format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+fn models(action: &cli::ModelsCommand) -> Result<()> {
+	match action {
+		cli::ModelsCommand::List => {
+			for name in model::MODEL_IDS {
+				let files = model::model_files(name)?;
+				let sizes: Vec<u64> = files.iter().filter_map(|f| std::fs::metadata(f).ok()).map(|m| m.len()).collect();
+				let status = if sizes.is_empty() {
+					"-".to_string()
+				} else {
+					let partial = if sizes.len() < files.len() { " (incomplete)" } else { "" };
+					format!("{}{partial}", format_mib(sizes.iter().sum()))
+				};
+				println!("{name:<24} {status}");
+			}
+			println!("\ncache: {}", model::models_dir()?.display());
+		}
+		cli::ModelsCommand::Download { names, no_verify } => {
+			for name in names {
+				let m = model::ensure_model(name, !no_verify)?;
+				eprintln!("{name}: {}", m.path.display());
+			}
+		}
+		cli::ModelsCommand::Remove { names } => {
+			for name in names {
+				if model::remove_model(name)? {
+					eprintln!("removed {name}");
+				} else {
+					eprintln!("{name} is not cached");
+				}
+			}
+		}
+		cli::ModelsCommand::Path { name: None } => println!("{}", model::models_dir()?.display()),
+		cli::ModelsCommand::Path { name: Some(name) } => {
+			for file in model::model_files(name)? {
+				println!("{}", file.display());
+			}
+		}
+	}
+	Ok(())
+}
+
+fn inspect(path: &Path) -> Result<()> {
+	let bytes = std::fs::read(path).with_context(|| format!("read {}", path.display()))?;
+	let info = rembg_rs::onnx::inspect(&bytes).with_context(|| format!("inspect {}", path.display()))?;
+	let opsets: Vec<String> = info.opsets.iter().map(|(domain, version)| format!("{domain} {version}")).collect();
+	println!("{}", path.display());
+	println!("  producer: {} (IR version {})", if info.producer.is_empty() { "-" } else { &info.producer }, info.ir_version);
+	println!("  opsets:   {}", opsets.join(", "));
+	for (title, tensors) in [("inputs", &info.inputs), ("outputs", &info.outputs)] {
+		println!("  {title}:");
+		for t in tensors {
+			println!("    {:<24} {:<8} {}", t.name, t.dtype(), t.shape_string());
+		}
+	}
+	println!();
+	match info.compatibility() {
+		Ok(size) => {
+			let size = size.map_or_else(|| format!("PX (dynamic; default {})", model::LOCAL_INPUT_SIZE), |s| s.to_string());
+			println!("compatible: --model-path {} --model-input-size {size}", path.display());
+			Ok(())
+		}
+		Err(problems) => {
+			println!("not compatible with --model-path:");
+			for p in &problems {
+				println!("  - {p}");
+			}
+			bail!("{} doesn't match what --model-path expects", path.display())
+		}
+	}
+}
+
+/// Load the segmentation model, plus `--matting-model` when given.
+fn load_remover(
+	args: &cli::Args,
+	install: &model::ModelInstall,
+	ep: Option<runtime::PreferredEp>,
+	session: &u2net::SessionConfig
+) -> Result<u2net::Remover> {
+	let mut remover = u2net::Remover::from_install(install, ep, session)
+		.with_context(|| format!("load model: {}", install.path.display()))
+		.classify(ExitStatus::Model)?;
+	if let Some(path) = &args.matting_model {
+		remover
+			.load_matting(path, args.matting_input_size.unwrap_or(model::MATTING_INPUT_SIZE), ep, session)
+			.with_context(|| format!("load matting model: {}", path.display()))
+			.classify(ExitStatus::Model)?;
+	}
+	Ok(remover)
+}
+
+/// One file to process. `rel_dir` is where it sits below the input directory it was found in,
+/// mirrored under `--output-dir`.
+struct Input {
+	path: PathBuf,
+	rel_dir: PathBuf,
+	/// Where this input's result goes, when that's decided per input (numbered sequence output).
+	output: Option<PathBuf>
+}
+
+/// Expand directories into the image files they contain (subdirectories too with `recursive`);
+/// plain files are taken as given. `exclude` keeps an output directory nested in an input
+/// directory from being picked up on re-runs.
+fn collect_inputs(paths: &[PathBuf], recursive: bool, exclude: Option<&Path>) -> Result<Vec<Input>> {
+	let exclude = exclude.and_then(|p| p.canonicalize().ok());
+	let mut out = Vec::new();
+	for p in paths {
+		if !p.is_dir() {
+			out.push(Input {
+				path: p.clone(),
+				rel_dir: PathBuf::new(),
+				output: None
+			});
+			continue;
+		}
+		let mut files = Vec::new();
+		walk_dir(p, Path::new(""), recursive, exclude.as_deref(), &mut files)?;
+		files.sort_by(|a, b| a.path.cmp(&b.path));
+		out.extend(files);
+	}
+	if out.is_empty() {
+		bail!("no input images found");
+	}
+	Ok(out)
+}
+
+/// The files matching `template`, in frame order, each paired with its numbered `output` path.
+fn collect_sequence(template: &cli::FrameTemplate, output: Option<&cli::FrameTemplate>) -> Result<Vec<Input>> {
+	let dir = if template.dir().as_os_str().is_empty() { Path::new(".") } else { template.dir() };
+	let mut frames = Vec::new();
+	for entry in std::fs::read_dir(dir).with_context(|| format!("read dir: {}", dir.display()))? {
+		let entry = entry.with_context(|| format!("read dir: {}", dir.display()))?;
+		if let Some(frame) = entry.file_name().to_str().and_then(|name| template.frame(name))
+			&& entry.path().is_file()
+		{
+			frames.push(frame);
+		}
+	}
+	if frames.is_empty() {
+		bail!("no frames match {template}");
+	}
+	frames.sort_unstable();
+	Ok(frames
+		.into_iter()
+		.map(|frame| Input {
+			path: template.path(frame),
+			rel_dir: PathBuf::new(),
+			output: output.map(|o| o.path(frame))
+		})
+		.collect())
+}
+
+fn walk_dir(dir: &Path, rel: &Path, recursive: bool, exclude: Option<&Path>, out: &mut Vec<Input>) -> Result<()> {
+	for entry in std::fs::read_dir(dir).with_context(|| format!("read dir: {}", dir.display()))? {
+		let entry = entry.with_context(|| format!("read dir: {}", dir.display()))?;
+		let path = entry.path();
+		if path.is_file() {
+			if is_image_path(&path) {
+				out.push(Input {
+					path,
+					rel_dir: rel.to_path_buf(),
+					output: None
+				});
+			}
+		} else if recursive && entry.file_type().is_ok_and(|t| t.is_dir()) {
+			// `file_type` doesn't follow symlinks, so linked directories can't send us in circles.
+			if exclude.is_some_and(|ex| path.canonicalize().is_ok_and(|c| c == ex)) {
+				continue;
+			}
+			walk_dir(&path, &rel.join(entry.file_name()), recursive, exclude, out)?;
+		}
+	}
+	Ok(())
+}
+
+fn sam_prompt(args: &cli::Args) -> sam::Prompt {
+	let point = |p: &cli::Offset, foreground| sam::Point {
+		x: p.x as f32,
+		y: p.y as f32,
+		foreground
 	};
+	sam::Prompt {
+		points: args
+			.sam_point
+			.iter()
+			.map(|p| point(p, true))
+			.chain(args.sam_exclude.iter().map(|p| point(p, false)))
+			.collect(),
+		bbox: args.sam_box.map(|r| [r.x0, r.y0, r.x1, r.y1])
+	}
+}
+
+fn is_image_path(path: &Path) -> bool {
+	image::ImageFormat::from_path(path).is_ok_and(|f| f.reading_enabled()) || rembg_rs::decode::is_jxl_path(path)
+}
+
+/// Loaded once, on the first image that actually needs inference, and reused for the rest.
+struct Engine {
+	model: model::ModelInstall,
+	ep: Option<runtime::PreferredEp>,
+	remover: u2net::Remover
+}
+
+enum Outcome {
+	/// Several paths with `--cloth-category all`.
+	Written(Vec<PathBuf>),
+	Skipped(String)
+}
+
+/// Settings shared by every input of a run.
+struct Job<'a> {
+	args: &'a cli::Args,
+	format: Option<OutputFormat>,
+	encode_opts: EncodeOptions,
+	metadata: MetadataPolicy,
+	engine: Option<Engine>,
+	stack: Option<(TiffStackWriter, PathBuf)>,
+	/// Output paths (`--split-instances` manifests) claimed by this run's inputs so far, shared by
+	/// the `--jobs` workers.
+	reserved: &'a Mutex<HashSet<PathBuf>>
+}
+
+impl<'a> Job<'a> {
+	fn engine(&mut self) -> Result<&mut Engine> {
+		if self.engine.is_none() {
+			let plan = runtime::resolve_plan(self.args)?;
+			init_runtime(&plan, self.args.progress)?;
+			let model = match self.args.model_path.as_deref() {
+				Some(path) => {
+					let input_size = self.args.model_input_size.unwrap_or(model::LOCAL_INPUT_SIZE);
+					model::local_model(path, input_size).classify(ExitStatus::Model)?
+				}
+				None => fetch_model(&self.args.model, !self.args.no_verify, self.args.progress)?
+			};
+			if self.args.progress == cli::ProgressFormat::Json {
+				emit_json(&core::ProgressEvent::infer(u2net::InferProgress::LoadSession));
+			}
+			let remover = load_remover(self.args, &model, plan.ep, &session_config(self.args))?;
+			self.engine = Some(Engine { model, ep: plan.ep, remover });
+		}
+		Ok(self.engine.as_mut().expect("engine initialized above"))
+	}
+
+	/// Another `--jobs` worker: the same settings with a session of its own on the loaded model.
+	fn worker(&mut self) -> Result<Job<'a>> {
+		let args = self.args;
+		let session = session_config(args);
+		let engine = self.engine()?;
+		let remover = load_remover(args, &engine.model, engine.ep, &session)?;
+		let engine = Engine {
+			model: engine.model.clone(),
+			ep: engine.ep,
+			remover
+		};
+		Ok(Job {
+			args,
+			format: self.format,
+			encode_opts: self.encode_opts.clone(),
+			metadata: self.metadata,
+			engine: Some(engine),
+			stack: None,
+			reserved: self.reserved
+		})
+	}
 
-	if args.only_mask {
-		let mask_out = if let Some(t) = args.mask_threshold {
-			let mut m = mask.clone();
-			for p in m.pixels_mut() {
-				p.0[0] = if p.0[0] >= t { 255 } else { 0 };
+	fn process(&mut self, input: &Input) -> Result<Outcome> {
+		let args = self.args;
+		let format = self.format;
+		let input_path = input.path.as_path();
+
+		let out_path: PathBuf = match input.output.clone().or_else(|| args.output.clone()) {
+			Some(p) => p,
+			// Nothing to name the file after: piped in, piped out.
+			None if is_stdio(input_path) && args.output_dir.is_none() => PathBuf::from("-"),
+			None => {
+				let stem = if is_stdio(input_path) {
+					Cow::Borrowed("stdin")
+				} else if let Some(url) = input_url(input_path) {
+					url_stem(url)
+				} else {
+					Cow::Borrowed(input_path.file_stem().and_then(|s| s.to_str()).unwrap_or("out"))
+				};
+				let suffix = if args.only_mask { "_mask" } else { "_rembg" };
+				let ext = format.unwrap_or(OutputFormat::Png).extension();
+				let name = format!("{stem}{suffix}.{ext}");
+				match args.output_dir.as_deref() {
+					Some(dir) => dir.join(&input.rel_dir).join(name),
+					// There's no directory next to a URL; use the current one.
+					None if input_url(input_path).is_some() => PathBuf::from(name),
+					None => input_path.with_file_name(name)
+				}
+			}
+		};
+
+		// `image` can't guess a format for stdout, so default to PNG there.
+		let format = if is_stdio(&out_path) { Some(format.unwrap_or(OutputFormat::Png)) } else { format };
+
+		// Several clothing categories: one output each, labelled after the category.
+		let classes = args.cloth_category.map(|c| c.classes());
+		let out_paths: Vec<PathBuf> = match classes {
+			Some(classes) if classes.len() > 1 => {
+				if is_stdio(&out_path) {
+					bail!("--cloth-category all writes one file per category and can't write to stdout");
+				}
+				classes.iter().map(|c| labeled_path(&out_path, c.name())).collect()
+			}
+			_ => vec![out_path]
+		};
+		if args.split_instances && is_stdio(&out_paths[0]) {
+			bail!("--split-instances writes one file per subject and can't write to stdout");
+		}
+		if args.raw_mask.is_some() && is_stdio(&out_paths[0]) {
+			bail!("--raw-mask writes a second file and can't be used when writing to stdout");
+		}
+		if out_paths.len() > 1 && args.visualize.as_deref().and_then(OutputFormat::from_path).is_some() {
+			bail!("--visualize takes a directory when writing several outputs per input");
+		}
+
+		// With `--split-instances` the manifest stands in for the outputs, whose number isn't known yet.
+		let claimed = |p: &PathBuf| if args.split_instances { manifest_path(p) } else { p.clone() };
+
+		// Checked and claimed in one go so `--jobs` workers whose inputs map to the same output
+		// (photo.jpg and photo.png) conflict like an existing file instead of overwriting each other.
+		let mut reserved = self.reserved.lock().unwrap();
+		let taken = |p: &Path| p.exists() || reserved.contains(p);
+		// Pages of `--tiff-stack` don't write these paths.
+		let existing = out_paths.iter().map(claimed).find(|p| !is_stdio(p) && taken(p)).filter(|_| self.stack.is_none());
+		let on_conflict = if args.skip_existing { cli::OnConflict::Skip } else { args.on_conflict };
+		let out_paths = match (existing, on_conflict) {
+			(None, _) | (Some(_), cli::OnConflict::Overwrite) => out_paths,
+			(Some(p), cli::OnConflict::Skip) => return Ok(Outcome::Skipped(format!("{} already exists", p.display()))),
+			(Some(_), cli::OnConflict::Rename) if args.split_instances => out_paths
+				.iter()
+				.map(|p| unused_path(&claimed(p), taken).with_extension(p.extension().unwrap_or_default()))
+				.collect(),
+			(Some(_), cli::OnConflict::Rename) => out_paths.iter().map(|p| unused_path(p, taken)).collect(),
+			(Some(p), cli::OnConflict::Error) if p.exists() => {
+				bail!("{} already exists (pass --on-conflict overwrite, skip or rename)", p.display())
 			}
-			m
+			(Some(p), cli::OnConflict::Error) => {
+				bail!("{} is also the output of another input (pass --on-conflict overwrite, skip or rename)", p.display())
+			}
+		};
+		if self.stack.is_none() {
+			reserved.extend(out_paths.iter().map(claimed).filter(|p| !is_stdio(p)));
+		}
+		drop(reserved);
+		let out_path = &out_paths[0];
+		if !input.rel_dir.as_os_str().is_empty()
+			&& !cloud::is_object_url(out_path)
+			&& let Some(parent) = out_path.parent()
+		{
+			std::fs::create_dir_all(parent).with_context(|| format!("create dir: {}", parent.display()))?;
+		}
+
+		let json = args.progress == cli::ProgressFormat::Json;
+		let decode = core::ProgressEvent {
+			message: Some(input_path.display().to_string()),
+			..core::ProgressEvent::started("decode")
+		};
+		if json {
+			emit_input_json(input_path, &decode);
+		}
+		let input_bytes = read_input(input_path, args.progress).classify(ExitStatus::Io)?;
+		let cancel = interrupt();
+		// Animated output for animated inputs; other formats keep the first frame.
+		if matches!(format, Some(OutputFormat::Apng | OutputFormat::Webp))
+			&& out_paths.len() == 1
+			&& self.stack.is_none()
+			&& !args.split_instances
+			&& let Some(frames) = animation::decode_frames(&input_bytes, cancel)
+				.with_context(|| format!("open image: {}", input_path.display()))?
+		{
+			if json {
+				emit_input_json(input_path, &decode.finished());
+			}
+			return self.process_animation(input_path, frames, &out_paths[0], format == Some(OutputFormat::Apng), cancel);
+		}
+		let img = rembg_rs::decode::load_from_memory(&input_bytes)
+			.map_err(|source| RembgError::Decode { what: "image", source })
+			.with_context(|| format!("open image: {}", input_path.display()))?;
+		if let Some(reason) = size_filter(args, img.width(), img.height()) {
+			return Ok(Outcome::Skipped(reason));
+		}
+		let img_rgb = img.to_rgb8();
+		let meta = ImageMetadata::read_with(&input_bytes, self.metadata);
+		if json {
+			emit_input_json(input_path, &decode.finished());
+		}
+
+		let engine = self.engine()?;
+		let infer = core::ProgressEvent::started("infer");
+		if json {
+			emit_input_json(input_path, &infer);
+		}
+		let on_progress = |p| {
+			if json {
+				emit_input_json(input_path, &core::ProgressEvent::infer(p));
+			}
+		};
+		let probs = match classes {
+			Some(classes) => engine.remover.predict_classes(&img_rgb, classes, cancel, on_progress),
+			None if args.refine => engine
+				.remover
+				.predict_probabilities_refined(&img_rgb, &sam_prompt(args), cancel, on_progress)
+				.map(|p| vec![p]),
+			None => engine
+				.remover
+				.predict_probabilities_prompted(&img_rgb, &sam_prompt(args), cancel, on_progress)
+				.map(|p| vec![p])
+		}
+		.with_context(|| format!("run model: {}", engine.model.path.display()))
+		.classify(ExitStatus::Inference)?;
+		let probs = probs
+			.into_iter()
+			.map(|p| engine.remover.refine_edges(&img_rgb, p, cancel, on_progress))
+			.collect::<Result<Vec<_>>>()
+			.context("run matting model")
+			.classify(ExitStatus::Inference)?;
+
+		let encode = core::ProgressEvent::started("encode");
+		if json {
+			emit_input_json(input_path, &infer.finished());
+			emit_input_json(input_path, &encode);
+		}
+
+		let mut written = Vec::new();
+		for (probs, out_path) in probs.into_iter().zip(out_paths) {
+			written.extend(self.write_result(probs, &img, &img_rgb, &meta, out_path, format)?);
+		}
+		if json {
+			emit_input_json(input_path, &encode.finished());
+		}
+		// Every category lands in the same `--tiff-stack` file.
+		written.dedup();
+		Ok(Outcome::Written(written))
+	}
+
+	/// Cut out every frame; with `--progress json`, the infer events carry the frame as
+	/// `frame N/M` in `message`.
+	fn process_animation(
+		&mut self,
+		input_path: &Path,
+		frames: Vec<AnimationFrame>,
+		out_path: &Path,
+		apng: bool,
+		cancel: &CancellationToken
+	) -> Result<Outcome> {
+		let args = self.args;
+		let json = args.progress == cli::ProgressFormat::Json;
+		let engine = self.engine()?;
+		let infer = core::ProgressEvent::started("infer");
+		if json {
+			emit_input_json(input_path, &infer);
+		}
+		let total = frames.len();
+		let mut out = Vec::with_capacity(total);
+		for (i, frame) in frames.into_iter().enumerate() {
+			cancel.check()?;
+			let on_progress = |p| {
+				if json {
+					let evt = core::ProgressEvent {
+						message: Some(format!("frame {}/{total}", i + 1)),
+						..core::ProgressEvent::infer(p)
+					};
+					emit_input_json(input_path, &evt);
+				}
+			};
+			let img = DynamicImage::ImageRgba8(frame.image);
+			let img_rgb = img.to_rgb8();
+			let probs = if args.refine {
+				engine.remover.predict_probabilities_refined(&img_rgb, &sam_prompt(args), cancel, on_progress)
+			} else {
+				engine.remover.predict_probabilities_prompted(&img_rgb, &sam_prompt(args), cancel, on_progress)
+			};
+			let mut probs = probs
+				.and_then(|p| engine.remover.refine_edges(&img_rgb, p, cancel, on_progress))
+				.with_context(|| format!("run model on frame {}: {}", i + 1, engine.model.path.display()))
+				.classify(ExitStatus::Inference)?;
+			if args.guided_filter {
+				probs = rembg_rs::mask::guided_filter(&probs, &img_rgb);
+			}
+			let mask = refine_mask(args, u2net::quantize_mask(&probs));
+			let result = if args.only_mask { DynamicImage::ImageLuma8(mask) } else { render(args, &img, &img_rgb, &mask)? };
+			let result = resize_output(args, result);
+			out.push(AnimationFrame {
+				image: result.to_rgba8(),
+				delay_ms: frame.delay_ms
+			});
+		}
+
+		let encode = core::ProgressEvent::started("encode");
+		if json {
+			emit_input_json(input_path, &infer.finished());
+			emit_input_json(input_path, &encode);
+		}
+		let bytes = if apng {
+			animation::encode_apng(&out, cancel)?
 		} else {
-			mask.clone()
+			animation::encode_webp(&out, &self.encode_opts, cancel)?
 		};
-		mask_out.save(&out_path)
-			.with_context(|| format!("write mask: {}", out_path.display()))?;
-		return Ok(());
+		write_output(out_path, &bytes).with_context(|| format!("write animation: {}", out_path.display()))?;
+		if json {
+			emit_input_json(input_path, &encode.finished());
+		}
+		Ok(Outcome::Written(vec![out_path.to_path_buf()]))
+	}
+
+	fn write_result(
+		&mut self,
+		probs: u2net::ProbabilityMap,
+		img: &DynamicImage,
+		img_rgb: &RgbImage,
+		meta: &ImageMetadata,
+		out_path: PathBuf,
+		format: Option<OutputFormat>
+	) -> Result<Vec<PathBuf>> {
+		let args = self.args;
+		let mut probs = probs;
+		if args.guided_filter {
+			probs = rembg_rs::mask::guided_filter(&probs, img_rgb);
+		}
+		let mut outputs = Vec::new();
+		if let Some(raw) = args.raw_mask {
+			let (bytes, ext) = match raw {
+				cli::RawMaskFormat::Exr => (encode::encode_exr_mask(&probs)?, "exr"),
+				cli::RawMaskFormat::Png16 => (encode::encode_png16_mask(&probs)?, "png")
+			};
+			let raw_path = labeled_path(&out_path, "raw").with_extension(ext);
+			write_output(&raw_path, &bytes).with_context(|| format!("write raw mask: {}", raw_path.display()))?;
+			outputs.push(raw_path);
+		}
+		let mask = u2net::quantize_mask(&probs);
+
+		if args.only_mask && format == Some(OutputFormat::Exr) {
+			if args.mask_mode == Some(cli::MaskMode::Logits) {
+				for p in probs.pixels_mut() {
+					let v = p.0[0].clamp(LOGIT_EPS, 1.0 - LOGIT_EPS);
+					p.0[0] = (v / (1.0 - v)).ln();
+				}
+			} else if let Some(t) = mask_threshold(args) {
+				let t = t as f32 / 255.0;
+				for p in probs.pixels_mut() {
+					p.0[0] = if p.0[0] >= t { 1.0 } else { 0.0 };
+				}
+			}
+			let bytes = encode::encode_exr_mask(&probs)?;
+			write_output(&out_path, &bytes)
+				.with_context(|| format!("write mask: {}", out_path.display()))?;
+			outputs.push(out_path);
+			return Ok(outputs);
+		}
+
+		// The float map is the largest buffer left; the quantized mask replaces it.
+		drop(probs);
+		let mask = refine_mask(args, mask);
+		if let Some(mask_format) = args.mask_format.filter(|_| !args.only_mask) {
+			let path = labeled_path(&out_path, "mask").with_extension(mask_format.output_format().extension());
+			let bytes = match mask_format {
+				cli::MaskFormat::Png => {
+					let mut png = Vec::new();
+					mask.write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png).context("encode mask png")?;
+					png
+				}
+				cli::MaskFormat::Svg => rembg_rs::trace::mask_to_svg(&mask, 128, SVG_TOLERANCE).into_bytes()
+			};
+			write_output(&path, &bytes).with_context(|| format!("write mask: {}", path.display()))?;
+			outputs.push(path);
+		}
+		if let Some(viz) = args.visualize.as_deref() {
+			let path = overlay_path(args, viz, &out_path);
+			if !cloud::is_object_url(&path)
+				&& let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty())
+			{
+				std::fs::create_dir_all(parent).with_context(|| format!("create dir: {}", parent.display()))?;
+			}
+			let overlay = DynamicImage::ImageRgb8(compose::overlay(img_rgb, &mask));
+			save(&overlay, &path, None, &self.encode_opts, &ImageMetadata::default())
+				.with_context(|| format!("write overlay: {}", path.display()))?;
+			outputs.push(path);
+		}
+		if args.split_instances {
+			outputs.extend(self.write_instances(img, img_rgb, &mask, meta, &out_path, format)?);
+			return Ok(outputs);
+		}
+
+		if args.low_memory
+			&& !args.only_mask
+			&& self.stack.is_none()
+			&& !cloud::is_object_url(&out_path)
+			&& format.or_else(|| OutputFormat::from_path(&out_path)) == Some(OutputFormat::Png)
+			&& self.encode_opts.png_optimize.is_none()
+			&& is_plain_cutout(args)
+			&& args.resize.is_none()
+			&& args.max_dim.is_none()
+			&& args.scale.is_none()
+			&& !compose::is_high_bit_depth(img)
+		{
+			let key = compose::ColorKey::new(img_rgb, args.color_key_tolerance, color_key_color(args)?, args.despill);
+			let (w, h) = img_rgb.dimensions();
+			let write = |out: &mut dyn Write| {
+				encode::write_png_strips(out, w, h, meta, |y, rows| compose::apply_alpha_rows(img_rgb, &mask, None, key, y, rows))
+			};
+			let written = if is_stdio(&out_path) {
+				write(&mut std::io::stdout().lock())
+			} else {
+				std::fs::File::create(&out_path)
+					.map_err(anyhow::Error::from)
+					.and_then(|f| write(&mut std::io::BufWriter::new(f)))
+			};
+			written.with_context(|| format!("write image: {}", out_path.display()))?;
+			outputs.push(out_path);
+			return Ok(outputs);
+		}
+
+		let (result, what) = if args.only_mask {
+			(DynamicImage::ImageLuma8(mask), "mask")
+		} else {
+			(render(args, img, img_rgb, &mask)?, "image")
+		};
+		let result = resize_output(args, result);
+		outputs.push(self.save_result(&result, what, meta, out_path, format)?);
+		Ok(outputs)
 	}
 
-	let out = if let Some(bg) = args.bgcolor.as_deref() {
-		compose::composite_over_bg(&img_rgb, &mask, args.mask_threshold, bg)?
+	/// `--split-instances`: one output per subject, cropped to it, then the manifest of their boxes.
+	fn write_instances(
+		&mut self,
+		img: &DynamicImage,
+		img_rgb: &RgbImage,
+		mask: &GrayImage,
+		meta: &ImageMetadata,
+		out_path: &Path,
+		format: Option<OutputFormat>
+	) -> Result<Vec<PathBuf>> {
+		let args = self.args;
+		let mut instances = rembg_rs::mask::instances(mask);
+		let largest = instances.iter().map(|i| i.area).max().unwrap_or(0);
+		instances.retain(|i| i.area >= largest / MIN_INSTANCE_SHARE);
+
+		let mut written = Vec::new();
+		let mut boxes = Vec::new();
+		for (n, inst) in instances.into_iter().enumerate() {
+			let (w, h) = inst.mask.dimensions();
+			let path = labeled_path(out_path, &(n + 1).to_string());
+			boxes.push(serde_json::json!({
+				"file": path.file_name().map(|f| f.to_string_lossy()),
+				"x": inst.x,
+				"y": inst.y,
+				"width": w,
+				"height": h,
+				"area": inst.area
+			}));
+			let (result, what) = if args.only_mask {
+				(DynamicImage::ImageLuma8(inst.mask), "mask")
+			} else {
+				let crop = img.crop_imm(inst.x, inst.y, w, h);
+				let crop_rgb = image::imageops::crop_imm(img_rgb, inst.x, inst.y, w, h).to_image();
+				(render(args, &crop, &crop_rgb, &inst.mask)?, "image")
+			};
+			let result = resize_output(args, result);
+			written.push(self.save_result(&result, what, meta, path, format)?);
+		}
+
+		let manifest = serde_json::json!({
+			"width": img.width(),
+			"height": img.height(),
+			"instances": boxes
+		});
+		let manifest_path = manifest_path(out_path);
+		let json = serde_json::to_string_pretty(&manifest).expect("manifest serializes");
+		write_output(&manifest_path, json.as_bytes()).with_context(|| format!("write manifest: {}", manifest_path.display()))?;
+		written.push(manifest_path);
+		Ok(written)
+	}
+
+	/// Write a finished result as a `--tiff-stack` page, traced SVG or image file.
+	fn save_result(
+		&mut self,
+		result: &DynamicImage,
+		what: &str,
+		meta: &ImageMetadata,
+		out_path: PathBuf,
+		format: Option<OutputFormat>
+	) -> Result<PathBuf> {
+		if let Some((stack, stack_path)) = self.stack.as_mut() {
+			stack.push(result, meta)
+				.with_context(|| format!("write tiff page {}: {}", stack.pages() + 1, stack_path.display()))?;
+			return Ok(stack_path.clone());
+		}
+
+		if format == Some(OutputFormat::Svg) {
+			let svg = rembg_rs::trace::mask_to_svg(&result.to_luma8(), 128, SVG_TOLERANCE);
+			write_output(&out_path, svg.as_bytes()).with_context(|| format!("write mask: {}", out_path.display()))?;
+			return Ok(out_path);
+		}
+		save(result, &out_path, format, &self.encode_opts, meta)
+			.with_context(|| format!("write {what}: {}", out_path.display()))?;
+		Ok(out_path)
+	}
+}
+
+/// Intra-op threads per session with `--low-memory`; each thread holds its own scratch buffers.
+const LOW_MEMORY_THREADS: usize = 2;
+
+/// ORT session tuning from the flags. With `--jobs` the workers split the cores between them
+/// unless `--ort-intra-threads` says otherwise.
+fn session_config(args: &cli::Args) -> u2net::SessionConfig {
+	let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+	let mut intra_threads = args.ort_intra_threads.or_else(|| (args.jobs > 1).then(|| (cores / args.jobs).max(1)));
+	if args.low_memory {
+		intra_threads = Some(intra_threads.unwrap_or(cores).min(LOW_MEMORY_THREADS));
+	}
+	u2net::SessionConfig {
+		intra_threads,
+		graph_opt_level: args.ort_graph_opt_level,
+		mem_pattern: args.ort_mem_pattern.or(args.low_memory.then_some(false)),
+		mem_arena: args.low_memory.then_some(false),
+		device_id: args.device_id
+	}
+}
+
+/// Ensure (downloading or asking as `plan` allows) and load the ONNX Runtime.
+fn init_runtime(plan: &runtime::Plan, progress: cli::ProgressFormat) -> Result<()> {
+	let rt = match progress {
+		cli::ProgressFormat::Human => runtime::ensure_onnxruntime(plan)?,
+		cli::ProgressFormat::Json => runtime::ensure_onnxruntime_with_progress(plan, |p| {
+			emit_json(&core::ProgressEvent::download("runtime", p.url, p.progress))
+		})?
+	};
+	runtime::init_ort(&rt)
+}
+
+fn fetch_model(name: &str, verify: bool, progress: cli::ProgressFormat) -> Result<model::ModelInstall> {
+	match progress {
+		cli::ProgressFormat::Human => model::ensure_model(name, verify),
+		cli::ProgressFormat::Json => model::ensure_model_noninteractive(name, true, verify, interrupt(), |p| {
+			emit_json(&core::ProgressEvent::download("model", p.url, p.progress))
+		})
+	}
+	.classify(ExitStatus::Model)
+}
+
+/// `--progress json`: one event per stderr line.
+fn emit_json(evt: &core::ProgressEvent) {
+	eprintln!("{}", serde_json::to_string(evt).expect("progress events serialize"));
+}
+
+/// A `--progress json` event of one input, named so the events of parallel `--jobs` can be told
+/// apart.
+#[derive(serde::Serialize)]
+struct InputProgress<'a> {
+	input: Cow<'a, str>,
+	#[serde(flatten)]
+	evt: &'a core::ProgressEvent
+}
+
+/// `emit_json` for an event of `input`.
+fn emit_input_json(input: &Path, evt: &core::ProgressEvent) {
+	let evt = InputProgress {
+		input: input.to_string_lossy(),
+		evt
+	};
+	eprintln!("{}", serde_json::to_string(&evt).expect("progress events serialize"));
+}
+
+/// Where `--visualize` puts the overlay for the output at `out_path`: `viz` itself when it names an
+/// image file, else `viz/.../NAME_overlay.png` for output `NAME`, with the subdirectories it has
+/// below `--output-dir`.
+fn overlay_path(args: &cli::Args, viz: &Path, out_path: &Path) -> PathBuf {
+	if OutputFormat::from_path(viz).is_some() {
+		return viz.to_path_buf();
+	}
+	let rel = args
+		.output_dir
+		.as_deref()
+		.and_then(|dir| out_path.strip_prefix(dir).ok())
+		.map_or_else(|| PathBuf::from(out_path.file_name().unwrap_or("out".as_ref())), Path::to_path_buf);
+	let name = if is_stdio(out_path) { Path::new("stdout") } else { rel.as_path() };
+	viz.join(labeled_path(name, "overlay")).with_extension("png")
+}
+
+/// `dir/photo_rembg.png` -> `dir/photo_rembg_<label>.png`.
+fn labeled_path(path: &Path, label: &str) -> PathBuf {
+	let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("out");
+	let name = match path.extension().and_then(|e| e.to_str()) {
+		Some(ext) => format!("{stem}_{label}.{ext}"),
+		None => format!("{stem}_{label}")
+	};
+	path.with_file_name(name)
+}
+
+/// `path`, or the first of `name_1.ext`, `name_2.ext`, ... that isn't `taken`.
+fn unused_path(path: &Path, taken: impl Fn(&Path) -> bool) -> PathBuf {
+	if !taken(path) {
+		return path.to_path_buf();
+	}
+	(1..).map(|n| labeled_path(path, &n.to_string())).find(|p| !taken(p)).expect("unbounded range")
+}
+
+/// `--split-instances`: subjects smaller than 1/N of the largest one are specks, not outputs.
+const MIN_INSTANCE_SHARE: u32 = 100;
+
+/// `dir/photo_rembg.png` -> `dir/photo_rembg.json`, the `--split-instances` manifest.
+fn manifest_path(path: &Path) -> PathBuf {
+	path.with_extension("json")
+}
+
+/// How far (in pixels) `--format svg` may move the traced outline to save vertices.
+const SVG_TOLERANCE: f32 = 0.75;
+
+/// Threshold, then feather: the order matters, feathering exists to soften binarized edges.
+fn refine_mask(args: &cli::Args, mut mask: GrayImage) -> GrayImage {
+	if let Some(t) = mask_threshold(args) {
+		mask = rembg_rs::mask::threshold(&mask, t);
+	}
+	if let Some(max_area) = args.fill_holes {
+		mask = rembg_rs::mask::fill_holes(&mask, max_area);
+	}
+	if let Some(sigma) = args.feather {
+		mask = rembg_rs::mask::feather(&mask, sigma, args.feather_band);
+	}
+	mask
+}
+
+/// `--mask-mode logits` clamps probabilities this far from 0 and 1, so saturated pixels come out
+/// as about ±16 instead of infinite.
+const LOGIT_EPS: f32 = 1e-7;
+
+/// The cut `--mask-threshold` and `--mask-mode` ask for, if any.
+fn mask_threshold(args: &cli::Args) -> Option<u8> {
+	match args.mask_mode {
+		Some(cli::MaskMode::Soft) => None,
+		Some(cli::MaskMode::Binary) => Some(args.mask_threshold.unwrap_or(128)),
+		_ => args.mask_threshold
+	}
+}
+
+fn color_key_color(args: &cli::Args) -> Result<Option<(u8, u8, u8)>> {
+	args.color_key_color.as_deref().map(|c| compose::parse_hex_rgb(c).context("--color-key-color")).transpose()
+}
+
+fn has_background(args: &cli::Args) -> bool {
+	args.bgcolor.is_some()
+		|| args.background_file.is_some()
+		|| args.bg_tile.is_some()
+		|| args.bg_blur.is_some()
+		|| args.checkerboard.is_some()
+}
+
+/// The source pixels with the mask as alpha: no background or effects (resizing aside).
+fn is_plain_cutout(args: &cli::Args) -> bool {
+	!has_background(args) && !args.reflection && args.canvas.is_none() && args.place.is_none()
+}
+
+/// `--resize`, `--max-dim` or `--scale`, applied to the finished result (after any canvas and background).
+fn resize_output(args: &cli::Args, img: DynamicImage) -> DynamicImage {
+	let (w, h) = (img.width(), img.height());
+	let (nw, nh) = if let Some(size) = args.resize {
+		let s = (size.width as f32 / w as f32).min(size.height as f32 / h as f32);
+		(w as f32 * s, h as f32 * s)
+	} else if let Some(max) = args.max_dim
+		&& w.max(h) > max
+	{
+		let s = max as f32 / w.max(h) as f32;
+		(w as f32 * s, h as f32 * s)
+	} else if let Some(s) = args.scale {
+		(w as f32 * s, h as f32 * s)
+	} else {
+		return img;
+	};
+	let (nw, nh) = ((nw.round() as u32).max(1), (nh.round() as u32).max(1));
+	if (nw, nh) == (w, h) {
+		return img;
+	}
+	img.resize_exact(nw, nh, FilterType::Lanczos3)
+}
+
+/// Why an input falls outside `--min-size`/`--max-size`, if it does.
+fn size_filter(args: &cli::Args, w: u32, h: u32) -> Option<String> {
+	if let Some(min) = args.min_size
+		&& (w < min.width || h < min.height)
+	{
+		return Some(format!("{w}x{h} is smaller than --min-size {min}"));
+	}
+	if let Some(max) = args.max_size
+		&& (w > max.width || h > max.height)
+	{
+		return Some(format!("{w}x{h} is larger than --max-size {max}"));
+	}
+	None
+}
+
+fn render(args: &cli::Args, img: &DynamicImage, img_rgb: &RgbImage, mask: &GrayImage) -> Result<DynamicImage> {
+	// Image-like backgrounds: a file, a tiled texture, the blurred original, or the built-in checkerboard.
+	let placement = compose::BgPlacement {
+		fit: args.bg_fit,
+		anchor: args.bg_anchor,
+		brightness: args.background_brightness,
+		blur: args.background_blur
+	};
+	let bg_source = if let Some(p) = args.background_file.as_deref() {
+		Some((open_background(p)?, placement))
+	} else if let Some(p) = args.bg_tile.as_deref() {
+		Some((open_background(p)?, compose::BgPlacement { fit: compose::BgFit::Tile, ..placement }))
+	} else if let Some(sigma) = args.bg_blur {
+		Some((img_rgb.clone(), compose::BgPlacement { blur: Some(sigma), ..placement }))
+	} else if let Some(cell) = args.checkerboard {
+		let [a, b] = match args.checkerboard_colors.as_deref() {
+			Some(s) => parse_color_pair(s)?,
+			None => [(255, 255, 255), (204, 204, 204)]
+		};
+		let tile = compose::checkerboard_tile(cell, a, b);
+		Some((tile, compose::BgPlacement { fit: compose::BgFit::Tile, ..placement }))
+	} else {
+		None
+	};
+	let bgcolor = args.bgcolor.as_deref().map(compose::parse_fill).transpose()?;
+
+	// Color-keying only makes sense when the result stays transparent.
+	let color_key = if bg_source.is_none() && bgcolor.is_none() {
+		compose::ColorKey::new(img_rgb, args.color_key_tolerance, color_key_color(args)?, args.despill)
 	} else {
-		compose::apply_alpha(&img_rgb, &mask, args.mask_threshold, args.color_key_tolerance)
+		None
 	};
 
-	out.save(&out_path)
-		.with_context(|| format!("write image: {}", out_path.display()))?;
+	// Plain cutouts of 16-bit sources stay 16-bit; the compositing effects below work in 8-bit.
+	if is_plain_cutout(args) && compose::is_high_bit_depth(img) {
+		return Ok(compose::apply_alpha_16(&img.to_rgb16(), mask, None, color_key));
+	}
+
+	let mut fg = compose::apply_alpha(img_rgb, mask, None, color_key).into_rgba8();
+
+	if args.reflection {
+		let r = compose::Reflection {
+			height: args.reflection_height,
+			opacity: args.reflection_opacity,
+			fade: args.reflection_fade,
+			gap: args.reflection_gap
+		};
+		fg = compose::add_reflection(&fg, &r);
+	}
+	if let Some(place) = args.place {
+		let (w, h) = match (args.canvas, &bg_source) {
+			(Some(size), _) => (size.width, size.height),
+			(None, Some((bg, _))) if args.background_file.is_some() => bg.dimensions(),
+			_ => fg.dimensions()
+		};
+		fg = compose::place_transformed(&fg, w, h, place.scale, place.rotate, place.x as i64, place.y as i64);
+	} else if let Some(size) = args.canvas {
+		fg = compose::place_on_canvas(&fg, size.width, size.height, args.canvas_margin, args.position);
+	}
+
+	let shadow = if args.shadow {
+		Some(compose::Shadow {
+			offset_x: args.shadow_offset.x,
+			offset_y: args.shadow_offset.y,
+			blur: args.shadow_blur,
+			opacity: args.shadow_opacity,
+			color: compose::parse_hex_rgb(&args.shadow_color)?
+		})
+	} else {
+		None
+	};
+
+	let (w, h) = fg.dimensions();
+	if let Some((bg, placement)) = bg_source {
+		let canvas = compose::place_background(&bg, w, h, &placement, bgcolor.as_ref());
+		if let Some(strength) = args.harmonize {
+			fg = compose::harmonize(&fg, &canvas, strength);
+		}
+		if let Some(s) = &shadow {
+			fg = compose::add_shadow(&fg, s);
+		}
+		return Ok(DynamicImage::ImageRgba8(compose::flatten(&fg, &canvas)));
+	}
+	if let Some(fill) = bgcolor {
+		if let Some(s) = &shadow {
+			fg = compose::add_shadow(&fg, s);
+		}
+		let out = match fill {
+			compose::Fill::Solid(c) => compose::flatten_onto_color(&fg, c),
+			gradient => DynamicImage::ImageRgba8(compose::flatten(&fg, &gradient.canvas(w, h))).to_rgb8()
+		};
+		return Ok(DynamicImage::ImageRgb8(out));
+	}
+	Ok(DynamicImage::ImageRgba8(fg))
+}
+
+fn open_background(path: &Path) -> Result<RgbImage> {
+	let bytes = std::fs::read(path).with_context(|| format!("read background image: {}", path.display()))?;
+	let img = rembg_rs::decode::load_from_memory(&bytes).with_context(|| format!("open background image: {}", path.display()))?;
+	Ok(img.to_rgb8())
+}
+
+fn parse_color_pair(s: &str) -> Result<[(u8, u8, u8); 2]> {
+	let Some((a, b)) = s.split_once(',') else {
+		bail!("invalid color pair {s:?} (expected RRGGBB,RRGGBB)");
+	};
+	Ok([compose::parse_hex_rgb(a)?, compose::parse_hex_rgb(b)?])
+}
+
+fn save(
+	img: &DynamicImage,
+	path: &Path,
+	format: Option<OutputFormat>,
+	opts: &EncodeOptions,
+	meta: &ImageMetadata
+) -> Result<()> {
+	// Without `--format` the extension picks it (PNG for anything else), so encoder settings and
+	// metadata apply to the default outputs too.
+	let format = format.or_else(|| OutputFormat::from_path(path)).unwrap_or(OutputFormat::Png);
+	let bytes = encode::encode(img, format, opts, meta)?;
+	write_output(path, &bytes)
+}
+
+/// `-` stands for stdin (as input) or stdout (as output).
+fn is_stdio(path: &Path) -> bool {
+	path.as_os_str() == "-"
+}
+
+/// An `http://` or `https://` input.
+fn input_url(path: &Path) -> Option<&str> {
+	path.to_str().filter(|s| s.starts_with("http://") || s.starts_with("https://"))
+}
+
+/// `https://example.com/img/photo.jpg?w=800` -> `photo`. Characters Windows doesn't allow in file
+/// names become `_`.
+fn url_stem(url: &str) -> Cow<'_, str> {
+	let path = url.split(['?', '#']).next().unwrap_or(url);
+	let name = path.rsplit(['/', '\\']).next().unwrap_or_default();
+	let stem = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+	let invalid = |c: char| c.is_control() || matches!(c, '<' | '>' | ':' | '"' | '|' | '?' | '*');
+	if stem.is_empty() {
+		Cow::Borrowed("download")
+	} else if stem.contains(invalid) {
+		Cow::Owned(stem.replace(invalid, "_"))
+	} else {
+		Cow::Borrowed(stem)
+	}
+}
+
+fn read_input(path: &Path, progress: cli::ProgressFormat) -> Result<Vec<u8>> {
+	if is_stdio(path) {
+		let mut buf = Vec::new();
+		std::io::stdin().lock().read_to_end(&mut buf).context("read image from stdin")?;
+		return Ok(buf);
+	}
+	if let Some(url) = input_url(path) {
+		return download_input(url, progress);
+	}
+	if cloud::is_object_url(path) {
+		return cloud::get(path);
+	}
+	std::fs::read(path).with_context(|| format!("read image: {}", path.display()))
+}
+
+/// Fetch a URL input through a temp file (removed again either way), with the usual download progress.
+/// The file lives in a fresh private directory, so nobody sharing the temp dir can plant a symlink
+/// where it gets written.
+#[cfg(feature = "download")]
+fn download_input(url: &str, progress: cli::ProgressFormat) -> Result<Vec<u8>> {
+	use rembg_rs::download;
+
+	let dir = tempfile::tempdir().context("create temp dir for download")?;
+	let tmp = dir.path().join("input");
+	let digests = download::Digests { sha256_hex: None, md5_hex: None };
+	let cancel = interrupt();
+	let fetched = match progress {
+		cli::ProgressFormat::Human => {
+			let mut human = download::HumanProgress::default();
+			download::download_to_path_with_progress(url, &tmp, digests, cancel, |p| human.report(url, p))
+		}
+		cli::ProgressFormat::Json => download::download_to_path_with_progress(url, &tmp, digests, cancel, |p| {
+			emit_input_json(Path::new(url), &core::ProgressEvent::download("input", url, p))
+		})
+	};
+	let bytes = fetched.and_then(|()| std::fs::read(&tmp).with_context(|| format!("read download: {}", tmp.display())));
+	bytes.with_context(|| format!("download image: {url}"))
+}
+
+#[cfg(not(feature = "download"))]
+fn download_input(url: &str, _progress: cli::ProgressFormat) -> Result<Vec<u8>> {
+	bail!("URL inputs are not compiled in (rebuild with `--features download`): {url}")
+}
+
+fn write_output(path: &Path, bytes: &[u8]) -> Result<()> {
+	if is_stdio(path) {
+		let mut out = std::io::stdout().lock();
+		out.write_all(bytes)?;
+		out.flush()?;
+		return Ok(());
+	}
+	if cloud::is_object_url(path) {
+		return cloud::put(path, bytes);
+	}
+	std::fs::write(path, bytes)?;
 	Ok(())
 }
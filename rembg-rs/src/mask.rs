@@ -0,0 +1,378 @@
+use std::collections::VecDeque;
+
+use image::imageops::FilterType;
+use image::{GrayImage, Luma, RgbImage};
+
+use crate::u2net::ProbabilityMap;
+
+/// Longest side of the grid `guided_filter` solves on.
+const GUIDED_WORK_SIZE: u32 = 1024;
+/// Window radius of `guided_filter`, in work-grid pixels.
+const GUIDED_RADIUS: usize = 4;
+/// Regularization of `guided_filter`: higher keeps more of the input mask in low-contrast areas.
+const GUIDED_EPS: f32 = 1e-3;
+/// How far (in pixels) `instances` lets a subject claim the soft pixels around its solid core.
+const INSTANCE_EDGE_RADIUS: u32 = 16;
+
+/// Binarize: alpha becomes 0 or 255 depending on `t`.
+pub fn threshold(mask: &GrayImage, t: u8) -> GrayImage {
+	let mut out = mask.clone();
+	for p in out.pixels_mut() {
+		p.0[0] = if p.0[0] >= t { 255 } else { 0 };
+	}
+	out
+}
+
+/// Fill enclosed background regions (holes not connected to the image border) of at most
+/// `max_area` pixels, e.g. specular highlights the model took for background.
+pub fn fill_holes(mask: &GrayImage, max_area: u32) -> GrayImage {
+	let (w, h) = (mask.width() as usize, mask.height() as usize);
+	let bg: Vec<bool> = mask.pixels().map(|p| p.0[0] < 128).collect();
+	let mut seen = vec![false; bg.len()];
+	let mut out = mask.clone();
+	let mut stack = Vec::new();
+	let mut region = Vec::new();
+
+	for start in 0..bg.len() {
+		if !bg[start] || seen[start] {
+			continue;
+		}
+		// 4-connected flood fill of one background region.
+		seen[start] = true;
+		stack.push(start);
+		region.clear();
+		let mut touches_border = false;
+		while let Some(i) = stack.pop() {
+			region.push(i);
+			let (x, y) = (i % w, i / w);
+			if x == 0 || y == 0 || x + 1 == w || y + 1 == h {
+				touches_border = true;
+			}
+			let neighbors = [
+				(x > 0).then(|| i - 1),
+				(x + 1 < w).then(|| i + 1),
+				(y > 0).then(|| i - w),
+				(y + 1 < h).then(|| i + w)
+			];
+			for n in neighbors.into_iter().flatten() {
+				if bg[n] && !seen[n] {
+					seen[n] = true;
+					stack.push(n);
+				}
+			}
+		}
+		if !touches_border && region.len() <= max_area as usize {
+			for &i in &region {
+				out.as_mut()[i] = 255;
+			}
+		}
+	}
+	out
+}
+
+/// Soften mask edges with a Gaussian blur of `sigma` pixels.
+///
+/// With `band`, only pixels within that many pixels of the foreground/background boundary are
+/// touched, so solid interiors and thin gaps elsewhere stay exactly as they were.
+pub fn feather(mask: &GrayImage, sigma: f32, band: Option<u32>) -> GrayImage {
+	if sigma <= 0.0 {
+		return mask.clone();
+	}
+	let blurred = image::imageops::blur(mask, sigma);
+	let Some(r) = band else {
+		return blurred;
+	};
+
+	let (w, h) = mask.dimensions();
+	let fg: Vec<bool> = mask.pixels().map(|p| p.0[0] >= 128).collect();
+	let grown_fg = dilate(&fg, w as usize, h as usize, r as usize);
+	let inverted: Vec<bool> = fg.iter().map(|v| !v).collect();
+	let grown_bg = dilate(&inverted, w as usize, h as usize, r as usize);
+
+	GrayImage::from_fn(w, h, |x, y| {
+		let i = (y * w + x) as usize;
+		// Near an edge when both foreground and background occur within the band.
+		if grown_fg[i] && grown_bg[i] { *blurred.get_pixel(x, y) } else { *mask.get_pixel(x, y) }
+	})
+}
+
+/// One subject found by `instances`: its pixels of the mask, cropped to its bounding box at (`x`, `y`).
+pub struct Instance {
+	pub x: u32,
+	pub y: u32,
+	/// Pixels that belong to the subject (mask > 0).
+	pub area: u32,
+	/// Zero outside the subject, so neighbours overlapping the box don't show up in it.
+	pub mask: GrayImage
+}
+
+/// Split a mask into separate subjects: 8-connected regions of solid pixels (>= 128), each
+/// extended by the soft edge pixels within `INSTANCE_EDGE_RADIUS` of it so feathered outlines and
+/// hair stay with the subject they border. Ordered left to right (then top to bottom).
+pub fn instances(mask: &GrayImage) -> Vec<Instance> {
+	let (w, h) = (mask.width() as usize, mask.height() as usize);
+	let alpha = mask.as_raw();
+	let neighbors = |i: usize| {
+		let (x, y) = ((i % w) as isize, (i / w) as isize);
+		[(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)]
+			.into_iter()
+			.map(move |(dx, dy)| (x + dx, y + dy))
+			.filter(|&(x, y)| x >= 0 && y >= 0 && x < w as isize && y < h as isize)
+			.map(|(x, y)| y as usize * w + x as usize)
+	};
+
+	// Labels start at 1; 0 is "no subject".
+	let mut label = vec![0u32; alpha.len()];
+	let mut count = 0;
+	let mut stack = Vec::new();
+	for start in 0..alpha.len() {
+		if alpha[start] < 128 || label[start] != 0 {
+			continue;
+		}
+		count += 1;
+		label[start] = count;
+		stack.push(start);
+		while let Some(i) = stack.pop() {
+			for n in neighbors(i) {
+				if alpha[n] >= 128 && label[n] == 0 {
+					label[n] = count;
+					stack.push(n);
+				}
+			}
+		}
+	}
+
+	// Breadth-first, so a soft pixel between two subjects goes to the nearer one.
+	let mut queue: VecDeque<(usize, u32)> = (0..alpha.len()).filter(|&i| label[i] != 0).map(|i| (i, 0)).collect();
+	while let Some((i, dist)) = queue.pop_front() {
+		if dist == INSTANCE_EDGE_RADIUS {
+			continue;
+		}
+		for n in neighbors(i) {
+			if alpha[n] > 0 && label[n] == 0 {
+				label[n] = label[i];
+				queue.push_back((n, dist + 1));
+			}
+		}
+	}
+
+	// Per label: x0, y0, x1, y1 (inclusive), area.
+	let mut boxes = vec![(u32::MAX, u32::MAX, 0, 0, 0); count as usize];
+	for (i, &l) in label.iter().enumerate() {
+		if l != 0 {
+			let (x, y) = ((i % w) as u32, (i / w) as u32);
+			let b = &mut boxes[l as usize - 1];
+			*b = (b.0.min(x), b.1.min(y), b.2.max(x), b.3.max(y), b.4 + 1);
+		}
+	}
+	let mut out: Vec<Instance> = boxes
+		.iter()
+		.zip(1..)
+		.map(|(&(x0, y0, x1, y1, area), l)| Instance {
+			x: x0,
+			y: y0,
+			area,
+			mask: GrayImage::from_fn(x1 - x0 + 1, y1 - y0 + 1, |x, y| {
+				let i = (y0 + y) as usize * w + (x0 + x) as usize;
+				Luma([if label[i] == l { alpha[i] } else { 0 }])
+			})
+		})
+		.collect();
+	out.sort_by_key(|inst| (inst.x, inst.y));
+	out
+}
+
+/// Snap a soft mask's edges to the edges in `guide` (the photo it was predicted from) with a
+/// color guided filter (He et al.), so an upsampled low-res mask follows hair and outlines instead
+/// of blurring across them. The filter is solved on a grid of at most `GUIDED_WORK_SIZE` pixels
+/// and its coefficients upsampled ("fast guided filter"), which keeps large photos cheap.
+pub fn guided_filter(probs: &ProbabilityMap, guide: &RgbImage) -> ProbabilityMap {
+	let (w, h) = probs.dimensions();
+	if w == 0 || h == 0 || guide.dimensions() != (w, h) {
+		return probs.clone();
+	}
+	let scale = (w.max(h) as f32 / GUIDED_WORK_SIZE as f32).max(1.0);
+	let (lw, lh) = (((w as f32 / scale).round() as u32).max(1), ((h as f32 / scale).round() as u32).max(1));
+	let small_guide = image::imageops::resize(guide, lw, lh, FilterType::Triangle);
+	let small_probs = image::imageops::resize(probs, lw, lh, FilterType::Triangle);
+	let (lw, lh) = (lw as usize, lh as usize);
+	let channel = |c: usize| -> Vec<f32> { small_guide.pixels().map(|p| p.0[c] as f32 / 255.0).collect() };
+	let i = [channel(0), channel(1), channel(2)];
+	let p: Vec<f32> = small_probs.pixels().map(|v| v.0[0]).collect();
+	let mean = |v: &[f32]| box_mean(v, lw, lh, GUIDED_RADIUS);
+	let product = |a: &[f32], b: &[f32]| -> Vec<f32> { a.iter().zip(b).map(|(x, y)| x * y).collect() };
+
+	let mean_i = [mean(&i[0]), mean(&i[1]), mean(&i[2])];
+	let mean_p = mean(&p);
+	let corr_ip = [mean(&product(&i[0], &p)), mean(&product(&i[1], &p)), mean(&product(&i[2], &p))];
+	// Upper triangle of E[I Iᵀ]: rr, rg, rb, gg, gb, bb.
+	let pairs = [(0, 0), (0, 1), (0, 2), (1, 1), (1, 2), (2, 2)];
+	let corr_ii = pairs.map(|(a, b)| mean(&product(&i[a], &i[b])));
+
+	// Per window: a = (Σ + εI)⁻¹ cov(I, p), b = mean(p) - a·mean(I).
+	let n = lw * lh;
+	let (mut a, mut b) = ([vec![0.0; n], vec![0.0; n], vec![0.0; n]], vec![0.0; n]);
+	for k in 0..n {
+		let m = [mean_i[0][k], mean_i[1][k], mean_i[2][k]];
+		let cov = [0, 1, 2].map(|c| (corr_ip[c][k] - m[c] * mean_p[k]) as f64);
+		let [rr, rg, rb, gg, gb, bb] = [0, 1, 2, 3, 4, 5].map(|j| (corr_ii[j][k] - m[pairs[j].0] * m[pairs[j].1]) as f64);
+		let eps = GUIDED_EPS as f64;
+		let sigma = [[rr + eps, rg, rb], [rg, gg + eps, gb], [rb, gb, bb + eps]];
+		let coef = solve3(&sigma, &cov).map(|v| v as f32);
+		for c in 0..3 {
+			a[c][k] = coef[c];
+		}
+		b[k] = mean_p[k] - coef[0] * m[0] - coef[1] * m[1] - coef[2] * m[2];
+	}
+	let mean_a = [mean(&a[0]), mean(&a[1]), mean(&a[2])];
+	let mean_b = mean(&b);
+
+	// q = mean(a)·I + mean(b), with the coefficients sampled bilinearly at full resolution.
+	ProbabilityMap::from_fn(w, h, |x, y| {
+		let fx = ((x as f32 + 0.5) * lw as f32 / w as f32 - 0.5).clamp(0.0, (lw - 1) as f32);
+		let fy = ((y as f32 + 0.5) * lh as f32 / h as f32 - 0.5).clamp(0.0, (lh - 1) as f32);
+		let (x0, y0) = (fx as usize, fy as usize);
+		let (x1, y1) = ((x0 + 1).min(lw - 1), (y0 + 1).min(lh - 1));
+		let (tx, ty) = (fx - x0 as f32, fy - y0 as f32);
+		let sample = |v: &[f32]| {
+			let top = v[y0 * lw + x0] * (1.0 - tx) + v[y0 * lw + x1] * tx;
+			let bottom = v[y1 * lw + x0] * (1.0 - tx) + v[y1 * lw + x1] * tx;
+			top * (1.0 - ty) + bottom * ty
+		};
+		let px = guide.get_pixel(x, y).0;
+		let q = (0..3).map(|c| sample(&mean_a[c]) * px[c] as f32 / 255.0).sum::<f32>() + sample(&mean_b);
+		Luma([q.clamp(0.0, 1.0)])
+	})
+}
+
+/// A matting trimap of `probs`: 255 for sure foreground, 0 for sure background and 128 for the
+/// unknown band within `radius` pixels of the 0.5 boundary, where a matting model should decide.
+pub fn trimap(probs: &ProbabilityMap, radius: u32) -> GrayImage {
+	let (w, h) = probs.dimensions();
+	let fg: Vec<bool> = probs.pixels().map(|p| p.0[0] >= 0.5).collect();
+	let grown_fg = dilate(&fg, w as usize, h as usize, radius as usize);
+	let inverted: Vec<bool> = fg.iter().map(|v| !v).collect();
+	let grown_bg = dilate(&inverted, w as usize, h as usize, radius as usize);
+	GrayImage::from_fn(w, h, |x, y| {
+		let i = (y * w + x) as usize;
+		Luma([match (grown_fg[i], grown_bg[i]) {
+			(true, true) => 128,
+			(true, false) => 255,
+			_ => 0
+		}])
+	})
+}
+
+/// Paint one brush stroke along the polyline `points` (a single point is a dab) into `mask`: towards
+/// 255 with `add`, towards 0 otherwise. Full strength within `radius * hardness` of the line,
+/// fading linearly to nothing at `radius`.
+pub fn paint_stroke(mask: &mut GrayImage, points: &[[f32; 2]], radius: f32, hardness: f32, add: bool) {
+	if points.is_empty() || radius <= 0.0 {
+		return;
+	}
+	let core = radius * hardness.clamp(0.0, 1.0);
+	let (w, h) = mask.dimensions();
+	let (mut x0, mut y0, mut x1, mut y1) = (f32::MAX, f32::MAX, f32::MIN, f32::MIN);
+	for [x, y] in points {
+		x0 = x0.min(x - radius);
+		y0 = y0.min(y - radius);
+		x1 = x1.max(x + radius);
+		y1 = y1.max(y + radius);
+	}
+	let clip = |v: f32, len: u32| v.floor().clamp(0.0, len as f32) as u32;
+	let (x0, y0, x1, y1) = (clip(x0, w), clip(y0, h), clip(x1 + 1.0, w), clip(y1 + 1.0, h));
+	let segments: Vec<([f32; 2], [f32; 2])> = match points {
+		[p] => vec![(*p, *p)],
+		_ => points.windows(2).map(|s| (s[0], s[1])).collect()
+	};
+	for y in y0..y1 {
+		for x in x0..x1 {
+			let p = [x as f32 + 0.5, y as f32 + 0.5];
+			let d = segments.iter().map(|&(a, b)| segment_distance(p, a, b)).fold(f32::MAX, f32::min);
+			let strength = if d <= core {
+				1.0
+			} else if d < radius {
+				(radius - d) / (radius - core)
+			} else {
+				continue;
+			};
+			let v = &mut mask.get_pixel_mut(x, y).0[0];
+			let target = if add { 255.0 } else { 0.0 };
+			*v = (*v as f32 + (target - *v as f32) * strength).round() as u8;
+		}
+	}
+}
+
+/// Distance from `p` to the segment `a`-`b`.
+fn segment_distance(p: [f32; 2], a: [f32; 2], b: [f32; 2]) -> f32 {
+	let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+	let len2 = dx * dx + dy * dy;
+	let t = if len2 > 0.0 { (((p[0] - a[0]) * dx + (p[1] - a[1]) * dy) / len2).clamp(0.0, 1.0) } else { 0.0 };
+	(p[0] - a[0] - t * dx).hypot(p[1] - a[1] - t * dy)
+}
+
+/// Mean over the `(2r + 1)²` window around each pixel, clipped at the borders.
+fn box_mean(src: &[f32], w: usize, h: usize, r: usize) -> Vec<f32> {
+	let rows = box_mean_1d(src, w, h, r, 1, w);
+	box_mean_1d(&rows, h, w, r, w, 1)
+}
+
+/// `box_mean` along one axis, laid out like `dilate_1d`.
+fn box_mean_1d(src: &[f32], len: usize, lines: usize, r: usize, step: usize, line_stride: usize) -> Vec<f32> {
+	let mut out = vec![0.0; src.len()];
+	let mut prefix = vec![0.0f64; len + 1];
+	for l in 0..lines {
+		let base = l * line_stride;
+		for k in 0..len {
+			prefix[k + 1] = prefix[k] + src[base + k * step] as f64;
+		}
+		for k in 0..len {
+			let lo = k.saturating_sub(r);
+			let hi = (k + r + 1).min(len);
+			out[base + k * step] = ((prefix[hi] - prefix[lo]) / (hi - lo) as f64) as f32;
+		}
+	}
+	out
+}
+
+/// `m⁻¹ v` for a symmetric positive definite 3x3 `m` (Cramer's rule). In f64: with ε = 1e-3 the
+/// determinant of a flat window is about 1e-9.
+fn solve3(m: &[[f64; 3]; 3], v: &[f64; 3]) -> [f64; 3] {
+	let det3 = |c0: [f64; 3], c1: [f64; 3], c2: [f64; 3]| {
+		c0[0] * (c1[1] * c2[2] - c1[2] * c2[1]) - c1[0] * (c0[1] * c2[2] - c0[2] * c2[1]) + c2[0] * (c0[1] * c1[2] - c0[2] * c1[1])
+	};
+	let col = |j: usize| [m[0][j], m[1][j], m[2][j]];
+	let det = det3(col(0), col(1), col(2));
+	if det.abs() < 1e-30 {
+		return [0.0; 3];
+	}
+	[
+		det3(*v, col(1), col(2)) / det,
+		det3(col(0), *v, col(2)) / det,
+		det3(col(0), col(1), *v) / det
+	]
+}
+
+/// Square dilation with radius `r` (separable: rows, then columns).
+fn dilate(src: &[bool], w: usize, h: usize, r: usize) -> Vec<bool> {
+	let rows = dilate_1d(src, w, h, r, 1, w);
+	dilate_1d(&rows, h, w, r, w, 1)
+}
+
+/// Dilate `lines` runs of `len` pixels each (pixel `k` of line `l` is at `l * line_stride + k * step`).
+fn dilate_1d(src: &[bool], len: usize, lines: usize, r: usize, step: usize, line_stride: usize) -> Vec<bool> {
+	let mut out = vec![false; src.len()];
+	let mut prefix = vec![0u32; len + 1];
+	for l in 0..lines {
+		let base = l * line_stride;
+		for k in 0..len {
+			prefix[k + 1] = prefix[k] + src[base + k * step] as u32;
+		}
+		for k in 0..len {
+			let lo = k.saturating_sub(r);
+			let hi = (k + r + 1).min(len);
+			out[base + k * step] = prefix[hi] > prefix[lo];
+		}
+	}
+	out
+}
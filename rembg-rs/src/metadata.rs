@@ -0,0 +1,414 @@
+use std::io::{Cursor, Write};
+
+use anyhow::{Context, Result, bail};
+use image::ImageDecoder;
+use serde::{Deserialize, Serialize};
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const INCH_PER_METER: f64 = 0.0254;
+
+/// Physical pixel density in dots per inch.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Density {
+	pub x: f64,
+	pub y: f64
+}
+
+/// Metadata carried over from the input to the output. The pixels are re-encoded from scratch, so
+/// anything we want to keep has to be read from the original bytes and written back explicitly.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImageMetadata {
+	pub dpi: Option<Density>,
+	/// Embedded ICC color profile, verbatim. Pixels are never color-converted, so the original
+	/// profile still describes the output.
+	#[serde(default)]
+	pub icc: Option<Vec<u8>>,
+	/// Raw EXIF (TIFF-structured, without the JPEG `Exif\0\0` prefix).
+	#[serde(default)]
+	pub exif: Option<Vec<u8>>,
+	/// XMP packet (UTF-8 XML).
+	#[serde(default)]
+	pub xmp: Option<Vec<u8>>
+}
+
+/// Which input metadata ends up in the output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetadataPolicy {
+	/// DPI and ICC profile only: they change how the pixels are interpreted.
+	#[default]
+	Basic,
+	/// Also EXIF and XMP (capture date, camera, GPS, copyright, ...).
+	Keep,
+	/// Nothing at all.
+	Strip
+}
+
+impl ImageMetadata {
+	/// Best-effort read from encoded input bytes; unknown formats or malformed headers yield nothing.
+	pub fn read(bytes: &[u8]) -> ImageMetadata {
+		ImageMetadata::read_with(bytes, MetadataPolicy::default())
+	}
+
+	pub fn read_with(bytes: &[u8], policy: MetadataPolicy) -> ImageMetadata {
+		let (exif, xmp) = match policy {
+			MetadataPolicy::Strip => return ImageMetadata::default(),
+			MetadataPolicy::Basic => (None, None),
+			MetadataPolicy::Keep => read_exif_xmp(bytes)
+		};
+		ImageMetadata {
+			dpi: read_dpi(bytes),
+			icc: read_icc(bytes),
+			exif,
+			xmp
+		}
+	}
+}
+
+pub fn read_dpi(bytes: &[u8]) -> Option<Density> {
+	if bytes.starts_with(PNG_SIGNATURE) {
+		png_dpi(bytes)
+	} else if bytes.starts_with(&[0xFF, 0xD8]) {
+		jpeg_dpi(bytes)
+	} else if bytes.starts_with(b"II*\0") || bytes.starts_with(b"MM\0*") {
+		tiff_dpi(bytes)
+	} else {
+		None
+	}
+}
+
+pub fn read_icc(bytes: &[u8]) -> Option<Vec<u8>> {
+	if bytes.starts_with(b"II*\0") || bytes.starts_with(b"MM\0*") {
+		// `image`'s TIFF decoder looks the profile up under a tag id this `tiff` version no longer
+		// reports, so ask `tiff` directly.
+		let mut dec = tiff::decoder::Decoder::new(Cursor::new(bytes)).ok()?;
+		return dec.get_tag_u8_vec(tiff::tags::Tag::IccProfile).ok().filter(|p| !p.is_empty());
+	}
+	let reader = image::ImageReader::new(Cursor::new(bytes)).with_guessed_format().ok()?;
+	let mut dec = reader.into_decoder().ok()?;
+	dec.icc_profile().ok().flatten().filter(|p| !p.is_empty())
+}
+
+/// EXIF and XMP as exposed by `image` (PNG, JPEG, WebP; TIFF inputs only yield XMP).
+pub fn read_exif_xmp(bytes: &[u8]) -> (Option<Vec<u8>>, Option<Vec<u8>>) {
+	let dec = image::ImageReader::new(Cursor::new(bytes))
+		.with_guessed_format()
+		.ok()
+		.and_then(|r| r.into_decoder().ok());
+	let Some(mut dec) = dec else {
+		return (None, None);
+	};
+	let exif = dec
+		.exif_metadata()
+		.ok()
+		.flatten()
+		.map(|e| e.strip_prefix(b"Exif\0\0").map(<[u8]>::to_vec).unwrap_or(e))
+		.filter(|e| !e.is_empty());
+	let xmp = dec.xmp_metadata().ok().flatten().filter(|x| !x.is_empty());
+	(exif, xmp)
+}
+
+/// Re-emit `meta` into an encoded PNG (replacing any chunks of the same kind).
+pub fn embed_png(png: &[u8], meta: &ImageMetadata) -> Result<Vec<u8>> {
+	let mut out = png.to_vec();
+	for (kind, data) in png_metadata_chunks(meta, png_is_color(&out))? {
+		out = insert_png_chunk(&out, kind, &data)?;
+	}
+	Ok(out)
+}
+
+/// `meta` as PNG chunks (type and data), for encoders that write them themselves. An RGB profile
+/// on a grayscale PNG (e.g. a mask) is invalid, so only `color` images get one.
+pub fn png_metadata_chunks(meta: &ImageMetadata, color: bool) -> Result<Vec<([u8; 4], Vec<u8>)>> {
+	let mut chunks = Vec::new();
+	if let Some(d) = meta.dpi {
+		let ppm = |dpi: f64| (dpi / INCH_PER_METER).round().clamp(1.0, u32::MAX as f64) as u32;
+		let mut data = Vec::with_capacity(9);
+		data.extend_from_slice(&ppm(d.x).to_be_bytes());
+		data.extend_from_slice(&ppm(d.y).to_be_bytes());
+		data.push(1); // unit: meter
+		chunks.push((*b"pHYs", data));
+	}
+	if let Some(icc) = meta.icc.as_deref()
+		&& color
+	{
+		let mut data = b"ICC Profile\0\0".to_vec(); // name, compression method 0 (zlib)
+		let mut z = flate2::write::ZlibEncoder::new(&mut data, flate2::Compression::default());
+		z.write_all(icc).context("compress icc profile")?;
+		z.finish().context("compress icc profile")?;
+		chunks.push((*b"iCCP", data));
+	}
+	if let Some(exif) = meta.exif.as_deref() {
+		chunks.push((*b"eXIf", exif.to_vec()));
+	}
+	if let Some(xmp) = meta.xmp.as_deref() {
+		// iTXt: keyword, uncompressed, no language tag / translated keyword.
+		let mut data = b"XML:com.adobe.xmp\0\0\0\0\0".to_vec();
+		data.extend_from_slice(xmp);
+		chunks.push((*b"iTXt", data));
+	}
+	Ok(chunks)
+}
+
+/// Add an XMP `APP1` segment after the leading `APPn` segments. EXIF and ICC are written by the
+/// encoder itself.
+pub fn embed_jpeg(jpeg: &[u8], meta: &ImageMetadata) -> Result<Vec<u8>> {
+	const XMP_NS: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+	let Some(xmp) = meta.xmp.as_deref() else {
+		return Ok(jpeg.to_vec());
+	};
+	if !jpeg.starts_with(&[0xFF, 0xD8]) {
+		bail!("not a jpeg stream");
+	}
+	let seg_len = XMP_NS.len() + xmp.len() + 2;
+	if seg_len > u16::MAX as usize {
+		bail!("xmp packet too large for a jpeg segment ({} bytes)", xmp.len());
+	}
+
+	let mut pos = 2;
+	while pos + 4 <= jpeg.len() && jpeg[pos] == 0xFF && (0xE0..=0xEF).contains(&jpeg[pos + 1]) {
+		pos += 2 + u16::from_be_bytes([jpeg[pos + 2], jpeg[pos + 3]]) as usize;
+	}
+	let pos = pos.min(jpeg.len());
+
+	let mut out = Vec::with_capacity(jpeg.len() + seg_len + 2);
+	out.extend_from_slice(&jpeg[..pos]);
+	out.extend_from_slice(&[0xFF, 0xE1]);
+	out.extend_from_slice(&(seg_len as u16).to_be_bytes());
+	out.extend_from_slice(XMP_NS);
+	out.extend_from_slice(xmp);
+	out.extend_from_slice(&jpeg[pos..]);
+	Ok(out)
+}
+
+/// Re-emit `meta` into an encoded WebP. Simple (`VP8 `/`VP8L`) files are upgraded to the
+/// extended layout, since only that can carry `ICCP`/`EXIF`/`XMP ` chunks.
+pub fn embed_webp(webp: &[u8], meta: &ImageMetadata) -> Result<Vec<u8>> {
+	// VP8X feature flags for each chunk we may add.
+	let extras = [(*b"ICCP", 0x20, &meta.icc), (*b"EXIF", 0x08, &meta.exif), (*b"XMP ", 0x04, &meta.xmp)];
+	let flags = extras.iter().filter(|(_, _, d)| d.is_some()).fold(0u8, |f, (_, bit, _)| f | bit);
+	if flags == 0 {
+		return Ok(webp.to_vec());
+	}
+	if webp.len() < 12 || &webp[0..4] != b"RIFF" || &webp[8..12] != b"WEBP" {
+		bail!("not a webp stream");
+	}
+	let chunks: Vec<([u8; 4], &[u8])> = riff_chunks(&webp[12..]).collect();
+	let Some(&(kind, data)) = chunks.first() else {
+		bail!("webp stream has no chunks");
+	};
+
+	let extra_len: usize = extras.iter().filter_map(|(_, _, d)| d.as_ref().map(Vec::len)).sum();
+	let mut body = Vec::with_capacity(webp.len() + extra_len + 64);
+	let rest = match &kind {
+		b"VP8X" => {
+			let mut vp8x = data.to_vec();
+			vp8x[0] |= flags;
+			write_riff_chunk(&mut body, *b"VP8X", &vp8x);
+			&chunks[1..]
+		}
+		b"VP8 " | b"VP8L" => {
+			let (w, h, alpha) = webp_bitstream_info(&kind, data).context("read webp header")?;
+			let mut vp8x = [0u8; 10];
+			vp8x[0] = flags | if alpha { 0x10 } else { 0 };
+			vp8x[4..7].copy_from_slice(&(w - 1).to_le_bytes()[..3]);
+			vp8x[7..10].copy_from_slice(&(h - 1).to_le_bytes()[..3]);
+			write_riff_chunk(&mut body, *b"VP8X", &vp8x);
+			&chunks[..]
+		}
+		_ => bail!("unsupported webp layout")
+	};
+	// Chunk order per spec: VP8X, ICCP, image data, EXIF, XMP.
+	if let Some(icc) = meta.icc.as_deref() {
+		write_riff_chunk(&mut body, *b"ICCP", icc);
+	}
+	for (k, d) in rest {
+		if !extras.iter().any(|(kind, _, _)| kind == k) {
+			write_riff_chunk(&mut body, *k, d);
+		}
+	}
+	for (kind, _, data) in &extras[1..] {
+		if let Some(d) = data.as_deref() {
+			write_riff_chunk(&mut body, *kind, d);
+		}
+	}
+
+	let mut out = Vec::with_capacity(body.len() + 12);
+	out.extend_from_slice(b"RIFF");
+	out.extend_from_slice(&(body.len() as u32 + 4).to_le_bytes());
+	out.extend_from_slice(b"WEBP");
+	out.extend_from_slice(&body);
+	Ok(out)
+}
+
+fn riff_chunks(data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+	let mut pos = 0;
+	std::iter::from_fn(move || {
+		if pos + 8 > data.len() {
+			return None;
+		}
+		let kind: [u8; 4] = data[pos..pos + 4].try_into().ok()?;
+		let len = u32::from_le_bytes(data[pos + 4..pos + 8].try_into().ok()?) as usize;
+		let body = data.get(pos + 8..pos + 8 + len)?;
+		pos += 8 + len + (len & 1);
+		Some((kind, body))
+	})
+}
+
+fn write_riff_chunk(out: &mut Vec<u8>, kind: [u8; 4], data: &[u8]) {
+	out.extend_from_slice(&kind);
+	out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+	out.extend_from_slice(data);
+	if data.len() % 2 == 1 {
+		out.push(0);
+	}
+}
+
+/// Canvas size and alpha usage from a simple-format WebP bitstream.
+fn webp_bitstream_info(kind: &[u8; 4], data: &[u8]) -> Option<(u32, u32, bool)> {
+	if kind == b"VP8L" {
+		if data.len() < 5 || data[0] != 0x2f {
+			return None;
+		}
+		let bits = u32::from_le_bytes(data[1..5].try_into().ok()?);
+		let w = (bits & 0x3fff) + 1;
+		let h = ((bits >> 14) & 0x3fff) + 1;
+		Some((w, h, (bits >> 28) & 1 == 1))
+	} else {
+		// VP8 key frame: 3-byte frame tag, start code 9d 01 2a, then 14-bit width/height.
+		if data.len() < 10 || data[3..6] != [0x9d, 0x01, 0x2a] {
+			return None;
+		}
+		let w = u16::from_le_bytes([data[6], data[7]]) as u32 & 0x3fff;
+		let h = u16::from_le_bytes([data[8], data[9]]) as u32 & 0x3fff;
+		Some((w, h, false))
+	}
+}
+
+fn png_is_color(png: &[u8]) -> bool {
+	// IHDR color type: 2 = RGB, 3 = palette, 6 = RGBA.
+	png_chunks(png)
+		.find(|(k, _, _)| k == b"IHDR")
+		.is_some_and(|(_, data, _)| data.len() == 13 && matches!(data[9], 2 | 3 | 6))
+}
+
+fn png_chunks(png: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8], std::ops::Range<usize>)> {
+	let mut pos = PNG_SIGNATURE.len();
+	std::iter::from_fn(move || {
+		if pos + 12 > png.len() {
+			return None;
+		}
+		let len = u32::from_be_bytes(png[pos..pos + 4].try_into().ok()?) as usize;
+		let end = pos.checked_add(12 + len)?;
+		if end > png.len() {
+			return None;
+		}
+		let kind: [u8; 4] = png[pos + 4..pos + 8].try_into().ok()?;
+		let data = &png[pos + 8..pos + 8 + len];
+		let range = pos..end;
+		pos = end;
+		Some((kind, data, range))
+	})
+}
+
+/// Insert a chunk right after IHDR, dropping existing chunks of the same type.
+fn insert_png_chunk(png: &[u8], kind: [u8; 4], data: &[u8]) -> Result<Vec<u8>> {
+	if !png.starts_with(PNG_SIGNATURE) {
+		bail!("not a png stream");
+	}
+	let mut out = Vec::with_capacity(png.len() + data.len() + 12);
+	out.extend_from_slice(PNG_SIGNATURE);
+	let mut inserted = false;
+	for (k, _, range) in png_chunks(png) {
+		if k == kind {
+			continue;
+		}
+		out.extend_from_slice(&png[range]);
+		if k == *b"IHDR" && !inserted {
+			write_png_chunk(&mut out, kind, data);
+			inserted = true;
+		}
+	}
+	if !inserted {
+		bail!("png stream has no IHDR chunk");
+	}
+	Ok(out)
+}
+
+fn write_png_chunk(out: &mut Vec<u8>, kind: [u8; 4], data: &[u8]) {
+	out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+	out.extend_from_slice(&kind);
+	out.extend_from_slice(data);
+	let mut crc = crc32fast::Hasher::new();
+	crc.update(&kind);
+	crc.update(data);
+	out.extend_from_slice(&crc.finalize().to_be_bytes());
+}
+
+fn png_dpi(png: &[u8]) -> Option<Density> {
+	let (_, data, _) = png_chunks(png).find(|(k, _, _)| k == b"pHYs")?;
+	if data.len() != 9 || data[8] != 1 {
+		// Unit 0 only encodes an aspect ratio, not a physical size.
+		return None;
+	}
+	let x = u32::from_be_bytes(data[0..4].try_into().ok()?) as f64;
+	let y = u32::from_be_bytes(data[4..8].try_into().ok()?) as f64;
+	Some(Density {
+		x: x * INCH_PER_METER,
+		y: y * INCH_PER_METER
+	})
+}
+
+fn jpeg_dpi(jpeg: &[u8]) -> Option<Density> {
+	let mut pos = 2;
+	while pos + 4 <= jpeg.len() {
+		if jpeg[pos] != 0xFF {
+			return None;
+		}
+		let marker = jpeg[pos + 1];
+		if marker == 0xDA || marker == 0xD9 {
+			// Start of scan / end of image: no more header segments.
+			return None;
+		}
+		let len = u16::from_be_bytes([jpeg[pos + 2], jpeg[pos + 3]]) as usize;
+		let seg = jpeg.get(pos + 4..pos + 2 + len)?;
+		if marker == 0xE0 && seg.len() >= 12 && seg.starts_with(b"JFIF\0") {
+			let units = seg[7];
+			let x = u16::from_be_bytes([seg[8], seg[9]]) as f64;
+			let y = u16::from_be_bytes([seg[10], seg[11]]) as f64;
+			return match units {
+				1 => Some(Density { x, y }),
+				2 => Some(Density { x: x * 2.54, y: y * 2.54 }),
+				_ => None
+			};
+		}
+		pos += 2 + len;
+	}
+	None
+}
+
+fn tiff_dpi(bytes: &[u8]) -> Option<Density> {
+	use tiff::decoder::{Decoder, ifd::Value};
+	use tiff::tags::Tag;
+
+	let as_f64 = |v: Value| match v {
+		Value::Rational(n, d) if d != 0 => Some(n as f64 / d as f64),
+		Value::Double(v) => Some(v),
+		Value::Float(v) => Some(v as f64),
+		Value::Unsigned(v) => Some(v as f64),
+		Value::Short(v) => Some(v as f64),
+		_ => None
+	};
+
+	let mut dec = Decoder::new(Cursor::new(bytes)).ok()?;
+	let x = as_f64(dec.find_tag(Tag::XResolution).ok()??)?;
+	let y = as_f64(dec.find_tag(Tag::YResolution).ok()??)?;
+	// ResolutionUnit: 1 = none, 2 = inch (default), 3 = centimeter.
+	let unit = dec.find_tag_unsigned::<u16>(Tag::ResolutionUnit).ok().flatten().unwrap_or(2);
+	match unit {
+		2 => Some(Density { x, y }),
+		3 => Some(Density { x: x * 2.54, y: y * 2.54 }),
+		_ => None
+	}
+}
@@ -1,16 +1,48 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use anyhow::{Context, Result, bail};
+use serde::Serialize;
 
+use crate::cancel::CancellationToken;
 use crate::download;
+use crate::error::RembgError;
 
+#[derive(Debug, Clone)]
 pub struct ModelInstall {
 	pub path: PathBuf,
-	pub input_size: u32
+	pub input_size: u32,
+	/// Prompt decoder for two-stage models (SAM); `path` is then the image encoder.
+	pub decoder_path: Option<PathBuf>
 }
 
-pub fn ensure_model(name: &str) -> Result<ModelInstall> {
-	ensure_model_noninteractive(name, true, |_p| {}) // CLI behavior: always allow download, progress prints via download.rs
+/// Input side assumed for a local model when none is given (the U2Net convention).
+pub const LOCAL_INPUT_SIZE: u32 = 320;
+
+/// Input side assumed for a matting model (`--matting-model`) when none is given.
+pub const MATTING_INPUT_SIZE: u32 = 512;
+
+/// A segmentation export on disk, run like the built-in U2Net models: `1x3xSxS` input normalized
+/// to `[-1, 1]`, `1x1xHxW` probabilities or logits out (`S` = `input_size`).
+pub fn local_model(path: &Path, input_size: u32) -> Result<ModelInstall> {
+	if !path.is_file() {
+		bail!("model file not found: {}", path.display());
+	}
+	if input_size == 0 {
+		bail!("model input size must be positive");
+	}
+	Ok(ModelInstall {
+		path: path.to_path_buf(),
+		input_size,
+		decoder_path: None
+	})
+}
+
+/// `verify: false` accepts downloads whose digest doesn't match (e.g. from a mirror serving
+/// re-exported files).
+pub fn ensure_model(name: &str, verify: bool) -> Result<ModelInstall> {
+	// CLI behavior: always allow download.
+	let mut human = download::HumanProgress::default();
+	ensure_model_noninteractive(name, true, verify, &CancellationToken::new(), |p| human.report(p.url, p.progress))
 }
 
 pub struct DownloadProgress<'a> {
@@ -21,39 +53,222 @@ pub struct DownloadProgress<'a> {
 pub fn ensure_model_noninteractive(
 	name: &str,
 	allow_download: bool,
+	verify: bool,
+	cancel: &CancellationToken,
 	mut on_progress: impl FnMut(DownloadProgress<'_>)
 ) -> Result<ModelInstall> {
 	let m = model_spec(name)?;
-	let base = cache_base_dir()?.join("models");
+	let base = models_dir()?;
 	let path = base.join(format!("{}.onnx", m.name));
+	let pinned = Pinned { sha256: m.sha256, md5: m.md5 };
+	fetch(m.name, m.url, &path, allow_download, verify.then_some(pinned), cancel, &mut on_progress)?;
 
-	if !path.exists() {
-		if !allow_download {
-			bail!("download required: model {} ({})", m.name, m.url);
+	let decoder_path = match m.decoder_url {
+		Some(url) => {
+			let path = decoder_file(&base, m.name);
+			let pinned = Pinned {
+				sha256: m.decoder_sha256,
+				md5: m.decoder_md5
+			};
+			let verify = verify.then_some(pinned);
+			fetch(&format!("{}-decoder", m.name), url, &path, allow_download, verify, cancel, &mut on_progress)?;
+			Some(path)
 		}
-
-		download::download_to_path_with_progress(
-			m.url,
-			&path,
-			download::Digests {
-				sha256_hex: None,
-				md5_hex: None
-			},
-			|p| on_progress(DownloadProgress { url: m.url, progress: p })
-		)
-		.with_context(|| format!("download model {} from {}", m.name, m.url))?;
-	}
+		None => None
+	};
 
 	Ok(ModelInstall {
 		path,
-		input_size: m.input_size
+		input_size: m.input_size,
+		decoder_path
 	})
 }
 
+/// Every model id `model_spec` accepts.
+pub const MODEL_IDS: &[&str] = &[
+	"u2netp",
+	"u2net",
+	"u2net_human_seg",
+	"u2net_cloth_seg",
+	"silueta",
+	"isnet-general-use",
+	"isnet-anime",
+	"sam"
+];
+
+/// A supported model as a model picker shows it.
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelInfo {
+	pub name: &'static str,
+	pub description: &'static str,
+	pub input_size: u32,
+	/// Approximate bytes the first run downloads.
+	pub download_size: u64,
+	/// Every file of the model is in the cache, so using it needs no download.
+	pub cached: bool
+}
+
+/// Every model `model_spec` accepts, in `MODEL_IDS` order.
+pub fn list_models() -> Result<Vec<ModelInfo>> {
+	MODEL_IDS
+		.iter()
+		.map(|name| {
+			let m = model_spec(name)?;
+			Ok(ModelInfo {
+				name: m.name,
+				description: m.description,
+				input_size: m.input_size,
+				download_size: m.download_size,
+				cached: model_files(name)?.iter().all(|f| f.is_file())
+			})
+		})
+		.collect()
+}
+
+/// Where downloaded models are cached.
+pub fn models_dir() -> Result<PathBuf> {
+	Ok(cache_base_dir()?.join("models"))
+}
+
+/// The cache files of model `name` (two for two-stage models), whether downloaded or not.
+pub fn model_files(name: &str) -> Result<Vec<PathBuf>> {
+	let m = model_spec(name)?;
+	let base = models_dir()?;
+	let mut files = vec![base.join(format!("{}.onnx", m.name))];
+	if m.decoder_url.is_some() {
+		files.push(decoder_file(&base, m.name));
+	}
+	Ok(files)
+}
+
+/// Delete the cached files of model `name`; `false` if none were there.
+pub fn remove_model(name: &str) -> Result<bool> {
+	let mut removed = false;
+	for file in model_files(name)? {
+		if file.exists() {
+			std::fs::remove_file(&file).with_context(|| format!("remove {}", file.display()))?;
+			removed = true;
+		}
+	}
+	Ok(removed)
+}
+
+fn decoder_file(base: &Path, name: &str) -> PathBuf {
+	base.join(format!("{name}.decoder.onnx"))
+}
+
+/// Base URL of a mirror holding the model files under their upstream file names (e.g.
+/// `https://mirror.internal/rembg/u2net.onnx`), used instead of GitHub releases.
+pub const MODEL_BASE_URL_ENV: &str = "REMBG_MODEL_BASE_URL";
+
+/// `url`, moved to the `REMBG_MODEL_BASE_URL` (or config file) mirror if one is set.
+#[cfg(feature = "download")]
+fn mirrored(url: &str) -> String {
+	let base = std::env::var(MODEL_BASE_URL_ENV)
+		.ok()
+		.or_else(|| crate::config::current().model_base_url.clone())
+		.filter(|v| !v.trim().is_empty());
+	match base {
+		Some(base) => {
+			let file = url.rsplit('/').next().unwrap_or(url);
+			format!("{}/{file}", base.trim().trim_end_matches('/'))
+		}
+		None => url.to_string()
+	}
+}
+
+/// The digests `model_spec` pins for one file.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(not(feature = "download"), allow(dead_code))]
+struct Pinned {
+	sha256: Option<&'static str>,
+	md5: Option<&'static str>
+}
+
+/// `verify` carries the pinned digests, of which there must be at least one; `None` skips
+/// verification altogether.
+#[cfg(feature = "download")]
+fn fetch(
+	name: &str,
+	url: &str,
+	path: &Path,
+	allow_download: bool,
+	verify: Option<Pinned>,
+	cancel: &CancellationToken,
+	on_progress: &mut impl FnMut(DownloadProgress<'_>)
+) -> Result<()> {
+	if path.exists() {
+		return Ok(());
+	}
+	let url = &mirrored(url);
+	if !allow_download {
+		return Err(RembgError::DownloadRequired {
+			what: format!("model {name}"),
+			url: url.clone()
+		}
+		.into());
+	}
+	let digests = match verify {
+		Some(Pinned { sha256: None, md5: None }) => {
+			bail!("model {name} has no pinned digest to check it against (pass --no-verify to download it unchecked)")
+		}
+		Some(pinned) => download::Digests {
+			sha256_hex: pinned.sha256.map(str::to_string),
+			md5_hex: pinned.md5.map(str::to_string)
+		},
+		None => download::Digests {
+			sha256_hex: None,
+			md5_hex: None
+		}
+	};
+	download::download_to_path_with_progress(
+		url,
+		path,
+		digests,
+		cancel,
+		|p| on_progress(DownloadProgress { url, progress: p })
+	)
+	.with_context(|| format!("download model {name} from {url}"))
+}
+
+/// Without downloads, built-in models must already be in the cache directory.
+#[cfg(not(feature = "download"))]
+fn fetch(
+	name: &str,
+	_url: &str,
+	path: &Path,
+	_allow_download: bool,
+	_verify: Option<Pinned>,
+	_cancel: &CancellationToken,
+	_on_progress: &mut impl FnMut(DownloadProgress<'_>)
+) -> Result<()> {
+	if path.exists() {
+		return Ok(());
+	}
+	bail!(
+		"model {name} is not installed at {} (downloads are not compiled in; rebuild with `--features download`, or pass a model path)",
+		path.display()
+	)
+}
+
+const MB: u64 = 1_000_000;
+
 struct ModelSpec {
 	name: &'static str,
+	/// One line for model pickers.
+	description: &'static str,
 	url: &'static str,
-	input_size: u32
+	/// Checked after download.
+	sha256: Option<&'static str>,
+	/// The digest upstream rembg pins for `url`, checked as well.
+	md5: Option<&'static str>,
+	/// Second network of a two-stage model, stored next to the first as `<name>.decoder.onnx`.
+	decoder_url: Option<&'static str>,
+	decoder_sha256: Option<&'static str>,
+	decoder_md5: Option<&'static str>,
+	input_size: u32,
+	/// Approximate, both files included; enough to warn before a large first download.
+	download_size: u64
 }
 
 fn model_spec(name: &str) -> Result<ModelSpec> {
@@ -62,47 +277,114 @@ fn model_spec(name: &str) -> Result<ModelSpec> {
 	match name.trim().to_ascii_lowercase().as_str() {
 		"u2netp" => Ok(ModelSpec {
 			name: "u2netp",
+			description: "Fast general-purpose model",
 			url: "https://github.com/danielgatis/rembg/releases/download/v0.0.0/u2netp.onnx",
-			input_size: 320
+			sha256: None,
+			md5: Some("8e83ca70e441ab06c318d82300c84806"),
+			decoder_url: None,
+			decoder_sha256: None,
+			decoder_md5: None,
+			input_size: 320,
+			download_size: 5 * MB
 		}),
 		"u2net" => Ok(ModelSpec {
 			name: "u2net",
+			description: "General-purpose, more detail than u2netp",
 			url: "https://github.com/danielgatis/rembg/releases/download/v0.0.0/u2net.onnx",
-			input_size: 320
+			sha256: None,
+			md5: Some("60024c5c889badc19c04ad937298a77b"),
+			decoder_url: None,
+			decoder_sha256: None,
+			decoder_md5: None,
+			input_size: 320,
+			download_size: 176 * MB
 		}),
 		"u2net_human_seg" => Ok(ModelSpec {
 			name: "u2net_human_seg",
+			description: "People",
 			url: "https://github.com/danielgatis/rembg/releases/download/v0.0.0/u2net_human_seg.onnx",
-			input_size: 320
+			sha256: None,
+			md5: Some("c09ddc2e0104f800e3e1bb4652583d1f"),
+			decoder_url: None,
+			decoder_sha256: None,
+			decoder_md5: None,
+			input_size: 320,
+			download_size: 176 * MB
 		}),
+		// Outputs background/upper/lower/full-body logits rather than one mask; the export has a fixed
+		// 768x768 input.
 		"u2net_cloth_seg" => Ok(ModelSpec {
 			name: "u2net_cloth_seg",
+			description: "Clothing (upper body, lower body, full body)",
 			url: "https://github.com/danielgatis/rembg/releases/download/v0.0.0/u2net_cloth_seg.onnx",
-			input_size: 320
+			sha256: None,
+			md5: Some("2434d1f3cb744e0e49386c906e5a08bb"),
+			decoder_url: None,
+			decoder_sha256: None,
+			decoder_md5: None,
+			input_size: 768,
+			download_size: 176 * MB
 		}),
 		"silueta" => Ok(ModelSpec {
 			name: "silueta",
+			description: "Small general-purpose model",
 			url: "https://github.com/danielgatis/rembg/releases/download/v0.0.0/silueta.onnx",
-			input_size: 320
+			sha256: None,
+			md5: Some("55e59e0d8062d2f5d013f4725ee84782"),
+			decoder_url: None,
+			decoder_sha256: None,
+			decoder_md5: None,
+			input_size: 320,
+			download_size: 44 * MB
 		}),
 		// ISNet models tend to prefer larger input sizes; 1024 is common in rembg usage.
 		// This will be slower but should improve detail and interior background separation.
 		"isnet-general-use" => Ok(ModelSpec {
 			name: "isnet-general-use",
+			description: "High quality general-purpose model (slow)",
 			url: "https://github.com/danielgatis/rembg/releases/download/v0.0.0/isnet-general-use.onnx",
-			input_size: 1024
+			sha256: None,
+			md5: Some("fc16ebd8b0c10d971d3513d564d01e29"),
+			decoder_url: None,
+			decoder_sha256: None,
+			decoder_md5: None,
+			input_size: 1024,
+			download_size: 179 * MB
 		}),
 		"isnet-anime" => Ok(ModelSpec {
 			name: "isnet-anime",
+			description: "Anime characters",
 			url: "https://github.com/danielgatis/rembg/releases/download/v0.0.0/isnet-anime.onnx",
-			input_size: 1024
+			sha256: None,
+			md5: Some("6f184e756bb3bd901c8849220a83e38e"),
+			decoder_url: None,
+			decoder_sha256: None,
+			decoder_md5: None,
+			input_size: 1024,
+			download_size: 176 * MB
+		}),
+		// Segment Anything (ViT-B): an image encoder plus a decoder that turns point/box prompts into a
+		// mask, so re-prompting the same image only reruns the small decoder.
+		"sam" => Ok(ModelSpec {
+			name: "sam",
+			description: "Segment Anything: click the subject to pick it",
+			url: "https://github.com/danielgatis/rembg/releases/download/v0.0.0/vit_b-encoder-quant.onnx",
+			sha256: None,
+			md5: Some("13d97c5c79ab13ef86d67cbde5f1b250"),
+			decoder_url: Some("https://github.com/danielgatis/rembg/releases/download/v0.0.0/vit_b-decoder-quant.onnx"),
+			decoder_sha256: None,
+			decoder_md5: Some("fa3d1c36a3187d3de1c8deebf33dd127"),
+			input_size: crate::sam::INPUT_SIZE,
+			download_size: 118 * MB
 		}),
-		other => bail!("unsupported model: {other} (supported: u2netp, u2net, u2net_human_seg, u2net_cloth_seg, silueta, isnet-general-use, isnet-anime)")
+		other => Err(RembgError::UnsupportedModel {
+			name: other.to_string(),
+			supported: MODEL_IDS.join(", ")
+		}
+		.into())
 	}
 }
 
 fn cache_base_dir() -> Result<PathBuf> {
-	let dirs = directories::ProjectDirs::from("rs", "rembg", "rembg-rs")
-		.ok_or_else(|| anyhow::anyhow!("unable to resolve user cache directory"))?;
-	Ok(dirs.cache_dir().to_path_buf())
+	crate::config::cache_dir()
 }
@@ -0,0 +1,289 @@
+use anyhow::{Context, Result, bail};
+
+/// What `rembg-rs inspect` reports about an ONNX file, read straight from its protobuf (no ONNX
+/// Runtime needed).
+#[derive(Debug, Default)]
+pub struct ModelInfo {
+	pub ir_version: i64,
+	pub producer: String,
+	/// `(domain, version)`; the default domain is reported as `ai.onnx`.
+	pub opsets: Vec<(String, i64)>,
+	/// Graph inputs that are fed at run time (weights listed as inputs by old exporters are left out).
+	pub inputs: Vec<TensorInfo>,
+	pub outputs: Vec<TensorInfo>
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct TensorInfo {
+	pub name: String,
+	/// ONNX `TensorProto.DataType`; 0 when the value isn't a tensor.
+	pub elem_type: i32,
+	/// `None` when the model doesn't declare a shape.
+	pub shape: Option<Vec<Dim>>
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Dim {
+	Fixed(i64),
+	/// A symbolic dimension such as `batch_size`.
+	Named(String),
+	Unknown
+}
+
+impl std::fmt::Display for Dim {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Dim::Fixed(n) => write!(f, "{n}"),
+			Dim::Named(name) => f.write_str(name),
+			Dim::Unknown => f.write_str("?")
+		}
+	}
+}
+
+impl TensorInfo {
+	pub fn dtype(&self) -> &'static str {
+		match self.elem_type {
+			1 => "float32",
+			2 => "uint8",
+			3 => "int8",
+			4 => "uint16",
+			5 => "int16",
+			6 => "int32",
+			7 => "int64",
+			8 => "string",
+			9 => "bool",
+			10 => "float16",
+			11 => "float64",
+			12 => "uint32",
+			13 => "uint64",
+			16 => "bfloat16",
+			0 => "non-tensor",
+			_ => "other"
+		}
+	}
+
+	/// `[1, 3, 320, 320]`, or `[?]` without a declared shape.
+	pub fn shape_string(&self) -> String {
+		match &self.shape {
+			Some(dims) => format!("[{}]", dims.iter().map(Dim::to_string).collect::<Vec<_>>().join(", ")),
+			None => "[?]".to_string()
+		}
+	}
+}
+
+impl ModelInfo {
+	/// Check the model against what `--model-path` feeds it and reads back: one float32
+	/// `1x3xSxS` image input, and a float32 `1xCxHxW` first output (a mask, or per-class logits when
+	/// `C` > 1). `Ok` carries the input size when the model fixes it; `Err` lists the problems.
+	pub fn compatibility(&self) -> std::result::Result<Option<u32>, Vec<String>> {
+		let mut problems = Vec::new();
+		let mut size = None;
+
+		match self.inputs.as_slice() {
+			[input] => {
+				if input.elem_type != 1 {
+					problems.push(format!("input {:?} is {}, expected float32", input.name, input.dtype()));
+				}
+				match input.shape.as_deref() {
+					Some([n, c, h, w]) => {
+						if matches!(n, Dim::Fixed(n) if *n != 1) {
+							problems.push(format!("input batch dimension is {n}, expected 1"));
+						}
+						if *c != Dim::Fixed(3) {
+							problems.push(format!("input has {c} channels, expected 3 (RGB, channels first)"));
+						}
+						match (h, w) {
+							(Dim::Fixed(h), Dim::Fixed(w)) if h != w => {
+								problems.push(format!("input is {w}x{h}; only square inputs are supported"))
+							}
+							(Dim::Fixed(h), Dim::Fixed(_)) => size = u32::try_from(*h).ok(),
+							_ => {}
+						}
+					}
+					Some(dims) => problems.push(format!("input has rank {}, expected 4 (NCHW)", dims.len())),
+					None => {}
+				}
+			}
+			[] => problems.push("the model has no inputs".to_string()),
+			inputs => problems.push(format!("the model has {} inputs, expected one image", inputs.len()))
+		}
+
+		match self.outputs.first() {
+			Some(output) => {
+				if output.elem_type != 1 {
+					problems.push(format!("output {:?} is {}, expected float32", output.name, output.dtype()));
+				}
+				if let Some(dims) = &output.shape
+					&& dims.len() != 4
+				{
+					problems.push(format!("output {:?} has rank {}, expected 4 (1xCxHxW)", output.name, dims.len()));
+				}
+			}
+			None => problems.push("the model has no outputs".to_string())
+		}
+
+		if problems.is_empty() { Ok(size) } else { Err(problems) }
+	}
+}
+
+/// Read the graph signature and opsets from an ONNX model file's bytes.
+pub fn inspect(bytes: &[u8]) -> Result<ModelInfo> {
+	let mut info = ModelInfo::default();
+	let mut graph = None;
+	for field in Fields(bytes) {
+		match field.context("parse ONNX model")? {
+			(1, Value::Varint(v)) => info.ir_version = v as i64,
+			(2, Value::Bytes(b)) => info.producer = String::from_utf8_lossy(b).into_owned(),
+			(3, Value::Bytes(b)) if !b.is_empty() => {
+				info.producer = format!("{} {}", info.producer, String::from_utf8_lossy(b));
+			}
+			(7, Value::Bytes(b)) => graph = Some(b),
+			(8, Value::Bytes(b)) => {
+				let (mut domain, mut version) = (String::new(), 0);
+				for field in Fields(b) {
+					match field? {
+						(1, Value::Bytes(d)) => domain = String::from_utf8_lossy(d).into_owned(),
+						(2, Value::Varint(v)) => version = v as i64,
+						_ => {}
+					}
+				}
+				if domain.is_empty() {
+					domain = "ai.onnx".to_string();
+				}
+				info.opsets.push((domain, version));
+			}
+			_ => {}
+		}
+	}
+	let Some(graph) = graph else {
+		bail!("not an ONNX model (no graph)");
+	};
+
+	let mut initializers = std::collections::HashSet::new();
+	let mut inputs = Vec::new();
+	for field in Fields(graph) {
+		match field.context("parse ONNX graph")? {
+			(5, Value::Bytes(t)) => {
+				for field in Fields(t) {
+					if let (8, Value::Bytes(name)) = field? {
+						initializers.insert(String::from_utf8_lossy(name).into_owned());
+					}
+				}
+			}
+			(11, Value::Bytes(v)) => inputs.push(value_info(v)?),
+			(12, Value::Bytes(v)) => info.outputs.push(value_info(v)?),
+			_ => {}
+		}
+	}
+	info.inputs = inputs.into_iter().filter(|i| !initializers.contains(&i.name)).collect();
+	Ok(info)
+}
+
+/// A `ValueInfoProto`: name, and element type and shape when it's a tensor.
+fn value_info(bytes: &[u8]) -> Result<TensorInfo> {
+	let mut info = TensorInfo::default();
+	for field in Fields(bytes) {
+		match field? {
+			(1, Value::Bytes(b)) => info.name = String::from_utf8_lossy(b).into_owned(),
+			(2, Value::Bytes(ty)) => {
+				for field in Fields(ty) {
+					let (1, Value::Bytes(tensor)) = field? else { continue };
+					for field in Fields(tensor) {
+						match field? {
+							(1, Value::Varint(v)) => info.elem_type = v as i32,
+							(2, Value::Bytes(shape)) => info.shape = Some(dims(shape)?),
+							_ => {}
+						}
+					}
+				}
+			}
+			_ => {}
+		}
+	}
+	Ok(info)
+}
+
+/// The dimensions of a `TensorShapeProto`.
+fn dims(bytes: &[u8]) -> Result<Vec<Dim>> {
+	let mut out = Vec::new();
+	for field in Fields(bytes) {
+		let (1, Value::Bytes(dim)) = field? else { continue };
+		let mut d = Dim::Unknown;
+		for field in Fields(dim) {
+			match field? {
+				(1, Value::Varint(v)) => d = Dim::Fixed(v as i64),
+				(2, Value::Bytes(name)) => d = Dim::Named(String::from_utf8_lossy(name).into_owned()),
+				_ => {}
+			}
+		}
+		out.push(d);
+	}
+	Ok(out)
+}
+
+enum Value<'a> {
+	Varint(u64),
+	Bytes(&'a [u8]),
+	Fixed
+}
+
+/// The `(field number, value)` pairs of one protobuf message, in wire order.
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Iterator for Fields<'a> {
+	type Item = Result<(u64, Value<'a>)>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.0.is_empty() {
+			return None;
+		}
+		let field = self.field();
+		if field.is_err() {
+			// Malformed: report once, then stop.
+			self.0 = &[];
+		}
+		Some(field)
+	}
+}
+
+impl<'a> Fields<'a> {
+	fn field(&mut self) -> Result<(u64, Value<'a>)> {
+		let tag = self.varint()?;
+		let value = match tag & 7 {
+			0 => Value::Varint(self.varint()?),
+			1 => self.skip(8)?,
+			2 => {
+				let len = usize::try_from(self.varint()?).context("field too long")?;
+				if len > self.0.len() {
+					bail!("truncated field");
+				}
+				let (bytes, rest) = self.0.split_at(len);
+				self.0 = rest;
+				Value::Bytes(bytes)
+			}
+			5 => self.skip(4)?,
+			wire => bail!("unsupported protobuf wire type {wire}")
+		};
+		Ok((tag >> 3, value))
+	}
+
+	fn varint(&mut self) -> Result<u64> {
+		let mut v = 0u64;
+		for (i, &b) in self.0.iter().enumerate().take(10) {
+			v |= ((b & 0x7f) as u64) << (7 * i);
+			if b & 0x80 == 0 {
+				self.0 = &self.0[i + 1..];
+				return Ok(v);
+			}
+		}
+		bail!("truncated varint")
+	}
+
+	fn skip(&mut self, n: usize) -> Result<Value<'a>> {
+		if n > self.0.len() {
+			bail!("truncated field");
+		}
+		self.0 = &self.0[n..];
+		Ok(Value::Fixed)
+	}
+}
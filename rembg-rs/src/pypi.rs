@@ -27,8 +27,19 @@ pub struct PypiDigests {
 	pub sha256: String
 }
 
+/// Root of a PyPI JSON API mirror (e.g. `https://pypi.tuna.tsinghua.edu.cn/pypi`) to use instead of
+/// pypi.org. The wheel URLs the mirror returns are downloaded as given.
+pub const INDEX_URL_ENV: &str = "REMBG_PYPI_INDEX_URL";
+
+const DEFAULT_INDEX_URL: &str = "https://pypi.org/pypi";
+
 pub fn fetch_project(name: &str) -> Result<PypiProject> {
-	let url = format!("https://pypi.org/pypi/{name}/json");
+	let index = std::env::var(INDEX_URL_ENV)
+		.ok()
+		.or_else(|| crate::config::current().pypi_index_url.clone())
+		.filter(|v| !v.trim().is_empty())
+		.unwrap_or_else(|| DEFAULT_INDEX_URL.to_string());
+	let url = format!("{}/{name}/json", index.trim().trim_end_matches('/'));
 	let resp = ureq::get(&url)
 		.call()
 		.with_context(|| format!("GET {url}"))?;
@@ -41,8 +52,8 @@ pub fn fetch_project(name: &str) -> Result<PypiProject> {
 	Ok(proj)
 }
 
-pub fn select_wheel<'a>(proj: &'a PypiProject, os: &str, arch: &str) -> Result<&'a PypiReleaseFile> {
-	let version = &proj.info.version;
+/// The wheel of release `version` (e.g. `proj.info.version`, the latest) for `os`/`arch`.
+pub fn select_wheel<'a>(proj: &'a PypiProject, version: &str, os: &str, arch: &str) -> Result<&'a PypiReleaseFile> {
 	let files = proj
 		.releases
 		.get(version)
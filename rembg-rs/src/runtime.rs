@@ -7,12 +7,18 @@ use std::{
 use anyhow::{Context, Result, bail};
 use std::sync::OnceLock;
 
-use crate::{cli, download, pypi};
+use crate::cancel::CancellationToken;
+use crate::{cli, download, term};
+#[cfg(all(feature = "download", feature = "load-dynamic"))]
+use crate::{error::RembgError, pypi};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PreferredEp {
 	DirectML,
-	Cuda
+	Cuda,
+	CoreML,
+	TensorRT,
+	OpenVINO
 }
 
 #[derive(Debug, Clone)]
@@ -24,6 +30,7 @@ pub struct Plan {
 }
 
 pub struct OnnxRuntimeInstall {
+	/// The library `init_ort` loads; the executable itself when ONNX Runtime is linked in (`static-ort`).
 	pub main_lib: PathBuf
 }
 
@@ -44,7 +51,9 @@ pub fn plan_noninteractive(device: cli::Device, gpu_backend: cli::GpuBackend, al
 		// without requiring an app restart.
 		//
 		// If downloads are disallowed and DirectML isn't cached yet, fall back to the CPU runtime.
-		if os == "windows" && gpu_backend != cli::GpuBackend::Cuda {
+		if os == "windows"
+			&& !matches!(gpu_backend, cli::GpuBackend::Cuda | cli::GpuBackend::Tensorrt | cli::GpuBackend::Openvino)
+		{
 			if allow_download || has_any_cached_runtime("onnxruntime-directml")? {
 				return Ok(Plan {
 					runtime_package: "onnxruntime-directml",
@@ -73,6 +82,8 @@ pub fn plan_noninteractive(device: cli::Device, gpu_backend: cli::GpuBackend, al
 				}
 			}
 			"linux" => cli::GpuBackend::Cuda,
+			// The plain `onnxruntime` wheel for macOS ships the CoreML EP.
+			"macos" => cli::GpuBackend::Coreml,
 			_ => cli::GpuBackend::Auto
 		},
 		other => other
@@ -89,15 +100,37 @@ pub fn plan_noninteractive(device: cli::Device, gpu_backend: cli::GpuBackend, al
 				allow_download
 			})
 		}
-		cli::GpuBackend::Cuda => {
+		cli::GpuBackend::Cuda | cli::GpuBackend::Tensorrt => {
 			let ok = (os == "windows" && arch == "x86_64")
 				|| (os == "linux" && (arch == "x86_64" || arch == "aarch64"));
 			if !ok {
 				bail!("CUDA backend not supported on this platform ({os}/{arch})");
 			}
 			Ok(Plan {
+				// The onnxruntime-gpu wheel ships the TensorRT EP too.
 				runtime_package: "onnxruntime-gpu",
-				ep: Some(PreferredEp::Cuda),
+				ep: Some(if backend == cli::GpuBackend::Tensorrt { PreferredEp::TensorRT } else { PreferredEp::Cuda }),
+				allow_download
+			})
+		}
+		cli::GpuBackend::Coreml => {
+			if os != "macos" {
+				bail!("CoreML backend is only supported on macOS");
+			}
+			Ok(Plan {
+				runtime_package: "onnxruntime",
+				ep: Some(PreferredEp::CoreML),
+				allow_download
+			})
+		}
+		cli::GpuBackend::Openvino => {
+			let ok = (os == "windows" || os == "linux") && arch == "x86_64";
+			if !ok {
+				bail!("OpenVINO backend not supported on this platform ({os}/{arch})");
+			}
+			Ok(Plan {
+				runtime_package: "onnxruntime-openvino",
+				ep: Some(PreferredEp::OpenVINO),
 				allow_download
 			})
 		}
@@ -115,13 +148,20 @@ pub fn resolve_plan(args: &cli::Args) -> Result<Plan> {
 		cli::Device::Cpu => false,
 		cli::Device::Gpu => true,
 		cli::Device::Auto => {
-			if os != "windows" {
+			if os == "macos" {
+				// CoreML only runs parts of these models, so it's used when asked for by name, never by default.
+				args.gpu_backend == cli::GpuBackend::Coreml
+			} else if os != "windows" || cfg!(not(feature = "load-dynamic")) {
+				// Elsewhere (or with the CPU runtime linked in) there's no GPU backend to offer.
 				false
 			} else {
 				// If any GPU runtime is already cached, enable GPU without prompting.
 				// On Windows, we prefer DirectML when auto-selecting, but CUDA might be cached instead.
 				if has_any_cached_runtime("onnxruntime-directml")? || has_any_cached_runtime("onnxruntime-gpu")? {
 					true
+				} else if !args.yes && !term::interactive() {
+					// Nobody to ask: stay on CPU rather than failing the run.
+					false
 				} else {
 					let msg = "Enable GPU acceleration? This will download a GPU-enabled ONNX Runtime backend.";
 					let ok = prompt_yes_no(msg, args.yes)?;
@@ -155,6 +195,8 @@ pub fn resolve_plan(args: &cli::Args) -> Result<Plan> {
 				}
 			}
 			"linux" => cli::GpuBackend::Cuda,
+			// The plain `onnxruntime` wheel for macOS ships the CoreML EP.
+			"macos" => cli::GpuBackend::Coreml,
 			_ => cli::GpuBackend::Auto
 		},
 		other => other
@@ -171,15 +213,37 @@ pub fn resolve_plan(args: &cli::Args) -> Result<Plan> {
 				allow_download
 			})
 		}
-		cli::GpuBackend::Cuda => {
+		cli::GpuBackend::Cuda | cli::GpuBackend::Tensorrt => {
 			let ok = (os == "windows" && arch == "x86_64")
 				|| (os == "linux" && (arch == "x86_64" || arch == "aarch64"));
 			if !ok {
 				bail!("CUDA backend not supported on this platform ({os}/{arch})");
 			}
 			Ok(Plan {
+				// The onnxruntime-gpu wheel ships the TensorRT EP too.
 				runtime_package: "onnxruntime-gpu",
-				ep: Some(PreferredEp::Cuda),
+				ep: Some(if backend == cli::GpuBackend::Tensorrt { PreferredEp::TensorRT } else { PreferredEp::Cuda }),
+				allow_download
+			})
+		}
+		cli::GpuBackend::Coreml => {
+			if os != "macos" {
+				bail!("CoreML backend is only supported on macOS");
+			}
+			Ok(Plan {
+				runtime_package: "onnxruntime",
+				ep: Some(PreferredEp::CoreML),
+				allow_download
+			})
+		}
+		cli::GpuBackend::Openvino => {
+			let ok = (os == "windows" || os == "linux") && arch == "x86_64";
+			if !ok {
+				bail!("OpenVINO backend not supported on this platform ({os}/{arch})");
+			}
+			Ok(Plan {
+				runtime_package: "onnxruntime-openvino",
+				ep: Some(PreferredEp::OpenVINO),
 				allow_download
 			})
 		}
@@ -188,61 +252,66 @@ pub fn resolve_plan(args: &cli::Args) -> Result<Plan> {
 }
 
 pub fn ensure_onnxruntime(plan: &Plan) -> Result<OnnxRuntimeInstall> {
-	let os = env::consts::OS;
-	let arch = env::consts::ARCH;
-
-	let package = plan.runtime_package;
-	let pkg_dir = cache_base_dir()?
-		.join("onnxruntime")
-		.join(package);
+	let mut human = download::HumanProgress::default();
+	ensure_onnxruntime_with_progress(plan, |p| human.report(p.url, p.progress))
+}
 
-	// 1) If any version is already installed, use it (avoid prompting on new upstream releases).
-	if let Some(main_lib) = find_any_installed_lib(os, &pkg_dir)? {
+/// Like `ensure_onnxruntime` (including the download prompt), but download progress goes to
+/// `on_progress` instead of the terminal.
+pub fn ensure_onnxruntime_with_progress(
+	plan: &Plan,
+	on_progress: impl FnMut(DownloadProgress<'_>)
+) -> Result<OnnxRuntimeInstall> {
+	if let Some(main_lib) = configured_lib()? {
 		return Ok(OnnxRuntimeInstall { main_lib });
 	}
+	fetch(plan, true, &CancellationToken::new(), on_progress)
+}
 
-	// 2) Otherwise, download latest wheel for this platform.
-	let proj = pypi::fetch_project(package)?;
-	let os_norm = normalize_os(os);
-	let arch_norm = normalize_arch(arch);
-	let wheel = pypi::select_wheel(&proj, &os_norm, &arch_norm)?;
-
-	let base = pkg_dir.join(&proj.info.version);
-	let wheel_path = base.join(&wheel.filename);
-	let lib_dir = base.join("lib");
-
-	let msg = match package {
-		"onnxruntime" => "Download ONNX Runtime CPU backend now?",
-		"onnxruntime-directml" => "Download ONNX Runtime DirectML (GPU) backend now?",
-		"onnxruntime-gpu" => "Download ONNX Runtime CUDA (GPU) backend now?",
-		_ => "Download ONNX Runtime backend now?"
-	};
-	if !wheel_path.exists() && !prompt_yes_no(msg, plan.allow_download)? {
-		bail!("runtime download cancelled by user");
+pub fn ensure_onnxruntime_noninteractive(
+	plan: &Plan,
+	cancel: &CancellationToken,
+	on_progress: impl FnMut(DownloadProgress<'_>)
+) -> Result<OnnxRuntimeInstall> {
+	if let Some(main_lib) = configured_lib()? {
+		return Ok(OnnxRuntimeInstall { main_lib });
 	}
+	fetch(plan, false, cancel, on_progress)
+}
 
-	if !wheel_path.exists() {
-		download::download_to_path(
-			&wheel.url,
-			&wheel_path,
-			download::Digests {
-				sha256_hex: Some(wheel.digests.sha256.clone()),
-				md5_hex: None
-			}
-		)
-		.with_context(|| format!("download wheel: {}", wheel.filename))?;
+/// Names an ONNX Runtime library to load instead of a cached or downloaded one (for builds
+/// without the `download` feature, the only way to get a runtime).
+pub const LIB_ENV: &str = "REMBG_ONNXRUNTIME_LIB";
+
+/// The library from `REMBG_ONNXRUNTIME_LIB`, `ORT_DYLIB_PATH` or the config file's `onnxruntime_lib`,
+/// whichever is set first.
+#[cfg(feature = "load-dynamic")]
+fn configured_lib() -> Result<Option<PathBuf>> {
+	let lib = [LIB_ENV, "ORT_DYLIB_PATH"]
+		.into_iter()
+		.find_map(|var| env::var_os(var).filter(|v| !v.is_empty()))
+		.map(PathBuf::from)
+		.or_else(|| crate::config::current().onnxruntime_lib.clone());
+	match lib {
+		Some(lib) if !lib.is_file() => bail!("ONNX Runtime library not found: {}", lib.display()),
+		lib => Ok(lib)
 	}
+}
 
-	extract_ort_libs_from_wheel(&wheel_path, &lib_dir)?;
-
-	let main_lib = find_main_lib(os, &lib_dir)
-		.ok_or_else(|| anyhow::anyhow!("unable to find ONNX Runtime library after extraction in {}", lib_dir.display()))?;
-
-	Ok(OnnxRuntimeInstall { main_lib })
+/// ONNX Runtime is linked into the executable: nothing to find or download.
+#[cfg(not(feature = "load-dynamic"))]
+fn configured_lib() -> Result<Option<PathBuf>> {
+	env::current_exe().context("locate the executable").map(Some)
 }
 
-pub fn ensure_onnxruntime_noninteractive(
+/// The cached runtime for `plan`, downloading the latest wheel for this platform if there's none.
+/// With `prompt` it asks before downloading (`plan.allow_download` answers yes); without, a missing
+/// runtime is `DownloadRequired` unless `plan.allow_download`.
+#[cfg(all(feature = "download", feature = "load-dynamic"))]
+fn fetch(
 	plan: &Plan,
+	prompt: bool,
+	cancel: &CancellationToken,
 	mut on_progress: impl FnMut(DownloadProgress<'_>)
 ) -> Result<OnnxRuntimeInstall> {
 	let os = env::consts::OS;
@@ -258,21 +327,46 @@ pub fn ensure_onnxruntime_noninteractive(
 		return Ok(OnnxRuntimeInstall { main_lib });
 	}
 
-	// 2) Otherwise, download latest wheel for this platform.
+	// 2) A compatible system install saves the download.
+	if let Some(main_lib) = system_lib(plan)? {
+		return Ok(OnnxRuntimeInstall { main_lib });
+	}
+
+	// 3) Otherwise, download the pinned or newest supported wheel for this platform.
 	let proj = pypi::fetch_project(package)?;
+	let version = match pinned_version()? {
+		Some(version) => version.to_string(),
+		None => newest_supported(&proj).with_context(|| format!("no {package} release on PyPI is in {}", supported_range()))?
+	};
 	let os_norm = normalize_os(os);
 	let arch_norm = normalize_arch(arch);
-	let wheel = pypi::select_wheel(&proj, &os_norm, &arch_norm)?;
+	let wheel = pypi::select_wheel(&proj, &version, &os_norm, &arch_norm)?;
 
-	let base = pkg_dir.join(&proj.info.version);
+	let base = pkg_dir.join(&version);
 	let wheel_path = base.join(&wheel.filename);
 	let lib_dir = base.join("lib");
 
-	if !wheel_path.exists() && !plan.allow_download {
-		bail!("download required: runtime package {} ({})", package, wheel.url);
-	}
-
 	if !wheel_path.exists() {
+		let refused = || RembgError::DownloadRequired {
+			what: format!("runtime package {package}"),
+			url: wheel.url.clone()
+		};
+		if prompt {
+			let msg = match package {
+				"onnxruntime" => "Download ONNX Runtime CPU backend now?",
+				"onnxruntime-directml" => "Download ONNX Runtime DirectML (GPU) backend now?",
+				"onnxruntime-gpu" => "Download ONNX Runtime CUDA (GPU) backend now?",
+				_ => "Download ONNX Runtime backend now?"
+			};
+			match prompt_yes_no(msg, plan.allow_download) {
+				Ok(true) => {}
+				Ok(false) => return Err(anyhow::anyhow!("runtime download cancelled by user").context(refused())),
+				Err(e) => return Err(e.context(refused()))
+			}
+		} else if !plan.allow_download {
+			return Err(refused().into());
+		}
+
 		download::download_to_path_with_progress(
 			&wheel.url,
 			&wheel_path,
@@ -280,6 +374,7 @@ pub fn ensure_onnxruntime_noninteractive(
 				sha256_hex: Some(wheel.digests.sha256.clone()),
 				md5_hex: None
 			},
+			cancel,
 			|p| on_progress(DownloadProgress { url: &wheel.url, progress: p })
 		)
 		.with_context(|| format!("download wheel: {}", wheel.filename))?;
@@ -293,6 +388,110 @@ pub fn ensure_onnxruntime_noninteractive(
 	Ok(OnnxRuntimeInstall { main_lib })
 }
 
+#[cfg(not(all(feature = "download", feature = "load-dynamic")))]
+fn fetch(
+	plan: &Plan,
+	_prompt: bool,
+	_cancel: &CancellationToken,
+	_on_progress: impl FnMut(DownloadProgress<'_>)
+) -> Result<OnnxRuntimeInstall> {
+	if let Some(main_lib) = system_lib(plan)? {
+		return Ok(OnnxRuntimeInstall { main_lib });
+	}
+	bail!(
+		"no ONNX Runtime library for {}: set {LIB_ENV} or `onnxruntime_lib` in the config file (downloads are not compiled in; rebuild with `--features download`)",
+		plan.runtime_package
+	)
+}
+
+/// ONNX Runtime releases the `ort` bindings can load: they need C API version 23 (1.23), which
+/// every later 1.x release still provides.
+const SUPPORTED_VERSIONS: std::ops::Range<[u64; 2]> = [1, 23]..[2, 0];
+
+fn supported(version: &[u64]) -> bool {
+	!version.is_empty() && SUPPORTED_VERSIONS.start[..] <= *version && *version < SUPPORTED_VERSIONS.end[..]
+}
+
+fn supported_range() -> String {
+	let [a, b] = SUPPORTED_VERSIONS.start;
+	let [c, d] = SUPPORTED_VERSIONS.end;
+	format!(">={a}.{b}, <{c}.{d}")
+}
+
+/// The `runtime_version` pin (config file or `--runtime-version`), rejected when it's outside
+/// `SUPPORTED_VERSIONS`.
+fn pinned_version() -> Result<Option<&'static str>> {
+	let Some(version) = crate::config::current().runtime_version.as_deref() else {
+		return Ok(None);
+	};
+	if !supported(&parse_version_prefix(version)) {
+		bail!("ONNX Runtime {version} can't be used: this build supports {}", supported_range());
+	}
+	Ok(Some(version))
+}
+
+/// Whether a runtime of `version` (a cache directory or file name version) may be loaded: the
+/// pinned version if there is one, otherwise any supported one.
+fn loadable(version: &str) -> Result<bool> {
+	Ok(match pinned_version()? {
+		Some(pinned) => parse_version_prefix(version) == parse_version_prefix(pinned),
+		None => supported(&parse_version_prefix(version))
+	})
+}
+
+/// The newest final (not pre-) release of `proj` in `SUPPORTED_VERSIONS`.
+#[cfg(all(feature = "download", feature = "load-dynamic"))]
+fn newest_supported(proj: &pypi::PypiProject) -> Option<String> {
+	proj.releases
+		.keys()
+		.filter(|v| v.split('.').all(|part| part.parse::<u64>().is_ok()))
+		.filter(|v| supported(&parse_version_prefix(v)))
+		.max_by(|a, b| cmp_versions(a, b))
+		.cloned()
+}
+
+/// An ONNX Runtime installed outside the cache (the active conda env, Homebrew, the system library
+/// directories), when `plan` wants the CPU package and the first one found is `loadable`. The
+/// version comes from the file name, so unversioned libraries (as on Windows) are never picked.
+fn system_lib(plan: &Plan) -> Result<Option<PathBuf>> {
+	// A system install is the CPU build; GPU packages still come from PyPI.
+	if plan.runtime_package != "onnxruntime" {
+		return Ok(None);
+	}
+	let os = env::consts::OS;
+	let mut dirs: Vec<PathBuf> = env::var_os("CONDA_PREFIX")
+		.map(|prefix| PathBuf::from(prefix).join("lib"))
+		.into_iter()
+		.collect();
+	match os {
+		"macos" => dirs.extend(["/opt/homebrew/lib", "/usr/local/lib"].map(PathBuf::from)),
+		"linux" => {
+			dirs.extend(["/usr/local/lib", "/usr/lib64", "/usr/lib"].map(PathBuf::from));
+			dirs.push(PathBuf::from(format!("/usr/lib/{}-linux-gnu", env::consts::ARCH)));
+		}
+		_ => {}
+	}
+	for dir in dirs {
+		if let Some(lib) = find_main_lib(os, &dir)
+			&& loadable(&lib_version(&lib))?
+		{
+			return Ok(Some(lib));
+		}
+	}
+	Ok(None)
+}
+
+/// `1.23.2` for `libonnxruntime.so.1.23.2` or `libonnxruntime.1.23.2.dylib` (symlinks resolved);
+/// empty when the name carries no version.
+fn lib_version(lib: &Path) -> String {
+	let real = std::fs::canonicalize(lib).unwrap_or_else(|_| lib.to_path_buf());
+	let name = real.file_name().and_then(|s| s.to_str()).unwrap_or("");
+	name.strip_prefix("libonnxruntime.so.")
+		.or_else(|| name.strip_prefix("libonnxruntime.").and_then(|v| v.strip_suffix(".dylib")))
+		.unwrap_or("")
+		.to_string()
+}
+
 pub fn init_ort(rt: &OnnxRuntimeInstall) -> Result<()> {
 	if let Some(p) = ORT_MAIN_LIB.get() {
 		if p != &rt.main_lib {
@@ -306,13 +505,21 @@ pub fn init_ort(rt: &OnnxRuntimeInstall) -> Result<()> {
 	}
 
 	// Must be called before any `Session` is created.
-	let builder = ort::init_from(&rt.main_lib)
-		.with_context(|| format!("load onnxruntime from {}", rt.main_lib.display()))?;
-	builder.commit();
+	#[cfg(feature = "load-dynamic")]
+	{
+		let builder = ort::init_from(&rt.main_lib)
+			.with_context(|| format!("load onnxruntime from {}", rt.main_lib.display()))?;
+		builder.commit();
+	}
 	let _ = ORT_MAIN_LIB.set(rt.main_lib.clone());
 	Ok(())
 }
 
+/// Whether `init_ort` has loaded a runtime into this process.
+pub fn ort_loaded() -> bool {
+	ORT_MAIN_LIB.get().is_some()
+}
+
 fn find_main_lib(os: &str, lib_dir: &Path) -> Option<PathBuf> {
 	let prefer = match os {
 		"windows" => "onnxruntime.dll",
@@ -349,6 +556,7 @@ fn find_main_lib(os: &str, lib_dir: &Path) -> Option<PathBuf> {
 	best.map(|(_, p)| p)
 }
 
+#[cfg(all(feature = "download", feature = "load-dynamic"))]
 fn extract_ort_libs_from_wheel(wheel_path: &Path, lib_dir: &Path) -> Result<()> {
 	use std::io::Read;
 
@@ -388,18 +596,128 @@ fn extract_ort_libs_from_wheel(wheel_path: &Path, lib_dir: &Path) -> Result<()>
 	Ok(())
 }
 
+#[cfg(all(feature = "download", feature = "load-dynamic"))]
 fn is_runtime_lib_file(name: &str) -> bool {
 	let lower = name.to_ascii_lowercase();
 	lower.ends_with(".dll") || lower.ends_with(".so") || lower.contains(".so.") || lower.ends_with(".dylib")
 }
 
+/// Where the TensorRT EP keeps built engines, so only the first session pays for the build.
+pub fn tensorrt_cache_dir() -> Result<PathBuf> {
+	Ok(cache_base_dir()?.join("tensorrt"))
+}
+
+/// Where the OpenVINO EP caches compiled models (compiling for the GPU/NPU is slow).
+pub fn openvino_cache_dir() -> Result<PathBuf> {
+	Ok(cache_base_dir()?.join("openvino"))
+}
+
+/// One cached runtime version, `<cache>/onnxruntime/<package>/<version>/`.
+pub struct InstalledRuntime {
+	pub package: String,
+	pub version: String,
+	pub dir: PathBuf,
+	/// Bytes on disk (wheel plus extracted libraries).
+	pub size: u64,
+	/// Whether this is the version a run would load for its package.
+	pub active: bool
+}
+
+/// Where runtime packages are cached.
+pub fn runtimes_dir() -> Result<PathBuf> {
+	Ok(cache_base_dir()?.join("onnxruntime"))
+}
+
+/// Every cached runtime version, by package, newest first.
+pub fn list_installed() -> Result<Vec<InstalledRuntime>> {
+	let base = runtimes_dir()?;
+	if !base.exists() {
+		return Ok(Vec::new());
+	}
+	let mut packages: Vec<PathBuf> = std::fs::read_dir(&base)
+		.with_context(|| format!("read cache dir: {}", base.display()))?
+		.flatten()
+		.map(|e| e.path())
+		.filter(|p| p.is_dir())
+		.collect();
+	packages.sort();
+
+	let mut out = Vec::new();
+	for pkg_dir in packages {
+		let package = pkg_dir.file_name().and_then(|s| s.to_str()).unwrap_or("").to_string();
+		let mut versions: Vec<PathBuf> = std::fs::read_dir(&pkg_dir)
+			.with_context(|| format!("read cache dir: {}", pkg_dir.display()))?
+			.flatten()
+			.map(|e| e.path())
+			.filter(|p| p.is_dir())
+			.collect();
+		versions.sort_by(|a, b| cmp_version_dir_names(b, a));
+
+		// Same pick as `find_any_installed_lib`: the newest loadable version with libraries or a wheel
+		// to extract.
+		let mut found_active = false;
+		for dir in versions {
+			let version = dir.file_name().and_then(|s| s.to_str()).unwrap_or("").to_string();
+			let usable = loadable(&version)?
+				&& (find_main_lib(env::consts::OS, &dir.join("lib")).is_some() || find_any_wheel(&dir)?.is_some());
+			let active = usable && !found_active;
+			found_active |= active;
+			out.push(InstalledRuntime {
+				package: package.clone(),
+				version,
+				size: dir_size(&dir),
+				dir,
+				active
+			});
+		}
+	}
+	Ok(out)
+}
+
+/// Delete every cached version a run wouldn't load (older releases, interrupted downloads) and
+/// return what was removed.
+pub fn prune() -> Result<Vec<InstalledRuntime>> {
+	let mut removed = Vec::new();
+	for rt in list_installed()? {
+		if rt.active {
+			continue;
+		}
+		std::fs::remove_dir_all(&rt.dir).with_context(|| format!("remove {}", rt.dir.display()))?;
+		removed.push(rt);
+	}
+	Ok(removed)
+}
+
+/// Delete every cached runtime; the next run downloads one again. Returns the bytes freed.
+pub fn clean() -> Result<u64> {
+	let base = runtimes_dir()?;
+	if !base.exists() {
+		return Ok(0);
+	}
+	let size = dir_size(&base);
+	std::fs::remove_dir_all(&base).with_context(|| format!("remove {}", base.display()))?;
+	Ok(size)
+}
+
+/// Bytes in `dir` and below; 0 if it doesn't exist.
+pub fn dir_size(dir: &Path) -> u64 {
+	let Ok(rd) = std::fs::read_dir(dir) else {
+		return 0;
+	};
+	rd.flatten()
+		.map(|e| match e.file_type() {
+			Ok(t) if t.is_dir() => dir_size(&e.path()),
+			Ok(_) => e.metadata().map_or(0, |m| m.len()),
+			Err(_) => 0
+		})
+		.sum()
+}
+
 fn cache_base_dir() -> Result<PathBuf> {
-	let dirs = directories::ProjectDirs::from("rs", "rembg", "rembg-rs")
-		.ok_or_else(|| anyhow::anyhow!("unable to resolve user cache directory"))?;
-	Ok(dirs.cache_dir().to_path_buf())
+	crate::config::cache_dir()
 }
 
-fn has_any_cached_runtime(package: &str) -> Result<bool> {
+pub(crate) fn has_any_cached_runtime(package: &str) -> Result<bool> {
 	let os = env::consts::OS;
 	let base = cache_base_dir()?
 		.join("onnxruntime")
@@ -420,6 +738,7 @@ fn has_any_cached_runtime(package: &str) -> Result<bool> {
 	Ok(false)
 }
 
+#[cfg(all(feature = "download", feature = "load-dynamic"))]
 fn find_any_installed_lib(os: &str, pkg_dir: &Path) -> Result<Option<PathBuf>> {
 	if !pkg_dir.exists() {
 		return Ok(None);
@@ -429,7 +748,7 @@ fn find_any_installed_lib(os: &str, pkg_dir: &Path) -> Result<Option<PathBuf>> {
 	let rd = std::fs::read_dir(pkg_dir).with_context(|| format!("read cache dir: {}", pkg_dir.display()))?;
 	for ent in rd.flatten() {
 		let p = ent.path();
-		if p.is_dir() {
+		if p.is_dir() && loadable(&ent.file_name().to_string_lossy())? {
 			versions.push(p);
 		}
 	}
@@ -523,6 +842,7 @@ fn parse_version_prefix(s: &str) -> Vec<u64> {
 	out
 }
 
+#[cfg(all(feature = "download", feature = "load-dynamic"))]
 fn normalize_os(os: &str) -> String {
 	match os {
 		"windows" => "windows".to_string(),
@@ -531,6 +851,7 @@ fn normalize_os(os: &str) -> String {
 	}
 }
 
+#[cfg(all(feature = "download", feature = "load-dynamic"))]
 fn normalize_arch(arch: &str) -> String {
 	match arch {
 		"x86_64" => "x86_64".to_string(),
@@ -543,6 +864,9 @@ fn prompt_yes_no(msg: &str, assume_yes: bool) -> Result<bool> {
 	if assume_yes {
 		return Ok(true);
 	}
+	if !term::interactive() {
+		bail!("{msg} Not asking in a non-interactive session (no TTY or CI is set); pass -y/--yes to allow it.");
+	}
 	eprint!("{msg} [y/N] ");
 	io::stderr().flush().ok();
 
@@ -0,0 +1,88 @@
+use image::RgbImage;
+use image::imageops::FilterType;
+use ndarray::{Array2, Array3, Array4};
+use serde::{Deserialize, Serialize};
+
+/// Side of SAM's square encoder input; the image is scaled to fit and padded bottom/right.
+pub const INPUT_SIZE: u32 = 1024;
+
+const PIXEL_MEAN: [f32; 3] = [123.675, 116.28, 103.53];
+const PIXEL_STD: [f32; 3] = [58.395, 57.12, 57.375];
+
+/// A click in input image pixels: on the subject (`foreground`) or on something to leave out.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Point {
+	pub x: f32,
+	pub y: f32,
+	#[serde(default = "foreground_default")]
+	pub foreground: bool
+}
+
+fn foreground_default() -> bool {
+	true
+}
+
+/// What SAM should segment. Without points or a box it takes the object at the image center.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Prompt {
+	#[serde(default)]
+	pub points: Vec<Point>,
+	/// `[x0, y0, x1, y1]` in input image pixels.
+	#[serde(default)]
+	pub bbox: Option<[f32; 4]>
+}
+
+/// The encoder's `1x3x1024x1024` input: longest side scaled to `INPUT_SIZE`, normalized with SAM's
+/// pixel mean/std, zero-padded.
+pub fn preprocess(img: &RgbImage) -> Array4<f32> {
+	let (w, h) = resized_size(img.width(), img.height());
+	let resized = image::imageops::resize(img, w, h, FilterType::Triangle);
+	let side = INPUT_SIZE as usize;
+	let mut t = Array4::<f32>::zeros((1, 3, side, side));
+	for (x, y, p) in resized.enumerate_pixels() {
+		for c in 0..3 {
+			t[[0, c, y as usize, x as usize]] = (p.0[c] as f32 - PIXEL_MEAN[c]) / PIXEL_STD[c];
+		}
+	}
+	t
+}
+
+/// The decoder's `point_coords` (`1xNx2`, in encoder input pixels) and `point_labels` (`1xN`) for a
+/// `width`x`height` image.
+pub fn prompt_tensors(prompt: &Prompt, width: u32, height: u32) -> (Array3<f32>, Array2<f32>) {
+	let (rw, rh) = resized_size(width, height);
+	let (sx, sy) = (rw as f32 / width as f32, rh as f32 / height as f32);
+
+	let center = [Point {
+		x: width as f32 / 2.0,
+		y: height as f32 / 2.0,
+		foreground: true
+	}];
+	let points: &[Point] = if prompt.points.is_empty() && prompt.bbox.is_none() { &center } else { &prompt.points };
+
+	// Labels: 1 foreground, 0 background, 2/3 box corners, -1 padding.
+	let mut coords: Vec<[f32; 2]> = points.iter().map(|p| [p.x * sx, p.y * sy]).collect();
+	let mut labels: Vec<f32> = points.iter().map(|p| if p.foreground { 1.0 } else { 0.0 }).collect();
+	match prompt.bbox {
+		Some([x0, y0, x1, y1]) => {
+			coords.extend([[x0 * sx, y0 * sy], [x1 * sx, y1 * sy]]);
+			labels.extend([2.0, 3.0]);
+		}
+		// Without a box the decoder expects a padding point in its place.
+		None => {
+			coords.push([0.0, 0.0]);
+			labels.push(-1.0);
+		}
+	}
+
+	let n = labels.len();
+	let coords = Array3::from_shape_fn((1, n, 2), |(_, i, j)| coords[i][j]);
+	let labels = Array2::from_shape_vec((1, n), labels).expect("one label per point");
+	(coords, labels)
+}
+
+fn resized_size(width: u32, height: u32) -> (u32, u32) {
+	let scale = INPUT_SIZE as f32 / width.max(height) as f32;
+	let side = |v: u32| ((v as f32 * scale + 0.5) as u32).clamp(1, INPUT_SIZE);
+	(side(width), side(height))
+}
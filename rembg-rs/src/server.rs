@@ -0,0 +1,216 @@
+use std::io::Read;
+
+use anyhow::{Context, Result, bail};
+use serde::de::DeserializeOwned;
+use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
+
+use crate::cancel::CancellationToken;
+use crate::core::{self, RemoveOptions};
+use crate::{cli, sam};
+
+/// Largest accepted request body (the image plus multipart overhead).
+const MAX_BODY_LEN: usize = 64 * 1024 * 1024;
+
+/// Serve `POST /remove` on `host:port` until the process is killed.
+///
+/// The image is either the `file` field of a `multipart/form-data` body (an optional `bg_image`
+/// field sets a background image) or the raw request body. Query parameters mirror
+/// `RemoveOptions` (`model`, `device`, `bgcolor`, `output_format`, `jpeg_quality`, ...); the
+/// response body is the encoded result.
+pub fn serve(host: &str, port: u16) -> Result<()> {
+	let server = Server::http((host, port)).map_err(|e| anyhow::anyhow!("listen on {host}:{port}: {e}"))?;
+	eprintln!("rembg-rs listening on http://{host}:{port}");
+	for req in server.incoming_requests() {
+		std::thread::spawn(move || {
+			if let Err(e) = handle(req) {
+				eprintln!("send response: {e}");
+			}
+		});
+	}
+	Ok(())
+}
+
+fn handle(mut req: Request) -> std::io::Result<()> {
+	let url = req.url().to_string();
+	let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+	if path != "/remove" {
+		return req.respond(text(404, "not found"));
+	}
+	if *req.method() != Method::Post {
+		return req.respond(text(405, "use POST"));
+	}
+
+	let (input, opts) = match parse_request(&mut req, query) {
+		Ok(v) => v,
+		Err(e) => return req.respond(text(400, &format!("{e:#}")))
+	};
+	match core::remove_background_bytes(&input, &opts, &CancellationToken::new(), |_| {}) {
+		Ok(result) => {
+			let content_type = Header::from_bytes("Content-Type", opts.output_format.mime_type()).expect("valid header");
+			req.respond(Response::from_data(result.output_png).with_header(content_type))
+		}
+		Err(e) => req.respond(text(500, &format!("{e:#}")))
+	}
+}
+
+/// Read the input image and build the options from the query string and form fields.
+fn parse_request(req: &mut Request, query: &str) -> Result<(Vec<u8>, RemoveOptions)> {
+	let mut opts = RemoveOptions::default();
+	for pair in query.split('&').filter(|p| !p.is_empty()) {
+		let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+		set_option(&mut opts, &percent_decode(key), &percent_decode(value))?;
+	}
+
+	let mut body = Vec::new();
+	req.as_reader()
+		.take(MAX_BODY_LEN as u64 + 1)
+		.read_to_end(&mut body)
+		.context("read request body")?;
+	if body.len() > MAX_BODY_LEN {
+		bail!("request body exceeds {} MiB", MAX_BODY_LEN / (1024 * 1024));
+	}
+
+	let boundary = req
+		.headers()
+		.iter()
+		.find(|h| h.field.equiv("Content-Type"))
+		.and_then(|h| {
+			let v = h.value.as_str();
+			v.starts_with("multipart/form-data")
+				.then(|| v.split(';').find_map(|p| p.trim().strip_prefix("boundary=")))
+				.flatten()
+				.map(|b| b.trim_matches('"').to_string())
+		});
+	let input = match boundary {
+		Some(boundary) => {
+			let mut input = None;
+			for (name, data) in multipart_parts(&body, &boundary) {
+				match name.as_str() {
+					"file" => input = Some(data.to_vec()),
+					"bg_image" => opts.bg_image = Some(data.to_vec()),
+					_ => {}
+				}
+			}
+			input.context("multipart body has no `file` field")?
+		}
+		None => body
+	};
+	// Unreadable input is the client's fault; report it as such rather than as a server error.
+	image::guess_format(&input).context("unrecognized image format")?;
+	Ok((input, opts))
+}
+
+fn set_option(opts: &mut RemoveOptions, key: &str, value: &str) -> Result<()> {
+	fn num<T: std::str::FromStr>(key: &str, value: &str) -> Result<T> {
+		value.parse().map_err(|_| anyhow::anyhow!("invalid {key}: {value:?}"))
+	}
+	// Enums use their serde names, so the query matches the JSON API.
+	fn named<T: DeserializeOwned>(key: &str, value: &str) -> Result<T> {
+		serde_json::from_value(serde_json::Value::String(value.to_ascii_lowercase()))
+			.map_err(|_| anyhow::anyhow!("invalid {key}: {value:?}"))
+	}
+
+	match key {
+		"model" => opts.model = value.to_string(),
+		"device" => opts.device = named(key, value)?,
+		"gpu_backend" => opts.gpu_backend = named(key, value)?,
+		"mask_threshold" => opts.mask_threshold = Some(num(key, value)?),
+		"bgcolor" => opts.bgcolor = Some(value.to_string()),
+		"bg_fit" => opts.bg_placement.fit = named(key, value)?,
+		"bg_anchor" => opts.bg_placement.anchor = named(key, value)?,
+		"color_key_tolerance" => opts.color_key_tolerance = Some(num(key, value)?),
+		"color_key_color" => opts.color_key_color = Some(value.to_string()),
+		"despill" => opts.despill = num(key, value)?,
+		"allow_download" => opts.allow_download = num(key, value)?,
+		"output_format" => opts.output_format = named(key, value)?,
+		"metadata" => opts.metadata = named(key, value)?,
+		"jpeg_quality" => opts.encode.jpeg_quality = num(key, value)?,
+		"webp_quality" => opts.encode.webp_quality = num(key, value)?,
+		"webp_lossless" => opts.encode.webp_lossless = num(key, value)?,
+		"png_optimize" => opts.encode.png_optimize = Some(num(key, value)?),
+		"cloth_category" => opts.cloth_category = Some(named(key, value)?),
+		"refine" => opts.refine = num(key, value)?,
+		"guided_filter" => opts.guided_filter = num(key, value)?,
+		// `sam` prompts; `point`/`exclude` may repeat.
+		"point" | "exclude" => {
+			let p: cli::Offset = value.parse().map_err(anyhow::Error::msg)?;
+			opts.points.push(sam::Point {
+				x: p.x as f32,
+				y: p.y as f32,
+				foreground: key == "point"
+			});
+		}
+		"bbox" => {
+			let r: cli::Rect = value.parse().map_err(anyhow::Error::msg)?;
+			opts.bbox = Some([r.x0, r.y0, r.x1, r.y1]);
+		}
+		_ => bail!("unknown query parameter {key:?}")
+	}
+	Ok(())
+}
+
+/// `(field name, content)` of each part of a `multipart/form-data` body.
+fn multipart_parts<'a>(body: &'a [u8], boundary: &str) -> Vec<(String, &'a [u8])> {
+	let delimiter = format!("--{boundary}");
+	let mut parts = Vec::new();
+	let mut rest = body;
+	while let Some(start) = find(rest, delimiter.as_bytes()) {
+		rest = &rest[start + delimiter.len()..];
+		// `--` after the delimiter closes the body.
+		let Some(part) = rest.strip_prefix(b"\r\n") else {
+			break;
+		};
+		let end = find(part, format!("\r\n{delimiter}").as_bytes()).unwrap_or(part.len());
+		let part = &part[..end];
+		rest = &rest[2 + end..];
+
+		let Some(header_end) = find(part, b"\r\n\r\n") else {
+			continue;
+		};
+		let headers = String::from_utf8_lossy(&part[..header_end]);
+		let name = headers
+			.lines()
+			.filter(|l| l.to_ascii_lowercase().starts_with("content-disposition:"))
+			.flat_map(|l| l.split(';'))
+			.find_map(|p| p.trim().strip_prefix("name="))
+			.map(|n| n.trim_matches('"').to_string());
+		if let Some(name) = name {
+			parts.push((name, &part[header_end + 4..]));
+		}
+	}
+	parts
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+	haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn percent_decode(s: &str) -> String {
+	let bytes = s.as_bytes();
+	let mut out = Vec::with_capacity(bytes.len());
+	let mut i = 0;
+	while i < bytes.len() {
+		let escaped = (bytes[i] == b'%')
+			.then(|| s.get(i + 1..i + 3))
+			.flatten()
+			.and_then(|h| u8::from_str_radix(h, 16).ok());
+		match (bytes[i], escaped) {
+			(_, Some(b)) => {
+				out.push(b);
+				i += 3;
+				continue;
+			}
+			(b'+', None) => out.push(b' '),
+			(b, None) => out.push(b)
+		}
+		i += 1;
+	}
+	String::from_utf8_lossy(&out).into_owned()
+}
+
+fn text(status: u16, body: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+	let content_type = Header::from_bytes("Content-Type", "text/plain; charset=utf-8").expect("valid header");
+	Response::from_string(format!("{body}\n"))
+		.with_status_code(StatusCode(status))
+		.with_header(content_type)
+}
@@ -0,0 +1,22 @@
+use std::env;
+use std::io::{self, IsTerminal};
+
+/// If set (to anything but `0`/`false`), we assume nobody is watching: most CI systems export `CI=true`.
+pub const CI_ENV: &str = "CI";
+
+/// True when a human can answer prompts: stdin is a terminal and we're not running under CI.
+pub fn interactive() -> bool {
+	io::stdin().is_terminal() && !ci()
+}
+
+/// True when `\r`-redrawn progress lines make sense (stderr is a terminal, not a log file or pipe).
+pub fn live_progress() -> bool {
+	io::stderr().is_terminal() && !ci()
+}
+
+fn ci() -> bool {
+	match env::var(CI_ENV) {
+		Ok(v) => !matches!(v.trim().to_ascii_lowercase().as_str(), "" | "0" | "false" | "no"),
+		Err(_) => false
+	}
+}
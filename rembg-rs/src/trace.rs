@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use image::GrayImage;
+
+/// Trace the outline of a mask into an SVG document with one `evenodd` path, so holes stay
+/// holes. Pixels at or above `threshold` are inside. Contours follow marching squares through
+/// pixel centers and are then simplified so no vertex moves more than `tolerance` pixels; specks
+/// that collapse under it are dropped.
+pub fn mask_to_svg(mask: &GrayImage, threshold: u8, tolerance: f32) -> String {
+	let (w, h) = mask.dimensions();
+	let mut d = String::new();
+	for contour in contours(mask, threshold) {
+		let points = simplify_closed(&contour, tolerance);
+		if points.len() < 3 {
+			continue;
+		}
+		for (i, (x, y)) in points.iter().enumerate() {
+			let _ = write!(d, "{}{x} {y}", if i == 0 { 'M' } else { 'L' });
+		}
+		d.push('Z');
+	}
+	format!(
+		"<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\n\
+		 <path fill-rule=\"evenodd\" d=\"{d}\"/>\n\
+		 </svg>\n"
+	)
+}
+
+/// Closed contours in image coordinates (pixel `(x, y)` covers `x..x+1`). The mask is treated as
+/// surrounded by background, so subjects touching the border still get closed outlines.
+fn contours(mask: &GrayImage, threshold: u8) -> Vec<Vec<(f32, f32)>> {
+	let (w, h) = (mask.width() as i32, mask.height() as i32);
+	let inside = |x: i32, y: i32| x >= 0 && y >= 0 && x < w && y < h && mask.get_pixel(x as u32, y as u32).0[0] >= threshold;
+
+	// Points are edge midpoints between two samples, kept as the sum of both sample coordinates
+	// so they stay integers. Each maps to the next point along its contour; `starts` keeps them in
+	// the order found, so each contour is picked up without rescanning the map.
+	let mut next: HashMap<(i32, i32), (i32, i32)> = HashMap::new();
+	let mut starts = Vec::new();
+	for cy in -1..h {
+		for cx in -1..w {
+			// Cell corners, clockwise from top-left.
+			let corners = [(cx, cy), (cx + 1, cy), (cx + 1, cy + 1), (cx, cy + 1)];
+			let set = corners.map(|(x, y)| inside(x, y));
+			if set.iter().all(|&s| s) || !set.iter().any(|&s| s) {
+				continue;
+			}
+			let midpoint = |i: usize| {
+				let (a, b) = (corners[i], corners[(i + 1) % 4]);
+				(a.0 + b.0, a.1 + b.1)
+			};
+			// Walking clockwise, join each edge entering the subject to the next one leaving it.
+			// In the two ambiguous (diagonal) cases this keeps the inside corners apart.
+			for i in 0..4 {
+				if set[i] || !set[(i + 1) % 4] {
+					continue;
+				}
+				let exit = (1..4).map(|k| (i + k) % 4).find(|&j| set[j] && !set[(j + 1) % 4]).expect("an entry has an exit");
+				next.insert(midpoint(i), midpoint(exit));
+				starts.push(midpoint(i));
+			}
+		}
+	}
+
+	let mut out = Vec::new();
+	for start in starts {
+		if !next.contains_key(&start) {
+			// Already walked as part of an earlier contour.
+			continue;
+		}
+		let mut contour = Vec::new();
+		let mut p = start;
+		while let Some(q) = next.remove(&p) {
+			// Samples sit at pixel centers, half a pixel in from the pixel's corner.
+			contour.push((p.0 as f32 / 2.0 + 0.5, p.1 as f32 / 2.0 + 0.5));
+			p = q;
+		}
+		out.push(contour);
+	}
+	out
+}
+
+/// Ramer-Douglas-Peucker on a closed polygon, split at the vertex farthest from the first.
+fn simplify_closed(points: &[(f32, f32)], tolerance: f32) -> Vec<(f32, f32)> {
+	if points.len() < 4 {
+		return points.to_vec();
+	}
+	let dist2 = |p: (f32, f32)| (p.0 - points[0].0).powi(2) + (p.1 - points[0].1).powi(2);
+	let far = (1..points.len()).max_by(|&a, &b| dist2(points[a]).total_cmp(&dist2(points[b]))).unwrap_or(1);
+
+	let mut out = Vec::new();
+	simplify(&points[..=far], tolerance, &mut out);
+	out.pop();
+	let mut rest = points[far..].to_vec();
+	rest.push(points[0]);
+	simplify(&rest, tolerance, &mut out);
+	out.pop();
+	out
+}
+
+/// Append the simplified `points` (both ends included) to `out`. Spans still to split wait on a
+/// stack, left half on top, so long contours can't run out of call stack.
+fn simplify(points: &[(f32, f32)], tolerance: f32, out: &mut Vec<(f32, f32)>) {
+	out.push(points[0]);
+	let mut spans = vec![(0, points.len() - 1)];
+	while let Some((first, last)) = spans.pop() {
+		let (a, b) = (points[first], points[last]);
+		let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+		let len = (dx * dx + dy * dy).sqrt();
+		let dist = |p: (f32, f32)| {
+			if len == 0.0 {
+				((p.0 - a.0).powi(2) + (p.1 - a.1).powi(2)).sqrt()
+			} else {
+				((p.0 - a.0) * dy - (p.1 - a.1) * dx).abs() / len
+			}
+		};
+		let worst = (first + 1..last).max_by(|&i, &j| dist(points[i]).total_cmp(&dist(points[j])));
+		match worst {
+			Some(i) if dist(points[i]) > tolerance => {
+				spans.push((i, last));
+				spans.push((first, i));
+			}
+			_ => out.push(b)
+		}
+	}
+}
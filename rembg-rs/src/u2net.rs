@@ -1,85 +1,725 @@
+#[cfg(feature = "native")]
 use std::path::Path;
+#[cfg(feature = "native")]
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+#[cfg(feature = "native")]
+use std::time::Instant;
 
-use anyhow::{Context, Result, bail};
-use image::{GrayImage, Luma, RgbImage};
+#[cfg(feature = "native")]
+use anyhow::Context;
+use anyhow::{Result, bail};
+use clap::ValueEnum;
+use image::{GrayImage, ImageBuffer, Luma, RgbImage};
 use image::imageops::FilterType;
-use ndarray::Array4;
-use ort::ep;
-use ort::session::Session;
-use ort::value::TensorRef;
+use ndarray::{Array4, ArrayD};
+#[cfg(feature = "native")]
+use ndarray::{Array1, Axis, Slice};
+#[cfg(feature = "native")]
+use ort::session::builder::{GraphOptimizationLevel, SessionBuilder};
+#[cfg(feature = "native")]
+use ort::{ep, session::{RunOptions, Session}, value::TensorRef};
+use serde::{Deserialize, Serialize};
 
+#[cfg(feature = "native")]
+use crate::cancel::{CancellationToken, Cancelled};
+#[cfg(feature = "native")]
+use crate::sam::{self, Prompt};
+
+/// ORT session tuning and the GPU to run on; unset fields keep ONNX Runtime's defaults.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionConfig {
+	/// Threads used within one operator (ORT default: one per physical core).
+	#[serde(default)]
+	pub intra_threads: Option<usize>,
+	#[serde(default)]
+	pub graph_opt_level: Option<GraphOptLevel>,
+	/// Pre-plan memory for the fixed input shape; turning it off lowers peak memory.
+	#[serde(default)]
+	pub mem_pattern: Option<bool>,
+	/// Keep freed tensor memory in ORT's CPU arena for reuse; turning it off hands it back.
+	#[serde(default)]
+	pub mem_arena: Option<bool>,
+	/// GPU the DirectML, CUDA or TensorRT EP runs on, an `id` from `core::list_devices` (default:
+	/// the first). CoreML and OpenVINO pick their own.
+	#[serde(default)]
+	pub device_id: Option<u32>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GraphOptLevel {
+	Disable,
+	Basic,
+	Extended,
+	All
+}
+
+/// A clothing class of `u2net_cloth_seg`, whose output has one channel per class after the
+/// background (channel 0).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClothClass {
+	Upper,
+	Lower,
+	Full
+}
+
+impl ClothClass {
+	pub fn name(self) -> &'static str {
+		match self {
+			ClothClass::Upper => "upper",
+			ClothClass::Lower => "lower",
+			ClothClass::Full => "full"
+		}
+	}
+
+	/// Output channel of this class (for `postprocess_class`).
+	pub fn channel(self) -> usize {
+		match self {
+			ClothClass::Upper => 1,
+			ClothClass::Lower => 2,
+			ClothClass::Full => 3
+		}
+	}
+}
+
+/// Full-resolution foreground probability map in `[0, 1]`, before 8-bit quantization.
+pub type ProbabilityMap = ImageBuffer<Luma<f32>, Vec<f32>>;
+
+#[cfg(feature = "native")]
 pub fn predict_mask(
 	model_path: &Path,
 	input_size: u32,
 	img: &RgbImage,
 	preferred_ep: Option<crate::runtime::PreferredEp>
 ) -> Result<GrayImage> {
-	let mut session = match preferred_ep {
-		None => Session::builder()
-			.context("create ORT session builder")?
-			.commit_from_file(model_path)
-			.with_context(|| format!("load onnx model: {}", model_path.display()))?,
-		Some(crate::runtime::PreferredEp::DirectML) => {
-			match Session::builder()
-				.context("create ORT session builder")?
-				.with_execution_providers([ep::DirectML::default().build()])
-				.context("configure DirectML EP")?
-				.commit_from_file(model_path)
-			{
-				Ok(s) => s,
-				Err(e) => {
-					eprintln!("DirectML init failed, falling back to CPU. This can happen if the DirectML provider cannot be loaded on this system: {e:#}");
-					Session::builder()
-						.context("create ORT session builder")?
-						.commit_from_file(model_path)
-						.with_context(|| format!("load onnx model (CPU fallback): {}", model_path.display()))?
+	let probs = predict_probabilities(model_path, input_size, img, preferred_ep)?;
+	Ok(quantize_mask(&probs))
+}
+
+pub fn quantize_mask(probs: &ProbabilityMap) -> GrayImage {
+	let mut mask = GrayImage::new(probs.width(), probs.height());
+	for (dst, src) in mask.pixels_mut().zip(probs.pixels()) {
+		dst.0[0] = (src.0[0].clamp(0.0, 1.0) * 255.0).round() as u8;
+	}
+	mask
+}
+
+/// Sub-steps of a single inference, reported while `predict_probabilities_with_progress` runs.
+#[derive(Debug, Clone, Copy)]
+pub enum InferProgress {
+	/// Creating the ORT session (model load + EP setup).
+	LoadSession,
+	Preprocess,
+	/// Heartbeat while the model runs; a 1024px ISNet pass on CPU can take tens of seconds.
+	Running { elapsed: Duration },
+	Postprocess
+}
+
+#[cfg(feature = "native")]
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(500);
+
+#[cfg(feature = "native")]
+pub fn predict_probabilities(
+	model_path: &Path,
+	input_size: u32,
+	img: &RgbImage,
+	preferred_ep: Option<crate::runtime::PreferredEp>
+) -> Result<ProbabilityMap> {
+	predict_probabilities_with_progress(model_path, input_size, img, preferred_ep, |_| {})
+}
+
+#[cfg(feature = "native")]
+pub fn predict_probabilities_with_progress(
+	model_path: &Path,
+	input_size: u32,
+	img: &RgbImage,
+	preferred_ep: Option<crate::runtime::PreferredEp>,
+	mut on_progress: impl FnMut(InferProgress)
+) -> Result<ProbabilityMap> {
+	on_progress(InferProgress::LoadSession);
+	let mut remover = Remover::new(model_path, input_size, preferred_ep, &SessionConfig::default())?;
+	remover.predict_probabilities(img, &CancellationToken::new(), on_progress)
+}
+
+/// A loaded model session. Build it once and reuse it across images: creating the session
+/// (parsing the model, setting up the EP) usually costs more than the inference itself.
+#[cfg(feature = "native")]
+pub struct Remover {
+	session: Session,
+	input_size: u32,
+	/// Set for SAM: `session` is then its image encoder.
+	sam_decoder: Option<Session>,
+	/// The last image's SAM embedding, keyed by `embedding_key`; new prompts on the same image skip
+	/// the encoder.
+	sam_embedding: Option<(u64, ArrayD<f32>)>,
+	/// Set by `load_matting`: the model `refine_edges` runs along the mask's edges.
+	matting: Option<Matting>
+}
+
+/// A matting model (ViTMatte, MODNet and similar exports) and its square input side. With a
+/// 4-channel input it gets the trimap after the RGB channels.
+#[cfg(feature = "native")]
+struct Matting {
+	session: Session,
+	input_size: u32,
+	trimap_input: bool
+}
+
+#[cfg(feature = "native")]
+impl Remover {
+	pub fn new(
+		model_path: &Path,
+		input_size: u32,
+		preferred_ep: Option<crate::runtime::PreferredEp>,
+		config: &SessionConfig
+	) -> Result<Self> {
+		Ok(Remover {
+			session: load_session(model_path, preferred_ep, config)?,
+			input_size,
+			sam_decoder: None,
+			sam_embedding: None,
+			matting: None
+		})
+	}
+
+	/// Load an installed model, including the prompt decoder of two-stage models.
+	pub fn from_install(
+		install: &crate::model::ModelInstall,
+		preferred_ep: Option<crate::runtime::PreferredEp>,
+		config: &SessionConfig
+	) -> Result<Self> {
+		let mut remover = Self::new(&install.path, install.input_size, preferred_ep, config)?;
+		if let Some(decoder) = &install.decoder_path {
+			remover.sam_decoder = Some(load_session(decoder, preferred_ep, config)?);
+		}
+		Ok(remover)
+	}
+
+	/// Load the matting model `refine_edges` uses; `input_size` is its square input side.
+	pub fn load_matting(
+		&mut self,
+		model_path: &Path,
+		input_size: u32,
+		preferred_ep: Option<crate::runtime::PreferredEp>,
+		config: &SessionConfig
+	) -> Result<()> {
+		let session = load_session(model_path, preferred_ep, config)?;
+		let trimap_input = session
+			.inputs()
+			.first()
+			.and_then(|input| input.dtype().tensor_shape())
+			.is_some_and(|shape| shape.get(1) == Some(&4));
+		self.matting = Some(Matting {
+			session,
+			input_size,
+			trimap_input
+		});
+		Ok(())
+	}
+
+	/// Re-estimate `probs` along its edges with the matting model from `load_matting`, for hair and
+	/// fur the segmentation model only gets roughly right. The matting model runs at full resolution
+	/// on tiles that cross the boundary band (see `mask::trimap`) and only band pixels change, so the
+	/// cost follows the length of the outline rather than the size of the photo. Without a matting
+	/// model `probs` comes back unchanged.
+	pub fn refine_edges(
+		&mut self,
+		img: &RgbImage,
+		probs: ProbabilityMap,
+		cancel: &CancellationToken,
+		mut on_progress: impl FnMut(InferProgress)
+	) -> Result<ProbabilityMap> {
+		let Some(matting) = &mut self.matting else {
+			return Ok(probs);
+		};
+		let (w, h) = probs.dimensions();
+		let radius = (w.max(h) / MATTING_BAND_DIVISOR).max(MATTING_MIN_BAND);
+		let trimap = crate::mask::trimap(&probs, radius);
+		let size = matting.input_size;
+		let margin = size / 8;
+		let cell = (size - 2 * margin).max(1);
+		let mut out = probs;
+		for cy in (0..h).step_by(cell as usize) {
+			for cx in (0..w).step_by(cell as usize) {
+				let (cw, ch) = (cell.min(w - cx), cell.min(h - cy));
+				let unknown = |x: u32, y: u32| trimap.get_pixel(x, y).0[0] == 128;
+				if !(cy..cy + ch).any(|y| (cx..cx + cw).any(|x| unknown(x, y))) {
+					continue;
+				}
+				cancel.check()?;
+				// An input-sized window around the cell, shifted to stay inside the image.
+				let (x0, y0) = (cx.saturating_sub(margin).min(w.saturating_sub(size)), cy.saturating_sub(margin).min(h.saturating_sub(size)));
+				let (tw, th) = (size.min(w), size.min(h));
+				let tile = image::imageops::crop_imm(img, x0, y0, tw, th).to_image();
+				let tile_trimap = image::imageops::crop_imm(&trimap, x0, y0, tw, th).to_image();
+				let alpha = matting.run(&tile, &tile_trimap, cancel, &mut on_progress)?;
+				for y in cy..cy + ch {
+					for x in cx..cx + cw {
+						if unknown(x, y) {
+							out.put_pixel(x, y, *alpha.get_pixel(x - x0, y - y0));
+						}
+					}
 				}
 			}
 		}
-		Some(crate::runtime::PreferredEp::Cuda) => {
-			match Session::builder()
-				.context("create ORT session builder")?
-				.with_execution_providers([ep::CUDA::default().build()])
-				.context("configure CUDA EP")?
-				.commit_from_file(model_path)
-			{
-				Ok(s) => s,
-				Err(e) => {
-					eprintln!("CUDA init failed, falling back to CPU. This often means the NVIDIA driver / CUDA libraries aren't available on this system: {e:#}");
-					Session::builder()
-						.context("create ORT session builder")?
-						.commit_from_file(model_path)
-						.with_context(|| format!("load onnx model (CPU fallback): {}", model_path.display()))?
-				}
+		Ok(out)
+	}
+
+	/// Predict the 8-bit mask for `img`.
+	pub fn process(&mut self, img: &RgbImage) -> Result<GrayImage> {
+		Ok(quantize_mask(&self.predict_probabilities(img, &CancellationToken::new(), |_| {})?))
+	}
+
+	/// Predict the full-resolution probability map for `img`, reporting every step but `LoadSession`.
+	/// Cancelling `cancel` terminates the running inference.
+	pub fn predict_probabilities(
+		&mut self,
+		img: &RgbImage,
+		cancel: &CancellationToken,
+		on_progress: impl FnMut(InferProgress)
+	) -> Result<ProbabilityMap> {
+		self.predict_probabilities_prompted(img, &Prompt::default(), cancel, on_progress)
+	}
+
+	/// `predict_probabilities` with the points/box a promptable model (SAM) should segment; other
+	/// models ignore `prompt`.
+	pub fn predict_probabilities_prompted(
+		&mut self,
+		img: &RgbImage,
+		prompt: &Prompt,
+		cancel: &CancellationToken,
+		mut on_progress: impl FnMut(InferProgress)
+	) -> Result<ProbabilityMap> {
+		if self.sam_decoder.is_some() {
+			return self.predict_sam(img, prompt, cancel, &mut on_progress);
+		}
+
+		let out = self.run(img, cancel, &mut on_progress)?;
+		on_progress(InferProgress::Postprocess);
+		postprocess(&out, img.width(), img.height())
+	}
+
+	/// `predict_probabilities_prompted`, then a second run on a crop around the subject so it fills
+	/// the model's input: much sharper edges for a small subject in a large frame, at up to twice
+	/// the cost. The second map takes over from the first across the crop's margin, so no seam
+	/// shows where the crop ends.
+	pub fn predict_probabilities_refined(
+		&mut self,
+		img: &RgbImage,
+		prompt: &Prompt,
+		cancel: &CancellationToken,
+		mut on_progress: impl FnMut(InferProgress)
+	) -> Result<ProbabilityMap> {
+		let mut probs = self.predict_probabilities_prompted(img, prompt, cancel, &mut on_progress)?;
+		let Some(RefineCrop { x, y, w, h, margin }) = refine_region(&probs) else {
+			return Ok(probs);
+		};
+		cancel.check()?;
+		let crop = image::imageops::crop_imm(img, x, y, w, h).to_image();
+		let (dx, dy) = (x as f32, y as f32);
+		let prompt = Prompt {
+			points: prompt
+				.points
+				.iter()
+				.map(|p| sam::Point {
+					x: p.x - dx,
+					y: p.y - dy,
+					..*p
+				})
+				.collect(),
+			bbox: prompt.bbox.map(|[x0, y0, x1, y1]| [x0 - dx, y0 - dy, x1 - dx, y1 - dy])
+		};
+		let fine = self.predict_probabilities_prompted(&crop, &prompt, cancel, on_progress)?;
+		blend_refined(&mut probs, &fine, x, y, margin);
+		Ok(probs)
+	}
+
+	/// One probability map per entry of `classes`, from a single run of a multi-class model
+	/// (`u2net_cloth_seg`).
+	pub fn predict_classes(
+		&mut self,
+		img: &RgbImage,
+		classes: &[ClothClass],
+		cancel: &CancellationToken,
+		mut on_progress: impl FnMut(InferProgress)
+	) -> Result<Vec<ProbabilityMap>> {
+		if self.sam_decoder.is_some() {
+			bail!("clothing categories need a multi-class model such as u2net_cloth_seg");
+		}
+		let out = self.run(img, cancel, &mut on_progress)?;
+		on_progress(InferProgress::Postprocess);
+		classes
+			.iter()
+			.map(|c| postprocess_class(&out, c.channel(), img.width(), img.height()))
+			.collect()
+	}
+
+	fn run(
+		&mut self,
+		img: &RgbImage,
+		cancel: &CancellationToken,
+		on_progress: &mut impl FnMut(InferProgress)
+	) -> Result<ArrayD<f32>> {
+		on_progress(InferProgress::Preprocess);
+		let input = preprocess(img, self.input_size)?;
+
+		cancel.check()?;
+		run_with_heartbeat(&mut self.session, &input, cancel, on_progress)
+	}
+
+	fn predict_sam(
+		&mut self,
+		img: &RgbImage,
+		prompt: &Prompt,
+		cancel: &CancellationToken,
+		on_progress: &mut impl FnMut(InferProgress)
+	) -> Result<ProbabilityMap> {
+		let key = embedding_key(img);
+		let embedding = match self.sam_embedding.take() {
+			Some((k, embedding)) if k == key => embedding,
+			_ => {
+				on_progress(InferProgress::Preprocess);
+				let input = sam::preprocess(img);
+				cancel.check()?;
+				run_with_heartbeat(&mut self.session, &input, cancel, on_progress)?
 			}
+		};
+		let embedding = &self.sam_embedding.insert((key, embedding)).1;
+
+		cancel.check()?;
+		let (coords, labels) = sam::prompt_tensors(prompt, img.width(), img.height());
+		let mask_input = Array4::<f32>::zeros((1, 1, 256, 256));
+		let has_mask_input = Array1::<f32>::zeros(1);
+		let orig_im_size = Array1::from_vec(vec![img.height() as f32, img.width() as f32]);
+		let decoder = self.sam_decoder.as_mut().expect("SAM decoder is loaded");
+		let outputs = decoder
+			.run(ort::inputs! {
+				"image_embeddings" => TensorRef::from_array_view(embedding)?,
+				"point_coords" => TensorRef::from_array_view(&coords)?,
+				"point_labels" => TensorRef::from_array_view(&labels)?,
+				"mask_input" => TensorRef::from_array_view(&mask_input)?,
+				"has_mask_input" => TensorRef::from_array_view(&has_mask_input)?,
+				"orig_im_size" => TensorRef::from_array_view(&orig_im_size)?
+			})
+			.context("run SAM decoder")?;
+		if outputs.len() == 0 {
+			bail!("SAM decoder produced no outputs");
 		}
+
+		on_progress(InferProgress::Postprocess);
+		// `masks` is 1xNxHxW logits at the original size, best candidate first.
+		let masks = outputs[0].try_extract_array::<f32>().context("extract SAM masks")?;
+		let best = masks.slice_axis(Axis(1), Slice::from(0..1)).to_owned();
+		postprocess(&best, img.width(), img.height())
+	}
+}
+
+#[cfg(feature = "native")]
+impl Matting {
+	/// Alpha for one tile, at the tile's size.
+	fn run(
+		&mut self,
+		tile: &RgbImage,
+		trimap: &GrayImage,
+		cancel: &CancellationToken,
+		on_progress: &mut impl FnMut(InferProgress)
+	) -> Result<ProbabilityMap> {
+		on_progress(InferProgress::Preprocess);
+		let s = self.input_size;
+		let rgb = image::imageops::resize(tile, s, s, FilterType::Lanczos3);
+		let mut input = image_to_tensor_nchw(&rgb)?;
+		if self.trimap_input {
+			let trimap = image::imageops::resize(trimap, s, s, FilterType::Nearest);
+			let channel = Array4::from_shape_fn((1, 1, s as usize, s as usize), |(_, _, y, x)| {
+				trimap.get_pixel(x as u32, y as u32).0[0] as f32 / 255.0
+			});
+			input = ndarray::concatenate(Axis(1), &[input.view(), channel.view()]).context("build matting input")?;
+		}
+		cancel.check()?;
+		let out = run_with_heartbeat(&mut self.session, &input, cancel, on_progress)?;
+		on_progress(InferProgress::Postprocess);
+		let (_, h, w) = output_dims(&out)?;
+		let alpha = ProbabilityMap::from_fn(w as u32, h as u32, |x, y| Luma([out[[0, 0, y as usize, x as usize]].clamp(0.0, 1.0)]));
+		Ok(upsample(&alpha, tile.width(), tile.height()))
+	}
+}
+
+/// The matting band reaches 1/`MATTING_BAND_DIVISOR` of the image's longer side past the mask's edge...
+#[cfg(feature = "native")]
+const MATTING_BAND_DIVISOR: u32 = 64;
+/// ...and at least this many pixels.
+#[cfg(feature = "native")]
+const MATTING_MIN_BAND: u32 = 8;
+
+/// Padding around the subject for the refinement crop, as a fraction of its size.
+#[cfg(feature = "native")]
+const REFINE_MARGIN: f32 = 0.1;
+/// ...and at least this many pixels, so thin edges aren't cut.
+#[cfg(feature = "native")]
+const REFINE_MIN_MARGIN: u32 = 16;
+
+/// The crop `predict_probabilities_refined` runs the model on again: the subject plus `margin`
+/// (horizontal, vertical) on each side, less where the frame ends.
+#[cfg(feature = "native")]
+struct RefineCrop {
+	x: u32,
+	y: u32,
+	w: u32,
+	h: u32,
+	margin: (u32, u32)
+}
+
+/// The subject in `probs` plus a margin, or `None` if there's no subject or the crop would keep
+/// most of the frame (so a second run adds little detail).
+#[cfg(feature = "native")]
+fn refine_region(probs: &ProbabilityMap) -> Option<RefineCrop> {
+	let (mut x0, mut y0, mut x1, mut y1) = (u32::MAX, u32::MAX, 0, 0);
+	for (x, y, p) in probs.enumerate_pixels() {
+		if p.0[0] >= 0.5 {
+			x0 = x0.min(x);
+			y0 = y0.min(y);
+			x1 = x1.max(x + 1);
+			y1 = y1.max(y + 1);
+		}
+	}
+	if x0 >= x1 {
+		return None;
+	}
+	let pad = |len: u32| ((len as f32 * REFINE_MARGIN) as u32).max(REFINE_MIN_MARGIN);
+	let (px, py) = (pad(x1 - x0), pad(y1 - y0));
+	let (x0, y0) = (x0.saturating_sub(px), y0.saturating_sub(py));
+	let (x1, y1) = ((x1 + px).min(probs.width()), (y1 + py).min(probs.height()));
+	let (w, h) = (x1 - x0, y1 - y0);
+	let frame = probs.width() as u64 * probs.height() as u64;
+	if w as u64 * h as u64 * 10 > frame * 7 {
+		return None;
+	}
+	Some(RefineCrop {
+		x: x0,
+		y: y0,
+		w,
+		h,
+		margin: (px, py)
+	})
+}
+
+/// Write `fine`, the map of the crop at `(x, y)`, into `probs`, fading from `probs` at the crop's
+/// edges to `fine` one `margin` in. Edges on the frame's border aren't faded: nothing lies beyond
+/// them to match.
+#[cfg(feature = "native")]
+fn blend_refined(probs: &mut ProbabilityMap, fine: &ProbabilityMap, x: u32, y: u32, margin: (u32, u32)) {
+	let (w, h) = fine.dimensions();
+	// Weight of `fine` at `i` pixels into a span of `len` at `start`, over a frame `size` long.
+	let ramp = |i: u32, len: u32, start: u32, size: u32, margin: u32| {
+		let before = if start > 0 { (i as f32 + 0.5) / margin as f32 } else { 1.0 };
+		let after = if start + len < size { ((len - i) as f32 - 0.5) / margin as f32 } else { 1.0 };
+		before.min(after).clamp(0.0, 1.0)
 	};
+	let (width, height) = probs.dimensions();
+	for (i, j, p) in fine.enumerate_pixels() {
+		let t = ramp(i, w, x, width, margin.0).min(ramp(j, h, y, height, margin.1));
+		let coarse = probs.get_pixel_mut(x + i, y + j);
+		coarse.0[0] += (p.0[0] - coarse.0[0]) * t;
+	}
+}
 
-	let resized = image::imageops::resize(img, input_size, input_size, FilterType::Lanczos3);
+#[cfg(feature = "native")]
+fn embedding_key(img: &RgbImage) -> u64 {
+	((crc32fast::hash(img.as_raw()) as u64) << 32) ^ ((img.width() as u64) << 16) ^ img.height() as u64
+}
 
-	let input = image_to_tensor_nchw(&resized)?;
-	let outputs = session
-		.run(ort::inputs![TensorRef::from_array_view(&input)?])
-		.context("run inference")?;
+#[cfg(feature = "native")]
+fn load_session(
+	model_path: &Path,
+	preferred_ep: Option<crate::runtime::PreferredEp>,
+	config: &SessionConfig
+) -> Result<Session> {
+	use crate::runtime::PreferredEp;
 
-	if outputs.len() == 0 {
-		bail!("model produced no outputs");
+	let device_id = config.device_id.unwrap_or(0) as i32;
+	let (name, providers, hint) = match preferred_ep {
+		None => return cpu_session(model_path, config),
+		Some(PreferredEp::DirectML) => (
+			"DirectML",
+			vec![ep::DirectML::default().with_device_id(device_id).build()],
+			"This can happen if the DirectML provider cannot be loaded on this system"
+		),
+		Some(PreferredEp::Cuda) => (
+			"CUDA",
+			vec![ep::CUDA::default().with_device_id(device_id).build()],
+			"This often means the NVIDIA driver / CUDA libraries aren't available on this system"
+		),
+		Some(PreferredEp::CoreML) => (
+			"CoreML",
+			vec![ep::CoreML::default().build()],
+			"CoreML needs macOS 10.15+; some models contain ops it can't compile"
+		),
+		Some(PreferredEp::TensorRT) => {
+			// Building an engine takes minutes; cache it (and the tactic timings) per model and GPU.
+			let cache = crate::runtime::tensorrt_cache_dir()?;
+			std::fs::create_dir_all(&cache).with_context(|| format!("create dir: {}", cache.display()))?;
+			let cache = cache.to_string_lossy();
+			(
+				"TensorRT",
+				// Nodes TensorRT can't take run on CUDA.
+				vec![
+					ep::TensorRT::default()
+						.with_device_id(device_id)
+						.with_engine_cache(true)
+						.with_engine_cache_path(&cache)
+						.with_timing_cache(true)
+						.with_timing_cache_path(&cache)
+						.build(),
+					ep::CUDA::default().with_device_id(device_id).build()
+				],
+				"TensorRT needs the TensorRT libraries installed next to the CUDA ones"
+			)
+		}
+		Some(PreferredEp::OpenVINO) => {
+			let cache = crate::runtime::openvino_cache_dir()?;
+			std::fs::create_dir_all(&cache).with_context(|| format!("create dir: {}", cache.display()))?;
+			(
+				"OpenVINO",
+				// Intel GPU first, then the NPU, then OpenVINO's own CPU plugin.
+				vec![
+					ep::OpenVINO::default()
+						.with_device_type("AUTO:GPU,NPU,CPU")
+						.with_cache_dir(cache.to_string_lossy())
+						.build()
+				],
+				"OpenVINO needs an Intel CPU/GPU/NPU with current drivers"
+			)
+		}
+	};
+	match session_builder(config)?
+		.with_execution_providers(providers)
+		.with_context(|| format!("configure {name} EP"))?
+		.commit_from_file(model_path)
+	{
+		Ok(s) => Ok(s),
+		Err(e) => {
+			eprintln!("{name} init failed, falling back to CPU. {hint}: {e:#}");
+			cpu_session(model_path, config).context("CPU fallback")
+		}
 	}
+}
 
-	let out0 = &outputs[0];
-	let out = out0.try_extract_array::<f32>().context("extract output tensor")?;
-	let shape = out.shape();
-	if shape.len() != 4 {
-		bail!("unexpected output rank: {} (expected 4)", shape.len());
+/// Whether the loaded ONNX Runtime was built with `ep`; it may still fail to start on this machine.
+#[cfg(feature = "native")]
+pub fn ep_available(preferred_ep: crate::runtime::PreferredEp) -> Result<bool> {
+	use crate::runtime::PreferredEp;
+	use ort::ep::ExecutionProvider;
+
+	match preferred_ep {
+		PreferredEp::DirectML => ep::DirectML::default().is_available(),
+		PreferredEp::Cuda => ep::CUDA::default().is_available(),
+		PreferredEp::CoreML => ep::CoreML::default().is_available(),
+		PreferredEp::TensorRT => ep::TensorRT::default().is_available(),
+		PreferredEp::OpenVINO => ep::OpenVINO::default().is_available()
 	}
-	let (n, c, h, w) = (shape[0], shape[1], shape[2], shape[3]);
-	if n != 1 {
-		bail!("unexpected batch size: {n} (expected 1)");
+	.context("query ONNX Runtime execution providers")
+}
+
+#[cfg(feature = "native")]
+fn cpu_session(model_path: &Path, config: &SessionConfig) -> Result<Session> {
+	session_builder(config)?
+		.commit_from_file(model_path)
+		.with_context(|| format!("load onnx model: {}", model_path.display()))
+}
+
+#[cfg(feature = "native")]
+fn session_builder(config: &SessionConfig) -> Result<SessionBuilder> {
+	let mut builder = Session::builder().context("create ORT session builder")?;
+	if let Some(n) = config.intra_threads {
+		builder = builder.with_intra_threads(n).context("set intra-op threads")?;
 	}
-	if c != 1 {
-		// Some exports can produce (1,H,W) or similar, but the common ones are (1,1,H,W).
-		// Fail loud for now.
-		bail!("unexpected output channels: {c} (expected 1)");
+	if let Some(level) = config.graph_opt_level {
+		let level = match level {
+			GraphOptLevel::Disable => GraphOptimizationLevel::Disable,
+			GraphOptLevel::Basic => GraphOptimizationLevel::Level1,
+			GraphOptLevel::Extended => GraphOptimizationLevel::Level2,
+			GraphOptLevel::All => GraphOptimizationLevel::All
+		};
+		builder = builder.with_optimization_level(level).context("set graph optimization level")?;
+	}
+	if let Some(on) = config.mem_pattern {
+		builder = builder.with_memory_pattern(on).context("set memory pattern")?;
+	}
+	if let Some(on) = config.mem_arena {
+		// Registering the CPU EP only sets this option; it doesn't change EP priority.
+		builder = builder
+			.with_execution_providers([ep::CPU::default().with_arena_allocator(on).build()])
+			.context("set CPU memory arena")?;
+	}
+	Ok(builder)
+}
+
+/// Run the session on a worker thread and emit `Running` heartbeats until it finishes.
+/// `Session::run` blocks with no progress hooks of its own; cancellation goes through
+/// `RunOptions::terminate`.
+#[cfg(feature = "native")]
+fn run_with_heartbeat(
+	session: &mut Session,
+	input: &Array4<f32>,
+	cancel: &CancellationToken,
+	on_progress: &mut impl FnMut(InferProgress)
+) -> Result<ArrayD<f32>> {
+	let run_options = RunOptions::new().context("create ORT run options")?;
+	let start = Instant::now();
+	std::thread::scope(|scope| {
+		let (tx, rx) = mpsc::channel();
+		let run_options = &run_options;
+		scope.spawn(move || {
+			let mut run = || -> Result<ArrayD<f32>> {
+				let outputs = session
+					.run_with_options(ort::inputs![TensorRef::from_array_view(input)?], run_options)
+					.context("run inference")?;
+				if outputs.len() == 0 {
+					bail!("model produced no outputs");
+				}
+				let out = outputs[0].try_extract_array::<f32>().context("extract output tensor")?;
+				Ok(out.to_owned())
+			};
+			let _ = tx.send(run());
+		});
+
+		on_progress(InferProgress::Running { elapsed: Duration::ZERO });
+		let mut terminated = false;
+		loop {
+			match rx.recv_timeout(HEARTBEAT_INTERVAL) {
+				// A terminated run fails with an ORT error; report it as the cancellation it is.
+				Ok(_) if terminated => return Err(Cancelled.into()),
+				Ok(res) => return res,
+				Err(RecvTimeoutError::Timeout) if cancel.is_cancelled() && !terminated => {
+					run_options.terminate().context("terminate inference")?;
+					terminated = true;
+				}
+				Err(RecvTimeoutError::Timeout) => on_progress(InferProgress::Running { elapsed: start.elapsed() }),
+				Err(RecvTimeoutError::Disconnected) => bail!("inference worker exited without a result")
+			}
+		}
+	})
+}
+
+/// Resize and normalize `img` into the model's `1x3xSxS` input tensor (`S` = `input_size`).
+pub fn preprocess(img: &RgbImage, input_size: u32) -> Result<Array4<f32>> {
+	let resized = image::imageops::resize(img, input_size, input_size, FilterType::Lanczos3);
+	image_to_tensor_nchw(&resized)
+}
+
+/// Turn the model's `1x1xHxW` output (probabilities or logits) into a probability map at the
+/// original image size. A multi-class `1xCxHxW` output (channel 0 being the background) gives the
+/// probability of any non-background class.
+pub fn postprocess(out: &ArrayD<f32>, width: u32, height: u32) -> Result<ProbabilityMap> {
+	let (c, h, w) = output_dims(out)?;
+	if c > 1 {
+		let mut probs = class_probabilities(out, 0, h, w);
+		for p in probs.pixels_mut() {
+			p.0[0] = 1.0 - p.0[0];
+		}
+		return Ok(upsample(&probs, width, height));
 	}
 
 	// Some exported models return probabilities in [0, 1], others return logits.
@@ -93,7 +733,7 @@ pub fn predict_mask(
 	}
 	let treat_as_prob = min_v >= -0.01 && max_v <= 1.01;
 
-	let mut mask_small = GrayImage::new(w as u32, h as u32);
+	let mut probs_small = ProbabilityMap::new(w as u32, h as u32);
 	for y in 0..h {
 		for x in 0..w {
 			let v = out[[0, 0, y, x]];
@@ -103,13 +743,63 @@ pub fn predict_mask(
 				// Most segmentation ONNX exports output logits; sigmoid gets us a stable [0,1] probability map.
 				1.0 / (1.0 + (-v).exp())
 			};
-			let px = (s.clamp(0.0, 1.0) * 255.0).round() as u8;
-			mask_small.put_pixel(x as u32, y as u32, Luma([px]));
+			probs_small.put_pixel(x as u32, y as u32, Luma([s.clamp(0.0, 1.0)]));
+		}
+	}
+	Ok(upsample(&probs_small, width, height))
+}
+
+/// The probability of class `channel` in a multi-class `1xCxHxW` output of per-class logits, at the
+/// original image size.
+pub fn postprocess_class(out: &ArrayD<f32>, channel: usize, width: u32, height: u32) -> Result<ProbabilityMap> {
+	let (c, h, w) = output_dims(out)?;
+	if c == 1 {
+		bail!("the model has a single output channel; clothing categories need a multi-class model such as u2net_cloth_seg");
+	}
+	if channel >= c {
+		bail!("output channel {channel} requested, but the model only has {c}");
+	}
+	Ok(upsample(&class_probabilities(out, channel, h, w), width, height))
+}
+
+/// `(channels, height, width)` of a `1xCxHxW` output.
+fn output_dims(out: &ArrayD<f32>) -> Result<(usize, usize, usize)> {
+	let shape = out.shape();
+	if shape.len() != 4 {
+		bail!("unexpected output rank: {} (expected 4)", shape.len());
+	}
+	let (n, c, h, w) = (shape[0], shape[1], shape[2], shape[3]);
+	if n != 1 {
+		bail!("unexpected batch size: {n} (expected 1)");
+	}
+	if c == 0 {
+		bail!("model output has no channels");
+	}
+	Ok((c, h, w))
+}
+
+/// Softmax over the channels, keeping `channel`.
+fn class_probabilities(out: &ArrayD<f32>, channel: usize, h: usize, w: usize) -> ProbabilityMap {
+	let c = out.shape()[1];
+	let mut probs = ProbabilityMap::new(w as u32, h as u32);
+	for y in 0..h {
+		for x in 0..w {
+			let max = (0..c).map(|k| out[[0, k, y, x]]).fold(f32::NEG_INFINITY, f32::max);
+			let sum: f32 = (0..c).map(|k| (out[[0, k, y, x]] - max).exp()).sum();
+			let p = (out[[0, channel, y, x]] - max).exp() / sum;
+			probs.put_pixel(x as u32, y as u32, Luma([p]));
 		}
 	}
+	probs
+}
 
-	let mask = image::imageops::resize(&mask_small, img.width(), img.height(), FilterType::Lanczos3);
-	Ok(mask)
+fn upsample(probs_small: &ProbabilityMap, width: u32, height: u32) -> ProbabilityMap {
+	// Lanczos overshoots around hard edges; clamp back into [0, 1] after upsampling.
+	let mut probs = image::imageops::resize(probs_small, width, height, FilterType::Lanczos3);
+	for p in probs.pixels_mut() {
+		p.0[0] = p.0[0].clamp(0.0, 1.0);
+	}
+	probs
 }
 
 fn image_to_tensor_nchw(img: &RgbImage) -> Result<Array4<f32>> {
@@ -0,0 +1,319 @@
+use std::io::{ErrorKind, Read, Write};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::thread::JoinHandle;
+
+use anyhow::{Context, Result, bail};
+use image::{GrayImage, RgbImage, RgbaImage};
+
+use crate::cancel::{Cancelled, CancellationToken};
+use crate::cli::VideoCodec;
+use crate::sam::Prompt;
+use crate::u2net::{self, ProbabilityMap, Remover};
+
+/// Overrides the `ffmpeg` binary (default: the one on `PATH`); `ffprobe` is looked up next to it.
+pub const FFMPEG_ENV: &str = "REMBG_FFMPEG";
+
+/// Input video properties, from `ffprobe`.
+#[derive(Debug, Clone)]
+pub struct VideoInfo {
+	/// As displayed, i.e. swapped for a stream stored on its side with a 90° rotation tag (phone
+	/// videos), which is how the decoder hands frames out.
+	pub width: u32,
+	pub height: u32,
+	/// As ffmpeg writes it, e.g. `30000/1001`.
+	pub frame_rate: String,
+	/// From the container; not every format records it.
+	pub frames: Option<u64>
+}
+
+impl VideoCodec {
+	/// `.webm` is VP9, `.mov` is ProRes 4444.
+	pub fn from_path(path: &Path) -> Option<VideoCodec> {
+		let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+		match ext.as_str() {
+			"webm" => Some(VideoCodec::Vp9),
+			"mov" => Some(VideoCodec::Prores),
+			_ => None
+		}
+	}
+
+	/// Encoder arguments for a stream with alpha, plus an audio codec the container takes.
+	fn encoder_args(self) -> &'static [&'static str] {
+		match self {
+			VideoCodec::Vp9 => &[
+				"-c:v", "libvpx-vp9", "-pix_fmt", "yuva420p", "-crf", "30", "-b:v", "0", "-auto-alt-ref", "0", "-c:a",
+				"libopus"
+			],
+			VideoCodec::Prores => &[
+				"-c:v", "prores_ks", "-profile:v", "4444", "-pix_fmt", "yuva444p10le", "-vendor", "apl0", "-c:a",
+				"pcm_s16le"
+			]
+		}
+	}
+}
+
+/// How each frame's mask is made.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameOptions {
+	/// 0..1: how much of the previous frame's mask to blend into each one, so edges don't flicker.
+	pub smoothing: f32,
+	/// Run the model again around the subject (`Remover::predict_probabilities_refined`).
+	pub refine: bool
+}
+
+/// Frame progress: frames written so far and the expected total, if known.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameProgress {
+	pub frame: u64,
+	pub total: Option<u64>
+}
+
+/// Write `input` to `output` with the background of every frame made transparent; the audio track
+/// is carried over. Returns the number of frames.
+pub fn remove_background(
+	input: &Path,
+	output: &Path,
+	codec: VideoCodec,
+	options: FrameOptions,
+	remover: &mut Remover,
+	cancel: &CancellationToken,
+	mut on_progress: impl FnMut(FrameProgress)
+) -> Result<u64> {
+	let info = probe(input)?;
+	let size = format!("{}x{}", info.width, info.height);
+	let input_arg = input.to_str().context("video path is not valid UTF-8")?;
+	let output_arg = output.to_str().context("video path is not valid UTF-8")?;
+
+	let mut decoder = spawn(decode_command(input_arg).stdout(Stdio::piped()))?;
+	let mut encoder = spawn(
+		Command::new(ffmpeg())
+			.args(["-v", "error", "-y", "-f", "rawvideo", "-pix_fmt", "rgba", "-s", &size])
+			.args(["-r", &info.frame_rate, "-i", "-", "-i", input_arg, "-map", "0:v", "-map", "1:a?", "-shortest"])
+			.args(codec.encoder_args())
+			.arg(output_arg)
+			.stdin(Stdio::piped())
+	)?;
+
+	let decoder_log = drain_stderr(&mut decoder);
+	let encoder_log = drain_stderr(&mut encoder);
+	let frames_in = decoder.stdout.take().expect("decoder stdout is piped");
+	// Dropped (closed) when `pump` returns, which lets the encoder finish the file.
+	let frames_out = encoder.stdin.take().expect("encoder stdin is piped");
+	let result = pump(frames_in, frames_out, &info, options, remover, cancel, &mut on_progress);
+	if let Err(e) = &result
+		&& e.chain().any(|e| e.is::<Cancelled>())
+	{
+		// Stop the encoder too rather than let it finish a truncated file.
+		let _ = decoder.kill();
+		let _ = encoder.kill();
+		let _ = decoder.wait();
+		let _ = encoder.wait();
+		let _ = std::fs::remove_file(output);
+		return result;
+	}
+	if result.is_err() {
+		let _ = decoder.kill();
+	}
+	let decoded = decoder.wait().context("wait for ffmpeg decoder")?;
+	let encoded = encoder.wait().context("wait for ffmpeg encoder")?;
+	// An encoder that died early is why writing a frame failed, so it's reported first.
+	if !encoded.success() {
+		bail!("ffmpeg failed to encode {} ({encoded}){}", output.display(), log_suffix(encoder_log));
+	}
+	let frames = result?;
+	if !decoded.success() {
+		bail!("ffmpeg failed to decode {} ({decoded}){}", input.display(), log_suffix(decoder_log));
+	}
+	Ok(frames)
+}
+
+/// Read raw RGB frames from `frames_in`, write RGBA cutouts to `frames_out`.
+fn pump(
+	mut frames_in: impl Read,
+	mut frames_out: impl Write,
+	info: &VideoInfo,
+	options: FrameOptions,
+	remover: &mut Remover,
+	cancel: &CancellationToken,
+	on_progress: &mut impl FnMut(FrameProgress)
+) -> Result<u64> {
+	let (w, h) = (info.width, info.height);
+	let mut buf = vec![0u8; w as usize * h as usize * 3];
+	let mut previous: Option<ProbabilityMap> = None;
+	let mut frame = 0;
+	while read_frame(&mut frames_in, &mut buf)? {
+		cancel.check()?;
+		let rgb = RgbImage::from_raw(w, h, buf.clone()).expect("buffer holds one frame");
+		let mut probs = if options.refine {
+			remover.predict_probabilities_refined(&rgb, &Prompt::default(), cancel, |_| {})?
+		} else {
+			remover.predict_probabilities(&rgb, cancel, |_| {})?
+		};
+		if let Some(prev) = &previous {
+			smooth(&mut probs, prev, options.smoothing);
+		}
+		let mask = u2net::quantize_mask(&probs);
+		frames_out
+			.write_all(cutout(&rgb, &mask).as_raw())
+			.context("write frame to ffmpeg")?;
+		previous = Some(probs);
+		frame += 1;
+		on_progress(FrameProgress { frame, total: info.frames });
+	}
+	Ok(frame)
+}
+
+/// Size, frame rate and frame count of the first video stream.
+pub fn probe(input: &Path) -> Result<VideoInfo> {
+	let out = Command::new(ffprobe())
+		.args(["-v", "error", "-select_streams", "v:0"])
+		.args(["-show_entries", "stream=width,height,r_frame_rate,nb_frames:stream_tags=rotate:stream_side_data=rotation"])
+		.args(["-of", "default=noprint_wrappers=1"])
+		.arg(input)
+		.output()
+		.context("run ffprobe (is ffmpeg installed?)")?;
+	if !out.status.success() {
+		bail!("ffprobe {}: {}", input.display(), String::from_utf8_lossy(&out.stderr).trim());
+	}
+
+	let text = String::from_utf8_lossy(&out.stdout);
+	let field = |key: &str| {
+		text.lines()
+			.find_map(|l| l.strip_prefix(key)?.strip_prefix('='))
+			.map(str::trim)
+	};
+	let dim = |key: &str| -> Result<u32> {
+		field(key)
+			.and_then(|v| v.parse().ok())
+			.with_context(|| format!("no video stream in {}", input.display()))
+	};
+	// The display matrix (newer muxers) or the older `rotate` tag; either may be negative.
+	let rotation = field("rotation")
+		.or_else(|| field("TAG:rotate"))
+		.and_then(|v| v.parse::<f64>().ok())
+		.map_or(0, |deg| (deg.round() as i64).rem_euclid(360));
+	let (width, height) = (dim("width")?, dim("height")?);
+	let (width, height) = if rotation == 90 || rotation == 270 { (height, width) } else { (width, height) };
+	Ok(VideoInfo {
+		width,
+		height,
+		frame_rate: field("r_frame_rate").unwrap_or("25").to_string(),
+		frames: field("nb_frames").and_then(|v| v.parse().ok())
+	})
+}
+
+/// `probs = (1 - smoothing) * probs + smoothing * previous`.
+fn smooth(probs: &mut ProbabilityMap, previous: &ProbabilityMap, smoothing: f32) {
+	for (p, q) in probs.pixels_mut().zip(previous.pixels()) {
+		p.0[0] = (1.0 - smoothing) * p.0[0] + smoothing * q.0[0];
+	}
+}
+
+fn cutout(rgb: &RgbImage, mask: &GrayImage) -> RgbaImage {
+	RgbaImage::from_fn(rgb.width(), rgb.height(), |x, y| {
+		let [r, g, b] = rgb.get_pixel(x, y).0;
+		image::Rgba([r, g, b, mask.get_pixel(x, y).0[0]])
+	})
+}
+
+/// Fill `buf` with the next frame; `false` at the end of the stream.
+fn read_frame(r: &mut impl Read, buf: &mut [u8]) -> Result<bool> {
+	match r.read_exact(buf) {
+		Ok(()) => Ok(true),
+		Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+		Err(e) => Err(e).context("read frame from ffmpeg")
+	}
+}
+
+/// ffmpeg decoding the first video stream of `input` to raw RGB frames on stdout. It applies the
+/// rotation tag while decoding, so frames come out upright and the output needs none.
+fn decode_command(input: &str) -> Command {
+	let mut cmd = Command::new(ffmpeg());
+	cmd.args(["-v", "error", "-i", input, "-map", "0:v:0"]).args(["-f", "rawvideo", "-pix_fmt", "rgb24", "-"]);
+	cmd
+}
+
+fn spawn(cmd: &mut Command) -> Result<Child> {
+	cmd.stderr(Stdio::piped()).spawn().context("run ffmpeg (is it installed and on PATH?)")
+}
+
+/// Collect the child's stderr on a thread of its own, so a chatty ffmpeg can't fill the pipe and stall.
+fn drain_stderr(child: &mut Child) -> JoinHandle<String> {
+	let mut stderr = child.stderr.take().expect("ffmpeg stderr is piped");
+	std::thread::spawn(move || {
+		let mut text = String::new();
+		let _ = stderr.read_to_string(&mut text);
+		text
+	})
+}
+
+/// `: <what ffmpeg printed>`, or nothing if it printed nothing.
+fn log_suffix(log: JoinHandle<String>) -> String {
+	let text = log.join().unwrap_or_default();
+	let text = text.trim();
+	if text.is_empty() { String::new() } else { format!(": {text}") }
+}
+
+fn ffmpeg() -> String {
+	std::env::var(FFMPEG_ENV).unwrap_or_else(|_| "ffmpeg".to_string())
+}
+
+fn ffprobe() -> String {
+	let ffmpeg = ffmpeg();
+	match ffmpeg.rsplit_once("ffmpeg") {
+		Some((dir, ext)) => format!("{dir}ffprobe{ext}"),
+		None => "ffprobe".to_string()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Runs `cmd`, which must succeed.
+	fn run(cmd: &mut Command) {
+		let status = cmd.stderr(Stdio::null()).status().expect("run ffmpeg");
+		assert!(status.success(), "{cmd:?} failed ({status})");
+	}
+
+	// A 64x32 clip stored with a 90° rotation has to probe and decode as 32x64. Skipped without
+	// ffmpeg.
+	#[test]
+	fn rotated_input_comes_out_upright() {
+		if !Command::new(ffmpeg()).arg("-version").output().is_ok_and(|o| o.status.success()) {
+			eprintln!("skipped: ffmpeg not found");
+			return;
+		}
+		let dir = std::env::temp_dir().join(format!("rembg-rs-video-test-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let (plain, rotated) = (dir.join("plain.mp4"), dir.join("rotated.mp4"));
+		run(Command::new(ffmpeg())
+			.args(["-v", "error", "-y", "-f", "lavfi", "-i", "testsrc=size=64x32:rate=5:duration=1", "-c:v", "mpeg4"])
+			.arg(&plain));
+		// `-display_rotation` (ffmpeg 6.1+) writes a display matrix; older versions take the `rotate` tag.
+		let matrix = Command::new(ffmpeg())
+			.args(["-v", "error", "-y", "-display_rotation", "90", "-i"])
+			.arg(&plain)
+			.args(["-c", "copy"])
+			.arg(&rotated)
+			.stderr(Stdio::null())
+			.status()
+			.is_ok_and(|s| s.success());
+		if !matrix {
+			run(Command::new(ffmpeg())
+				.args(["-v", "error", "-y", "-i"])
+				.arg(&plain)
+				.args(["-c", "copy", "-metadata:s:v:0", "rotate=90"])
+				.arg(&rotated));
+		}
+
+		let info = probe(&rotated).unwrap();
+		assert_eq!((info.width, info.height), (32, 64));
+		let out = decode_command(rotated.to_str().unwrap()).stderr(Stdio::null()).output().unwrap();
+		assert!(out.status.success());
+		let frame_len = info.width as usize * info.height as usize * 3;
+		assert!(!out.stdout.is_empty() && out.stdout.len().is_multiple_of(frame_len), "{} bytes of frames", out.stdout.len());
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+}
@@ -0,0 +1,500 @@
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use image::GrayImage;
+use serde::{Deserialize, Serialize};
+
+use crate::cancel::{CancellationToken, Cancelled};
+use crate::core::{self, GpuBackend, ProgressEvent, RemoveOptions, Timings};
+use crate::daemon::{read_frame, read_frame_opt, write_frame, write_json};
+use crate::error::RembgError;
+
+/// How often a job waiting on the worker checks its cancellation token.
+const CANCEL_POLL: Duration = Duration::from_millis(50);
+
+/// A message to the worker on its stdin.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Request {
+	/// Followed by a frame with the encoded input image.
+	Job { options: Box<RemoveOptions> },
+	/// Cancel the job in flight, if any.
+	Cancel,
+	/// Whether the runtime `backend` runs on has its EP; answered with `Probed` or `Error`.
+	Probe { backend: GpuBackend }
+}
+
+/// A message from the worker on its stdout. A job gets any number of `Progress` replies, then
+/// `Mask` or `Error`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Reply {
+	Progress { event: ProgressEvent },
+	/// Followed by a frame with the mask's `width * height` gray bytes.
+	Mask { width: u32, height: u32, timings: Timings },
+	Error {
+		/// The messages of the error's `chain()`, outermost first.
+		chain: Vec<String>,
+		/// The error at that position in `chain` whose type `error::code` looks for, so the other
+		/// side can rebuild it.
+		#[serde(default)]
+		cause: Option<(usize, Cause)>
+	},
+	Probed { available: bool }
+}
+
+/// A `Cancelled` or `RembgError` as it crosses the pipe.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Cause {
+	Cancelled,
+	DownloadRequired { what: String, url: String },
+	UnsupportedModel { name: String, supported: String },
+	/// The `image::ImageError` source follows in the chain.
+	Decode { what: String }
+}
+
+/// The running workers, shared by every job in this process.
+struct Pool {
+	/// Workers between jobs, least recently used first.
+	idle: Vec<Worker>,
+	/// Jobs running in a worker right now.
+	busy: usize,
+	/// At most this many jobs (and so workers) at once.
+	size: usize,
+	/// Bumped by `stop`: workers started before it are dropped after their job instead of kept.
+	generation: u64,
+	/// `ep_available` answers by runtime package; cleared by `stop`.
+	probed: Vec<(&'static str, GpuBackend, bool)>
+}
+
+impl Pool {
+	/// Idle workers (least recently used first) beyond `size` live ones. They're returned rather
+	/// than dropped so the caller can end them after releasing the lock.
+	fn trim(&mut self) -> Vec<Worker> {
+		let excess = (self.idle.len() + self.busy).saturating_sub(self.size);
+		self.idle.drain(..excess.min(self.idle.len())).collect()
+	}
+}
+
+/// `program` and `args` of `enable`.
+static COMMAND: Mutex<Option<(PathBuf, Vec<String>)>> = Mutex::new(None);
+static POOL: Mutex<Pool> = Mutex::new(Pool {
+	idle: Vec::new(),
+	busy: 0,
+	size: 1,
+	generation: 0,
+	probed: Vec::new()
+});
+/// Notified when a job slot frees up.
+static FREED: Condvar = Condvar::new();
+
+/// Run `core`'s inference (`remove_background_bytes`, `predict_mask_bytes` and what's built on
+/// them) in child processes, started as `program args...` and expected to call `serve`. Workers
+/// keep their sessions warm between jobs; a job that needs a different ONNX Runtime (CPU vs. a GPU
+/// package) gets a worker that loaded it, and a crash fails only the job in flight. Up to
+/// `set_pool_size` jobs run at once.
+pub fn enable(program: impl Into<PathBuf>, args: Vec<String>) {
+	*COMMAND.lock().unwrap() = Some((program.into(), args));
+	stop();
+}
+
+/// Go back to running inference in this process; the workers are stopped.
+pub fn disable() {
+	*COMMAND.lock().unwrap() = None;
+	stop();
+}
+
+pub fn enabled() -> bool {
+	COMMAND.lock().unwrap().is_some()
+}
+
+/// Run up to `size` jobs at once (at least 1), each in its own worker; 1 until this is called.
+/// Further jobs wait for a free worker.
+pub fn set_pool_size(size: usize) {
+	let mut pool = POOL.lock().unwrap();
+	pool.size = size.max(1);
+	let retired = pool.trim();
+	drop(pool);
+	FREED.notify_all();
+	drop(retired);
+}
+
+/// Stop the workers, releasing their sessions: idle ones now, busy ones once their job ends. Doesn't
+/// wait for running jobs; the next job starts a new worker.
+pub fn stop() {
+	let mut pool = POOL.lock().unwrap();
+	pool.generation += 1;
+	pool.probed.clear();
+	let retired = std::mem::take(&mut pool.idle);
+	drop(pool);
+	drop(retired);
+}
+
+/// The worker side of `enable`: run jobs from stdin and reply on stdout until stdin closes. Nothing
+/// else may write to stdout meanwhile.
+pub fn serve() -> Result<()> {
+	enum Task {
+		Job(Box<RemoveOptions>, Vec<u8>, CancellationToken),
+		Probe(GpuBackend)
+	}
+
+	let (tx, tasks) = mpsc::channel();
+	// Reads ahead of the jobs so a `Cancel` reaches the one running.
+	std::thread::spawn(move || -> Result<()> {
+		let mut stdin = io::stdin().lock();
+		let mut current = CancellationToken::new();
+		while let Some(frame) = read_frame_opt(&mut stdin)? {
+			let task = match serde_json::from_slice(&frame).context("parse request")? {
+				Request::Job { options } => {
+					let input = read_frame(&mut stdin)?;
+					current = CancellationToken::new();
+					Task::Job(options, input, current.clone())
+				}
+				Request::Probe { backend } => Task::Probe(backend),
+				Request::Cancel => {
+					current.cancel();
+					continue;
+				}
+			};
+			if tx.send(task).is_err() {
+				break;
+			}
+		}
+		Ok(())
+	});
+
+	let mut out = io::stdout().lock();
+	for task in tasks {
+		match task {
+			Task::Job(options, input, cancel) => {
+				let mut sent = Ok(());
+				let result = core::predict_mask_bytes(&input, &options, &cancel, |event| {
+					if sent.is_ok() {
+						sent = write_json(&mut out, &Reply::Progress { event }).and_then(|()| Ok(out.flush()?));
+					}
+				});
+				sent?;
+				match result {
+					Ok((mask, timings)) => {
+						let (width, height) = mask.dimensions();
+						write_json(&mut out, &Reply::Mask { width, height, timings })?;
+						write_frame(&mut out, mask.as_raw())?;
+					}
+					Err(e) => write_json(&mut out, &error_reply(&e))?
+				}
+			}
+			Task::Probe(backend) => match core::probe_ep(backend) {
+				Ok(available) => write_json(&mut out, &Reply::Probed { available })?,
+				Err(e) => write_json(&mut out, &error_reply(&e))?
+			}
+		}
+		out.flush()?;
+	}
+	Ok(())
+}
+
+/// `core::predict_mask_bytes` in a worker, waiting for a free one and starting one as needed.
+pub(crate) fn predict_mask(
+	input_bytes: &[u8],
+	opts: &RemoveOptions,
+	cancel: &CancellationToken,
+	on_progress: &mut impl FnMut(ProgressEvent)
+) -> Result<(GrayImage, Timings)> {
+	let package = core::plan(opts)?.runtime_package;
+	let command = command()?;
+	let mut lease = lease(package, Some(cancel))?.expect("waits for a slot");
+	cancel.check()?;
+	lease.worker(&command)?.run(input_bytes, opts, cancel, on_progress)
+}
+
+/// Whether `backend`'s runtime (`package`) has its EP, asked of a worker that loads it. `None` when
+/// every worker is busy; answers are kept until `stop`.
+pub(crate) fn ep_available(backend: GpuBackend, package: &'static str) -> Result<Option<bool>> {
+	let known = POOL.lock().unwrap().probed.iter().find(|p| p.0 == package && p.1 == backend).map(|p| p.2);
+	if known.is_some() {
+		return Ok(known);
+	}
+	let command = command()?;
+	let Some(mut lease) = lease(package, None)? else {
+		return Ok(None);
+	};
+	let available = lease.worker(&command)?.probe(backend)?;
+	POOL.lock().unwrap().probed.push((package, backend, available));
+	Ok(Some(available))
+}
+
+fn command() -> Result<(PathBuf, Vec<String>)> {
+	match COMMAND.lock().unwrap().clone() {
+		Some(command) => Ok(command),
+		None => bail!("no inference worker is enabled")
+	}
+}
+
+/// Take a job slot, with an idle worker that has `package` loaded if there is one. Without a free
+/// slot it waits (until `cancel` is cancelled) or, given no token, returns `None`.
+fn lease(package: &'static str, cancel: Option<&CancellationToken>) -> Result<Option<Lease>> {
+	let mut pool = POOL.lock().unwrap();
+	while pool.busy >= pool.size {
+		let Some(cancel) = cancel else {
+			return Ok(None);
+		};
+		cancel.check()?;
+		pool = FREED.wait_timeout(pool, CANCEL_POLL).unwrap().0;
+	}
+	pool.busy += 1;
+	let worker = pool.idle.iter().rposition(|w| w.package == package).map(|i| pool.idle.remove(i));
+	let lease = Lease {
+		worker,
+		package,
+		generation: pool.generation
+	};
+	// Makes room for the worker the lease may start.
+	let retired = pool.trim();
+	drop(pool);
+	drop(retired);
+	Ok(Some(lease))
+}
+
+/// A job slot and the worker it runs on; dropping it frees the slot and keeps a healthy worker.
+struct Lease {
+	worker: Option<Worker>,
+	package: &'static str,
+	generation: u64
+}
+
+impl Lease {
+	/// The leased worker, started now if the pool had none for the package.
+	fn worker(&mut self, (program, args): &(PathBuf, Vec<String>)) -> Result<&mut Worker> {
+		if self.worker.is_none() {
+			self.worker = Some(Worker::spawn(program, args, self.package)?);
+		}
+		Ok(self.worker.as_mut().expect("worker just started"))
+	}
+}
+
+impl Drop for Lease {
+	fn drop(&mut self) {
+		let mut pool = POOL.lock().unwrap();
+		pool.busy -= 1;
+		let mut retired = Vec::new();
+		match self.worker.take() {
+			// A process can only load one ONNX Runtime library, so workers are kept per package.
+			Some(worker) if !worker.broken && self.generation == pool.generation => {
+				pool.idle.push(worker);
+				retired = pool.trim();
+			}
+			worker => retired.extend(worker)
+		}
+		drop(pool);
+		FREED.notify_one();
+		drop(retired);
+	}
+}
+
+struct Worker {
+	child: Child,
+	stdin: ChildStdin,
+	/// Frames from the child's stdout, read on a thread so a waiting job can still notice cancellation.
+	replies: mpsc::Receiver<io::Result<Vec<u8>>>,
+	/// The ONNX Runtime package the child has (or will have) loaded.
+	package: &'static str,
+	/// The child died or the pipes are out of step; it is replaced before the next job.
+	broken: bool
+}
+
+impl Worker {
+	fn spawn(program: &Path, args: &[String], package: &'static str) -> Result<Worker> {
+		let mut command = Command::new(program);
+		command.args(args).stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::inherit());
+		#[cfg(windows)]
+		{
+			use std::os::windows::process::CommandExt;
+			// No console window when the parent is a GUI app.
+			const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+			command.creation_flags(CREATE_NO_WINDOW);
+		}
+		let mut child = command
+			.spawn()
+			.with_context(|| format!("start inference worker: {}", program.display()))?;
+		let stdin = child.stdin.take().context("worker stdin")?;
+		let mut stdout = child.stdout.take().context("worker stdout")?;
+		let (tx, replies) = mpsc::channel();
+		std::thread::spawn(move || {
+			loop {
+				let frame = read_frame(&mut stdout);
+				let failed = frame.is_err();
+				if tx.send(frame).is_err() || failed {
+					break;
+				}
+			}
+		});
+		Ok(Worker {
+			child,
+			stdin,
+			replies,
+			package,
+			broken: false
+		})
+	}
+
+	fn run(
+		&mut self,
+		input_bytes: &[u8],
+		opts: &RemoveOptions,
+		cancel: &CancellationToken,
+		on_progress: &mut impl FnMut(ProgressEvent)
+	) -> Result<(GrayImage, Timings)> {
+		let job = Request::Job { options: Box::new(opts.clone()) };
+		let sent = write_json(&mut self.stdin, &job)
+			.and_then(|()| Ok(write_frame(&mut self.stdin, input_bytes)?))
+			.and_then(|()| Ok(self.stdin.flush()?));
+		if sent.is_err() {
+			return Err(self.died()).context("send job to the inference worker");
+		}
+
+		let mut cancel_sent = false;
+		loop {
+			match self.recv(cancel, &mut cancel_sent)? {
+				Reply::Progress { event } => on_progress(event),
+				Reply::Mask { width, height, timings } => {
+					let Ok(Ok(raw)) = self.replies.recv() else {
+						return Err(self.died());
+					};
+					let mask = GrayImage::from_raw(width, height, raw).context("inference worker sent a malformed mask");
+					self.broken = mask.is_err();
+					return Ok((mask?, timings));
+				}
+				Reply::Error { chain, cause } => return Err(rebuild(chain, cause)),
+				Reply::Probed { .. } => return Err(self.out_of_step())
+			}
+		}
+	}
+
+	fn probe(&mut self, backend: GpuBackend) -> Result<bool> {
+		let sent = write_json(&mut self.stdin, &Request::Probe { backend }).and_then(|()| Ok(self.stdin.flush()?));
+		if sent.is_err() {
+			return Err(self.died()).context("send probe to the inference worker");
+		}
+		match self.recv(&CancellationToken::new(), &mut false)? {
+			Reply::Probed { available } => Ok(available),
+			Reply::Error { chain, cause } => Err(rebuild(chain, cause)),
+			_ => Err(self.out_of_step())
+		}
+	}
+
+	/// The next reply, sending `Cancel` (once) if `cancel` is cancelled while waiting.
+	fn recv(&mut self, cancel: &CancellationToken, cancel_sent: &mut bool) -> Result<Reply> {
+		loop {
+			let frame = match self.replies.recv_timeout(CANCEL_POLL) {
+				Ok(Ok(frame)) => frame,
+				Err(RecvTimeoutError::Timeout) => {
+					if cancel.is_cancelled() && !*cancel_sent {
+						*cancel_sent = true;
+						if write_json(&mut self.stdin, &Request::Cancel).and_then(|()| Ok(self.stdin.flush()?)).is_err() {
+							return Err(self.died());
+						}
+					}
+					continue;
+				}
+				Ok(Err(_)) | Err(RecvTimeoutError::Disconnected) => return Err(self.died())
+			};
+			return serde_json::from_slice(&frame).map_err(|e| {
+				self.broken = true;
+				anyhow::Error::from(e).context("parse inference worker reply")
+			});
+		}
+	}
+
+	fn out_of_step(&mut self) -> anyhow::Error {
+		self.broken = true;
+		anyhow::anyhow!("unexpected reply from the inference worker")
+	}
+
+	/// Mark the worker for replacement and describe how it ended.
+	fn died(&mut self) -> anyhow::Error {
+		self.broken = true;
+		let _ = self.child.kill();
+		match self.child.wait() {
+			Ok(status) => anyhow::anyhow!("inference worker exited ({status})"),
+			Err(e) => anyhow::anyhow!("inference worker lost: {e}")
+		}
+	}
+}
+
+impl Drop for Worker {
+	fn drop(&mut self) {
+		let _ = self.child.kill();
+		let _ = self.child.wait();
+	}
+}
+
+fn error_reply(e: &anyhow::Error) -> Reply {
+	let cause = e.chain().enumerate().find_map(|(at, cause)| {
+		if cause.is::<Cancelled>() {
+			return Some((at, Cause::Cancelled));
+		}
+		let cause = match cause.downcast_ref::<RembgError>()? {
+			RembgError::DownloadRequired { what, url } => Cause::DownloadRequired {
+				what: what.clone(),
+				url: url.clone()
+			},
+			RembgError::UnsupportedModel { name, supported } => Cause::UnsupportedModel {
+				name: name.clone(),
+				supported: supported.clone()
+			},
+			RembgError::Decode { what, .. } => Cause::Decode { what: what.to_string() }
+		};
+		Some((at, cause))
+	});
+	Reply::Error {
+		chain: e.chain().map(ToString::to_string).collect(),
+		cause
+	}
+}
+
+/// The error an `Error` reply describes, with the same `{:#}` message and `error::code`.
+fn rebuild(mut chain: Vec<String>, cause: Option<(usize, Cause)>) -> anyhow::Error {
+	let Some((at, cause)) = cause.filter(|(at, _)| *at < chain.len()) else {
+		let bottom = chain.pop().unwrap_or_else(|| "inference worker failed".to_string());
+		return with_context(anyhow::anyhow!(bottom), chain);
+	};
+	let below = chain.split_off(at + 1);
+	// The cause's own message, which the rebuilt error displays itself.
+	chain.pop();
+	let err = match cause {
+		Cause::Cancelled => Cancelled.into(),
+		Cause::DownloadRequired { what, url } => over(RembgError::DownloadRequired { what, url }, below),
+		Cause::UnsupportedModel { name, supported } => over(RembgError::UnsupportedModel { name, supported }, below),
+		Cause::Decode { what } => RembgError::Decode {
+			what: match what.as_str() {
+				"input image" => "input image",
+				"background image" => "background image",
+				"mask" => "mask",
+				_ => "image"
+			},
+			// The `ImageError` and its sources, as one message.
+			source: image::ImageError::IoError(io::Error::other(below.join(": ")))
+		}
+		.into()
+	};
+	with_context(err, chain)
+}
+
+/// `error`, as context over the error the messages `below` describe (if any).
+fn over(error: RembgError, mut below: Vec<String>) -> anyhow::Error {
+	match below.pop() {
+		Some(bottom) => with_context(anyhow::anyhow!(bottom), below).context(error),
+		None => error.into()
+	}
+}
+
+/// `err` wrapped in `messages`, outermost first.
+fn with_context(err: anyhow::Error, messages: Vec<String>) -> anyhow::Error {
+	messages.into_iter().rev().fold(err, |err, message| err.context(message))
+}
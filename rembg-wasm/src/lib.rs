@@ -0,0 +1,82 @@
+use image::GrayImage;
+use ndarray::{ArrayD, IxDyn};
+use rembg_rs::core::{self, RemoveOptions};
+use rembg_rs::{mask, u2net};
+use wasm_bindgen::prelude::*;
+
+/// Decode `image` and build the model input: the flattened `1x3xSxS` NCHW tensor
+/// (`S` = `input_size`) to feed onnxruntime-web.
+#[wasm_bindgen]
+pub fn preprocess(image: &[u8], input_size: u32) -> Result<Vec<f32>, JsError> {
+	let img = image::load_from_memory(image).map_err(js_error)?.to_rgb8();
+	let tensor = u2net::preprocess(&img, input_size).map_err(js_error)?;
+	Ok(tensor.into_iter().collect())
+}
+
+/// Turn the model's flattened `1x1xHxW` output into an 8-bit mask (`width * height` bytes,
+/// row-major) at the original image size.
+#[wasm_bindgen]
+pub fn postprocess(
+	output: Vec<f32>,
+	output_width: u32,
+	output_height: u32,
+	width: u32,
+	height: u32
+) -> Result<Vec<u8>, JsError> {
+	let shape = IxDyn(&[1, 1, output_height as usize, output_width as usize]);
+	let out = ArrayD::from_shape_vec(shape, output).map_err(js_error)?;
+	let probs = u2net::postprocess(&out, width, height).map_err(js_error)?;
+	Ok(u2net::quantize_mask(&probs).into_raw())
+}
+
+/// The CLI's `--mask-threshold`, `--fill-holes` and `--feather` passes, applied in that order.
+#[wasm_bindgen]
+pub fn refine_mask(
+	mask: Vec<u8>,
+	width: u32,
+	height: u32,
+	threshold: Option<u8>,
+	fill_holes: Option<u32>,
+	feather: Option<f32>
+) -> Result<Vec<u8>, JsError> {
+	let mut mask = mask_image(mask, width, height)?;
+	if let Some(t) = threshold {
+		mask = mask::threshold(&mask, t);
+	}
+	if let Some(max_area) = fill_holes {
+		mask = mask::fill_holes(&mask, max_area);
+	}
+	if let Some(sigma) = feather {
+		mask = mask::feather(&mask, sigma, None);
+	}
+	Ok(mask.into_raw())
+}
+
+/// Composite `image` with `mask` and encode the result exactly like the native pipeline.
+/// `options_json` holds any subset of the `RemoveOptions` fields; encoders that need the
+/// native build (lossy WebP, PNG optimization) fail with an error.
+#[wasm_bindgen]
+pub fn compose(
+	image: &[u8],
+	mask: Vec<u8>,
+	width: u32,
+	height: u32,
+	options_json: Option<String>
+) -> Result<Vec<u8>, JsError> {
+	let mask = mask_image(mask, width, height)?;
+	let opts = match options_json {
+		Some(json) => RemoveOptions::from_partial_json(&json).map_err(js_error)?,
+		None => RemoveOptions::default()
+	};
+	let result = core::compose_result(image, &mask, &opts).map_err(js_error)?;
+	Ok(result.output_png)
+}
+
+fn mask_image(mask: Vec<u8>, width: u32, height: u32) -> Result<GrayImage, JsError> {
+	GrayImage::from_raw(width, height, mask)
+		.ok_or_else(|| JsError::new(&format!("mask must be {width}x{height} bytes")))
+}
+
+fn js_error(e: impl Into<anyhow::Error>) -> JsError {
+	JsError::new(&format!("{:#}", e.into()))
+}